@@ -0,0 +1,97 @@
+//! Progress events emitted by the agent
+//!
+//! The agent does not write to the terminal itself; it reports what it is
+//! doing to an [`AgentObserver`]. The CLI renders events with
+//! [`crate::render::TerminalRenderer`], and library users can plug in their
+//! own UI or logging.
+
+use crate::model::TokenUsage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Something that happened during an agent run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// A model request was sent
+    ThinkingStarted { iteration: usize },
+    /// A fragment of the response text arrived from a streaming model
+    TokenDelta { text: String },
+    /// The model's response is complete
+    ThinkingFinished { iteration: usize },
+    /// Text of a model response and what it cost
+    ResponseReceived {
+        content: String,
+        usage: TokenUsage,
+        /// `None` when the model has no price
+        cost_usd: Option<f64>,
+    },
+    /// The model asked to run a tool
+    ToolCallRequested {
+        id: String,
+        name: String,
+        args: serde_json::Value,
+    },
+    /// The tool call passed the permission check
+    ToolCallApproved { id: String, name: String },
+    /// The tool call was refused by the user or a stored permission
+    ToolCallDenied { id: String, name: String },
+    /// The tool ran; `output` is the error text when it failed
+    ToolCallFinished {
+        id: String,
+        name: String,
+        success: bool,
+        output: String,
+        /// Tool-specific details such as `exit_code` or `diff`
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        metadata: HashMap<String, serde_json::Value>,
+    },
+    /// A tool wrote a file
+    FileChanged {
+        path: String,
+        /// New contents, when the tool wrote the whole file
+        content: Option<String>,
+    },
+    /// Instruction files were found in directories the agent touched
+    InstructionsLoaded { paths: Vec<String> },
+    /// The conversation is being summarized to free up context
+    CompactionStarted { percent: u32 },
+    /// The next request may not fit the model's context window
+    ContextWarning { used_tokens: usize, context_window: usize },
+    /// The run finished with the model's final answer
+    Completed { output: String, iterations: usize },
+}
+
+/// Receives events as the agent runs
+///
+/// Called synchronously from the agent loop, so implementations should
+/// return quickly.
+pub trait AgentObserver: Send + Sync {
+    fn on_event(&self, event: &AgentEvent);
+}
+
+/// Observer that ignores every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullObserver;
+
+impl AgentObserver for NullObserver {
+    fn on_event(&self, _event: &AgentEvent) {}
+}
+
+/// Forwards events to every observer in order
+impl AgentObserver for Vec<Arc<dyn AgentObserver>> {
+    fn on_event(&self, event: &AgentEvent) {
+        for observer in self {
+            observer.on_event(event);
+        }
+    }
+}
+
+/// Forwards events to a channel, for consuming them as a stream
+impl AgentObserver for tokio::sync::mpsc::UnboundedSender<AgentEvent> {
+    fn on_event(&self, event: &AgentEvent) {
+        // The receiver going away just means nobody is listening any more
+        let _ = self.send(event.clone());
+    }
+}
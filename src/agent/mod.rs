@@ -1,9 +1,12 @@
 //! Agent orchestration and ReACT loop
 
+use crate::approval::{ApprovalHandler, ApprovalRequest, RiskLevel};
 use crate::config::Config;
+use crate::context::{CompactionReport, Compactor, ContextUsage, ProjectAnalysis, ProjectInstructions, RepoMap};
 use crate::error::{AgentError, Result};
-use crate::model::{LanguageModel, Message};
+use crate::model::{ContentPart, LanguageModel, Message, ModelResponse, Role, ToolCall};
 use crate::tools::{ToolContext, ToolRegistry};
+use crate::usage::{PriceTable, UsageSummary, UsageTracker};
 use crate::prompt::templates::TemplateManager;
 
 use serde::{Deserialize, Serialize};
@@ -11,9 +14,12 @@ use serde::{Deserialize, Serialize};
 use crate::safety::SafetyValidator;
 use crate::permissions::PermissionManager;
 use crate::formatter::ResponseFormatter;
-use crate::loading::LoadingIndicator;
 use std::sync::{Arc, Mutex};
 
+mod events;
+
+pub use events::{AgentEvent, AgentObserver, NullObserver};
+
 /// Agent for orchestrating LLM interactions and tool execution
 pub struct Agent {
     model: Box<dyn LanguageModel>,
@@ -23,7 +29,17 @@ pub struct Agent {
     permission_manager: Arc<Mutex<PermissionManager>>,
     template_manager: TemplateManager,
     formatter: ResponseFormatter,
+    compactor: Compactor,
+    usage: UsageTracker,
+    observer: Arc<dyn AgentObserver>,
+    approval: Arc<dyn ApprovalHandler>,
+    lsp: Option<Arc<crate::lsp::LspManager>>,
+    instructions: ProjectInstructions,
+    project: ProjectAnalysis,
+    repo_map: RepoMap,
     iteration_count: usize,
+    /// Counter for ids linking tool calls to their results
+    next_tool_call_id: usize,
     pub conversation_history: Vec<Message>,
 }
 
@@ -34,6 +50,9 @@ pub struct AgentResult {
     pub output: String,
     pub iterations: usize,
     pub tool_calls: Vec<String>,
+    /// Token usage and cost of this run
+    #[serde(default)]
+    pub usage: UsageSummary,
 }
 
 impl Agent {
@@ -48,6 +67,15 @@ impl Agent {
         let safety_validator = SafetyValidator::new(config.clone())?;
         let template_manager = TemplateManager::new().await?;
         let formatter = ResponseFormatter::new();
+        let compactor = Compactor::new(config.context.clone());
+        let usage = UsageTracker::new(PriceTable::new(&config.models.pricing));
+        let cwd = std::env::current_dir()?;
+        let instructions = ProjectInstructions::new(&cwd)
+            .with_budget(config.context.instruction_budget)
+            .with_tokenizer(model.tokenizer());
+        let mut repo_map = RepoMap::new(&crate::context::project_root(&cwd));
+        repo_map.refresh();
+        let project = ProjectAnalysis::analyze(repo_map.root(), &repo_map.files());
         Ok(Self {
             model,
             tools,
@@ -56,25 +84,93 @@ impl Agent {
             permission_manager,
             template_manager,
             formatter,
+            compactor,
+            usage,
+            observer: Arc::new(NullObserver),
+            approval: crate::approval::default_handler(),
+            lsp: None,
+            instructions,
+            project,
+            repo_map,
             iteration_count: 0,
+            next_tool_call_id: last_tool_call_id(&conversation_history),
             conversation_history,
         })
     }
 
+    /// Report progress to `observer` instead of discarding it
+    pub fn with_observer(mut self, observer: Arc<dyn AgentObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Decide on tool calls and file changes with `handler`
+    ///
+    /// Defaults to asking on the terminal when stdin is one, and denying otherwise.
+    pub fn with_approval_handler(mut self, handler: Arc<dyn ApprovalHandler>) -> Self {
+        self.approval = handler;
+        self
+    }
+
+    /// Give tools access to language servers for diagnostics and navigation
+    pub fn with_lsp(mut self, lsp: Arc<crate::lsp::LspManager>) -> Self {
+        self.lsp = Some(lsp);
+        self
+    }
+
+    /// Use `instructions` instead of the ones found from the current directory
+    pub fn with_instructions(mut self, instructions: ProjectInstructions) -> Self {
+        self.instructions = instructions.with_tokenizer(self.model.tokenizer());
+        self
+    }
+
+    /// Project instruction files included in the system prompt
+    pub fn instructions(&self) -> &ProjectInstructions {
+        &self.instructions
+    }
+
+    /// Languages, workspaces and commands of the project
+    pub fn project(&self) -> &ProjectAnalysis {
+        &self.project
+    }
+
+    /// Map the project at `root` instead of the repository of the current directory
+    pub fn with_project_root(mut self, root: &std::path::Path) -> Self {
+        self.repo_map = RepoMap::new(root);
+        self.refresh_project();
+        self
+    }
+
+    /// Re-read files changed since the last run and analyse the project again if any did
+    fn refresh_project(&mut self) {
+        if self.repo_map.refresh() {
+            self.project = ProjectAnalysis::analyze(self.repo_map.root(), &self.repo_map.files());
+        }
+    }
+
+    fn emit(&self, event: AgentEvent) {
+        self.observer.on_event(&event);
+    }
+
     /// Run the agent on a task
     pub async fn run(&mut self, task: &str) -> Result<AgentResult> {
-        tracing::info!("Starting agent run for task: {}", task);
+        self.run_message(Message::user(task)).await
+    }
+
+    /// Run the agent on a user message, which may carry images
+    pub async fn run_message(&mut self, task: Message) -> Result<AgentResult> {
+        tracing::info!("Starting agent run for task: {}", task.text());
 
         self.iteration_count = 0;
+        self.usage.start_run();
 
-        // Add system prompt
-        let system_prompt = self.build_system_prompt().await;
-        self.conversation_history
-            .push(Message::system(system_prompt));
+        // Pick up edits to instruction files and sources since the last run
+        self.instructions.reload();
+        self.refresh_project();
+        self.refresh_system_prompt().await;
 
         // Add user task
-        self.conversation_history
-            .push(Message::user(task));
+        self.conversation_history.push(task);
 
         let mut tool_calls = Vec::new();
 
@@ -88,11 +184,42 @@ impl Agent {
 
             tracing::debug!("Agent iteration: {}", self.iteration_count);
 
-            // REASON: Get model response with loading indicator
-            let mut loading = LoadingIndicator::new();
-            loading.start();
-            let response = self.model.chat(&self.conversation_history).await?;
-            loading.stop().await;
+            let mut usage = self.context_usage();
+            if self.compactor.needs_compaction(&usage) {
+                self.emit(AgentEvent::CompactionStarted { percent: usage.percent() });
+                let report = self.compact().await?;
+                tracing::info!("{}", report);
+                usage = self.context_usage();
+            }
+
+            let reserved = self.model.model_info().max_tokens;
+            if usage.used_tokens + reserved > usage.context_window {
+                tracing::warn!(
+                    "Request may exceed the context window: {} prompt + {} completion tokens > {}",
+                    usage.used_tokens,
+                    reserved,
+                    usage.context_window
+                );
+                self.emit(AgentEvent::ContextWarning {
+                    used_tokens: usage.used_tokens,
+                    context_window: usage.context_window,
+                });
+            }
+
+            // REASON: Get model response, streaming text to the observer when the model can
+            self.emit(AgentEvent::ThinkingStarted { iteration: self.iteration_count });
+            let response = if self.model.supports_streaming() {
+                let observer = Arc::clone(&self.observer);
+                let mut on_delta = |text: &str| {
+                    observer.on_event(&AgentEvent::TokenDelta { text: text.to_string() });
+                };
+                self.model.chat_stream(&self.conversation_history, &[], &mut on_delta).await
+            } else {
+                self.model.chat(&self.conversation_history).await
+            };
+            self.emit(AgentEvent::ThinkingFinished { iteration: self.iteration_count });
+            let response = response?;
+            self.record_usage(&response)?;
 
             // Inject file content if mentioned in response
             // DISABLED: This was causing loops where the agent would mention files
@@ -104,40 +231,64 @@ impl Agent {
             tracing::info!("Response content: {:?}", response.content);
             if self.is_complete(&response.content) {
                 tracing::info!("Task complete detected!");
+                self.emit(AgentEvent::Completed {
+                    output: response.content.clone(),
+                    iterations: self.iteration_count,
+                });
                 return Ok(AgentResult {
                     success: true,
                     output: response.content,
                     iterations: self.iteration_count,
                     tool_calls,
+                    usage: self.usage.run().clone(),
                 });
             } else {
                 tracing::info!("Task not complete, continuing...");
             }
 
             // ACT: Parse and execute tool calls
-            if let Some(tool_call) = self.parse_tool_call(&response.content) {
+            if let Some(mut tool_call) = self.parse_tool_call(&response.content) {
+                self.next_tool_call_id += 1;
+                tool_call.id = format!("call_{}", self.next_tool_call_id);
+
                 // Clone tool call for later use (displaying output)
                 let tool_call_clone = ParsedToolCall {
+                    id: tool_call.id.clone(),
                     name: tool_call.name.clone(),
                     args: tool_call.args.clone(),
                 };
 
-                let result = self.execute_tool_call(tool_call, &mut tool_calls).await?;
+                // Signed thinking stays with the turn it belongs to
+                let mut content = response.thinking_parts;
+                content.push(ContentPart::text(response.content));
+                content.push(ContentPart::ToolCall(ToolCall {
+                    id: tool_call.id.clone(),
+                    name: tool_call.name.clone(),
+                    arguments: tool_call.args.clone(),
+                }));
+                self.conversation_history.push(Message::new(Role::Assistant, content));
+                self.emit(AgentEvent::ToolCallRequested {
+                    id: tool_call.id.clone(),
+                    name: tool_call.name.clone(),
+                    args: tool_call.args.clone(),
+                });
+
+                let result = match self.execute_tool_call(tool_call, &mut tool_calls).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        // Keep every call paired with a result so the history stays valid
+                        self.conversation_history
+                            .push(Message::tool_result(&tool_call_clone.id, e.to_string(), true));
+                        return Err(e);
+                    }
+                };
                 
-                // If this was a file write, show the content that was written
                 if tool_call_clone.name == "file_write" && result.success {
-                    if let Some(content) = tool_call_clone.args.get("content").and_then(|c| c.as_str()) {
-                        let path = tool_call_clone.args.get("path").and_then(|p| p.as_str()).unwrap_or("unknown");
-                        let ext = std::path::Path::new(path)
-                            .extension()
-                            .and_then(|e| e.to_str())
-                            .unwrap_or("txt");
-                        
-                        println!("\n\x1b[1;32mWritten to {}:\x1b[0m", path);
-                        println!("```{}", ext);
-                        println!("{}", content);
-                        println!("```\n");
-                    }
+                    let args = &tool_call_clone.args;
+                    self.emit(AgentEvent::FileChanged {
+                        path: args.get("path").and_then(|p| p.as_str()).unwrap_or("unknown").to_string(),
+                        content: args.get("content").and_then(|c| c.as_str()).map(str::to_string),
+                    });
                 }
 
                 if !result.success {
@@ -145,8 +296,9 @@ impl Agent {
                 }
             } else {
                 // No tool call found, add response to history
-                self.conversation_history
-                    .push(Message::assistant(response.content));
+                let mut content = response.thinking_parts;
+                content.push(ContentPart::text(response.content));
+                self.conversation_history.push(Message::new(Role::Assistant, content));
             }
         }
     }
@@ -159,7 +311,6 @@ impl Agent {
         
         // Lock the permission manager
         // We need to be careful about holding the lock across await points
-        // prompt_for_permission is synchronous (uses dialoguer), so it's fine
         let permission_level = {
             let pm = self.permission_manager.lock().unwrap();
             pm.check_permission(&tool_call.name)
@@ -167,23 +318,42 @@ impl Agent {
         
         match permission_level {
             PermissionLevel::Never => {
+                self.emit(AgentEvent::ToolCallDenied {
+                    id: tool_call.id.clone(),
+                    name: tool_call.name.clone(),
+                });
                 return Err(crate::error::ToolError::PermissionDenied(tool_call.name).into());
             }
             PermissionLevel::Ask => {
-                // Prompt user for permission
+                let risk = self.tools.get(&tool_call.name).map(|t| t.risk()).unwrap_or(RiskLevel::Medium);
+                let request = ApprovalRequest::new(
+                    &tool_call.name,
+                    tool_call.args.clone(),
+                    format!("Run {} with {}", tool_call.name, tool_call.args),
+                    risk,
+                );
+                let decision = self.approval.decide(&request).await?;
+
                 // We need to lock again for mutation
                 let allowed = {
                     let mut pm = self.permission_manager.lock().unwrap();
-                    pm.prompt_for_permission(&tool_call.name)
+                    pm.record_decision(&tool_call.name, decision)
                         .map_err(|e| crate::error::PromptLineError::Other(e.to_string()))?
                 };
                 
                 if !allowed {
+                    self.emit(AgentEvent::ToolCallDenied {
+                        id: tool_call.id.clone(),
+                        name: tool_call.name.clone(),
+                    });
+                    self.conversation_history
+                        .push(Message::tool_result(&tool_call.id, "Permission denied by user.", true));
                     return Ok(AgentResult {
                         success: false,
                         output: "Permission denied.".to_string(),
                         iterations: self.iteration_count,
                         tool_calls: tool_calls.clone(),
+                        usage: self.usage.run().clone(),
                     });
                 }
             }
@@ -191,6 +361,10 @@ impl Agent {
                 // Permission already granted
             }
         }
+        self.emit(AgentEvent::ToolCallApproved {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+        });
 
         // Validate command
         let command_str = format!("{} {}", tool_call.name, tool_call.args);
@@ -208,7 +382,11 @@ impl Agent {
 
         tool_calls.push(tool_call.name.clone());
 
-        let mut ctx = ToolContext::default();
+        let mut ctx = ToolContext {
+            approval: Arc::clone(&self.approval),
+            lsp: self.lsp.clone(),
+            ..ToolContext::default()
+        };
         if let Ok(output) = tokio::process::Command::new("git")
             .arg("rev-parse")
             .arg("--abbrev-ref")
@@ -220,42 +398,87 @@ impl Agent {
                 ctx.git_branch = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
             }
         }
+        let touched = touched_paths(&tool_call.args, &ctx.working_dir);
+
         // Execute the tool
         let result = self
             .tools
             .execute(&tool_call.name, tool_call.args, &ctx, &self.config)
             .await?;
 
-        // Show formatted result to user
+        // Instructions in subdirectories apply once the agent works there
+        let loaded: Vec<String> = touched
+            .iter()
+            .flat_map(|path| self.instructions.discover(path))
+            .map(|path| path.display().to_string())
+            .collect();
+        if !loaded.is_empty() {
+            self.emit(AgentEvent::InstructionsLoaded { paths: loaded });
+            self.refresh_system_prompt().await;
+        }
+
         let result_text = if result.success {
             &result.output
         } else {
             result.error.as_ref().unwrap_or(&result.output)
         };
-        
-        let formatted_output = self.formatter.format_tool_result(&tool_call.name, result_text);
-        print!("{}", formatted_output);
-        use std::io::Write;
-        std::io::stdout().flush().ok();
-
-        // OBSERVE: Add result to conversation (for the model)
-        let observation = format!(
-            "Tool '{}' result: {}",
-            tool_call.name,
-            result_text
-        );
+        self.emit(AgentEvent::ToolCallFinished {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            success: result.success,
+            output: result_text.clone(),
+            metadata: result.metadata.clone(),
+        });
 
+        // OBSERVE: Add result to conversation (for the model), linked to the call
+        let observation = self.compactor.truncate_tool_output(result_text);
         self.conversation_history
-            .push(Message::assistant(observation));
+            .push(Message::tool_result(&tool_call.id, observation, !result.success));
+
+        // Providers only accept images from the user, so attach them in a follow-up message
+        if !result.images.is_empty() {
+            let mut parts = vec![ContentPart::text(format!("Image(s) returned by tool '{}':", tool_call.name))];
+            parts.extend(result.images.iter().cloned().map(ContentPart::image));
+            self.conversation_history.push(Message::new(Role::User, parts));
+        }
 
         Ok(AgentResult {
             success: true,
             output: "".to_string(),
             iterations: self.iteration_count,
             tool_calls: tool_calls.clone(),
+            usage: self.usage.run().clone(),
         })
     }
 
+    /// Add a response's token usage to the totals and enforce the cost budget
+    fn record_usage(&mut self, response: &ModelResponse) -> Result<()> {
+        let provider = self.model.model_info().provider;
+        let cost_usd = self.usage.record(&provider, &response.model, &response.usage);
+        self.emit(AgentEvent::ResponseReceived {
+            content: response.content.clone(),
+            usage: response.usage.clone(),
+            cost_usd,
+        });
+
+        if let Some(limit) = self.config.safety.max_cost_usd {
+            let spent = self.usage.session().cost_usd;
+            if spent > limit {
+                return Err(AgentError::BudgetExceeded { spent, limit }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Add or refresh the system prompt (kept once, at the start)
+    async fn refresh_system_prompt(&mut self) {
+        let system_prompt = Message::system(self.build_system_prompt().await).pinned();
+        match self.conversation_history.first_mut() {
+            Some(first) if first.role == Role::System && first.pinned => *first = system_prompt,
+            _ => self.conversation_history.insert(0, system_prompt),
+        }
+    }
+
     async fn build_system_prompt(&self) -> String {
         let tool_descriptions: Vec<String> = self
             .tools
@@ -300,7 +523,7 @@ impl Agent {
                 let mut prompt = template.template.clone();
                 if let Some(examples) = &template.few_shot_examples {
                     for example in examples {
-                        prompt.push_str(&format!("\n\n{}: {}", example.role, example.content));
+                        prompt.push_str(&format!("\n\n{}: {}", example.role, example.text()));
                     }
                 }
                 prompt
@@ -312,34 +535,26 @@ impl Agent {
             self.default_system_prompt()
         };
 
-        let project_context = match crate::context::ContextManager::new().await {
-            Ok(context_manager) => context_manager.load_project_context().await.ok().flatten(),
-            Err(e) => {
-                tracing::warn!("Failed to load project context: {}", e);
-                None
-            }
-        };
+        let project_instructions = self.instructions.render();
 
-        let project_type = match crate::context::ContextManager::new().await {
-            Ok(context_manager) => context_manager.detect_project_type().await.unwrap_or_else(|e| {
-                tracing::warn!("Failed to detect project type: {}", e);
-                "Generic".to_string()
-            }),
-            Err(e) => {
-                tracing::warn!("Failed to create context manager: {}", e);
-                "Generic".to_string()
+        let mut project_summary = self.project.summary();
+        let budget = self.config.context.repo_map_budget;
+        if budget > 0 {
+            let map = self.repo_map.render(self.model.tokenizer().as_ref(), budget);
+            if !map.is_empty() {
+                project_summary.push_str(&format!("\n\nRepository map (files and top-level symbols):\n{}", map));
             }
-        };
+        }
 
         let mut final_prompt = String::new();
-        if let Some(context) = project_context {
-            final_prompt.push_str(&format!("Project Context:\n```\n{}\n```\n\n", context));
+        if !project_instructions.is_empty() {
+            final_prompt.push_str(&format!("Project Instructions:\n\n{}\n\n", project_instructions));
         }
         final_prompt.push_str(&format!(
             r###"{}
 
 Current working directory: {}
-Current project type: {}
+{}
 {}
 
 IDENTITY & BRANDING:
@@ -365,7 +580,7 @@ When you've completed the task, respond with: FINISH
 Always explain your reasoning before taking an action."###,
             base_prompt,
             current_dir,
-            project_type,
+            project_summary,
             git_info,
             tool_descriptions.join("\n")
         ));
@@ -420,6 +635,7 @@ Remember:
                 if let Ok(value) = serde_json::from_str::<serde_json::Value>(json_str) {
                     if let (Some(tool), Some(args)) = (value.get("tool").and_then(|v| v.as_str()), value.get("args")) {
                         return Some(ParsedToolCall {
+                            id: String::new(),
                             name: tool.to_string(),
                             args: args.clone(),
                         });
@@ -439,71 +655,99 @@ Remember:
         self.formatter.format_response(content)
     }
 
+    /// How much of the model's context window the conversation uses
+    pub fn context_usage(&self) -> ContextUsage {
+        self.compactor.usage(self.model.as_ref(), &self.conversation_history)
+    }
+
+    /// Token count of each message in the conversation, in order
+    pub fn message_token_counts(&self) -> Vec<usize> {
+        let tokenizer = self.model.tokenizer();
+        self.conversation_history
+            .iter()
+            .map(|m| crate::tokenizer::count_message(tokenizer.as_ref(), m))
+            .collect()
+    }
+
+    /// Replace the conversation, e.g. with a resumed session; new tool calls continue after its ids
+    pub fn set_history(&mut self, history: Vec<Message>) {
+        self.next_tool_call_id = last_tool_call_id(&history);
+        self.conversation_history = history;
+    }
+
+    /// Count `usage` of a resumed session towards `/cost` and `max_cost_usd`
+    pub fn restore_usage(&mut self, usage: UsageSummary) {
+        self.usage.restore_session(usage);
+    }
+
+    /// Summarize older turns to free up context
+    pub async fn compact(&mut self) -> Result<CompactionReport> {
+        let report = self
+            .compactor
+            .compact(self.model.as_ref(), &mut self.conversation_history)
+            .await?;
+        if report.messages_summarized > 0 {
+            let info = self.model.model_info();
+            self.usage.record(&info.provider, &info.model, &report.usage);
+        }
+        Ok(report)
+    }
 
+    /// Provider and model the agent is using
+    pub fn model_info(&self) -> crate::model::ModelInfo {
+        self.model.model_info()
+    }
+
+    /// Token usage and cost per iteration, run and session
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
 }
 
 #[derive(Debug)]
 struct ParsedToolCall {
+    id: String,
     name: String,
     args: serde_json::Value,
 }
 
+/// Paths named in tool arguments, resolved against `working_dir`
+fn touched_paths(args: &serde_json::Value, working_dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    ["path", "file", "directory"]
+        .iter()
+        .filter_map(|key| args.get(*key).and_then(|v| v.as_str()))
+        .map(|path| working_dir.join(path))
+        .collect()
+}
+
+/// Highest `call_N` id in `history`, so calls in a resumed conversation get new ids
+fn last_tool_call_id(history: &[Message]) -> usize {
+    history
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|part| match part {
+            ContentPart::ToolCall(call) => Some(call.id.as_str()),
+            ContentPart::ToolResult { tool_call_id, .. } => Some(tool_call_id.as_str()),
+            _ => None,
+        })
+        .filter_map(|id| id.strip_prefix("call_")?.parse().ok())
+        .max()
+        .unwrap_or(0)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::model::{ModelInfo, ModelResponse, TokenUsage};
-    use async_trait::async_trait;
-
-    struct MockModel {
-        responses: Vec<String>,
-        call_count: std::sync::Arc<std::sync::Mutex<usize>>,
-    }
-
-    #[async_trait]
-    impl LanguageModel for MockModel {
-        async fn complete(&self, _: &str, _: Option<&str>) -> Result<ModelResponse> {
-            unimplemented!()
-        }
-
-        async fn chat(&self, _: &[Message]) -> Result<ModelResponse> {
-            let mut count = self.call_count.lock().unwrap();
-            let response = self.responses[*count].clone();
-            *count += 1;
-
-            Ok(ModelResponse {
-                content: response,
-                model: "mock".to_string(),
-                usage: TokenUsage::default(),
-                tool_calls: None,
-                finish_reason: Some("stop".to_string()),
-            })
-        }
-
-        async fn chat_with_tools(
-            &self,
-            messages: &[crate::model::Message],
-            _: &[crate::model::ToolDefinition],
-        ) -> Result<ModelResponse> {
-            self.chat(messages).await
-        }
-
-        fn model_info(&self) -> ModelInfo {
-            ModelInfo {
-                provider: "mock".to_string(),
-                model: "test".to_string(),
-                max_tokens: 4096,
-                supports_tools: false,
-                supports_streaming: false,
-            }
-        }
-    }
+    use crate::model::{ModelInfo, TokenUsage};
+    use crate::testing::ScriptedModel;
 
     #[tokio::test]
     async fn test_agent_simple_task() {
-        let model = Box::new(MockModel {
-            responses: vec!["I will list the files. {\"tool\": \"file_list\", \"args\": {}}".to_string(), "FINISH".to_string()],
-            call_count: std::sync::Arc::new(std::sync::Mutex::new(0)),
-        });
+        let model = Box::new(
+            ScriptedModel::new()
+                .respond("I will list the files. {\"tool\": \"file_list\", \"args\": {}}")
+                .respond("FINISH"),
+        );
 
         let mut tools = ToolRegistry::new();
         tools.register(crate::tools::file_ops::FileListTool::new());
@@ -519,5 +763,281 @@ mod tests {
         assert!(result.success);
         assert_eq!(result.iterations, 2);
         assert_eq!(result.tool_calls.len(), 1);
+        assert_eq!(result.usage.requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_tool_results_linked_to_calls() {
+        let model = Box::new(
+            ScriptedModel::new()
+                .respond_tool_call("file_list", serde_json::json!({}))
+                .respond("FINISH"),
+        );
+
+        let mut tools = ToolRegistry::new();
+        tools.register(crate::tools::file_ops::FileListTool::new());
+        let permission_manager = std::sync::Arc::new(std::sync::Mutex::new(crate::permissions::PermissionManager::default()));
+        permission_manager.lock().unwrap().set_permission("file_list".to_string(), crate::permissions::PermissionLevel::Always).unwrap();
+        let mut agent = Agent::new(model, tools, Config::default(), Vec::new(), permission_manager).await.unwrap();
+
+        agent.run("List the files").await.unwrap();
+
+        let call = agent.conversation_history[2].tool_calls().next().cloned().unwrap();
+        assert_eq!(agent.conversation_history[2].role, Role::Assistant);
+        assert_eq!(call.name, "file_list");
+
+        let observation = &agent.conversation_history[3];
+        assert_eq!(observation.role, Role::Tool);
+        assert!(matches!(
+            &observation.content[0],
+            ContentPart::ToolResult { tool_call_id, is_error: false, .. } if *tool_call_id == call.id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resumed_conversation_gets_new_tool_call_ids() {
+        let permission_manager = std::sync::Arc::new(std::sync::Mutex::new(crate::permissions::PermissionManager::default()));
+        permission_manager.lock().unwrap().set_permission("file_list".to_string(), crate::permissions::PermissionLevel::Always).unwrap();
+        let run = |history: Vec<Message>| {
+            let permission_manager = permission_manager.clone();
+            async move {
+                let model = Box::new(
+                    ScriptedModel::new()
+                        .respond_tool_call("file_list", serde_json::json!({}))
+                        .respond("FINISH"),
+                );
+                let mut tools = ToolRegistry::new();
+                tools.register(crate::tools::file_ops::FileListTool::new());
+                let mut agent = Agent::new(model, tools, Config::default(), history, permission_manager).await.unwrap();
+                agent.run("List the files").await.unwrap();
+                agent.conversation_history
+            }
+        };
+
+        let history = run(run(Vec::new()).await).await;
+
+        let ids: Vec<&str> = history.iter().flat_map(Message::tool_calls).map(|call| call.id.as_str()).collect();
+        assert_eq!(ids, vec!["call_1", "call_2"]);
+    }
+
+    #[tokio::test]
+    async fn test_set_history_continues_tool_call_ids_and_usage() {
+        let permission_manager = crate::testing::PermissionPolicy::AllowAll.into_manager();
+        let agent = |permission_manager| async move {
+            let model = Box::new(
+                ScriptedModel::new()
+                    .respond_tool_call("file_list", serde_json::json!({}))
+                    .respond("FINISH"),
+            );
+            let mut tools = ToolRegistry::new();
+            tools.register(crate::tools::file_ops::FileListTool::new());
+            Agent::new(model, tools, Config::default(), Vec::new(), permission_manager).await.unwrap()
+        };
+
+        let mut first = agent(permission_manager.clone()).await;
+        first.run("List the files").await.unwrap();
+
+        // Resumed into an agent started without history, as /resume does
+        let mut resumed = agent(permission_manager).await;
+        resumed.set_history(first.conversation_history.clone());
+        resumed.restore_usage(first.usage().session().clone());
+        resumed.run("List them again").await.unwrap();
+
+        let ids: Vec<&str> = resumed
+            .conversation_history
+            .iter()
+            .flat_map(Message::tool_calls)
+            .map(|call| call.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["call_1", "call_2"]);
+        assert_eq!(resumed.usage().session().requests, 4);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_events() {
+        let model = Box::new(
+            ScriptedModel::new()
+                .respond_tool_call("file_list", serde_json::json!({}))
+                .respond("Done. FINISH"),
+        );
+        let recorder = crate::testing::EventRecorder::new();
+
+        let mut tools = ToolRegistry::new();
+        tools.register(crate::tools::file_ops::FileListTool::new());
+        let permission_manager = crate::testing::PermissionPolicy::AllowAll.into_manager();
+        let mut agent = Agent::new(model, tools, Config::default(), Vec::new(), permission_manager)
+            .await
+            .unwrap()
+            .with_observer(Arc::new(recorder.clone()));
+
+        agent.run("List the files").await.unwrap();
+
+        let kinds: Vec<&str> = recorder
+            .events()
+            .iter()
+            .map(|event| match event {
+                AgentEvent::ThinkingStarted { .. } => "thinking_started",
+                AgentEvent::ThinkingFinished { .. } => "thinking_finished",
+                AgentEvent::ResponseReceived { .. } => "response",
+                AgentEvent::ToolCallRequested { .. } => "requested",
+                AgentEvent::ToolCallApproved { .. } => "approved",
+                AgentEvent::ToolCallFinished { success: true, .. } => "finished",
+                AgentEvent::Completed { .. } => "completed",
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "thinking_started",
+                "thinking_finished",
+                "response",
+                "requested",
+                "approved",
+                "finished",
+                "thinking_started",
+                "thinking_finished",
+                "response",
+                "completed",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_approval_handler_decides_unknown_tools() {
+        use crate::approval::{ApprovalDecision, ScriptedApproval};
+
+        let model = Box::new(
+            ScriptedModel::new()
+                .respond_tool_call("file_list", serde_json::json!({}))
+                .respond("FINISH"),
+        );
+        let approval = ScriptedApproval::new(ApprovalDecision::Deny).with_rule("file_list", ApprovalDecision::AllowAlways);
+
+        let mut tools = ToolRegistry::new();
+        tools.register(crate::tools::file_ops::FileListTool::new());
+        let permission_manager = Arc::new(Mutex::new(crate::permissions::PermissionManager::in_memory()));
+        let mut agent = Agent::new(model, tools, Config::default(), Vec::new(), permission_manager.clone())
+            .await
+            .unwrap()
+            .with_approval_handler(Arc::new(approval.clone()));
+
+        let result = agent.run("List the files").await.unwrap();
+
+        assert!(result.success);
+        let requests = approval.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].risk, RiskLevel::Low);
+        assert_eq!(
+            permission_manager.lock().unwrap().check_permission("file_list"),
+            crate::permissions::PermissionLevel::Always
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cost_budget_stops_run() {
+        let mut model = ScriptedModel::new()
+            .with_model_info(ModelInfo {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                max_tokens: 1024,
+                context_window: 128_000,
+                supports_tools: false,
+                supports_streaming: false,
+            })
+            .with_usage(TokenUsage {
+                prompt_tokens: 10_000,
+                completion_tokens: 1_000,
+                total_tokens: 11_000,
+            });
+        for _ in 0..3 {
+            model = model.respond("Still thinking...");
+        }
+
+        let mut config = Config::default();
+        config.safety.max_cost_usd = Some(0.5);
+        let permission_manager = std::sync::Arc::new(std::sync::Mutex::new(crate::permissions::PermissionManager::default()));
+        let mut agent = Agent::new(Box::new(model), ToolRegistry::new(), config, Vec::new(), permission_manager).await.unwrap();
+
+        let err = agent.run("think forever").await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::PromptLineError::Agent(AgentError::BudgetExceeded { .. })
+        ));
+        // $0.36 per request at gpt-4 prices, so the second request crosses $0.50
+        assert_eq!(agent.usage().session().requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_not_duplicated_across_runs() {
+        let model = Box::new(ScriptedModel::new().respond("Hi! FINISH").respond("Hello again! FINISH"));
+
+        let permission_manager = std::sync::Arc::new(std::sync::Mutex::new(crate::permissions::PermissionManager::default()));
+        let mut agent = Agent::new(model, ToolRegistry::new(), Config::default(), Vec::new(), permission_manager).await.unwrap();
+
+        agent.run("hi").await.unwrap();
+        agent.run("hello").await.unwrap();
+
+        let system_count = agent.conversation_history.iter().filter(|m| m.role == Role::System).count();
+        assert_eq!(system_count, 1);
+        assert!(agent.context_usage().used_tokens > 0);
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_describes_project() {
+        let workspace = crate::testing::TempWorkspace::new().unwrap();
+        workspace.write("Cargo.toml", "[package]\nname = \"demo\"").unwrap();
+        workspace.write("src/lib.rs", "pub fn greet() {}").unwrap();
+
+        let model = ScriptedModel::new().respond("Hi! FINISH").respond("Again. FINISH");
+        let probe = model.clone();
+        let mut agent = crate::testing::build_agent(model, ToolRegistry::new(), crate::testing::PermissionPolicy::AllowAll)
+            .await
+            .unwrap()
+            .with_project_root(workspace.path());
+
+        agent.run("hi").await.unwrap();
+        let system = probe.request(0)[0].text();
+        assert!(system.contains("Project type: Rust"));
+        assert!(system.contains("- test: `cargo test` (from Cargo.toml)"));
+        assert!(system.contains("src/lib.rs: fn greet"));
+
+        // The map follows edits between runs
+        workspace.write("src/lib.rs", "pub fn greet() {}\npub struct Greeter;").unwrap();
+        agent.run("again").await.unwrap();
+        assert!(probe.request(1)[0].text().contains("src/lib.rs: fn greet, struct Greeter"));
+    }
+
+    #[tokio::test]
+    async fn test_nested_instructions_load_when_touched() {
+        let workspace = crate::testing::TempWorkspace::new().unwrap();
+        workspace.write(".git/HEAD", "ref: refs/heads/main").unwrap();
+        workspace.write("AGENTS.md", "Root rules").unwrap();
+        workspace.write("web/AGENTS.md", "Web rules").unwrap();
+        let page = workspace.write("web/index.html", "<p>hi</p>").unwrap();
+
+        let model = ScriptedModel::new()
+            .respond_tool_call("file_read", serde_json::json!({"path": page}))
+            .respond("Done. FINISH");
+        let probe = model.clone();
+        let recorder = crate::testing::EventRecorder::new();
+
+        let mut tools = ToolRegistry::new();
+        tools.register(crate::tools::file_ops::FileReadTool::new());
+        let mut agent = crate::testing::build_agent(model, tools, crate::testing::PermissionPolicy::AllowAll)
+            .await
+            .unwrap()
+            .with_instructions(ProjectInstructions::new(workspace.path()).with_user_dir(None))
+            .with_observer(Arc::new(recorder.clone()));
+
+        agent.run("Read the page").await.unwrap();
+
+        let system = |request: usize| probe.request(request)[0].text();
+        assert!(system(0).contains("Root rules") && !system(0).contains("Web rules"));
+        assert!(system(1).contains("Web rules"));
+        assert!(recorder
+            .events()
+            .iter()
+            .any(|event| matches!(event, AgentEvent::InstructionsLoaded { paths } if paths[0].ends_with("AGENTS.md"))));
     }
 }
\ No newline at end of file
@@ -0,0 +1,264 @@
+//! Approval of tool calls and file changes
+//!
+//! Core types never prompt directly. They describe what they are about to do
+//! in an [`ApprovalRequest`] and let an [`ApprovalHandler`] decide: the
+//! terminal handler asks the user, the others answer without any input so
+//! runs work under CI or with stdin redirected.
+
+use crate::error::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+
+/// How much damage an action could do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    /// Only reads
+    Low,
+    /// Changes files in the workspace
+    Medium,
+    /// Runs arbitrary commands or changes history
+    High,
+}
+
+impl std::fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        })
+    }
+}
+
+/// Proposed change to an existing file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffPreview {
+    pub path: String,
+    pub original: String,
+    pub modified: String,
+}
+
+/// An action waiting for approval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    /// Tool asking for approval
+    pub tool: String,
+    pub args: serde_json::Value,
+    /// One-line description of the action
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<DiffPreview>,
+    pub risk: RiskLevel,
+}
+
+impl ApprovalRequest {
+    pub fn new(tool: impl Into<String>, args: serde_json::Value, summary: impl Into<String>, risk: RiskLevel) -> Self {
+        Self {
+            tool: tool.into(),
+            args,
+            summary: summary.into(),
+            diff: None,
+            risk,
+        }
+    }
+
+    pub fn with_diff(mut self, diff: DiffPreview) -> Self {
+        self.diff = Some(diff);
+        self
+    }
+}
+
+/// Answer to an [`ApprovalRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    /// Allow this action only
+    AllowOnce,
+    /// Allow this tool from now on
+    AllowAlways,
+    /// Refuse this action only
+    Deny,
+    /// Refuse this tool from now on
+    DenyAlways,
+}
+
+impl ApprovalDecision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Self::AllowOnce | Self::AllowAlways)
+    }
+}
+
+/// Decides whether an action may go ahead
+#[async_trait]
+pub trait ApprovalHandler: Send + Sync {
+    async fn decide(&self, request: &ApprovalRequest) -> Result<ApprovalDecision>;
+}
+
+impl std::fmt::Debug for dyn ApprovalHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ApprovalHandler")
+    }
+}
+
+/// Handler for the current process: ask on a terminal, refuse otherwise
+pub fn default_handler() -> Arc<dyn ApprovalHandler> {
+    if std::io::stdin().is_terminal() {
+        Arc::new(TerminalApproval)
+    } else {
+        Arc::new(AutoDeny)
+    }
+}
+
+/// Asks the user with an interactive prompt
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerminalApproval;
+
+#[async_trait]
+impl ApprovalHandler for TerminalApproval {
+    async fn decide(&self, request: &ApprovalRequest) -> Result<ApprovalDecision> {
+        use dialoguer::{theme::ColorfulTheme, Confirm, Select};
+
+        // A diff is approved for this change only
+        if let Some(diff) = &request.diff {
+            crate::util::diff::display_diff(&diff.path, &diff.original, &diff.modified);
+            let approved = Confirm::new()
+                .with_prompt("Apply these changes?")
+                .default(false)
+                .interact()?;
+            return Ok(if approved {
+                ApprovalDecision::AllowOnce
+            } else {
+                ApprovalDecision::Deny
+            });
+        }
+
+        println!("\n⚠️  Permission Required: {} ({} risk)", request.tool, request.risk);
+        println!("   {}", request.summary);
+        println!();
+
+        let options = vec![
+            "Once     - Allow this time only",
+            "Always   - Always allow (recommended)",
+            "Never    - Block this tool",
+        ];
+
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Choice")
+            .items(&options)
+            .default(0)
+            .interact()?;
+
+        let decision = match selection {
+            0 => ApprovalDecision::AllowOnce,
+            1 => ApprovalDecision::AllowAlways,
+            _ => ApprovalDecision::DenyAlways,
+        };
+
+        if decision.is_allowed() {
+            println!("\n✓ Saved: {} = {:?}\n", request.tool, decision);
+        } else {
+            println!("\n✗ Blocked: {}\n", request.tool);
+        }
+        Ok(decision)
+    }
+}
+
+/// Allows every action once, without asking
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoApprove;
+
+#[async_trait]
+impl ApprovalHandler for AutoApprove {
+    async fn decide(&self, _request: &ApprovalRequest) -> Result<ApprovalDecision> {
+        Ok(ApprovalDecision::AllowOnce)
+    }
+}
+
+/// Refuses every action once, without asking
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoDeny;
+
+#[async_trait]
+impl ApprovalHandler for AutoDeny {
+    async fn decide(&self, _request: &ApprovalRequest) -> Result<ApprovalDecision> {
+        Ok(ApprovalDecision::Deny)
+    }
+}
+
+/// Answers from fixed rules and records every request it sees
+///
+/// Rules match by tool name; anything else gets the fallback decision.
+/// Clones share the request log.
+#[derive(Debug, Clone)]
+pub struct ScriptedApproval {
+    rules: HashMap<String, ApprovalDecision>,
+    max_risk: Option<RiskLevel>,
+    fallback: ApprovalDecision,
+    requests: Arc<Mutex<Vec<ApprovalRequest>>>,
+}
+
+impl ScriptedApproval {
+    pub fn new(fallback: ApprovalDecision) -> Self {
+        Self {
+            rules: HashMap::new(),
+            max_risk: None,
+            fallback,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answer requests from `tool` with `decision`
+    pub fn with_rule(mut self, tool: impl Into<String>, decision: ApprovalDecision) -> Self {
+        self.rules.insert(tool.into(), decision);
+        self
+    }
+
+    /// Deny anything riskier than `risk`, whatever the rules say
+    pub fn with_max_risk(mut self, risk: RiskLevel) -> Self {
+        self.max_risk = Some(risk);
+        self
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<ApprovalRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ApprovalHandler for ScriptedApproval {
+    async fn decide(&self, request: &ApprovalRequest) -> Result<ApprovalDecision> {
+        self.requests.lock().unwrap().push(request.clone());
+
+        if self.max_risk.is_some_and(|max| request.risk > max) {
+            return Ok(ApprovalDecision::Deny);
+        }
+        Ok(self.rules.get(&request.tool).copied().unwrap_or(self.fallback))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scripted_approval_rules() {
+        let handler = ScriptedApproval::new(ApprovalDecision::Deny)
+            .with_rule("file_write", ApprovalDecision::AllowOnce)
+            .with_rule("shell_execute", ApprovalDecision::AllowAlways)
+            .with_max_risk(RiskLevel::Medium);
+
+        let write = ApprovalRequest::new("file_write", serde_json::json!({}), "write a.txt", RiskLevel::Medium);
+        let shell = ApprovalRequest::new("shell_execute", serde_json::json!({}), "run ls", RiskLevel::High);
+        let fetch = ApprovalRequest::new("web_get", serde_json::json!({}), "fetch a page", RiskLevel::Low);
+
+        assert_eq!(handler.decide(&write).await.unwrap(), ApprovalDecision::AllowOnce);
+        assert_eq!(handler.decide(&shell).await.unwrap(), ApprovalDecision::Deny);
+        assert_eq!(handler.decide(&fetch).await.unwrap(), ApprovalDecision::Deny);
+        assert_eq!(handler.requests().len(), 3);
+    }
+}
@@ -1,6 +1,6 @@
 //! CLI interface
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -11,7 +11,7 @@ pub struct Cli {
     #[arg(short, long, value_name = "FILE")]
     pub config: Option<PathBuf>,
 
-    /// Model provider to use (openai, anthropic, local)
+    /// Model provider to use (openai, anthropic, gemini, ollama, or a configured provider)
     #[arg(short = 'p', long)]
     pub provider: Option<String>,
 
@@ -19,6 +19,10 @@ pub struct Cli {
     #[arg(short = 'm', long)]
     pub model: Option<String>,
 
+    /// Let the model think before answering, with this many tokens (Anthropic)
+    #[arg(long, value_name = "TOKENS")]
+    pub thinking: Option<usize>,
+
     /// Enable verbose logging
     #[arg(short, long)]
     pub verbose: bool,
@@ -27,6 +31,30 @@ pub struct Cli {
     #[arg(long)]
     pub auto_approve: bool,
 
+    /// Serve repeated deterministic requests from the response cache, even when the config disables it
+    #[arg(long, overrides_with = "no_cache")]
+    pub cache: bool,
+
+    /// Always send requests to the provider, bypassing the response cache
+    #[arg(long, overrides_with = "cache")]
+    pub no_cache: bool,
+
+    /// Record every model request and response to a JSONL file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Replay model responses from a file made with --record instead of calling a provider
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    /// Resume a saved chat session of this project by id (or unique id prefix)
+    #[arg(long, value_name = "ID", conflicts_with = "continue_session")]
+    pub resume: Option<String>,
+
+    /// Resume the most recent chat session of this project
+    #[arg(long = "continue")]
+    pub continue_session: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 
@@ -49,6 +77,24 @@ pub enum Commands {
         task: String,
     },
 
+    /// Run a task without prompts and print machine-readable output
+    Exec {
+        /// Task to execute; read from stdin when omitted or "-"
+        task: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output_format: OutputFormat,
+
+        /// Tools that may run besides read-only ones (comma-separated)
+        #[arg(long, value_delimiter = ',', value_name = "TOOLS")]
+        allow_tools: Vec<String>,
+
+        /// Tools that may never run, even if read-only or allowed (comma-separated)
+        #[arg(long, value_delimiter = ',', value_name = "TOOLS")]
+        deny_tools: Vec<String>,
+    },
+
     /// Start interactive chat mode
     Chat,
 
@@ -66,6 +112,89 @@ pub enum Commands {
 
     /// Check installation and configuration
     Doctor,
+
+    /// Manage the response cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Manage saved chat sessions of this project
+    Sessions {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+
+    /// Manage the code index of this project
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionAction {
+    /// List sessions, most recent first
+    List,
+
+    /// Print a session's messages
+    Show {
+        /// Session id or unique id prefix
+        id: String,
+    },
+
+    /// Delete a session
+    Delete {
+        /// Session id or unique id prefix
+        id: String,
+    },
+
+    /// Write a session as a transcript with secrets redacted
+    Export {
+        /// Session id or unique id prefix
+        id: String,
+
+        /// Transcript format: md, json or html
+        #[arg(short, long, default_value = "md")]
+        format: String,
+
+        /// Output file (default: <id>.<format> in the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Output format for `exec`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable progress and summary
+    Text,
+    /// One JSON object with the final result
+    Json,
+    /// One JSON object per line for each event, then the final result
+    StreamJson,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IndexAction {
+    /// Bring the index up to date with the project's files
+    Build {
+        /// Discard the stored index and index every file again
+        #[arg(long)]
+        full: bool,
+    },
+
+    /// Show the size of the index and how many files changed since it was built
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Remove all cached responses
+    Clear,
+
+    /// Show the number and size of cached responses
+    Stats,
 }
 
 impl Cli {
@@ -73,3 +202,24 @@ impl Cli {
         Self::parse()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_flags() {
+        let cli = Cli::try_parse_from(["promptline", "--cache"]).unwrap();
+        assert!(cli.cache && !cli.no_cache);
+
+        let cli = Cli::try_parse_from(["promptline", "--no-cache"]).unwrap();
+        assert!(!cli.cache && cli.no_cache);
+
+        // The last one wins
+        let cli = Cli::try_parse_from(["promptline", "--no-cache", "--cache"]).unwrap();
+        assert!(cli.cache && !cli.no_cache);
+
+        let cli = Cli::try_parse_from(["promptline"]).unwrap();
+        assert!(!cli.cache && !cli.no_cache);
+    }
+}
@@ -3,8 +3,13 @@
 //! Provides commands for configuration and control
 
 use crate::config::Config;
+use crate::context::{ContextUsage, ProjectInstructions};
+use crate::export::ExportFormat;
+use crate::model::{Message, Role};
 use crate::permissions::PermissionManager;
+use crate::usage::{UsageSummary, UsageTracker};
 use anyhow::Result;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 /// Command execution result
@@ -20,6 +25,16 @@ pub enum CommandAction {
     Quit,
     ClearHistory,
     ReloadAgent,
+    Compact,
+    /// Switch to a saved session, or list them when no id is given
+    Resume(Option<String>),
+    /// Continue in a copy of the current session
+    Fork,
+    /// Write the current session as a transcript; the default path is `<id>.<ext>`
+    Export {
+        format: ExportFormat,
+        path: Option<PathBuf>,
+    },
 }
 
 impl CommandOutput {
@@ -42,9 +57,16 @@ pub enum SlashCommand {
     Help,
     Settings,
     Clear,
+    Compact,
+    Context,
     Status,
+    Cost,
+    Usage,
     Model(Option<Vec<String>>), // Optional args
     Permissions(Option<Vec<String>>), // Optional args
+    Resume(Option<String>),
+    Fork,
+    Export(Option<Vec<String>>), // Optional args
     Quit,
     Version,
 }
@@ -53,6 +75,14 @@ pub enum SlashCommand {
 pub struct CommandHandler {
     config: Config,
     permissions: Arc<Mutex<PermissionManager>>,
+    context_usage: Option<ContextUsage>,
+    /// Messages and tokens per role, in conversation order of first appearance
+    role_tokens: Vec<(Role, usize, usize)>,
+    instructions: Option<ProjectInstructions>,
+    run_usage: Vec<crate::model::TokenUsage>,
+    last_run: UsageSummary,
+    session: UsageSummary,
+    model_info: Option<crate::model::ModelInfo>,
 }
 
 impl CommandHandler {
@@ -61,9 +91,57 @@ impl CommandHandler {
         Self {
             config,
             permissions,
+            context_usage: None,
+            role_tokens: Vec::new(),
+            instructions: None,
+            run_usage: Vec::new(),
+            last_run: UsageSummary::default(),
+            session: UsageSummary::default(),
+            model_info: None,
         }
     }
 
+    /// Current configuration, including changes made by commands
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Record the model the agent is running, shown by `/model`
+    pub fn set_model_info(&mut self, info: crate::model::ModelInfo) {
+        self.model_info = Some(info);
+    }
+
+    /// Update the token usage shown by `/usage` and `/cost`
+    pub fn set_usage(&mut self, tracker: &UsageTracker) {
+        self.run_usage = tracker.iterations().to_vec();
+        self.last_run = tracker.run().clone();
+        self.session = tracker.session().clone();
+    }
+
+    /// Update the context usage shown by `/status`
+    pub fn set_context_usage(&mut self, usage: ContextUsage) {
+        self.context_usage = Some(usage);
+    }
+
+    /// Update the per-role breakdown shown by `/status` from the token count of each message
+    pub fn set_message_tokens(&mut self, messages: &[Message], counts: &[usize]) {
+        self.role_tokens.clear();
+        for (message, &tokens) in messages.iter().zip(counts) {
+            match self.role_tokens.iter_mut().find(|(role, _, _)| *role == message.role) {
+                Some((_, count, total)) => {
+                    *count += 1;
+                    *total += tokens;
+                }
+                None => self.role_tokens.push((message.role, 1, tokens)),
+            }
+        }
+    }
+
+    /// Update the instruction files shown by `/context`
+    pub fn set_instructions(&mut self, instructions: ProjectInstructions) {
+        self.instructions = Some(instructions);
+    }
+
     /// Parse a slash command from input
     pub fn parse(input: &str) -> Option<SlashCommand> {
         let trimmed = input.trim();
@@ -87,9 +165,16 @@ impl CommandHandler {
             "/help" | "/h" => Some(SlashCommand::Help),
             "/settings" | "/config" => Some(SlashCommand::Settings),
             "/clear" | "/new" => Some(SlashCommand::Clear),
+            "/compact" => Some(SlashCommand::Compact),
+            "/context" => Some(SlashCommand::Context),
             "/status" => Some(SlashCommand::Status),
+            "/cost" => Some(SlashCommand::Cost),
+            "/usage" => Some(SlashCommand::Usage),
             "/model" => Some(SlashCommand::Model(args)),
             "/permissions" | "/perms" => Some(SlashCommand::Permissions(args)),
+            "/resume" | "/sessions" => Some(SlashCommand::Resume(args.and_then(|a| a.into_iter().next()))),
+            "/fork" => Some(SlashCommand::Fork),
+            "/export" => Some(SlashCommand::Export(args)),
             "/quit" | "/exit" | "/q" => Some(SlashCommand::Quit),
             "/version" | "/v" => Some(SlashCommand::Version),
             _ => None,
@@ -102,9 +187,16 @@ impl CommandHandler {
             SlashCommand::Help => Ok(CommandOutput::new(self.help())),
             SlashCommand::Settings => Ok(CommandOutput::new(self.settings())),
             SlashCommand::Clear => Ok(CommandOutput::new("Session cleared.").with_action(CommandAction::ClearHistory)),
+            SlashCommand::Compact => Ok(CommandOutput::new("Compacting conversation...").with_action(CommandAction::Compact)),
+            SlashCommand::Context => Ok(CommandOutput::new(self.context())),
             SlashCommand::Status => Ok(CommandOutput::new(self.status())),
+            SlashCommand::Cost => Ok(CommandOutput::new(self.cost())),
+            SlashCommand::Usage => Ok(CommandOutput::new(self.usage())),
             SlashCommand::Model(args) => self.handle_model(args),
             SlashCommand::Permissions(args) => Ok(CommandOutput::new(self.handle_permissions(args)?)),
+            SlashCommand::Resume(id) => Ok(CommandOutput::new("").with_action(CommandAction::Resume(id))),
+            SlashCommand::Fork => Ok(CommandOutput::new("").with_action(CommandAction::Fork)),
+            SlashCommand::Export(args) => Ok(self.handle_export(args)),
             SlashCommand::Quit => Ok(CommandOutput::new("Goodbye! 👋").with_action(CommandAction::Quit)),
             SlashCommand::Version => Ok(CommandOutput::new(format!("PromptLine v{}", crate::VERSION))),
         }
@@ -119,9 +211,17 @@ Available slash commands:
   /help         Show this help message
   /settings     Configure permissions and preferences
   /clear        Start new session (clear history)
+  /compact      Summarize older messages to free up context
+  /context      Show loaded instruction files and context usage
   /status       Show current configuration
+  /usage        Show token usage for the last run and session
+  /cost         Show estimated cost of this session
   /model        Show model information
   /permissions  Manage tool permissions
+  /resume [id]  List saved sessions, or switch to one
+  /fork         Continue in a copy of this session
+  /export [md|json|html] [path]
+                Save this session as a transcript
   /quit         Exit PromptLine
   /version      Show version info
 
@@ -130,9 +230,38 @@ Aliases:
   /q → /quit
   /v → /version
   /perms → /permissions
+
+Attachments:
+  @path/to/image.png  Attach an image (png, jpg, gif, webp) to your message
 "#.to_string()
     }
 
+    /// Parse `/export [md|json|html] [path]`
+    ///
+    /// Without a format, it is taken from the path's extension.
+    fn handle_export(&self, args: Option<Vec<String>>) -> CommandOutput {
+        let mut args = args.unwrap_or_default().into_iter().peekable();
+
+        let explicit = args.peek().and_then(|arg| arg.parse::<ExportFormat>().ok());
+        if explicit.is_some() {
+            args.next();
+        }
+        let path = args.next().map(PathBuf::from);
+        let format = match (explicit, &path) {
+            (Some(format), _) => format,
+            (None, Some(path)) => match path.extension().and_then(|e| e.to_str()) {
+                Some(ext) => match ext.parse() {
+                    Ok(format) => format,
+                    Err(e) => return CommandOutput::new(format!("✗ {}", e)),
+                },
+                None => ExportFormat::Markdown,
+            },
+            (None, None) => ExportFormat::Markdown,
+        };
+
+        CommandOutput::new("").with_action(CommandAction::Export { format, path })
+    }
+
     /// Show settings
     fn settings(&self) -> String {
         let perms = self.permissions.lock().unwrap();
@@ -147,7 +276,7 @@ Aliases:
             }
         }
 
-        output.push_str(&format!("\nProvider: {}\n", self.config.models.default));
+        output.push_str(&format!("\nModel: {}\n", self.config.models.default));
         output.push_str("\nType /help for available commands\n");
 
         output
@@ -155,11 +284,70 @@ Aliases:
 
     /// Show status
     fn status(&self) -> String {
-        format!(
-            "\n⚙️  Status\n\nProvider: {}\nVersion: {}\n",
+        let mut output = format!(
+            "\n⚙️  Status\n\nModel: {}\nVersion: {}\n",
             self.config.models.default,
             crate::VERSION
-        )
+        );
+        if let Some(usage) = &self.context_usage {
+            output.push_str(&format!("Context: {}\n", usage));
+        }
+        for (role, count, tokens) in &self.role_tokens {
+            output.push_str(&format!(
+                "  {}: {} message{}, {} tokens\n",
+                role,
+                count,
+                if *count == 1 { "" } else { "s" },
+                tokens
+            ));
+        }
+        output
+    }
+
+    /// Show instruction files and context usage
+    fn context(&self) -> String {
+        let mut output = String::from("\n📋 Context\n\n");
+        match &self.instructions {
+            Some(instructions) => output.push_str(&instructions.report()),
+            None => output.push_str("Instruction files not loaded yet."),
+        }
+        output.push('\n');
+        if let Some(usage) = &self.context_usage {
+            output.push_str(&format!("\nConversation: {}\n", usage));
+        }
+        output
+    }
+
+    /// Show token usage
+    fn usage(&self) -> String {
+        let mut output = String::from("\n📊 Token Usage\n\n");
+        output.push_str(&format!("Last run: {}\n", self.last_run));
+        for (i, usage) in self.run_usage.iter().enumerate() {
+            output.push_str(&format!(
+                "  {}. {} prompt + {} completion = {} tokens\n",
+                i + 1,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.total_tokens
+            ));
+        }
+        output.push_str(&format!("Session:  {}\n", self.session));
+        output
+    }
+
+    /// Show estimated cost
+    fn cost(&self) -> String {
+        let mut output = String::from("\n💰 Cost\n\n");
+        output.push_str(&format!("Last run: {}\n", self.last_run.cost_display()));
+        output.push_str(&format!("Session:  {}\n", self.session.cost_display()));
+        if let Some(limit) = self.config.safety.max_cost_usd {
+            output.push_str(&format!(
+                "Budget:   ${:.4} of ${:.4} remaining\n",
+                (limit - self.session.cost_usd).max(0.0),
+                limit
+            ));
+        }
+        output
     }
 
     /// Handle model command
@@ -186,31 +374,16 @@ Aliases:
                         match provider.as_str() {
                             "openai" => "gpt-3.5-turbo".to_string(),
                             "ollama" => "llama2".to_string(),
+                            "anthropic" => "claude-3-5-sonnet-latest".to_string(),
                             "gemini" => "gemini-pro".to_string(),
                             _ => "default".to_string(),
                         }
                     };
 
                     // Update config
+                    self.config.models.active_provider = Some(provider.clone());
                     self.config.models.default = model.clone();
-                    // We might need to store the active provider separately if it's not just inferred from the model
-                    // For now, we assume the environment variable PROMPTLINE_PROVIDER drives it, 
-                    // OR we update the config to explicitly store the active provider.
-                    // The current Config struct doesn't have an "active_provider" field, it just has "default" model.
-                    // But main.rs uses PROMPTLINE_PROVIDER env var.
-                    // We should probably update main.rs to look at config first.
-                    
-                    // Let's assume we set the env var for the current process so main.rs picks it up?
-                    // Or better, we persist it in the config if we add a field.
-                    // For now, let's just set the default model.
-                    
-                    // To switch provider, we really need to know which provider "gpt-4" belongs to.
-                    // Let's update the config to have an `active_provider` field? 
-                    // Or just rely on the user setting it.
-                    
-                    // Actually, let's set the env var for this process
-                    std::env::set_var("PROMPTLINE_PROVIDER", &provider);
-                    
+
                     // Save config
                     if let Ok(path) = self.get_config_path() {
                         let _ = self.config.save_to_file(&path);
@@ -230,12 +403,7 @@ Aliases:
                     let value = args[3].clone();
 
                     let provider_config = self.config.models.providers.entry(provider_name.clone())
-                        .or_insert_with(|| crate::config::ProviderConfig {
-                            api_key: None,
-                            models: vec![],
-                            default_params: crate::config::ModelParams::default(),
-                            base_url: None,
-                        });
+                        .or_default();
 
                     match setting.as_str() {
                         "key" | "api_key" => {
@@ -276,10 +444,15 @@ Aliases:
 
     /// Show model info
     fn model_info(&self) -> String {
-        let provider = std::env::var("PROMPTLINE_PROVIDER").unwrap_or_else(|_| "openai".to_string());
+        let factory = crate::model::factory::ProviderFactory::new(&self.config.models);
+        let (provider, model) = match &self.model_info {
+            Some(info) => (info.provider.clone(), info.model.clone()),
+            None => (factory.provider_name(), factory.model_name()),
+        };
         format!(
-            "\n🤖 Model Information\n\nProvider: {}\nDefault Model: {}\n\nUsage:\n  /model set <provider> [model]\n  /model config <provider> key <value>\n  /model config <provider> url <value>\n",
+            "\n🤖 Model Information\n\nProvider: {}\nModel: {}\nDefault Model: {}\n\nUsage:\n  /model set <provider> [model]\n  /model config <provider> key <value>\n  /model config <provider> url <value>\n",
             provider,
+            model,
             self.config.models.default
         )
     }
@@ -346,5 +519,100 @@ mod tests {
         assert_eq!(CommandHandler::parse("/quit"), Some(SlashCommand::Quit));
         assert_eq!(CommandHandler::parse("/h"), Some(SlashCommand::Help));
         assert_eq!(CommandHandler::parse("not a command"), None);
+        assert_eq!(CommandHandler::parse("/compact"), Some(SlashCommand::Compact));
+        assert_eq!(CommandHandler::parse("/context"), Some(SlashCommand::Context));
+        assert_eq!(CommandHandler::parse("/cost"), Some(SlashCommand::Cost));
+        assert_eq!(CommandHandler::parse("/usage"), Some(SlashCommand::Usage));
+        assert_eq!(CommandHandler::parse("/resume"), Some(SlashCommand::Resume(None)));
+        assert_eq!(
+            CommandHandler::parse("/resume 20261018"),
+            Some(SlashCommand::Resume(Some("20261018".to_string())))
+        );
+        assert_eq!(CommandHandler::parse("/fork"), Some(SlashCommand::Fork));
+        assert_eq!(
+            CommandHandler::parse("/export html out.html"),
+            Some(SlashCommand::Export(Some(vec!["html".to_string(), "out.html".to_string()])))
+        );
+    }
+
+    #[test]
+    fn test_export_args() {
+        let permissions = Arc::new(Mutex::new(PermissionManager::default()));
+        let handler = CommandHandler::new(Config::default(), permissions);
+        let export = |args: &[&str]| {
+            let args = (!args.is_empty()).then(|| args.iter().map(|a| a.to_string()).collect());
+            handler.handle_export(args).action
+        };
+
+        assert_eq!(
+            export(&[]),
+            CommandAction::Export {
+                format: ExportFormat::Markdown,
+                path: None
+            }
+        );
+        assert_eq!(
+            export(&["json"]),
+            CommandAction::Export {
+                format: ExportFormat::Json,
+                path: None
+            }
+        );
+        assert_eq!(
+            export(&["notes/run.html"]),
+            CommandAction::Export {
+                format: ExportFormat::Html,
+                path: Some(PathBuf::from("notes/run.html"))
+            }
+        );
+        assert_eq!(export(&["run.pdf"]), CommandAction::None);
+    }
+
+    #[test]
+    fn test_cost_shows_budget() {
+        let permissions = Arc::new(Mutex::new(PermissionManager::default()));
+        let mut config = Config::default();
+        config.safety.max_cost_usd = Some(1.0);
+        let mut handler = CommandHandler::new(config, permissions);
+
+        let mut tracker = UsageTracker::new(crate::usage::PriceTable::new(&std::collections::HashMap::new()));
+        tracker.record(
+            "openai",
+            "gpt-4o",
+            &crate::model::TokenUsage { prompt_tokens: 100_000, completion_tokens: 0, total_tokens: 100_000 },
+        );
+        handler.set_usage(&tracker);
+
+        let output = handler.execute(SlashCommand::Cost).unwrap();
+        assert!(output.message.contains("Session:  $0.2500"));
+        assert!(output.message.contains("Budget:   $0.7500 of $1.0000 remaining"));
+    }
+
+    #[test]
+    fn test_status_shows_context_usage() {
+        let permissions = Arc::new(Mutex::new(PermissionManager::default()));
+        let mut handler = CommandHandler::new(Config::default(), permissions);
+        handler.set_context_usage(ContextUsage { used_tokens: 2048, context_window: 8192, tokenizer: "cl100k_base" });
+
+        let output = handler.execute(SlashCommand::Status).unwrap();
+        assert!(output.message.contains("Context: 2048 / 8192 tokens (25%, cl100k_base)"));
+    }
+
+    #[test]
+    fn test_status_shows_tokens_per_role() {
+        let permissions = Arc::new(Mutex::new(PermissionManager::default()));
+        let mut handler = CommandHandler::new(Config::default(), permissions);
+        let messages = [
+            Message::system("prompt"),
+            Message::user("first"),
+            Message::assistant("answer"),
+            Message::user("second"),
+        ];
+        handler.set_message_tokens(&messages, &[500, 10, 40, 15]);
+
+        let output = handler.execute(SlashCommand::Status).unwrap();
+        assert!(output.message.contains("  system: 1 message, 500 tokens\n"), "{}", output.message);
+        assert!(output.message.contains("  user: 2 messages, 25 tokens\n"), "{}", output.message);
+        assert!(output.message.contains("  assistant: 1 message, 40 tokens\n"), "{}", output.message);
     }
 }
@@ -1,12 +1,12 @@
 //! Configuration management for PromptLine
 
-use crate::error::{ConfigError, Result};
+use crate::error::{ConfigError, ErrorClass, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 
 /// Main configuration structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     /// Model configuration
     #[serde(default)]
@@ -23,11 +23,31 @@ pub struct Config {
     /// Agent behavior
     #[serde(default)]
     pub agent: AgentConfig,
+
+    /// Context window management
+    #[serde(default)]
+    pub context: ContextConfig,
+
+    /// On-disk response cache
+    #[serde(default)]
+    pub cache: CacheConfig,
+
+    /// Code retrieval index
+    #[serde(default)]
+    pub index: IndexConfig,
+
+    /// Language servers for diagnostics and navigation
+    #[serde(default)]
+    pub lsp: LspConfig,
 }
 
 /// Model provider configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
+    /// Provider used unless overridden with `--provider`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_provider: Option<String>,
+
     /// Default model to use
     #[serde(default = "default_model")]
     pub default: String,
@@ -35,11 +55,46 @@ pub struct ModelConfig {
     /// Provider configurations
     #[serde(default)]
     pub providers: HashMap<String, ProviderConfig>,
+
+    /// Models tried in order when the active one fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<FallbackModelConfig>,
+
+    /// Error classes that trigger a fallback
+    #[serde(default = "default_fallback_on")]
+    pub fallback_on: Vec<ErrorClass>,
+
+    /// Prices per provider and model name prefix, overriding the built-in table
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pricing: HashMap<String, HashMap<String, ModelPrice>>,
+}
+
+/// A fallback entry under `models.fallbacks`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FallbackModelConfig {
+    /// Provider name (built in or configured under `models.providers`)
+    pub provider: String,
+
+    /// Model to request; defaults to `models.default`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// Model price in USD per million tokens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
 }
 
 /// Provider-specific configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProviderConfig {
+    /// Provider implementation; `openai-compatible` for custom endpoints.
+    /// Defaults to the provider's name.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
     /// API key (can use env var syntax: ${VAR_NAME})
     pub api_key: Option<String>,
 
@@ -53,6 +108,106 @@ pub struct ProviderConfig {
 
     /// Base URL for the API (optional)
     pub base_url: Option<String>,
+
+    /// Extra HTTP headers sent with every request
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+
+    /// Organization ID (OpenAI-compatible providers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+
+    /// API version passed as the `api-version` query parameter (Azure-style gateways)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+
+    /// Request quirks applied to every model of this provider
+    #[serde(default, skip_serializing_if = "ProviderQuirks::is_default")]
+    pub quirks: ProviderQuirks,
+
+    /// Per-model quirks, matched by model name prefix, overriding `quirks`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub model_quirks: HashMap<String, ProviderQuirks>,
+
+    /// Retry and timeout settings for requests to this provider
+    #[serde(default, skip_serializing_if = "RetryConfig::is_default")]
+    pub retry: RetryConfig,
+
+    /// Token budget for extended thinking (Anthropic); off when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<usize>,
+
+    /// Mark prompt-cache breakpoints in requests (Anthropic; on when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_caching: Option<bool>,
+}
+
+/// Retry and timeout settings for model requests
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Retries after the first attempt (0 disables retrying)
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry; doubled on every further attempt
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+
+    /// Upper bound for the backoff, including a server's `Retry-After`
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+
+    /// Timeout for a single request attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+impl RetryConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            timeout_secs: None,
+        }
+    }
+}
+
+/// Differences between OpenAI-compatible servers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderQuirks {
+    /// The server rejects `system` messages; send them as `user` messages instead
+    #[serde(default)]
+    pub no_system_role: bool,
+
+    /// Name of the output-limit field
+    #[serde(default)]
+    pub max_tokens_field: MaxTokensField,
+
+    /// The server rejects `stream_options`, so streamed responses carry no usage
+    #[serde(default)]
+    pub no_stream_usage: bool,
+}
+
+impl ProviderQuirks {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Request field used to limit output tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaxTokensField {
+    #[default]
+    MaxTokens,
+    MaxCompletionTokens,
 }
 
 /// Model parameters
@@ -157,6 +312,10 @@ pub struct SafetyConfig {
     /// Enable backups before file changes
     #[serde(default = "default_true")]
     pub enable_backups: bool,
+
+    /// Stop the agent once the session has cost more than this (USD)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cost_usd: Option<f64>,
 }
 
 /// Agent behavior configuration
@@ -178,6 +337,117 @@ pub struct AgentConfig {
     pub default_system_prompt_template: Option<String>,
 }
 
+/// Context window management configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextConfig {
+    /// Automatically compact the conversation when the threshold is reached
+    #[serde(default = "default_true")]
+    pub auto_compact: bool,
+
+    /// Fraction of the context window that triggers compaction (0.0 - 1.0)
+    #[serde(default = "default_compaction_threshold")]
+    pub compaction_threshold: f32,
+
+    /// Number of most recent messages kept verbatim during compaction
+    #[serde(default = "default_keep_recent_messages")]
+    pub keep_recent_messages: usize,
+
+    /// Tool outputs longer than this (in characters) are elided in the middle
+    #[serde(default = "default_max_tool_output_chars")]
+    pub max_tool_output_chars: usize,
+
+    /// Directory with `<encoding>.tiktoken` vocabularies overriding the bundled ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer_dir: Option<PathBuf>,
+
+    /// Most tokens of project instruction files (AGENTS.md etc.) added to the system prompt
+    #[serde(default = "default_instruction_budget")]
+    pub instruction_budget: usize,
+
+    /// Most tokens of the repository map in the system prompt (0 leaves it out)
+    #[serde(default = "default_repo_map_budget")]
+    pub repo_map_budget: usize,
+}
+
+/// Response cache configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Serve repeated requests from the cache
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Seconds before a cached response expires
+    #[serde(default = "default_cache_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// Oldest entries are evicted once the cache grows past this size
+    #[serde(default = "default_cache_max_size_mb")]
+    pub max_size_mb: u64,
+
+    /// Also cache requests with a non-zero temperature
+    #[serde(default)]
+    pub all_temperatures: bool,
+
+    /// Cache directory (defaults to `<config dir>/promptline/cache`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+/// Code retrieval index configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexConfig {
+    /// Embedding backend blended into the ranking: `hashing` (offline) or `none`
+    #[serde(default)]
+    pub embedder: crate::index::EmbedderKind,
+
+    /// Share of the embedding similarity in the ranking (0.0 - 1.0)
+    #[serde(default = "default_embedding_weight")]
+    pub embedding_weight: f32,
+}
+
+/// Language server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LspConfig {
+    /// Start language servers for files the agent edits or navigates
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Milliseconds to wait for diagnostics after an edit
+    #[serde(default = "default_diagnostics_timeout_ms")]
+    pub diagnostics_timeout_ms: u64,
+
+    /// Servers by name; replaces the built-in rust-analyzer, pyright and TypeScript servers
+    #[serde(default = "default_lsp_servers")]
+    pub servers: BTreeMap<String, LspServerConfig>,
+}
+
+/// A language server started over stdio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LspServerConfig {
+    pub command: String,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// File extensions handled by the server, without the dot
+    pub extensions: Vec<String>,
+
+    /// Sent as `initializationOptions`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initialization_options: Option<serde_json::Value>,
+}
+
+impl LspServerConfig {
+    pub fn new(command: impl Into<String>, args: &[&str], extensions: &[&str]) -> Self {
+        Self {
+            command: command.into(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            initialization_options: None,
+        }
+    }
+}
+
 impl Config {
     /// Load configuration from file
     pub fn load_from_file(path: &Path) -> Result<Self> {
@@ -221,10 +491,11 @@ impl Config {
     /// Expand environment variables in configuration
     fn expand_env_vars(&mut self) -> Result<()> {
         for provider in self.models.providers.values_mut() {
-            if let Some(ref mut key) = provider.api_key {
-                if key.starts_with("${") && key.ends_with("}") {
-                    let var_name = &key[2..key.len() - 1];
-                    *key = std::env::var(var_name).map_err(|_| {
+            let values = provider.api_key.iter_mut().chain(provider.headers.values_mut());
+            for value in values {
+                if value.starts_with("${") && value.ends_with("}") {
+                    let var_name = &value[2..value.len() - 1];
+                    *value = std::env::var(var_name).map_err(|_| {
                         ConfigError::Invalid(format!("Environment variable not found: {}", var_name))
                     })?;
                 }
@@ -241,22 +512,15 @@ impl Config {
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            models: ModelConfig::default(),
-            tools: ToolPermissions::default(),
-            safety: SafetyConfig::default(),
-            agent: AgentConfig::default(),
-        }
-    }
-}
-
 impl Default for ModelConfig {
     fn default() -> Self {
         Self {
+            active_provider: None,
             default: default_model(),
             providers: HashMap::new(),
+            fallbacks: Vec::new(),
+            fallback_on: default_fallback_on(),
+            pricing: HashMap::new(),
         }
     }
 }
@@ -297,6 +561,7 @@ impl Default for SafetyConfig {
             denied_commands: None,
             protected_patterns: default_protected_patterns(),
             enable_backups: true,
+            max_cost_usd: None,
         }
     }
 }
@@ -312,6 +577,51 @@ impl Default for AgentConfig {
     }
 }
 
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            auto_compact: true,
+            compaction_threshold: default_compaction_threshold(),
+            keep_recent_messages: default_keep_recent_messages(),
+            max_tool_output_chars: default_max_tool_output_chars(),
+            tokenizer_dir: None,
+            instruction_budget: default_instruction_budget(),
+            repo_map_budget: default_repo_map_budget(),
+        }
+    }
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            embedder: crate::index::EmbedderKind::default(),
+            embedding_weight: default_embedding_weight(),
+        }
+    }
+}
+
+impl Default for LspConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            diagnostics_timeout_ms: default_diagnostics_timeout_ms(),
+            servers: default_lsp_servers(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: default_cache_ttl_secs(),
+            max_size_mb: default_cache_max_size_mb(),
+            all_temperatures: false,
+            dir: None,
+        }
+    }
+}
+
 // Default value functions
 fn default_model() -> String {
     // Default model depends on provider
@@ -328,6 +638,22 @@ fn default_model() -> String {
         .unwrap_or_else(|| "llama3".to_string())
 }
 
+fn default_fallback_on() -> Vec<ErrorClass> {
+    vec![ErrorClass::Retryable]
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
 fn default_temperature() -> f32 {
     0.2
 }
@@ -348,6 +674,57 @@ fn default_max_iterations() -> usize {
     20
 }
 
+fn default_compaction_threshold() -> f32 {
+    0.8
+}
+
+fn default_keep_recent_messages() -> usize {
+    6
+}
+
+fn default_max_tool_output_chars() -> usize {
+    10_000
+}
+
+fn default_instruction_budget() -> usize {
+    crate::context::instructions::DEFAULT_INSTRUCTION_BUDGET
+}
+
+fn default_repo_map_budget() -> usize {
+    crate::context::repo_map::DEFAULT_REPO_MAP_BUDGET
+}
+
+fn default_embedding_weight() -> f32 {
+    crate::index::retrieval::DEFAULT_EMBEDDING_WEIGHT
+}
+
+fn default_diagnostics_timeout_ms() -> u64 {
+    3_000
+}
+
+fn default_lsp_servers() -> BTreeMap<String, LspServerConfig> {
+    BTreeMap::from([
+        ("rust".to_string(), LspServerConfig::new("rust-analyzer", &[], &["rs"])),
+        ("python".to_string(), LspServerConfig::new("pyright-langserver", &["--stdio"], &["py", "pyi"])),
+        (
+            "typescript".to_string(),
+            LspServerConfig::new(
+                "typescript-language-server",
+                &["--stdio"],
+                &["ts", "tsx", "mts", "cts", "js", "jsx", "mjs", "cjs"],
+            ),
+        ),
+    ])
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_cache_max_size_mb() -> u64 {
+    100
+}
+
 fn default_mode() -> String {
     "plan".to_string()
 }
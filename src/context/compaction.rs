@@ -0,0 +1,303 @@
+//! Context window accounting and conversation compaction
+
+use crate::config::ContextConfig;
+use crate::error::Result;
+use crate::model::{LanguageModel, Message, Role, TokenUsage};
+use std::fmt;
+
+/// Longest message excerpt included in the summarization transcript
+const SUMMARY_EXCERPT_CHARS: usize = 2_000;
+
+const SUMMARY_PROMPT: &str = "You are summarizing the earlier part of a conversation between a developer \
+and PromptLine, an AI coding agent. Write a concise summary that preserves the user's goals, decisions made, \
+files read or changed, commands run and their outcomes, and any open questions. Do not invent details. \
+Respond with the summary only.";
+
+/// Prefix marking a message produced by compaction
+pub const SUMMARY_PREFIX: &str = "Summary of earlier conversation:";
+
+/// Elide the middle of a long tool output, keeping its head and tail
+pub fn truncate_tool_output(output: &str, max_chars: usize) -> String {
+    let total = output.chars().count();
+    if max_chars == 0 || total <= max_chars {
+        return output.to_string();
+    }
+
+    let head_len = max_chars / 2;
+    let tail_len = max_chars - head_len;
+    let head: String = output.chars().take(head_len).collect();
+    let tail: String = output.chars().skip(total - tail_len).collect();
+
+    format!(
+        "{}\n... [{} characters elided] ...\n{}",
+        head,
+        total - head_len - tail_len,
+        tail
+    )
+}
+
+/// How much of the model's context window the conversation occupies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextUsage {
+    pub used_tokens: usize,
+    pub context_window: usize,
+    /// Name of the tokenizer that produced the count
+    pub tokenizer: &'static str,
+}
+
+impl ContextUsage {
+    /// Fraction of the context window in use
+    pub fn fraction(&self) -> f32 {
+        if self.context_window == 0 {
+            return 0.0;
+        }
+        self.used_tokens as f32 / self.context_window as f32
+    }
+
+    /// Percentage of the context window in use
+    pub fn percent(&self) -> u32 {
+        (self.fraction() * 100.0).round() as u32
+    }
+}
+
+impl fmt::Display for ContextUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} / {} tokens ({}%, {})",
+            self.used_tokens,
+            self.context_window,
+            self.percent(),
+            self.tokenizer
+        )
+    }
+}
+
+/// Outcome of a compaction pass
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    pub messages_summarized: usize,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+    /// Tokens spent on the summarization request
+    pub usage: TokenUsage,
+}
+
+impl fmt::Display for CompactionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.messages_summarized == 0 {
+            return write!(f, "Nothing to compact");
+        }
+        write!(
+            f,
+            "Compacted {} messages: {} → {} tokens",
+            self.messages_summarized, self.tokens_before, self.tokens_after
+        )
+    }
+}
+
+/// Summarizes old turns when the conversation nears the context limit
+pub struct Compactor {
+    config: ContextConfig,
+}
+
+impl Compactor {
+    pub fn new(config: ContextConfig) -> Self {
+        Self { config }
+    }
+
+    /// Compute context usage for a conversation
+    pub fn usage(&self, model: &dyn LanguageModel, history: &[Message]) -> ContextUsage {
+        let tokenizer = model.tokenizer();
+        ContextUsage {
+            used_tokens: history
+                .iter()
+                .map(|m| crate::tokenizer::count_message(tokenizer.as_ref(), m))
+                .sum(),
+            context_window: model.model_info().context_window,
+            tokenizer: tokenizer.name(),
+        }
+    }
+
+    /// Whether automatic compaction should run
+    pub fn needs_compaction(&self, usage: &ContextUsage) -> bool {
+        self.config.auto_compact && usage.fraction() >= self.config.compaction_threshold
+    }
+
+    /// Truncate a tool output according to the configured limit
+    pub fn truncate_tool_output(&self, output: &str) -> String {
+        truncate_tool_output(output, self.config.max_tool_output_chars)
+    }
+
+    /// Replace old, unpinned turns with a model-written summary
+    ///
+    /// The leading system prompt, pinned messages and the most recent
+    /// `keep_recent_messages` messages are kept verbatim.
+    pub async fn compact(
+        &self,
+        model: &dyn LanguageModel,
+        history: &mut Vec<Message>,
+    ) -> Result<CompactionReport> {
+        let tokens_before = self.usage(model, history).used_tokens;
+
+        let head = usize::from(history.first().is_some_and(|m| m.role == Role::System));
+        let mut tail_start = history
+            .len()
+            .saturating_sub(self.config.keep_recent_messages)
+            .max(head);
+        // Keep tool results with the assistant message that called them
+        while tail_start > head && history[tail_start].role == Role::Tool {
+            tail_start -= 1;
+        }
+
+        let eligible: Vec<usize> = (head..tail_start)
+            .filter(|&i| !history[i].pinned)
+            .collect();
+
+        if eligible.is_empty() {
+            return Ok(CompactionReport {
+                messages_summarized: 0,
+                tokens_before,
+                tokens_after: tokens_before,
+                usage: TokenUsage::default(),
+            });
+        }
+
+        let transcript = eligible
+            .iter()
+            .map(|&i| {
+                let msg = &history[i];
+                format!(
+                    "{}: {}",
+                    msg.role,
+                    truncate_tool_output(&msg.transcript(), SUMMARY_EXCERPT_CHARS)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let response = model
+            .chat(&[Message::system(SUMMARY_PROMPT), Message::user(transcript)])
+            .await?;
+
+        let summary = Message::system(format!("{}\n{}", SUMMARY_PREFIX, response.content.trim()));
+
+        let mut compacted = Vec::with_capacity(history.len() - eligible.len() + 1);
+        compacted.extend(history.drain(..head));
+        compacted.push(summary);
+        compacted.extend(
+            history
+                .drain(..)
+                .enumerate()
+                .filter(|(i, _)| eligible.binary_search(&(i + head)).is_err())
+                .map(|(_, m)| m),
+        );
+        *history = compacted;
+
+        Ok(CompactionReport {
+            messages_summarized: eligible.len(),
+            tokens_before,
+            tokens_after: self.usage(model, history).used_tokens,
+            usage: response.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ContentPart, ModelInfo, ToolCall};
+    use crate::testing::ScriptedModel;
+
+    fn summary_model() -> ScriptedModel {
+        ScriptedModel::new()
+            .with_model_info(ModelInfo {
+                provider: "mock".to_string(),
+                model: "test".to_string(),
+                max_tokens: 256,
+                context_window: 1000,
+                supports_tools: false,
+                supports_streaming: false,
+            })
+            .respond("The user asked about main.rs")
+    }
+
+    #[test]
+    fn test_truncate_tool_output() {
+        assert_eq!(truncate_tool_output("short", 100), "short");
+
+        let long = "a".repeat(50) + &"b".repeat(50);
+        let truncated = truncate_tool_output(&long, 20);
+        assert!(truncated.starts_with("aaaaaaaaaa\n"));
+        assert!(truncated.ends_with("\nbbbbbbbbbb"));
+        assert!(truncated.contains("[80 characters elided]"));
+    }
+
+    #[test]
+    fn test_usage_and_threshold() {
+        let compactor = Compactor::new(ContextConfig::default());
+        let history = vec![Message::user("x".repeat(3200))];
+        let usage = compactor.usage(&summary_model(), &history);
+
+        assert_eq!(usage.used_tokens, 804);
+        assert_eq!(usage.percent(), 80);
+        assert!(compactor.needs_compaction(&usage));
+    }
+
+    #[tokio::test]
+    async fn test_compact_keeps_system_pinned_and_recent() {
+        let config = ContextConfig {
+            keep_recent_messages: 2,
+            ..ContextConfig::default()
+        };
+        let compactor = Compactor::new(config);
+
+        let mut history = vec![
+            Message::system("system prompt"),
+            Message::user("first question"),
+            Message::user("remember this").pinned(),
+            Message::assistant("first answer"),
+            Message::user("second question"),
+            Message::assistant("second answer"),
+        ];
+
+        let report = compactor.compact(&summary_model(), &mut history).await.unwrap();
+
+        assert_eq!(report.messages_summarized, 2);
+        let contents: Vec<String> = history.iter().map(|m| m.text()).collect();
+        assert_eq!(contents[0], "system prompt");
+        assert!(contents[1].starts_with(SUMMARY_PREFIX));
+        assert_eq!(&contents[2..], &["remember this", "second question", "second answer"]);
+    }
+
+    #[tokio::test]
+    async fn test_compact_keeps_tool_results_with_their_call() {
+        let config = ContextConfig {
+            keep_recent_messages: 2,
+            ..ContextConfig::default()
+        };
+        let compactor = Compactor::new(config);
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "file_list".to_string(),
+            arguments: serde_json::json!({}),
+        };
+
+        let mut history = vec![
+            Message::system("system prompt"),
+            Message::user("first question"),
+            Message::assistant("first answer"),
+            Message::user("list the files"),
+            Message::new(Role::Assistant, vec![ContentPart::ToolCall(call)]),
+            Message::tool_result("call_1", "main.rs", false),
+            Message::assistant("There is one file"),
+        ];
+
+        let report = compactor.compact(&summary_model(), &mut history).await.unwrap();
+
+        assert_eq!(report.messages_summarized, 3);
+        let roles: Vec<Role> = history.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::System, Role::System, Role::Assistant, Role::Tool, Role::Assistant]);
+        assert_eq!(history[2].tool_calls().next().unwrap().id, "call_1");
+    }
+}
@@ -0,0 +1,436 @@
+//! Project instruction files
+//!
+//! Instructions are layered: the user-global file in the config directory
+//! comes first, then the file at the repository root, then files in the
+//! directories between the root and the working directory. Files in other
+//! subdirectories are picked up as the agent touches paths below them.
+//!
+//! A line consisting only of `@path` is replaced by the contents of that
+//! file, resolved relative to the file containing it. Repository files may
+//! only import files inside the repository; `@~/path` imports from the home
+//! directory are only followed in the user-global file.
+
+use crate::tokenizer::{HeuristicTokenizer, Tokenizer};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Instruction file names looked for in each directory, in order
+pub const INSTRUCTION_FILES: &[&str] = &["AGENTS.md", "PROMPTLINE.md", ".promptline/context.md"];
+
+/// Default token budget for all instruction files together
+pub const DEFAULT_INSTRUCTION_BUDGET: usize = 8_000;
+
+/// How deep `@path` imports may nest
+const MAX_IMPORT_DEPTH: usize = 5;
+
+/// Where an instruction file comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionScope {
+    /// The user's config directory
+    User,
+    /// The repository root
+    Project,
+    /// A subdirectory of the repository
+    Directory,
+}
+
+impl std::fmt::Display for InstructionScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::User => "user",
+            Self::Project => "project",
+            Self::Directory => "directory",
+        })
+    }
+}
+
+/// A loaded instruction file
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionFile {
+    pub path: PathBuf,
+    pub scope: InstructionScope,
+    /// Contents with imports expanded
+    pub content: String,
+    /// Files pulled in with `@path`
+    pub imports: Vec<PathBuf>,
+}
+
+/// Instruction files that apply to a working directory
+#[derive(Clone)]
+pub struct ProjectInstructions {
+    user_dir: Option<PathBuf>,
+    root: PathBuf,
+    cwd: PathBuf,
+    budget: usize,
+    tokenizer: Arc<dyn Tokenizer>,
+    /// Directories below the working directory searched on demand
+    discovered: BTreeSet<PathBuf>,
+    files: Vec<InstructionFile>,
+}
+
+impl ProjectInstructions {
+    /// Load the instructions for `cwd`, including the user-global file
+    pub fn new(cwd: &Path) -> Self {
+        let cwd = cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+        let mut instructions = Self {
+            user_dir: dirs::config_dir().map(|dir| dir.join("promptline")),
+            root: super::project_root(&cwd),
+            cwd,
+            budget: DEFAULT_INSTRUCTION_BUDGET,
+            tokenizer: Arc::new(HeuristicTokenizer),
+            discovered: BTreeSet::new(),
+            files: Vec::new(),
+        };
+        instructions.reload();
+        instructions
+    }
+
+    /// Read the user-global file from `dir` instead of the config directory
+    pub fn with_user_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.user_dir = dir;
+        self.reload();
+        self
+    }
+
+    /// Most tokens of instructions put in the system prompt
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Count tokens with the model's tokenizer
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Repository root, or the working directory outside a repository
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Loaded files, from the most general to the most specific
+    pub fn files(&self) -> &[InstructionFile] {
+        &self.files
+    }
+
+    /// Read every file again, keeping directories discovered so far
+    pub fn reload(&mut self) {
+        self.files.clear();
+        if let Some(dir) = self.user_dir.clone() {
+            self.load_dir(&dir, InstructionScope::User);
+        }
+        let mut dirs: Vec<PathBuf> = self
+            .cwd
+            .ancestors()
+            .take_while(|dir| dir.starts_with(&self.root))
+            .map(Path::to_path_buf)
+            .collect();
+        dirs.reverse();
+        dirs.extend(self.discovered.iter().cloned());
+        for dir in dirs {
+            let scope = self.scope_of(&dir);
+            self.load_dir(&dir, scope);
+        }
+    }
+
+    /// Load instruction files from the directories leading to `path`
+    ///
+    /// Returns the newly loaded files; paths outside the repository are ignored.
+    pub fn discover(&mut self, path: &Path) -> Vec<PathBuf> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dir = if path.is_dir() {
+            path
+        } else {
+            match path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return Vec::new(),
+            }
+        };
+        if !dir.starts_with(&self.root) {
+            return Vec::new();
+        }
+
+        let mut dirs: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|d| d.starts_with(&self.root) && !self.cwd.starts_with(d))
+            .map(Path::to_path_buf)
+            .collect();
+        dirs.reverse();
+
+        let before = self.files.len();
+        for dir in dirs {
+            if self.discovered.insert(dir.clone()) {
+                let scope = self.scope_of(&dir);
+                self.load_dir(&dir, scope);
+            }
+        }
+        self.files[before..].iter().map(|file| file.path.clone()).collect()
+    }
+
+    /// Instructions for the system prompt, within the token budget
+    ///
+    /// Files that would go over the budget are left out whole.
+    pub fn render(&self) -> String {
+        self.budgeted()
+            .into_iter()
+            .filter(|(_, _, included)| *included)
+            .map(|(file, _, _)| format!("# From {}\n\n{}", self.display_path(&file.path), file.content.trim()))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// What was loaded and what it costs, for `/context`
+    pub fn report(&self) -> String {
+        if self.files.is_empty() {
+            return format!(
+                "No instruction files found (looked for {} in {} and its subdirectories).",
+                INSTRUCTION_FILES.join(", "),
+                self.root.display()
+            );
+        }
+
+        let mut lines = vec!["Instruction files (general to specific):".to_string()];
+        let mut used = 0;
+        for (file, tokens, included) in self.budgeted() {
+            if included {
+                used += tokens;
+            }
+            lines.push(format!(
+                "  {} {} [{}] {} tokens{}",
+                if included { "✓" } else { "✗" },
+                self.display_path(&file.path),
+                file.scope,
+                tokens,
+                if included { "" } else { " (over budget, not sent)" }
+            ));
+            for import in &file.imports {
+                lines.push(format!("      @ {}", self.display_path(import)));
+            }
+        }
+        lines.push(format!("  Using {}/{} tokens of the instruction budget", used, self.budget));
+        lines.join("\n")
+    }
+
+    /// Each file with its token count and whether it fits the budget
+    fn budgeted(&self) -> Vec<(&InstructionFile, usize, bool)> {
+        let mut used = 0;
+        self.files
+            .iter()
+            .map(|file| {
+                let tokens = self.tokenizer.count(&file.content);
+                let included = used + tokens <= self.budget;
+                if included {
+                    used += tokens;
+                }
+                (file, tokens, included)
+            })
+            .collect()
+    }
+
+    fn scope_of(&self, dir: &Path) -> InstructionScope {
+        if dir == self.root {
+            InstructionScope::Project
+        } else {
+            InstructionScope::Directory
+        }
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path.display().to_string(),
+        }
+    }
+
+    fn load_dir(&mut self, dir: &Path, scope: InstructionScope) {
+        for name in INSTRUCTION_FILES {
+            let path = dir.join(name);
+            if !path.is_file() || self.files.iter().any(|file| file.path == path) {
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(content) => {
+                    let mut imports = Vec::new();
+                    let mut chain = vec![path.canonicalize().unwrap_or_else(|_| path.clone())];
+                    let root = self.root.canonicalize().unwrap_or_else(|_| self.root.clone());
+                    let within = (scope != InstructionScope::User).then_some(root.as_path());
+                    let content = expand_imports(&content, within, &mut chain, &mut imports);
+                    self.files.push(InstructionFile {
+                        path,
+                        scope,
+                        content,
+                        imports,
+                    });
+                }
+                Err(e) => tracing::warn!("Failed to read instructions {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ProjectInstructions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProjectInstructions")
+            .field("root", &self.root)
+            .field("files", &self.files.iter().map(|file| &file.path).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Replace `@path` lines with the files they name, outside code blocks
+///
+/// `chain` holds the file being expanded and the files importing it. With
+/// `within`, imports must resolve to files below it and `~/` is not followed.
+fn expand_imports(content: &str, within: Option<&Path>, chain: &mut Vec<PathBuf>, imports: &mut Vec<PathBuf>) -> String {
+    let path = chain.last().cloned().unwrap_or_default();
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut in_code = false;
+    let mut out = Vec::new();
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+        }
+        let target = trimmed
+            .strip_prefix('@')
+            .filter(|target| !in_code && !target.is_empty() && !target.contains(char::is_whitespace));
+        let Some(target) = target else {
+            out.push(line.to_string());
+            continue;
+        };
+
+        let import = match (target.strip_prefix("~/"), within) {
+            (Some(rest), None) => dirs::home_dir().unwrap_or_default().join(rest),
+            (Some(_), Some(_)) => {
+                tracing::warn!("Skipping home directory import {} from {}", target, path.display());
+                out.push(line.to_string());
+                continue;
+            }
+            (None, _) => base.join(target),
+        };
+        let import = match import.canonicalize() {
+            Ok(import) => import,
+            Err(e) => {
+                tracing::warn!("Failed to import {} from {}: {}", import.display(), path.display(), e);
+                out.push(line.to_string());
+                continue;
+            }
+        };
+        if within.is_some_and(|root| !import.starts_with(root)) {
+            tracing::warn!("Skipping import of {} from outside the project in {}", import.display(), path.display());
+            out.push(line.to_string());
+            continue;
+        }
+        if chain.len() > MAX_IMPORT_DEPTH || chain.contains(&import) || imports.contains(&import) {
+            tracing::warn!("Skipping import of {} from {}", import.display(), path.display());
+            out.push(line.to_string());
+            continue;
+        }
+        match std::fs::read_to_string(&import) {
+            Ok(imported) => {
+                imports.push(import.clone());
+                chain.push(import);
+                out.push(expand_imports(&imported, within, chain, imports));
+                chain.pop();
+            }
+            Err(e) => {
+                tracing::warn!("Failed to import {} from {}: {}", import.display(), path.display(), e);
+                out.push(line.to_string());
+            }
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn repo() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+        std::fs::create_dir_all(dir.path().join("crates/core/src")).unwrap();
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("AGENTS.md"), "Root rules\n@docs/style.md").unwrap();
+        std::fs::write(dir.path().join("docs/style.md"), "Use four spaces\n@../AGENTS.md").unwrap();
+        std::fs::write(dir.path().join("crates/core/AGENTS.md"), "Core rules").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_layered_loading_and_imports() {
+        let user = tempdir().unwrap();
+        std::fs::write(user.path().join("PROMPTLINE.md"), "Be terse").unwrap();
+        let repo = repo();
+
+        let instructions =
+            ProjectInstructions::new(&repo.path().join("crates")).with_user_dir(Some(user.path().to_path_buf()));
+        let scopes: Vec<_> = instructions.files().iter().map(|f| f.scope).collect();
+        assert_eq!(scopes, vec![InstructionScope::User, InstructionScope::Project]);
+
+        let root = &instructions.files()[1];
+        assert_eq!(root.content, "Root rules\nUse four spaces\n@../AGENTS.md");
+        assert_eq!(root.imports.len(), 1);
+
+        let rendered = instructions.render();
+        assert!(rendered.find("Be terse").unwrap() < rendered.find("# From AGENTS.md").unwrap());
+    }
+
+    #[test]
+    fn test_project_imports_stay_inside_the_project() {
+        let outside = tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "hunter2").unwrap();
+        let repo = outside.path().join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        let secret = outside.path().join("secret.txt").canonicalize().unwrap();
+        std::fs::write(
+            repo.join("AGENTS.md"),
+            format!("@../secret.txt\n@{}\n@~/.ssh/id_rsa", secret.display()),
+        )
+        .unwrap();
+        let user = tempdir().unwrap();
+        std::fs::write(user.path().join("PROMPTLINE.md"), format!("@{}", secret.display())).unwrap();
+
+        let instructions = ProjectInstructions::new(&repo).with_user_dir(Some(user.path().to_path_buf()));
+
+        let [user_file, project_file] = instructions.files() else {
+            panic!("expected user and project files");
+        };
+        assert_eq!(user_file.content, "hunter2");
+        assert!(!project_file.content.contains("hunter2"));
+        assert!(project_file.imports.is_empty());
+    }
+
+    #[test]
+    fn test_discover_nested_files() {
+        let repo = repo();
+        let mut instructions = ProjectInstructions::new(repo.path()).with_user_dir(None);
+        assert_eq!(instructions.files().len(), 1);
+
+        let loaded = instructions.discover(&repo.path().join("crates/core/src/lib.rs"));
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[0].ends_with("crates/core/AGENTS.md"));
+        assert!(instructions.discover(&repo.path().join("crates/core/src")).is_empty());
+        assert!(instructions.discover(Path::new("/")).is_empty());
+
+        std::fs::write(repo.path().join("crates/core/AGENTS.md"), "Updated core rules").unwrap();
+        instructions.reload();
+        assert!(instructions.render().contains("Updated core rules"));
+    }
+
+    #[test]
+    fn test_budget_leaves_out_whole_files() {
+        let repo = repo();
+        let mut instructions = ProjectInstructions::new(repo.path()).with_user_dir(None).with_budget(5);
+        instructions.discover(&repo.path().join("crates/core/src/lib.rs"));
+
+        let rendered = instructions.render();
+        assert!(!rendered.contains("Root rules"));
+        assert!(rendered.contains("Core rules"));
+        assert!(instructions.report().contains("(over budget, not sent)"));
+    }
+}
@@ -2,11 +2,29 @@
 
 use crate::error::Result;
 use crate::model::Message;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
+pub mod compaction;
+pub mod instructions;
+pub mod project;
+pub mod repo_map;
+
+pub use compaction::{CompactionReport, Compactor, ContextUsage};
+pub use instructions::{InstructionFile, InstructionScope, ProjectInstructions};
+pub use project::{CommandKind, ProjectAnalysis, ProjectCommand};
+pub use repo_map::RepoMap;
+
 const HISTORY_FILE_NAME: &str = "history.json";
 
+/// Nearest ancestor of `cwd` containing `.git`, or `cwd` itself
+pub fn project_root(cwd: &Path) -> PathBuf {
+    cwd.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(cwd)
+        .to_path_buf()
+}
+
 pub struct ContextManager {
     context_dir: PathBuf,
 }
@@ -56,33 +74,19 @@ impl ContextManager {
         Ok(())
     }
 
+    /// Instruction files for the current directory, layered from user-global to nested
     pub async fn load_project_context(&self) -> Result<Option<String>> {
-        let mut project_context_path = std::env::current_dir()?;
-        project_context_path.push(".promptline");
-        project_context_path.push("context.md");
-
-        if project_context_path.exists() {
-            let content = fs::read_to_string(&project_context_path).await?;
-            Ok(Some(content))
-        } else {
-            Ok(None)
-        }
+        let cwd = std::env::current_dir()?;
+        let rendered = tokio::task::spawn_blocking(move || ProjectInstructions::new(&cwd).render())
+            .await
+            .map_err(|e| crate::error::PromptLineError::Other(e.to_string()))?;
+        Ok((!rendered.is_empty()).then_some(rendered))
     }
 
+    /// Main project type of the current directory, from its manifests
     pub async fn detect_project_type(&self) -> Result<String> {
         let current_dir = std::env::current_dir()?;
-
-        if current_dir.join("Cargo.toml").exists() {
-            Ok("Rust".to_string())
-        } else if current_dir.join("package.json").exists() {
-            Ok("Node.js".to_string())
-        } else if current_dir.join("requirements.txt").exists() || current_dir.join("pyproject.toml").exists() {
-            Ok("Python".to_string())
-        } else if current_dir.join("pom.xml").exists() || current_dir.join("build.gradle").exists() {
-            Ok("Java/Gradle".to_string())
-        } else {
-            Ok("Generic".to_string())
-        }
+        Ok(ProjectAnalysis::analyze(&current_dir, &[]).kind)
     }
 }
 
@@ -107,8 +111,8 @@ mod tests {
         // Load history
         let loaded_messages = manager.load_history().await.unwrap();
         assert_eq!(messages.len(), loaded_messages.len());
-        assert_eq!(messages[0].content, loaded_messages[0].content);
-        assert_eq!(messages[1].content, loaded_messages[1].content);
+        assert_eq!(messages[0].text(), loaded_messages[0].text());
+        assert_eq!(messages[1].text(), loaded_messages[1].text());
 
         // Clear history
         manager.clear_history().await.unwrap();
@@ -0,0 +1,467 @@
+//! Project analysis
+//!
+//! Looks at the manifests and files of a repository to find its languages,
+//! workspaces, build/test/lint commands and CI configuration.
+
+use crate::index::language_name;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Directories never worth walking, whether or not they are gitignored
+const SKIPPED_DIRS: &[&str] = &[
+    ".git",
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    "vendor",
+    "__pycache__",
+    ".venv",
+    "venv",
+    ".tox",
+    ".next",
+    ".gradle",
+];
+
+/// CI configuration files, besides GitHub workflows
+const CI_FILES: &[&str] = &[
+    ".gitlab-ci.yml",
+    ".circleci/config.yml",
+    ".travis.yml",
+    "azure-pipelines.yml",
+    "Jenkinsfile",
+    "bitbucket-pipelines.yml",
+    ".buildkite/pipeline.yml",
+];
+
+/// Files in a repository, relative to `root` and sorted, honouring `.gitignore`
+///
+/// Stops after `limit` files; the walk is in name order, so the same files are
+/// kept every time.
+pub fn walk_files(root: &Path, limit: usize) -> Vec<PathBuf> {
+    let walker = ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.depth() == 0
+                || !(entry.file_type().is_some_and(|t| t.is_dir()) && SKIPPED_DIRS.contains(&name.as_ref()))
+        })
+        .build();
+
+    let mut files: Vec<PathBuf> = walker
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .take(limit)
+        .collect();
+    files.sort();
+    files
+}
+
+/// What a project command is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommandKind {
+    Build,
+    Test,
+    Lint,
+    Format,
+    Run,
+}
+
+impl std::fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Build => "build",
+            Self::Test => "test",
+            Self::Lint => "lint",
+            Self::Format => "format",
+            Self::Run => "run",
+        })
+    }
+}
+
+/// A command for working on the project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectCommand {
+    pub kind: CommandKind,
+    pub command: String,
+    /// Manifest the command was derived from
+    pub source: String,
+}
+
+/// A group of packages managed together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    /// `cargo`, `npm`, `yarn` or `pnpm`
+    pub tool: String,
+    /// Member patterns as written in the manifest
+    pub members: Vec<String>,
+}
+
+/// Result of analysing a repository
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProjectAnalysis {
+    /// Main project type, e.g. "Rust" or "Node.js"
+    pub kind: String,
+    /// Languages with their file counts, most files first
+    pub languages: Vec<(String, usize)>,
+    pub workspaces: Vec<Workspace>,
+    pub commands: Vec<ProjectCommand>,
+    /// CI configuration files, relative to the root
+    pub ci: Vec<String>,
+}
+
+impl ProjectAnalysis {
+    /// Analyse the project at `root`, counting languages over `files`
+    pub fn analyze(root: &Path, files: &[PathBuf]) -> Self {
+        let mut analysis = Self {
+            languages: count_languages(files),
+            ..Self::default()
+        };
+
+        if let Some(manifest) = read_toml(&root.join("Cargo.toml")) {
+            analysis.kind = "Rust".to_string();
+            analysis.cargo(&manifest);
+        }
+        if let Some(package) = read_json(&root.join("package.json")) {
+            set_kind(&mut analysis.kind, "Node.js");
+            analysis.node(root, &package);
+        }
+        if root.join("pyproject.toml").exists() || root.join("requirements.txt").exists() {
+            set_kind(&mut analysis.kind, "Python");
+            analysis.python(root, files);
+        }
+        if root.join("go.mod").exists() {
+            set_kind(&mut analysis.kind, "Go");
+            analysis.add(CommandKind::Build, "go build ./...", "go.mod");
+            analysis.add(CommandKind::Test, "go test ./...", "go.mod");
+            analysis.add(CommandKind::Lint, "go vet ./...", "go.mod");
+            analysis.add(CommandKind::Format, "gofmt -l -w .", "go.mod");
+        }
+        if root.join("pom.xml").exists() {
+            set_kind(&mut analysis.kind, "Java/Maven");
+            analysis.add(CommandKind::Build, "mvn package", "pom.xml");
+            analysis.add(CommandKind::Test, "mvn test", "pom.xml");
+        }
+        for gradle in ["build.gradle", "build.gradle.kts"] {
+            if root.join(gradle).exists() {
+                set_kind(&mut analysis.kind, "Java/Gradle");
+                let wrapper = if root.join("gradlew").exists() { "./gradlew" } else { "gradle" };
+                analysis.add(CommandKind::Build, &format!("{} build", wrapper), gradle);
+                analysis.add(CommandKind::Test, &format!("{} test", wrapper), gradle);
+            }
+        }
+        if let Ok(makefile) = std::fs::read_to_string(root.join("Makefile")) {
+            analysis.make(&makefile);
+        }
+
+        analysis.ci = find_ci(root);
+        if analysis.kind.is_empty() {
+            analysis.kind = match analysis.languages.first() {
+                Some((language, _)) => language.clone(),
+                None => "Generic".to_string(),
+            };
+        }
+        analysis
+    }
+
+    /// Whether the repository holds several packages
+    pub fn is_monorepo(&self) -> bool {
+        !self.workspaces.is_empty()
+    }
+
+    /// First command of `kind`
+    pub fn command(&self, kind: CommandKind) -> Option<&ProjectCommand> {
+        self.commands.iter().find(|c| c.kind == kind)
+    }
+
+    /// Short description for the system prompt
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!("Project type: {}", self.kind)];
+        for workspace in &self.workspaces {
+            lines.push(format!(
+                "Monorepo: {} workspace with members {}",
+                workspace.tool,
+                workspace.members.join(", ")
+            ));
+        }
+        if !self.languages.is_empty() {
+            let languages: Vec<String> = self
+                .languages
+                .iter()
+                .take(5)
+                .map(|(language, files)| format!("{} ({} files)", language, files))
+                .collect();
+            lines.push(format!("Languages: {}", languages.join(", ")));
+        }
+        if !self.commands.is_empty() {
+            lines.push("Commands:".to_string());
+            for command in &self.commands {
+                lines.push(format!("- {}: `{}` (from {})", command.kind, command.command, command.source));
+            }
+        }
+        if !self.ci.is_empty() {
+            lines.push(format!("CI: {}", self.ci.join(", ")));
+        }
+        lines.join("\n")
+    }
+
+    /// Add a command unless one of the same kind already exists
+    fn add(&mut self, kind: CommandKind, command: &str, source: &str) {
+        if self.command(kind).is_none() {
+            self.commands.push(ProjectCommand {
+                kind,
+                command: command.to_string(),
+                source: source.to_string(),
+            });
+        }
+    }
+
+    fn cargo(&mut self, manifest: &toml::Value) {
+        let workspace = manifest.get("workspace");
+        let members = string_list(workspace.and_then(|w| w.get("members")));
+        let flag = if workspace.is_some() { " --workspace" } else { "" };
+        if !members.is_empty() {
+            self.workspaces.push(Workspace {
+                tool: "cargo".to_string(),
+                members,
+            });
+        }
+        self.add(CommandKind::Build, &format!("cargo build{}", flag), "Cargo.toml");
+        self.add(CommandKind::Test, &format!("cargo test{}", flag), "Cargo.toml");
+        self.add(
+            CommandKind::Lint,
+            &format!("cargo clippy{} --all-targets -- -D warnings", flag),
+            "Cargo.toml",
+        );
+        self.add(CommandKind::Format, "cargo fmt --all", "Cargo.toml");
+    }
+
+    fn node(&mut self, root: &Path, package: &serde_json::Value) {
+        let manager = if root.join("pnpm-lock.yaml").exists() || root.join("pnpm-workspace.yaml").exists() {
+            "pnpm"
+        } else if root.join("yarn.lock").exists() {
+            "yarn"
+        } else if root.join("bun.lockb").exists() {
+            "bun"
+        } else {
+            "npm"
+        };
+
+        // npm and yarn list workspaces in package.json, pnpm in its own file
+        let workspaces = package.get("workspaces");
+        let members = match workspaces {
+            Some(serde_json::Value::Object(w)) => json_strings(w.get("packages")),
+            other => json_strings(other),
+        };
+        if !members.is_empty() {
+            self.workspaces.push(Workspace {
+                tool: if manager == "yarn" { "yarn" } else { "npm" }.to_string(),
+                members,
+            });
+        }
+        if let Ok(content) = std::fs::read_to_string(root.join("pnpm-workspace.yaml")) {
+            let members = serde_yaml::from_str::<serde_yaml::Value>(&content)
+                .ok()
+                .and_then(|v| v.get("packages").cloned())
+                .and_then(|p| serde_yaml::from_value::<Vec<String>>(p).ok())
+                .unwrap_or_default();
+            if !members.is_empty() {
+                self.workspaces.push(Workspace {
+                    tool: "pnpm".to_string(),
+                    members,
+                });
+            }
+        }
+
+        let scripts = package.get("scripts").and_then(|s| s.as_object());
+        for (kind, names) in [
+            (CommandKind::Build, &["build"][..]),
+            (CommandKind::Test, &["test"][..]),
+            (CommandKind::Lint, &["lint", "check"][..]),
+            (CommandKind::Format, &["format", "fmt", "prettier"][..]),
+            (CommandKind::Run, &["dev", "start"][..]),
+        ] {
+            if let Some(script) = names.iter().find(|name| scripts.is_some_and(|s| s.contains_key(**name))) {
+                self.add(kind, &format!("{} run {}", manager, script), "package.json");
+            }
+        }
+    }
+
+    fn python(&mut self, root: &Path, files: &[PathBuf]) {
+        let pyproject = read_toml(&root.join("pyproject.toml"));
+        let tool = |name: &str| pyproject.as_ref().and_then(|p| p.get("tool")).and_then(|t| t.get(name)).is_some();
+        let source = if pyproject.is_some() { "pyproject.toml" } else { "requirements.txt" };
+
+        let has_tests = files.iter().any(|f| {
+            f.starts_with("tests") || f.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("test_"))
+        });
+        if tool("pytest") || has_tests {
+            self.add(CommandKind::Test, "pytest", source);
+        }
+        if tool("ruff") {
+            self.add(CommandKind::Lint, "ruff check .", source);
+            self.add(CommandKind::Format, "ruff format .", source);
+        }
+        if tool("mypy") {
+            self.add(CommandKind::Lint, "mypy .", source);
+        }
+        if tool("black") {
+            self.add(CommandKind::Format, "black .", source);
+        }
+    }
+
+    fn make(&mut self, makefile: &str) {
+        let targets: Vec<&str> = makefile
+            .lines()
+            .filter_map(|line| line.split_once(':').map(|(target, _)| target))
+            .filter(|target| !target.starts_with(['.', '\t', ' ', '#']) && !target.contains(['=', '$', '%']))
+            .collect();
+        for (kind, target) in [
+            (CommandKind::Build, "build"),
+            (CommandKind::Test, "test"),
+            (CommandKind::Lint, "lint"),
+            (CommandKind::Format, "fmt"),
+            (CommandKind::Format, "format"),
+        ] {
+            if targets.contains(&target) {
+                self.add(kind, &format!("make {}", target), "Makefile");
+            }
+        }
+    }
+}
+
+/// Keep the first detected project type
+fn set_kind(kind: &mut String, detected: &str) {
+    if kind.is_empty() {
+        *kind = detected.to_string();
+    }
+}
+
+fn count_languages(files: &[PathBuf]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    for language in files.iter().filter_map(|f| language_name(f)) {
+        *counts.entry(language).or_default() += 1;
+    }
+    let mut languages: Vec<(String, usize)> = counts.into_iter().map(|(l, n)| (l.to_string(), n)).collect();
+    languages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    languages
+}
+
+fn find_ci(root: &Path) -> Vec<String> {
+    let mut ci = BTreeSet::new();
+    if let Ok(entries) = std::fs::read_dir(root.join(".github/workflows")) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".yml") || name.ends_with(".yaml") {
+                ci.insert(format!(".github/workflows/{}", name));
+            }
+        }
+    }
+    for file in CI_FILES {
+        if root.join(file).exists() {
+            ci.insert(file.to_string());
+        }
+    }
+    ci.into_iter().collect()
+}
+
+fn read_toml(path: &Path) -> Option<toml::Value> {
+    let content = std::fs::read_to_string(path).ok()?;
+    match toml::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            tracing::warn!("Failed to parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+    let content = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            tracing::warn!("Failed to parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn string_list(value: Option<&toml::Value>) -> Vec<String> {
+    value
+        .and_then(|v| v.as_array())
+        .map(|items| items.iter().filter_map(|i| i.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+fn json_strings(value: Option<&serde_json::Value>) -> Vec<String> {
+    value
+        .and_then(|v| v.as_array())
+        .map(|items| items.iter().filter_map(|i| i.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cargo_workspace_with_ci() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"crates/*\"]\n").unwrap();
+        std::fs::create_dir_all(root.join(".github/workflows")).unwrap();
+        std::fs::write(root.join(".github/workflows/ci.yml"), "on: push").unwrap();
+        std::fs::create_dir_all(root.join("crates/core/src")).unwrap();
+        std::fs::write(root.join("crates/core/src/lib.rs"), "pub fn core() {}").unwrap();
+        std::fs::create_dir_all(root.join("node_modules/dep")).unwrap();
+        std::fs::write(root.join("node_modules/dep/index.js"), "").unwrap();
+
+        let files = walk_files(root, 100);
+        assert!(!files.iter().any(|f| f.starts_with("node_modules")));
+
+        let analysis = ProjectAnalysis::analyze(root, &files);
+        assert_eq!(analysis.kind, "Rust");
+        assert!(analysis.is_monorepo());
+        assert_eq!(analysis.workspaces[0].members, vec!["crates/*"]);
+        assert_eq!(analysis.command(CommandKind::Test).unwrap().command, "cargo test --workspace");
+        assert_eq!(analysis.languages, vec![("Rust".to_string(), 1)]);
+        assert_eq!(analysis.ci, vec![".github/workflows/ci.yml"]);
+    }
+
+    #[test]
+    fn test_walk_limit_keeps_first_files_by_name() {
+        let dir = tempdir().unwrap();
+        for name in ["zeta.rs", "alpha.rs", "src/beta.rs", "src/aaa/gamma.rs", "mid.rs"] {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let files = walk_files(dir.path(), 3);
+        assert_eq!(files, vec![PathBuf::from("alpha.rs"), PathBuf::from("mid.rs"), PathBuf::from("src/aaa/gamma.rs")]);
+    }
+
+    #[test]
+    fn test_pnpm_workspace_scripts() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(
+            root.join("package.json"),
+            r#"{"scripts": {"build": "tsc", "test": "vitest", "lint": "eslint ."}}"#,
+        )
+        .unwrap();
+        std::fs::write(root.join("pnpm-workspace.yaml"), "packages:\n  - 'packages/*'\n").unwrap();
+        std::fs::write(root.join("Makefile"), "test:\n\tmake -C x\n").unwrap();
+
+        let analysis = ProjectAnalysis::analyze(root, &[]);
+        assert_eq!(analysis.kind, "Node.js");
+        assert_eq!(analysis.workspaces[0].tool, "pnpm");
+        assert_eq!(analysis.command(CommandKind::Test).unwrap().command, "pnpm run test");
+        assert_eq!(analysis.command(CommandKind::Lint).unwrap().source, "package.json");
+        assert!(analysis.summary().contains("Monorepo: pnpm workspace with members packages/*"));
+    }
+}
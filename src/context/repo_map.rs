@@ -0,0 +1,243 @@
+//! Compact map of a repository for the system prompt
+//!
+//! Lists key files and the top-level definitions of source files with a
+//! bundled grammar (see [`crate::index::outline`]), trimmed to a token
+//! budget. Symbols are cached per file and only re-read when a file's size
+//! or modification time changes.
+
+use super::project::walk_files;
+use crate::index::outline::{Lang, OutlineItem, ParsedFile};
+use crate::tokenizer::Tokenizer;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default token budget for the map
+pub const DEFAULT_REPO_MAP_BUDGET: usize = 1_500;
+
+/// Most files looked at
+const MAX_FILES: usize = 10_000;
+
+/// Larger files are listed without symbols
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+
+/// Symbols shown per file
+const MAX_SYMBOLS_PER_FILE: usize = 12;
+
+/// Files that describe the project, listed first when present at the root
+const KEY_FILES: &[&str] = &[
+    "README.md",
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "build.gradle.kts",
+    "Makefile",
+];
+
+/// Usual entry points, listed before other source files
+const ENTRY_POINTS: &[&str] = &[
+    "main.rs",
+    "lib.rs",
+    "main.py",
+    "__main__.py",
+    "app.py",
+    "main.go",
+    "index.ts",
+    "index.tsx",
+    "index.js",
+    "main.ts",
+    "App.tsx",
+];
+
+#[derive(Debug, Clone)]
+struct CachedFile {
+    modified: Option<SystemTime>,
+    len: u64,
+    symbols: Vec<OutlineItem>,
+}
+
+/// Files and symbols of a repository
+#[derive(Debug, Clone)]
+pub struct RepoMap {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, CachedFile>,
+}
+
+impl RepoMap {
+    /// Map of the repository at `root`, built on the first [`refresh`](Self::refresh)
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Files in the map, relative to the root
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Top-level definitions of `path` (relative to the root)
+    pub fn symbols(&self, path: &Path) -> &[OutlineItem] {
+        self.files.get(path).map(|f| f.symbols.as_slice()).unwrap_or_default()
+    }
+
+    /// Pick up added, changed and removed files; returns whether anything changed
+    pub fn refresh(&mut self) -> bool {
+        let mut changed = false;
+        let mut files = BTreeMap::new();
+
+        for path in walk_files(&self.root, MAX_FILES) {
+            let Ok(metadata) = std::fs::metadata(self.root.join(&path)) else {
+                continue;
+            };
+            let modified = metadata.modified().ok();
+            let len = metadata.len();
+
+            let cached = self
+                .files
+                .remove(&path)
+                .filter(|cached| cached.modified == modified && cached.len == len);
+            let file = match cached {
+                Some(cached) => cached,
+                None => {
+                    changed = true;
+                    CachedFile {
+                        modified,
+                        len,
+                        symbols: self.read_symbols(&path, len),
+                    }
+                }
+            };
+            files.insert(path, file);
+        }
+
+        // Anything left over was removed
+        changed |= !self.files.is_empty();
+        self.files = files;
+        changed
+    }
+
+    /// The map as text, stopping before `budget` tokens
+    pub fn render(&self, tokenizer: &dyn Tokenizer, budget: usize) -> String {
+        let mut entries: Vec<(u8, &PathBuf, &CachedFile)> = self
+            .files
+            .iter()
+            .filter_map(|(path, file)| {
+                let name = path.file_name()?.to_str()?;
+                let rank = if path.parent() == Some(Path::new("")) && KEY_FILES.contains(&name) {
+                    0
+                } else if ENTRY_POINTS.contains(&name) && !file.symbols.is_empty() {
+                    1
+                } else if !file.symbols.is_empty() {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, path, file))
+            })
+            .collect();
+        entries.sort_by_key(|(rank, path, _)| (*rank, path.components().count(), (*path).clone()));
+
+        let mut lines = Vec::new();
+        let mut used = 0;
+        for (i, (_, path, file)) in entries.iter().enumerate() {
+            let mut line = path.display().to_string();
+            if !file.symbols.is_empty() {
+                let symbols: Vec<String> = file.symbols.iter().take(MAX_SYMBOLS_PER_FILE).map(|s| format!("{} {}", s.kind, s.name)).collect();
+                line.push_str(": ");
+                line.push_str(&symbols.join(", "));
+                if file.symbols.len() > MAX_SYMBOLS_PER_FILE {
+                    line.push_str(", …");
+                }
+            }
+
+            let tokens = tokenizer.count(&line) + 1;
+            if used + tokens > budget {
+                lines.push(format!("… and {} more files", entries.len() - i));
+                break;
+            }
+            used += tokens;
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    /// Top-level definitions other than impl blocks, whose methods are not listed anyway
+    fn read_symbols(&self, path: &Path, len: u64) -> Vec<OutlineItem> {
+        let Some(lang) = Lang::for_path(path) else {
+            return Vec::new();
+        };
+        if len > MAX_FILE_BYTES {
+            return Vec::new();
+        }
+        let Ok(content) = std::fs::read_to_string(self.root.join(path)) else {
+            return Vec::new();
+        };
+        match ParsedFile::parse(lang, content) {
+            Ok(parsed) => parsed
+                .outline()
+                .into_iter()
+                .filter(|item| item.depth == 0 && item.kind != "impl")
+                .collect(),
+            Err(e) => {
+                tracing::warn!("No repo map symbols for {}: {}", path.display(), e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::HeuristicTokenizer;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render_within_budget() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/deep")).unwrap();
+        std::fs::write(root.join("Cargo.toml"), "[package]").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/deep/util.rs"), "pub struct Helper;\npub fn help() {}").unwrap();
+        std::fs::write(root.join("notes.txt"), "not code").unwrap();
+
+        let mut map = RepoMap::new(root);
+        assert!(map.refresh());
+        assert!(!map.refresh());
+
+        let full = map.render(&HeuristicTokenizer, 1_000);
+        assert_eq!(full, "Cargo.toml\nsrc/main.rs: fn main\nsrc/deep/util.rs: struct Helper, fn help");
+
+        let trimmed = map.render(&HeuristicTokenizer, 10);
+        assert!(trimmed.starts_with("Cargo.toml\nsrc/main.rs: fn main\n… and 1 more files"));
+    }
+
+    #[test]
+    fn test_refresh_picks_up_changes() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("app.py"), "def one():\n    pass\n").unwrap();
+
+        let mut map = RepoMap::new(dir.path());
+        map.refresh();
+        assert_eq!(map.symbols(Path::new("app.py")).len(), 1);
+
+        std::fs::write(dir.path().join("app.py"), "def one():\n    pass\n\nclass Two:\n    pass\n").unwrap();
+        assert!(map.refresh());
+        let symbols = map.symbols(Path::new("app.py"));
+        assert_eq!((symbols[1].kind.as_str(), symbols[1].name.as_str()), ("class", "Two"));
+
+        std::fs::remove_file(dir.path().join("app.py")).unwrap();
+        assert!(map.refresh());
+        assert!(map.files().is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 //! Error types for PromptLine
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Main error type for PromptLine
@@ -49,7 +50,20 @@ pub enum ModelError {
     Auth(String),
 
     #[error("Rate limit exceeded")]
-    RateLimit,
+    RateLimit {
+        /// Delay requested by the server's `Retry-After` header
+        retry_after: Option<std::time::Duration>,
+    },
+
+    #[error("Server error ({status}): {message}")]
+    Server {
+        status: u16,
+        message: String,
+        retry_after: Option<std::time::Duration>,
+    },
+
+    #[error("Request timed out after {0:?}")]
+    Timeout(std::time::Duration),
 
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
@@ -57,10 +71,47 @@ pub enum ModelError {
     #[error("Model not available: {0}")]
     NotAvailable(String),
 
+    #[error("Response blocked by the provider's safety filters: {0}")]
+    ContentFiltered(String),
+
     #[error("Request failed: {0}")]
     Request(#[from] reqwest::Error),
 }
 
+/// How a model error should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// Transient failure; the request may succeed if retried
+    Retryable,
+    /// Credentials are missing or rejected
+    Auth,
+    /// Retrying will not help
+    Fatal,
+}
+
+impl ModelError {
+    /// Classify the error for retry handling
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::RateLimit { .. } | Self::Server { .. } | Self::Timeout(_) => ErrorClass::Retryable,
+            Self::Request(e) if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() => {
+                ErrorClass::Retryable
+            }
+            Self::Auth(_) => ErrorClass::Auth,
+            _ => ErrorClass::Fatal,
+        }
+    }
+
+    /// Delay requested by the server before retrying
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            Self::RateLimit { retry_after } | Self::Server { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
 /// Tool execution errors
 #[derive(Debug, Error)]
 pub enum ToolError {
@@ -94,6 +145,12 @@ pub enum ConfigError {
 
     #[error("Missing required field: {0}")]
     MissingField(String),
+
+    #[error("Unknown provider '{name}'. Known providers: {known}")]
+    UnknownProvider { name: String, known: String },
+
+    #[error("No API key for provider '{provider}'. Set the {env_var} environment variable or models.providers.{provider}.api_key in the config file")]
+    MissingApiKey { provider: String, env_var: String },
 }
 
 /// Agent execution errors
@@ -102,6 +159,9 @@ pub enum AgentError {
     #[error("Maximum iterations exceeded")]
     MaxIterationsExceeded,
 
+    #[error("Cost budget exceeded: ${spent:.4} spent, limit is ${limit:.4}")]
+    BudgetExceeded { spent: f64, limit: f64 },
+
     #[error("User cancelled operation")]
     UserCancelled,
 
@@ -0,0 +1,193 @@
+//! Headless execution for scripts and CI
+//!
+//! Provides the pieces behind `promptline exec`: a tool policy that never
+//! prompts, an observer that writes events as JSON lines, and the final
+//! report with an exit status callers can branch on.
+
+use crate::agent::{AgentEvent, AgentObserver, AgentResult};
+use crate::error::{AgentError, PromptLineError, Result, ToolError};
+use crate::permissions::{PermissionLevel, PermissionManager};
+use crate::tools::ToolRegistry;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// How a headless run ended, mapped to the process exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStatus {
+    Success,
+    Failure,
+    MaxIterations,
+    PermissionDenied,
+}
+
+impl ExitStatus {
+    /// Classify the outcome of `Agent::run`
+    pub fn of(outcome: &Result<AgentResult>) -> Self {
+        match outcome {
+            Ok(result) if result.success => Self::Success,
+            Ok(_) => Self::Failure,
+            Err(PromptLineError::Agent(AgentError::MaxIterationsExceeded)) => Self::MaxIterations,
+            Err(PromptLineError::Tool(ToolError::PermissionDenied(_))) => Self::PermissionDenied,
+            Err(_) => Self::Failure,
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Success => 0,
+            Self::Failure => 1,
+            Self::MaxIterations => 2,
+            Self::PermissionDenied => 3,
+        }
+    }
+}
+
+/// Final record of a headless run
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename = "result")]
+pub struct ExecReport {
+    pub status: ExitStatus,
+    pub exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<AgentResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ExecReport {
+    pub fn new(outcome: Result<AgentResult>) -> Self {
+        let status = ExitStatus::of(&outcome);
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            status,
+            exit_code: status.code(),
+            result,
+            error,
+        }
+    }
+}
+
+/// Which tools a headless run may use
+///
+/// Read-only tools are allowed unless denied; other tools only when allowed
+/// explicitly or with `allow_all`. Denials win over allowances.
+#[derive(Debug, Clone, Default)]
+pub struct ToolPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub allow_all: bool,
+}
+
+impl ToolPolicy {
+    /// Whether the tool may run
+    pub fn allows(&self, tool: &str, read_only: bool) -> bool {
+        if self.deny.iter().any(|t| t == tool) {
+            return false;
+        }
+        self.allow_all || read_only || self.allow.iter().any(|t| t == tool)
+    }
+
+    /// In-memory permission manager applying the policy to the registered tools
+    ///
+    /// Unknown tools are refused instead of asked about.
+    pub fn permission_manager(&self, tools: &ToolRegistry) -> Arc<Mutex<PermissionManager>> {
+        let mut manager = PermissionManager::in_memory().with_default_level(PermissionLevel::Never);
+        for name in tools.list() {
+            let read_only = tools.get(name).is_some_and(|t| t.is_read_only());
+            if self.allows(name, read_only) {
+                // Session-level, so the stored permissions file is never touched
+                let _ = manager.set_permission(name.to_string(), PermissionLevel::Once);
+            }
+        }
+        Arc::new(Mutex::new(manager))
+    }
+}
+
+/// Writes each event as one line of JSON
+pub struct JsonLinesObserver<W: Write + Send> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesObserver<W> {
+    pub fn new(out: W) -> Self {
+        Self { out: Mutex::new(out) }
+    }
+
+    /// Write any serializable value as a line, e.g. the final [`ExecReport`]
+    pub fn write_line(&self, value: &impl Serialize) {
+        let mut out = self.out.lock().unwrap();
+        let written = serde_json::to_writer(&mut *out, value)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(out))
+            .and_then(|_| out.flush());
+        if let Err(e) = written {
+            tracing::warn!("Failed to write event: {}", e);
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> AgentObserver for JsonLinesObserver<W> {
+    fn on_event(&self, event: &AgentEvent) {
+        self.write_line(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_status() {
+        let denied: Result<AgentResult> = Err(ToolError::PermissionDenied("shell_execute".to_string()).into());
+        let exhausted: Result<AgentResult> = Err(AgentError::MaxIterationsExceeded.into());
+
+        assert_eq!(ExitStatus::of(&denied).code(), 3);
+        assert_eq!(ExitStatus::of(&exhausted).code(), 2);
+
+        let report = serde_json::to_value(ExecReport::new(denied)).unwrap();
+        assert_eq!(report["type"], "result");
+        assert_eq!(report["status"], "permission_denied");
+        assert!(report.get("result").is_none());
+    }
+
+    #[test]
+    fn test_tool_policy() {
+        let mut tools = ToolRegistry::new();
+        tools.register(crate::tools::file_ops::FileReadTool::new());
+        tools.register(crate::tools::file_ops::FileWriteTool::new());
+        tools.register(crate::tools::shell::ShellTool::new());
+
+        let policy = ToolPolicy {
+            allow: vec!["file_write".to_string()],
+            deny: vec!["file_read".to_string()],
+            allow_all: false,
+        };
+        let manager = policy.permission_manager(&tools);
+        let manager = manager.lock().unwrap();
+
+        assert_eq!(manager.check_permission("file_write"), PermissionLevel::Once);
+        assert_eq!(manager.check_permission("file_read"), PermissionLevel::Never);
+        assert_eq!(manager.check_permission("shell_execute"), PermissionLevel::Never);
+    }
+
+    #[test]
+    fn test_json_lines_observer() {
+        let observer = JsonLinesObserver::new(Vec::new());
+        observer.on_event(&AgentEvent::ThinkingStarted { iteration: 1 });
+        observer.on_event(&AgentEvent::TokenDelta { text: "hi".to_string() });
+
+        let out = String::from_utf8(observer.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = out.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines[0], serde_json::json!({"type": "thinking_started", "iteration": 1}));
+        assert_eq!(lines[1]["type"], "token_delta");
+    }
+}
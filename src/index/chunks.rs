@@ -0,0 +1,264 @@
+//! Splitting files into retrieval chunks and chunks into search terms
+//!
+//! Source files with a bundled grammar are split at definition boundaries,
+//! descending into large classes and impl blocks; Markdown at headings; other
+//! source files into fixed windows.
+
+use super::outline::{language_name, Lang, OutlineItem, ParsedFile};
+use std::path::Path;
+
+/// Definitions longer than this are split into their members or into windows
+pub const MAX_CHUNK_LINES: usize = 80;
+
+/// Chunk size for files without definition boundaries
+const WINDOW_LINES: usize = 40;
+
+/// Words too common in questions and code to help ranking
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "do", "does", "for", "fn", "from", "how", "if", "in", "is",
+    "it", "let", "mut", "of", "on", "or", "pub", "return", "self", "that", "the", "this", "to", "use", "var",
+    "what", "where", "which", "who", "with",
+];
+
+/// A line range of a file, labelled with the definition it covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSpan {
+    /// 1-based, inclusive line range
+    pub start_line: usize,
+    pub end_line: usize,
+    /// E.g. `fn authenticate` or `method open (in Shop)`
+    pub symbol: Option<String>,
+}
+
+/// Whether `path` is worth indexing for retrieval
+pub fn is_indexable(path: &Path) -> bool {
+    language_name(path).is_some() || is_markdown(path)
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "md" | "markdown"))
+}
+
+/// Chunks of a file covering all of its non-blank lines
+pub fn split(path: &Path, source: &str) -> Vec<ChunkSpan> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut spans = Vec::new();
+    if let Some(lang) = Lang::for_path(path) {
+        match ParsedFile::parse(lang, source.to_string()) {
+            Ok(parsed) => {
+                let items = parsed.outline();
+                cover(&lines, &items, 1, lines.len(), None, &mut spans);
+                return spans;
+            }
+            Err(e) => tracing::warn!("Chunking {} by lines: {}", path.display(), e),
+        }
+    }
+    if is_markdown(path) {
+        sections(&lines, &mut spans);
+    } else {
+        windows(&lines, 1, lines.len(), None, &mut spans);
+    }
+    spans
+}
+
+/// Cover lines `start..=end` with chunks for `items`, the definitions inside that range
+fn cover(
+    lines: &[&str],
+    items: &[OutlineItem],
+    start: usize,
+    end: usize,
+    label: Option<&str>,
+    spans: &mut Vec<ChunkSpan>,
+) {
+    let mut cursor = start;
+    let mut i = 0;
+    while i < items.len() {
+        let item = &items[i];
+        // Items nested in this one follow it directly, with a greater depth
+        let nested = items[i + 1..]
+            .iter()
+            .take_while(|other| other.depth > item.depth)
+            .count();
+
+        if item.start_line > cursor {
+            windows(lines, cursor, item.start_line - 1, label, spans);
+        }
+        let symbol = describe(item);
+        if item.end_line - item.start_line < MAX_CHUNK_LINES {
+            push(lines, item.start_line, item.end_line, Some(&symbol), spans);
+        } else if nested > 0 {
+            cover(lines, &items[i + 1..=i + nested], item.start_line, item.end_line, Some(&symbol), spans);
+        } else {
+            windows(lines, item.start_line, item.end_line, Some(&symbol), spans);
+        }
+        cursor = cursor.max(item.end_line + 1);
+        i += nested + 1;
+    }
+    if cursor <= end {
+        windows(lines, cursor, end, label, spans);
+    }
+}
+
+/// Split Markdown at headings
+fn sections(lines: &[&str], spans: &mut Vec<ChunkSpan>) {
+    let mut start = 1;
+    let mut heading: Option<String> = None;
+    let mut in_fence = false;
+    for (i, line) in lines.iter().enumerate() {
+        if line.starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence || !line.starts_with('#') {
+            continue;
+        }
+        if i + 1 > start {
+            windows(lines, start, i, heading.as_deref(), spans);
+            start = i + 1;
+        }
+        heading = Some(line.trim_start_matches('#').trim().to_string());
+    }
+    windows(lines, start, lines.len(), heading.as_deref(), spans);
+}
+
+/// Cover lines `start..=end` with fixed-size chunks
+fn windows(lines: &[&str], start: usize, end: usize, label: Option<&str>, spans: &mut Vec<ChunkSpan>) {
+    let mut from = start;
+    while from <= end {
+        let to = (from + WINDOW_LINES - 1).min(end);
+        push(lines, from, to, label, spans);
+        from = to + 1;
+    }
+}
+
+/// Add a chunk, trimmed of blank lines, unless it has no real content
+fn push(lines: &[&str], start: usize, end: usize, label: Option<&str>, spans: &mut Vec<ChunkSpan>) {
+    let has_content = |n: &usize| lines.get(n - 1).is_some_and(|line| !line.trim().is_empty());
+    let Some(start) = (start..=end).find(has_content) else {
+        return;
+    };
+    let end = (start..=end).rev().find(has_content).unwrap_or(start);
+    // Leftovers such as a closing brace carry no meaning of their own
+    let content: usize = lines[start - 1..end]
+        .iter()
+        .map(|line| line.chars().filter(|c| c.is_alphanumeric()).count())
+        .sum();
+    if content < 3 {
+        return;
+    }
+    spans.push(ChunkSpan {
+        start_line: start,
+        end_line: end,
+        symbol: label.map(str::to_string),
+    });
+}
+
+fn describe(item: &OutlineItem) -> String {
+    match &item.container {
+        Some(container) => format!("{} {} (in {})", item.kind, item.name, container),
+        None => format!("{} {}", item.kind, item.name),
+    }
+}
+
+/// Search terms of `text`: lowercase, stemmed identifier parts, plus whole compound identifiers
+pub fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let parts = identifier_parts(word);
+        for part in &parts {
+            push_term(part, &mut terms);
+        }
+        if parts.len() > 1 {
+            push_term(&word.trim_matches('_').to_lowercase(), &mut terms);
+        }
+    }
+    terms
+}
+
+fn push_term(term: &str, terms: &mut Vec<String>) {
+    if term.len() < 2 || term.chars().all(|c| c.is_ascii_digit()) || STOP_WORDS.contains(&term) {
+        return;
+    }
+    terms.push(stem(term).to_string());
+}
+
+/// Lowercase parts of a `snake_case`, `camelCase` or `HTTPServer` identifier
+fn identifier_parts(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    for piece in word.split('_').filter(|p| !p.is_empty()) {
+        let chars: Vec<char> = piece.chars().collect();
+        let mut current = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            let boundary = i > 0
+                && c.is_uppercase()
+                && (chars[i - 1].is_lowercase()
+                    || chars[i - 1].is_ascii_digit()
+                    || chars.get(i + 1).is_some_and(|next| next.is_lowercase()));
+            if boundary && !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.extend(c.to_lowercase());
+        }
+        if !current.is_empty() {
+            parts.push(current);
+        }
+    }
+    parts
+}
+
+/// Strip common English suffixes so `handle`, `handled` and `handling` match
+pub fn stem(term: &str) -> &str {
+    for suffix in ["ing", "ed", "es", "s", "e"] {
+        if term.len() > suffix.len() + 2 && term.ends_with(suffix) && !term.ends_with("ss") {
+            return &term[..term.len() - suffix.len()];
+        }
+    }
+    term
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(terms("fn handle_login(req: HttpRequest)"), vec!["handl", "login", "handle_login", "req", "http", "request", "httprequest"]);
+        assert_eq!(terms("Where is auth handled?"), vec!["auth", "handl"]);
+        assert_eq!(identifier_parts("parseHTTPResponse2"), vec!["parse", "http", "response2"]);
+        assert_eq!(stem("class"), "class");
+    }
+
+    #[test]
+    fn test_split_at_definitions() {
+        let mut source = String::from("use std::io;\n\npub struct Shop;\n\nimpl Shop {\n");
+        for i in 0..MAX_CHUNK_LINES {
+            source.push_str(&format!("    fn item{}() {{}}\n", i));
+        }
+        source.push_str("}\n");
+
+        let spans = split(Path::new("shop.rs"), &source);
+        assert_eq!(spans[0], ChunkSpan { start_line: 1, end_line: 1, symbol: None });
+        assert_eq!(spans[1].symbol.as_deref(), Some("struct Shop"));
+        // The impl block is too long, so its methods become chunks of their own
+        assert_eq!(spans[2].symbol.as_deref(), Some("impl Shop"));
+        assert_eq!(spans[3].symbol.as_deref(), Some("method item0 (in Shop)"));
+        assert_eq!(spans.len(), 3 + MAX_CHUNK_LINES);
+    }
+
+    #[test]
+    fn test_split_markdown_and_plain_files() {
+        let spans = split(Path::new("README.md"), "# Title\nIntro text\n\n## Setup\n```sh\n# not a heading\n```\n");
+        assert_eq!(
+            spans,
+            vec![
+                ChunkSpan { start_line: 1, end_line: 2, symbol: Some("Title".to_string()) },
+                ChunkSpan { start_line: 4, end_line: 7, symbol: Some("Setup".to_string()) },
+            ]
+        );
+
+        let script = "echo one\n".repeat(WINDOW_LINES + 1);
+        assert_eq!(split(Path::new("run.sh"), &script).len(), 2);
+        assert!(!is_indexable(Path::new("data.bin")));
+    }
+}
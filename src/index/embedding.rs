@@ -0,0 +1,133 @@
+//! Embedding backends for code retrieval
+//!
+//! Embeddings are blended into the lexical ranking when a backend is
+//! configured. The bundled [`HashingEmbedder`] needs no model or network: it
+//! hashes search terms and their character trigrams into a fixed-size vector,
+//! which catches partial matches such as `auth` and `authenticate`.
+
+use super::chunks::terms;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Turns text into vectors whose cosine similarity reflects relatedness
+pub trait Embedder: Send + Sync {
+    /// Identifies the backend and model; stored vectors are recomputed when it changes
+    fn id(&self) -> String;
+
+    /// One vector per text, all of the same length
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>>;
+}
+
+/// Embedding backend selected in the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbedderKind {
+    /// Rank by BM25 alone
+    None,
+    /// Offline feature hashing
+    #[default]
+    Hashing,
+}
+
+impl EmbedderKind {
+    pub fn build(&self) -> Option<Arc<dyn Embedder>> {
+        match self {
+            Self::None => None,
+            Self::Hashing => Some(Arc::new(HashingEmbedder::default())),
+        }
+    }
+}
+
+/// Offline embedder hashing terms and character trigrams into buckets
+#[derive(Debug, Clone)]
+pub struct HashingEmbedder {
+    dimensions: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        Self {
+            dimensions: dimensions.max(1),
+        }
+    }
+
+    fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimensions];
+        let mut add = |feature: &str, weight: f32| {
+            let hash = fnv1a(feature.as_bytes());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dimensions as u64) as usize] += sign * weight;
+        };
+        for term in terms(text) {
+            add(&term, 1.0);
+            let padded: Vec<char> = format!("^{}$", term).chars().collect();
+            for trigram in padded.windows(3) {
+                add(&trigram.iter().collect::<String>(), 0.5);
+            }
+        }
+        normalize(&mut vector);
+        vector
+    }
+}
+
+impl Default for HashingEmbedder {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn id(&self) -> String {
+        format!("hashing-{}", self.dimensions)
+    }
+
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_one(text)).collect())
+    }
+}
+
+/// Cosine similarity, 0 for vectors of different lengths
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|y| y * y).sum::<f32>().sqrt();
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// FNV-1a, stable across runs and platforms unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashing_embedder_similarity() {
+        let embedder = HashingEmbedder::default();
+        let vectors = embedder
+            .embed(&["where is auth handled", "fn authenticate(token: &str)", "fn render_table(rows: &[Row])"])
+            .unwrap();
+        assert_eq!(vectors[0].len(), 256);
+        assert!(cosine(&vectors[0], &vectors[1]) > cosine(&vectors[0], &vectors[2]));
+        assert_eq!(embedder.embed(&["same"]).unwrap(), embedder.embed(&["same"]).unwrap());
+        assert_eq!(EmbedderKind::None.build().map(|e| e.id()), None);
+    }
+}
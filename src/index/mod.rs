@@ -0,0 +1,73 @@
+//! Code indexes for navigating the project
+//!
+//! Indexes are stored under `.promptline/index` in the project root and
+//! updated incrementally: only files whose size or modification time changed
+//! are parsed again.
+
+pub mod chunks;
+pub mod embedding;
+pub mod outline;
+pub mod retrieval;
+pub mod symbols;
+
+pub use embedding::{Embedder, EmbedderKind, HashingEmbedder};
+pub use outline::{language_name, Lang, OutlineItem};
+pub use retrieval::{CodeIndex, Hit, IndexStatus};
+pub use symbols::{Definition, Reference, SymbolIndex};
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+/// Directory holding the indexes of the project at `root`
+pub fn index_dir(root: &Path) -> PathBuf {
+    root.join(".promptline").join("index")
+}
+
+/// Size and modification time of a file, to tell when it needs indexing again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileStamp {
+    /// Modification time in nanoseconds since the epoch
+    pub modified: u64,
+    pub len: u64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Some(Self {
+            modified,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Write index file `name` of the project at `root`
+pub(crate) fn write_index_file(root: &Path, name: &str, content: &str) -> Result<()> {
+    let dir = index_dir(root);
+    std::fs::create_dir_all(&dir)?;
+    // Keep the indexes out of version control
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        std::fs::write(&gitignore, "*\n")?;
+    }
+    // Write then rename, so a concurrent reader never sees half a file
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let tmp = dir.join(format!(
+        "{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, dir.join(name))?;
+    Ok(())
+}
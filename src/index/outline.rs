@@ -0,0 +1,360 @@
+//! Source outlines with tree-sitter
+//!
+//! Parses files with the bundled grammars and pulls out definitions with
+//! their line ranges, and identifier occurrences for reference lookups.
+
+use crate::error::{PromptLineError, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tree_sitter::{Node, Parser, Tree};
+
+/// Languages with a bundled grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Tsx,
+    Go,
+    Java,
+}
+
+impl Lang {
+    /// Language of a file, from its extension
+    pub fn for_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        Some(match ext.as_str() {
+            "rs" => Self::Rust,
+            "py" | "pyi" => Self::Python,
+            "js" | "jsx" | "mjs" | "cjs" => Self::JavaScript,
+            "ts" | "mts" | "cts" => Self::TypeScript,
+            "tsx" => Self::Tsx,
+            "go" => Self::Go,
+            "java" => Self::Java,
+            _ => return None,
+        })
+    }
+
+    /// Human-readable name, as used in project summaries
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rust => "Rust",
+            Self::Python => "Python",
+            Self::JavaScript => "JavaScript",
+            Self::TypeScript | Self::Tsx => "TypeScript",
+            Self::Go => "Go",
+            Self::Java => "Java",
+        }
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+            Self::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Self::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Self::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Self::Go => tree_sitter_go::LANGUAGE.into(),
+            Self::Java => tree_sitter_java::LANGUAGE.into(),
+        }
+    }
+
+    /// Outline kind of a definition node, if it is one
+    fn definition_kind(&self, node: &Node) -> Option<&'static str> {
+        let kind = match (self, node.kind()) {
+            (Self::Rust, "function_item" | "function_signature_item") => "fn",
+            (Self::Rust, "struct_item") => "struct",
+            (Self::Rust, "enum_item") => "enum",
+            (Self::Rust, "union_item") => "union",
+            (Self::Rust, "trait_item") => "trait",
+            (Self::Rust, "impl_item") => "impl",
+            (Self::Rust, "mod_item") => "mod",
+            (Self::Rust, "type_item") => "type",
+            (Self::Rust, "const_item") => "const",
+            (Self::Rust, "static_item") => "static",
+            (Self::Rust, "macro_definition") => "macro",
+            (Self::Python, "function_definition") => "def",
+            (Self::Python, "class_definition") => "class",
+            (Self::JavaScript | Self::TypeScript | Self::Tsx, kind) => match kind {
+                "function_declaration" | "generator_function_declaration" => "function",
+                "class_declaration" | "abstract_class_declaration" => "class",
+                "method_definition" | "method_signature" | "abstract_method_signature" => "method",
+                "interface_declaration" => "interface",
+                "type_alias_declaration" => "type",
+                "enum_declaration" => "enum",
+                "variable_declarator" if is_function_value(node) => "function",
+                _ => return None,
+            },
+            (Self::Go, "function_declaration") => "func",
+            (Self::Go, "method_declaration") => "method",
+            (Self::Go, "type_spec") => "type",
+            (Self::Java, kind) => match kind {
+                "class_declaration" => "class",
+                "interface_declaration" => "interface",
+                "enum_declaration" => "enum",
+                "record_declaration" => "record",
+                "method_declaration" => "method",
+                "constructor_declaration" => "constructor",
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// Programming language of a file, from its extension
+///
+/// Covers the languages of [`Lang`] and common ones without a bundled grammar.
+pub fn language_name(path: &Path) -> Option<&'static str> {
+    if let Some(lang) = Lang::for_path(path) {
+        return Some(lang.name());
+    }
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "kt" | "kts" => "Kotlin",
+        "rb" => "Ruby",
+        "c" | "h" => "C",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "C++",
+        "cs" => "C#",
+        "php" => "PHP",
+        "swift" => "Swift",
+        "scala" => "Scala",
+        "sh" | "bash" | "zsh" => "Shell",
+        "ps1" => "PowerShell",
+        _ => return None,
+    })
+}
+
+/// A definition in a source file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutlineItem {
+    /// Keyword-like kind, e.g. `fn`, `class`, `method`
+    pub kind: String,
+    pub name: String,
+    /// Enclosing definition, e.g. the type of an `impl` block or class
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// 1-based, inclusive line range
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Nesting level, 0 for top-level items
+    pub depth: usize,
+}
+
+impl std::fmt::Display for OutlineItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} {}  L{}-{}",
+            "  ".repeat(self.depth),
+            self.kind,
+            self.name,
+            self.start_line,
+            self.end_line
+        )
+    }
+}
+
+/// An identifier occurrence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub name: String,
+    /// 1-based line and column
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A parsed source file
+pub struct ParsedFile {
+    lang: Lang,
+    source: String,
+    tree: Tree,
+}
+
+impl ParsedFile {
+    pub fn parse(lang: Lang, source: String) -> Result<Self> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&lang.grammar())
+            .map_err(|e| PromptLineError::Other(format!("Failed to load {:?} grammar: {}", lang, e)))?;
+        let tree = parser
+            .parse(&source, None)
+            .ok_or_else(|| PromptLineError::Other(format!("Failed to parse {:?} source", lang)))?;
+        Ok(Self { lang, source, tree })
+    }
+
+    /// Definitions in document order
+    pub fn outline(&self) -> Vec<OutlineItem> {
+        let mut items = Vec::new();
+        self.collect_definitions(self.tree.root_node(), None, 0, &mut items);
+        items
+    }
+
+    /// Every identifier in the file, in document order
+    pub fn occurrences(&self) -> Vec<Occurrence> {
+        let mut occurrences = Vec::new();
+        let mut stack = vec![self.tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.child_count() == 0 && node.kind().ends_with("identifier") {
+                let position = node.start_position();
+                occurrences.push(Occurrence {
+                    name: self.text(&node).to_string(),
+                    line: position.row + 1,
+                    column: position.column + 1,
+                });
+            }
+            let mut cursor = node.walk();
+            let children: Vec<Node> = node.children(&mut cursor).collect();
+            stack.extend(children.into_iter().rev());
+        }
+        occurrences
+    }
+
+    /// Line `line` (1-based) of the source
+    pub fn line(&self, line: usize) -> &str {
+        self.source.lines().nth(line.saturating_sub(1)).unwrap_or_default()
+    }
+
+    fn text(&self, node: &Node) -> &str {
+        node.utf8_text(self.source.as_bytes()).unwrap_or_default()
+    }
+
+    /// Walk `node` for definitions; `container` is the kind and name of the enclosing one
+    fn collect_definitions(
+        &self,
+        node: Node,
+        container: Option<(&str, &str)>,
+        depth: usize,
+        items: &mut Vec<OutlineItem>,
+    ) {
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            match self.lang.definition_kind(&child) {
+                Some(kind) => {
+                    let name = self.definition_name(&child);
+                    let kind = match (kind, container) {
+                        ("fn" | "def", Some(("impl" | "trait" | "class", _))) => "method",
+                        _ => kind,
+                    };
+                    items.push(OutlineItem {
+                        kind: kind.to_string(),
+                        name: name.clone(),
+                        container: container
+                            .map(|(_, name)| name.to_string())
+                            .or_else(|| self.receiver(&child)),
+                        start_line: child.start_position().row + 1,
+                        end_line: child.end_position().row + 1,
+                        depth,
+                    });
+                    // Items in an impl block belong to the implemented type
+                    let inner = match kind {
+                        "impl" => self.impl_type(&child),
+                        _ => name,
+                    };
+                    self.collect_definitions(child, Some((kind, &inner)), depth + 1, items);
+                }
+                None => self.collect_definitions(child, container, depth, items),
+            }
+        }
+    }
+
+    fn definition_name(&self, node: &Node) -> String {
+        match node.kind() {
+            "impl_item" => {
+                let ty = self.impl_type(node);
+                match node.child_by_field_name("trait") {
+                    Some(trait_) => format!("{} for {}", self.text(&trait_), ty),
+                    None => ty,
+                }
+            }
+            _ => node
+                .child_by_field_name("name")
+                .map(|n| self.text(&n).to_string())
+                .unwrap_or_else(|| "<anonymous>".to_string()),
+        }
+    }
+
+    /// Receiver type of a Go method such as `func (s *Server) Start()`
+    fn receiver(&self, node: &Node) -> Option<String> {
+        if self.lang != Lang::Go || node.kind() != "method_declaration" {
+            return None;
+        }
+        let receiver = node.child_by_field_name("receiver")?;
+        let mut cursor = receiver.walk();
+        let parameter = receiver.named_children(&mut cursor).next()?;
+        let ty = parameter.child_by_field_name("type")?;
+        Some(self.text(&ty).trim_start_matches('*').to_string())
+    }
+
+    fn impl_type(&self, node: &Node) -> String {
+        node.child_by_field_name("type")
+            .map(|n| self.text(&n).to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Whether a JS/TS variable is initialised with a function
+fn is_function_value(node: &Node) -> bool {
+    node.child_by_field_name("value")
+        .is_some_and(|v| matches!(v.kind(), "arrow_function" | "function_expression" | "function"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outline(lang: Lang, source: &str) -> Vec<String> {
+        ParsedFile::parse(lang, source.to_string())
+            .unwrap()
+            .outline()
+            .iter()
+            .map(|item| item.to_string().trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_outline() {
+        let source = "pub struct Agent {\n    name: String,\n}\n\nimpl Agent {\n    pub fn new() -> Self {\n        todo!()\n    }\n}\n\nimpl Default for Agent {\n    fn default() -> Self { Self::new() }\n}\n";
+        let file = ParsedFile::parse(Lang::Rust, source.to_string()).unwrap();
+        let items = file.outline();
+
+        assert_eq!(
+            outline(Lang::Rust, source),
+            vec![
+                "struct Agent  L1-3",
+                "impl Agent  L5-9",
+                "method new  L6-8",
+                "impl Default for Agent  L11-13",
+                "method default  L12-12"
+            ]
+        );
+        assert_eq!(items[2].container.as_deref(), Some("Agent"));
+        assert!(file.occurrences().iter().filter(|o| o.name == "Agent").count() >= 3);
+    }
+
+    #[test]
+    fn test_other_languages() {
+        assert_eq!(
+            outline(Lang::Python, "class Shop:\n    def buy(self):\n        pass\n\ndef main():\n    pass\n"),
+            vec!["class Shop  L1-3", "method buy  L2-3", "def main  L5-6"]
+        );
+        assert_eq!(
+            outline(Lang::TypeScript, "export interface Props {}\nexport const App = () => 1;\nclass Store { load() {} }\n"),
+            vec!["interface Props  L1-1", "function App  L2-2", "class Store  L3-3", "method load  L3-3"]
+        );
+        assert_eq!(
+            outline(Lang::Go, "package main\ntype Server struct{}\nfunc (s *Server) Start() {}\nfunc main() {}\n"),
+            vec!["type Server  L2-2", "method Start  L3-3", "func main  L4-4"]
+        );
+        assert_eq!(
+            outline(Lang::Java, "class Shop {\n  Shop() {}\n  void buy() {}\n}\n"),
+            vec!["class Shop  L1-4", "constructor Shop  L2-2", "method buy  L3-3"]
+        );
+        assert_eq!(Lang::for_path(Path::new("ui/App.tsx")), Some(Lang::Tsx));
+        assert_eq!(language_name(Path::new("src/App.TSX")), Some("TypeScript"));
+        assert_eq!(language_name(Path::new("build.sh")), Some("Shell"));
+        assert_eq!(language_name(Path::new("README.md")), None);
+    }
+}
@@ -0,0 +1,477 @@
+//! Ranked retrieval of code chunks
+//!
+//! Chunks are ranked with BM25 over identifier-aware terms, where longer query
+//! terms also match as prefixes. With an [`Embedder`] the cosine similarity of
+//! chunk and query vectors is blended in. The index lives in
+//! `.promptline/index/chunks.json`.
+
+use super::chunks::{is_indexable, split, terms};
+use super::embedding::{cosine, Embedder};
+use super::FileStamp;
+use crate::context::project::walk_files;
+use crate::error::{PromptLineError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const INDEX_FILE: &str = "chunks.json";
+
+/// Bumped when the stored format, chunking or term extraction changes
+const INDEX_VERSION: u32 = 2;
+
+/// Most files indexed
+const MAX_FILES: usize = 20_000;
+
+/// Larger files are skipped; they are usually generated
+const MAX_FILE_BYTES: u64 = 512 * 1024;
+
+/// BM25 term frequency saturation and length normalisation
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Query terms at least this long also match longer terms starting with them
+const MIN_PREFIX_LEN: usize = 3;
+
+/// Score of a prefix match relative to an exact one
+const PREFIX_WEIGHT: f32 = 0.5;
+
+/// Chunks without lexical matches need at least this similarity to be returned
+const MIN_SIMILARITY: f32 = 0.25;
+
+/// Default share of the embedding similarity in the blended score
+pub const DEFAULT_EMBEDDING_WEIGHT: f32 = 0.3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedChunk {
+    start_line: usize,
+    end_line: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+    /// Term frequencies, sorted by term
+    terms: Vec<(String, u32)>,
+    /// Number of terms, counting repeats
+    len: u32,
+    /// Stored as base64 of the little-endian floats, a fraction of the size of a JSON array
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "packed_floats")]
+    embedding: Vec<f32>,
+}
+
+mod packed_floats {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(vector: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+        let bytes = STANDARD.decode(String::deserialize(deserializer)?).map_err(D::Error::custom)?;
+        if bytes.len() % 4 != 0 {
+            return Err(D::Error::custom("vector length is not a multiple of 4 bytes"));
+        }
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+}
+
+impl IndexedChunk {
+    /// Terms of the chunk matching query term `term`, with their frequency and weight
+    fn matches<'a>(&'a self, term: &'a str) -> impl Iterator<Item = (&'a str, u32, f32)> + 'a {
+        let from = self.terms.partition_point(|(t, _)| t.as_str() < term);
+        self.terms[from..]
+            .iter()
+            .take_while(move |(t, _)| t.starts_with(term))
+            .filter(move |(t, _)| t == term || term.len() >= MIN_PREFIX_LEN)
+            .map(move |(t, tf)| (t.as_str(), *tf, if t == term { 1.0 } else { PREFIX_WEIGHT }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedFile {
+    #[serde(flatten)]
+    stamp: FileStamp,
+    chunks: Vec<IndexedChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredIndex {
+    version: u32,
+    /// [`Embedder::id`] of the stored vectors
+    #[serde(default)]
+    embedder: Option<String>,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+/// A retrieved chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    /// Relative to the project root
+    pub path: PathBuf,
+    /// 1-based, inclusive line range
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Definition the chunk covers
+    pub symbol: Option<String>,
+    pub score: f32,
+}
+
+impl std::fmt::Display for Hit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}-{}", self.path.display(), self.start_line, self.end_line)?;
+        if let Some(symbol) = &self.symbol {
+            write!(f, "  {}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// Size and freshness of a code index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexStatus {
+    pub files: usize,
+    pub chunks: usize,
+    /// Embedder of the stored vectors, if any
+    pub embedder: Option<String>,
+    /// Files added, changed or removed since the last update
+    pub stale: usize,
+}
+
+/// Chunks of the project's code, searchable by natural-language queries
+pub struct CodeIndex {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, IndexedFile>,
+    embedder: Option<Arc<dyn Embedder>>,
+    /// Embedder of the vectors in `files`
+    embedder_id: Option<String>,
+    embedding_weight: f32,
+    dirty: bool,
+}
+
+impl CodeIndex {
+    /// Index stored for the project at `root`, or an empty one
+    pub fn open(root: &Path) -> Self {
+        let path = super::index_dir(root).join(INDEX_FILE);
+        let stored = match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<StoredIndex>(&content) {
+                Ok(stored) if stored.version == INDEX_VERSION => Some(stored),
+                Ok(_) => None,
+                Err(e) => {
+                    tracing::warn!("Rebuilding unreadable code index {}: {}", path.display(), e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        let (files, embedder_id) = stored.map(|s| (s.files, s.embedder)).unwrap_or_default();
+        Self {
+            root: root.to_path_buf(),
+            files,
+            embedder: None,
+            embedder_id,
+            embedding_weight: DEFAULT_EMBEDDING_WEIGHT,
+            dirty: false,
+        }
+    }
+
+    /// Blend similarities from `embedder` into the ranking; stored chunks embedded differently are redone
+    pub fn with_embedder(mut self, embedder: Option<Arc<dyn Embedder>>) -> Self {
+        let id = embedder.as_ref().map(|e| e.id());
+        if id != self.embedder_id {
+            self.dirty |= !self.files.is_empty();
+            self.files.clear();
+            self.embedder_id = id;
+        }
+        self.embedder = embedder;
+        self
+    }
+
+    /// Share of the embedding similarity in the score (0.0 - 1.0)
+    pub fn with_embedding_weight(mut self, weight: f32) -> Self {
+        self.embedding_weight = weight.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Open the index with `embedder`, bring it up to date and store it
+    pub fn load(root: &Path, embedder: Option<Arc<dyn Embedder>>) -> Result<Self> {
+        let mut index = Self::open(root).with_embedder(embedder);
+        index.update()?;
+        index.save()?;
+        Ok(index)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn status(&self) -> IndexStatus {
+        let current = self.source_files();
+        let changed = current
+            .iter()
+            .filter(|(path, stamp)| self.files.get(*path).is_none_or(|file| file.stamp != **stamp))
+            .count();
+        let removed = self.files.keys().filter(|path| !current.contains_key(*path)).count();
+        IndexStatus {
+            files: self.files.len(),
+            chunks: self.files.values().map(|file| file.chunks.len()).sum(),
+            embedder: self.embedder_id.clone(),
+            stale: changed + removed,
+        }
+    }
+
+    /// Chunk new and changed files and forget removed ones; returns how many were chunked
+    pub fn update(&mut self) -> Result<usize> {
+        let mut indexed = 0;
+        let mut files = BTreeMap::new();
+
+        for (path, stamp) in self.source_files() {
+            let cached = self.files.remove(&path).filter(|file| file.stamp == stamp);
+            let file = match cached {
+                Some(file) => file,
+                None => match std::fs::read_to_string(self.root.join(&path)) {
+                    Ok(source) => {
+                        indexed += 1;
+                        IndexedFile {
+                            stamp,
+                            chunks: self.chunk(&path, &source)?,
+                        }
+                    }
+                    // Not text
+                    Err(_) => continue,
+                },
+            };
+            files.insert(path, file);
+        }
+
+        self.dirty |= indexed > 0 || !self.files.is_empty();
+        self.files = files;
+        Ok(indexed)
+    }
+
+    /// Write the index if it changed since it was opened
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let stored = StoredIndex {
+            version: INDEX_VERSION,
+            embedder: self.embedder_id.clone(),
+            files: std::mem::take(&mut self.files),
+        };
+        let written = serde_json::to_string(&stored)
+            .map_err(PromptLineError::from)
+            .and_then(|content| super::write_index_file(&self.root, INDEX_FILE, &content));
+        self.files = stored.files;
+        written?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Best `limit` chunks for `query`, optionally only under `within` (relative to the root)
+    pub fn search(&self, query: &str, limit: usize, within: Option<&Path>) -> Result<Vec<Hit>> {
+        let query_terms: BTreeSet<String> = terms(query).into_iter().collect();
+        let query_vector = match &self.embedder {
+            Some(embedder) if self.embedding_weight > 0.0 => embedder.embed(&[query])?.pop(),
+            _ => None,
+        };
+
+        let chunks: Vec<(&PathBuf, &IndexedChunk)> = self
+            .files
+            .iter()
+            .flat_map(|(path, file)| file.chunks.iter().map(move |chunk| (path, chunk)))
+            .collect();
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let average_len = chunks.iter().map(|(_, c)| c.len as f32).sum::<f32>() / chunks.len() as f32;
+
+        // Document frequencies, over all chunks so scores don't depend on `within`
+        let mut frequencies: HashMap<&str, usize> = HashMap::new();
+        for (_, chunk) in &chunks {
+            for term in &query_terms {
+                for (matched, _, _) in chunk.matches(term) {
+                    *frequencies.entry(matched).or_default() += 1;
+                }
+            }
+        }
+        let idf = |term: &str| {
+            let df = frequencies.get(term).copied().unwrap_or_default() as f32;
+            (1.0 + (chunks.len() as f32 - df + 0.5) / (df + 0.5)).ln()
+        };
+
+        let mut scored: Vec<(&PathBuf, &IndexedChunk, f32, f32)> = chunks
+            .iter()
+            .filter(|(path, _)| within.is_none_or(|within| path.starts_with(within)))
+            .map(|&(path, chunk)| {
+                let norm = K1 * (1.0 - B + B * chunk.len as f32 / average_len);
+                let bm25: f32 = query_terms
+                    .iter()
+                    .flat_map(|term| chunk.matches(term))
+                    .map(|(matched, tf, weight)| weight * idf(matched) * tf as f32 * (K1 + 1.0) / (tf as f32 + norm))
+                    .sum();
+                let similarity = query_vector
+                    .as_ref()
+                    .map(|v| cosine(v, &chunk.embedding).max(0.0))
+                    .unwrap_or_default();
+                (path, chunk, bm25, similarity)
+            })
+            .filter(|&(_, _, bm25, similarity)| bm25 > 0.0 || similarity >= MIN_SIMILARITY)
+            .collect();
+
+        let max_bm25 = scored.iter().map(|s| s.2).fold(0.0f32, f32::max);
+        let mut hits: Vec<Hit> = scored
+            .drain(..)
+            .map(|(path, chunk, bm25, similarity)| {
+                let score = match &query_vector {
+                    Some(_) => {
+                        let lexical = if max_bm25 > 0.0 { bm25 / max_bm25 } else { 0.0 };
+                        (1.0 - self.embedding_weight) * lexical + self.embedding_weight * similarity
+                    }
+                    None => bm25,
+                };
+                Hit {
+                    path: path.clone(),
+                    start_line: chunk.start_line,
+                    end_line: chunk.end_line,
+                    symbol: chunk.symbol.clone(),
+                    score,
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+                .then(a.start_line.cmp(&b.start_line))
+        });
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// Indexable files with their stamps
+    fn source_files(&self) -> BTreeMap<PathBuf, FileStamp> {
+        walk_files(&self.root, MAX_FILES)
+            .into_iter()
+            .filter(|path| is_indexable(path))
+            .filter_map(|path| {
+                let stamp = FileStamp::of(&self.root.join(&path))?;
+                (stamp.len <= MAX_FILE_BYTES).then_some((path, stamp))
+            })
+            .collect()
+    }
+
+    fn chunk(&self, path: &Path, source: &str) -> Result<Vec<IndexedChunk>> {
+        let lines: Vec<&str> = source.lines().collect();
+        let spans = split(path, source);
+        // The path and the symbol are searchable along with the code
+        let texts: Vec<String> = spans
+            .iter()
+            .map(|span| {
+                format!(
+                    "{}\n{}\n{}",
+                    path.display(),
+                    span.symbol.as_deref().unwrap_or_default(),
+                    lines[span.start_line - 1..span.end_line].join("\n")
+                )
+            })
+            .collect();
+        let embeddings = match &self.embedder {
+            Some(embedder) => embedder.embed(&texts.iter().map(String::as_str).collect::<Vec<_>>())?,
+            None => Vec::new(),
+        };
+
+        Ok(spans
+            .into_iter()
+            .zip(&texts)
+            .enumerate()
+            .map(|(i, (span, text))| {
+                let mut counts: BTreeMap<String, u32> = BTreeMap::new();
+                for term in terms(text) {
+                    *counts.entry(term).or_default() += 1;
+                }
+                IndexedChunk {
+                    start_line: span.start_line,
+                    end_line: span.end_line,
+                    symbol: span.symbol,
+                    len: counts.values().sum(),
+                    terms: counts.into_iter().collect(),
+                    embedding: embeddings.get(i).cloned().unwrap_or_default(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::HashingEmbedder;
+    use tempfile::tempdir;
+
+    fn project() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/auth")).unwrap();
+        std::fs::write(
+            root.join("src/auth/middleware.rs"),
+            "pub fn authenticate(token: &str) -> bool {\n    verify_signature(token)\n}\n\nfn verify_signature(token: &str) -> bool {\n    !token.is_empty()\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("src/table.rs"),
+            "pub fn render_table(rows: &[String]) -> String {\n    rows.join(\"\\n\")\n}\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("README.md"), "# Usage\nRun the server and render a table of results.\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_search_ranks_relevant_chunks() {
+        let dir = project();
+        let index = CodeIndex::load(dir.path(), None).unwrap();
+
+        let hits = index.search("where is auth handled?", 5, None).unwrap();
+        assert_eq!(hits[0].to_string(), "src/auth/middleware.rs:1-3  fn authenticate");
+        assert!(hits.iter().all(|hit| hit.path.starts_with("src/auth")));
+
+        let hits = index.search("render table", 5, None).unwrap();
+        assert_eq!(hits[0].path, Path::new("src/table.rs"));
+        assert_eq!(hits[1].path, Path::new("README.md"));
+        assert_eq!(index.search("render table", 5, Some(Path::new("src"))).unwrap().len(), 1);
+        assert!(index.search("nothing matches this", 5, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_embedder_and_incremental_update() {
+        let dir = project();
+        let embedder: Arc<dyn Embedder> = Arc::new(HashingEmbedder::default());
+        let index = CodeIndex::load(dir.path(), Some(embedder.clone())).unwrap();
+        let status = index.status();
+        assert_eq!((status.files, status.chunks, status.stale), (3, 4, 0));
+        assert_eq!(status.embedder.as_deref(), Some("hashing-256"));
+
+        let hits = index.search("authentication", 1, None).unwrap();
+        assert_eq!(hits[0].symbol.as_deref(), Some("fn authenticate"));
+
+        // Vectors are stored packed and read back exactly
+        let stored = std::fs::read_to_string(crate::index::index_dir(dir.path()).join(INDEX_FILE)).unwrap();
+        assert!(!stored.contains("\"embedding\":["), "vectors stored as JSON arrays");
+        let reopened = CodeIndex::open(dir.path());
+        let chunk = &reopened.files[Path::new("src/auth/middleware.rs")].chunks[0];
+        assert_eq!(chunk.embedding, index.files[Path::new("src/auth/middleware.rs")].chunks[0].embedding);
+        assert_eq!(chunk.embedding.len(), 256);
+
+        std::fs::write(dir.path().join("src/table.rs"), "pub fn draw() {}\n").unwrap();
+        let mut index = CodeIndex::open(dir.path()).with_embedder(Some(embedder));
+        assert_eq!(index.status().stale, 1);
+        assert_eq!(index.update().unwrap(), 1);
+
+        // Vectors from another embedder are not mixed with new ones
+        let index = CodeIndex::open(dir.path()).with_embedder(None);
+        assert_eq!(index.status().files, 0);
+    }
+}
@@ -0,0 +1,331 @@
+//! Symbol index for definition and reference lookups
+//!
+//! Keeps the outline and the identifiers of every supported source file in
+//! `.promptline/index/symbols.json`. References are found by narrowing down
+//! to files mentioning the identifier and parsing only those again.
+
+use super::outline::{Lang, OutlineItem, ParsedFile};
+use super::FileStamp;
+use crate::context::project::walk_files;
+use crate::error::{PromptLineError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE: &str = "symbols.json";
+
+/// Bumped when the stored format or the extracted data changes
+const INDEX_VERSION: u32 = 1;
+
+/// Most files indexed
+const MAX_FILES: usize = 20_000;
+
+/// Larger files are skipped; they are usually generated
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedFile {
+    #[serde(flatten)]
+    stamp: FileStamp,
+    items: Vec<OutlineItem>,
+    /// Distinct identifiers, sorted
+    identifiers: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredIndex {
+    version: u32,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+/// Where a symbol is defined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    /// Relative to the project root
+    pub path: PathBuf,
+    pub item: OutlineItem,
+}
+
+impl std::fmt::Display for Definition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}  {} {}",
+            self.path.display(),
+            self.item.start_line,
+            self.item.end_line,
+            self.item.kind,
+            self.item.name
+        )?;
+        if let Some(container) = &self.item.container {
+            write!(f, " (in {})", container)?;
+        }
+        Ok(())
+    }
+}
+
+/// A use of an identifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Relative to the project root
+    pub path: PathBuf,
+    /// 1-based line and column
+    pub line: usize,
+    pub column: usize,
+    /// The line, trimmed
+    pub text: String,
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path.display(), self.line, self.column, self.text)
+    }
+}
+
+/// Definitions and identifiers of the source files in a project
+#[derive(Debug)]
+pub struct SymbolIndex {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, IndexedFile>,
+    dirty: bool,
+}
+
+impl SymbolIndex {
+    /// Index stored for the project at `root`, or an empty one
+    pub fn open(root: &Path) -> Self {
+        let path = super::index_dir(root).join(INDEX_FILE);
+        let files = match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<StoredIndex>(&content) {
+                Ok(stored) if stored.version == INDEX_VERSION => stored.files,
+                Ok(_) => BTreeMap::new(),
+                Err(e) => {
+                    tracing::warn!("Rebuilding unreadable symbol index {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            root: root.to_path_buf(),
+            files,
+            dirty: false,
+        }
+    }
+
+    /// Open the index, bring it up to date and store it
+    pub fn load(root: &Path) -> Result<Self> {
+        let mut index = Self::open(root);
+        index.update();
+        index.save()?;
+        Ok(index)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Number of indexed files
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Parse new and changed files and forget removed ones; returns how many were parsed
+    pub fn update(&mut self) -> usize {
+        let mut parsed = 0;
+        let mut files = BTreeMap::new();
+
+        for path in walk_files(&self.root, MAX_FILES) {
+            if Lang::for_path(&path).is_none() {
+                continue;
+            }
+            let Some(stamp) = FileStamp::of(&self.root.join(&path)) else {
+                continue;
+            };
+            if stamp.len > MAX_FILE_BYTES {
+                continue;
+            }
+
+            let cached = self.files.remove(&path).filter(|file| file.stamp == stamp);
+            let file = match cached {
+                Some(file) => file,
+                None => match self.parse(&path) {
+                    Some((items, identifiers)) => {
+                        parsed += 1;
+                        IndexedFile {
+                            stamp,
+                            items,
+                            identifiers,
+                        }
+                    }
+                    None => continue,
+                },
+            };
+            files.insert(path, file);
+        }
+
+        self.dirty |= parsed > 0 || !self.files.is_empty();
+        self.files = files;
+        parsed
+    }
+
+    /// Write the index if it changed since it was opened
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let stored = StoredIndex {
+            version: INDEX_VERSION,
+            files: std::mem::take(&mut self.files),
+        };
+        let written = serde_json::to_string(&stored)
+            .map_err(PromptLineError::from)
+            .and_then(|content| super::write_index_file(&self.root, INDEX_FILE, &content));
+        self.files = stored.files;
+        written?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Definitions in `path` (relative to the root), or `None` if it is not indexed
+    pub fn outline(&self, path: &Path) -> Option<&[OutlineItem]> {
+        self.files.get(path).map(|file| file.items.as_slice())
+    }
+
+    /// Definitions named `query`, which may be qualified as `Type::name` or `Type.name`
+    pub fn find_definition(&self, query: &str) -> Vec<Definition> {
+        let (container, name) = split_qualified(query);
+        self.files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.items
+                    .iter()
+                    .filter(|item| item.kind != "impl" && item.name == name)
+                    .filter(|item| container.is_none() || item.container.as_deref() == container)
+                    .map(|item| Definition {
+                        path: path.clone(),
+                        item: item.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Uses of the identifier `name`, optionally only under `within` (relative to the root)
+    pub fn find_references(&self, name: &str, within: Option<&Path>) -> Vec<Reference> {
+        let (_, name) = split_qualified(name);
+        let mut references = Vec::new();
+
+        for (path, file) in &self.files {
+            if within.is_some_and(|within| !path.starts_with(within)) {
+                continue;
+            }
+            if file.identifiers.binary_search_by(|id| id.as_str().cmp(name)).is_err() {
+                continue;
+            }
+            let Some(parsed) = self.read(path) else {
+                continue;
+            };
+            references.extend(parsed.occurrences().into_iter().filter(|o| o.name == name).map(|o| Reference {
+                path: path.clone(),
+                line: o.line,
+                column: o.column,
+                text: parsed.line(o.line).trim().to_string(),
+            }));
+        }
+        references
+    }
+
+    fn read(&self, path: &Path) -> Option<ParsedFile> {
+        let lang = Lang::for_path(path)?;
+        let source = std::fs::read_to_string(self.root.join(path)).ok()?;
+        match ParsedFile::parse(lang, source) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Outline and sorted distinct identifiers of `path`
+    fn parse(&self, path: &Path) -> Option<(Vec<OutlineItem>, Vec<String>)> {
+        let parsed = self.read(path)?;
+        let identifiers: BTreeSet<String> = parsed.occurrences().into_iter().map(|o| o.name).collect();
+        Some((parsed.outline(), identifiers.into_iter().collect()))
+    }
+}
+
+/// Split `Type::name` or `Type.name` into the container and the name
+fn split_qualified(query: &str) -> (Option<&str>, &str) {
+    let query = query.trim();
+    match query.rsplit_once("::").or_else(|| query.rsplit_once('.')) {
+        Some((container, name)) if !container.is_empty() && !name.is_empty() => {
+            // Only the innermost container is recorded
+            let container = container.rsplit("::").next().unwrap_or(container);
+            (Some(container.rsplit('.').next().unwrap_or(container)), name)
+        }
+        _ => (None, query),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn project() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("src/lib.rs"),
+            "pub struct Cart;\n\nimpl Cart {\n    pub fn total(&self) -> u32 {\n        0\n    }\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {\n    let cart = Cart;\n    cart.total();\n}\n").unwrap();
+        std::fs::write(dir.path().join("shop.py"), "def total():\n    return 0\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_definitions_and_references() {
+        let dir = project();
+        let index = SymbolIndex::load(dir.path()).unwrap();
+        assert_eq!(index.len(), 3);
+
+        let total = index.find_definition("total");
+        assert_eq!(total.len(), 2);
+        let method = index.find_definition("Cart::total");
+        assert_eq!(method.len(), 1);
+        assert_eq!(method[0].to_string(), "src/lib.rs:4-6  method total (in Cart)");
+
+        let references = index.find_references("Cart", None);
+        let lines: Vec<String> = references.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec!["src/lib.rs:1:12: pub struct Cart;", "src/lib.rs:3:6: impl Cart {", "src/main.rs:2:16: let cart = Cart;"]
+        );
+        assert_eq!(index.find_references("Cart", Some(Path::new("src/main.rs"))).len(), 1);
+    }
+
+    #[test]
+    fn test_incremental_update_is_stored() {
+        let dir = project();
+        SymbolIndex::load(dir.path()).unwrap();
+        assert!(dir.path().join(".promptline/index/symbols.json").exists());
+
+        let mut index = SymbolIndex::open(dir.path());
+        assert_eq!(index.update(), 0);
+
+        std::fs::write(dir.path().join("shop.py"), "def total():\n    return 0\n\nclass Shop:\n    pass\n").unwrap();
+        std::fs::remove_file(dir.path().join("src/main.rs")).unwrap();
+        assert_eq!(index.update(), 1);
+        index.save().unwrap();
+
+        let index = SymbolIndex::open(dir.path());
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.find_definition("Shop")[0].item.start_line, 4);
+    }
+}
@@ -3,18 +3,29 @@
 // Library entry point exposing public API
 
 pub mod agent;
+pub mod approval;
 pub mod commands;
 pub mod config;
 pub mod context;
 pub mod error;
+pub mod exec;
+pub mod export;
 pub mod formatter;
+pub mod index;
 pub mod loading;
+pub mod lsp;
 pub mod model;
 pub mod permissions;
 pub mod prompt;
+pub mod render;
 pub mod repl;
 pub mod safety;
+pub mod session;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+pub mod tokenizer;
 pub mod tools;
+pub mod usage;
 pub mod util;
 
 // Re-export commonly used types
@@ -85,6 +85,17 @@ impl LoadingIndicator {
         }
     }
 
+    /// Stop the loading indicator without waiting for it, clearing its line
+    pub fn cancel(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            print!("\r                                        \r");
+            std::io::stdout().flush().ok();
+        }
+    }
+
     /// Get a random loading message
     pub fn get_message() -> &'static str {
         use rand::Rng;
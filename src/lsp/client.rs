@@ -0,0 +1,391 @@
+//! A language server process and the JSON-RPC connection to it
+
+use super::protocol::{self, path_to_uri, Diagnostic};
+use crate::config::LspServerConfig;
+use crate::error::{PromptLineError, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::Instant;
+
+/// Longest wait for a response to a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// After diagnostics arrive, how long to wait for more from a second pass (e.g. `cargo check`)
+const DIAGNOSTICS_SETTLE: Duration = Duration::from_millis(300);
+
+type Pending = Arc<Mutex<HashMap<i64, oneshot::Sender<std::result::Result<Value, String>>>>>;
+
+/// Latest diagnostics published per document
+#[derive(Default)]
+struct DiagnosticStore {
+    /// URI to the generation it was last published in, and its diagnostics
+    entries: Mutex<HashMap<String, (u64, Vec<Diagnostic>)>>,
+    generation: AtomicU64,
+    published: Notify,
+}
+
+impl DiagnosticStore {
+    fn publish(&self, uri: String, diagnostics: Vec<Diagnostic>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.entries.lock().unwrap().insert(uri, (generation, diagnostics));
+        self.published.notify_waiters();
+    }
+
+    fn generation_of(&self, uri: &str) -> u64 {
+        self.entries.lock().unwrap().get(uri).map(|(g, _)| *g).unwrap_or_default()
+    }
+
+    fn get(&self, uri: &str) -> Vec<Diagnostic> {
+        self.entries.lock().unwrap().get(uri).map(|(_, d)| d.clone()).unwrap_or_default()
+    }
+}
+
+/// A document as last sent to the server
+struct OpenDocument {
+    version: i32,
+    content: String,
+    /// Diagnostics generation when the content was sent
+    marker: u64,
+}
+
+/// What [`LspClient::sync`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Synced {
+    /// Whether anything was sent; unchanged documents are not re-sent
+    pub sent: bool,
+    /// Marker for [`LspClient::wait_for_diagnostics`] from when the content was last sent
+    pub marker: u64,
+}
+
+/// Connection to a running language server
+pub struct LspClient {
+    name: String,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    child: tokio::sync::Mutex<Child>,
+    next_id: AtomicI64,
+    pending: Pending,
+    diagnostics: Arc<DiagnosticStore>,
+    documents: tokio::sync::Mutex<HashMap<PathBuf, OpenDocument>>,
+}
+
+impl LspClient {
+    /// Spawn the server for the workspace at `root` and initialize it
+    pub async fn start(name: &str, config: &LspServerConfig, root: &Path) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| PromptLineError::Other(format!("Failed to start {} ({}): {}", name, config.command, e)))?;
+
+        let stdin = Arc::new(tokio::sync::Mutex::new(child.stdin.take().expect("piped stdin")));
+        let stdout = child.stdout.take().expect("piped stdout");
+        let stderr = child.stderr.take().expect("piped stderr");
+        let pending: Pending = Arc::default();
+        let diagnostics = Arc::new(DiagnosticStore::default());
+
+        tokio::spawn(read_loop(
+            name.to_string(),
+            BufReader::new(stdout),
+            Arc::clone(&stdin),
+            Arc::clone(&pending),
+            Arc::clone(&diagnostics),
+        ));
+        let server = name.to_string();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!("{}: {}", server, line);
+            }
+        });
+
+        let client = Self {
+            name: name.to_string(),
+            stdin,
+            child: tokio::sync::Mutex::new(child),
+            next_id: AtomicI64::new(1),
+            pending,
+            diagnostics,
+            documents: tokio::sync::Mutex::new(HashMap::new()),
+        };
+        client.initialize(config, root).await?;
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn initialize(&self, config: &LspServerConfig, root: &Path) -> Result<()> {
+        let root_uri = path_to_uri(root);
+        let folder = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let params = json!({
+            "processId": std::process::id(),
+            "clientInfo": {"name": "promptline", "version": crate::VERSION},
+            "rootUri": root_uri,
+            "rootPath": root,
+            "workspaceFolders": [{"uri": root_uri, "name": folder}],
+            "initializationOptions": config.initialization_options,
+            "capabilities": {
+                "textDocument": {
+                    "synchronization": {"didSave": true, "dynamicRegistration": false},
+                    "publishDiagnostics": {"relatedInformation": false, "versionSupport": true},
+                    "hover": {"contentFormat": ["markdown", "plaintext"]},
+                    "definition": {"linkSupport": true},
+                    "rename": {"prepareSupport": false}
+                },
+                "workspace": {
+                    "workspaceEdit": {"documentChanges": true},
+                    "workspaceFolders": true,
+                    "configuration": true
+                }
+            }
+        });
+        self.request("initialize", params).await?;
+        self.notify("initialized", json!({})).await
+    }
+
+    /// Send a request and wait for its result
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let sent = self
+            .send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))
+            .await;
+        if let Err(e) = sent {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+
+        match tokio::time::timeout(REQUEST_TIMEOUT, receiver).await {
+            Ok(Ok(Ok(result))) => Ok(result),
+            Ok(Ok(Err(message))) => Err(PromptLineError::Other(format!("{} {}: {}", self.name, method, message))),
+            Ok(Err(_)) => Err(PromptLineError::Other(format!("{} exited", self.name))),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(PromptLineError::Other(format!("{} did not answer {} in time", self.name, method)))
+            }
+        }
+    }
+
+    pub async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params})).await
+    }
+
+    async fn send(&self, message: Value) -> Result<()> {
+        write_message(&self.stdin, &message)
+            .await
+            .map_err(|e| PromptLineError::Other(format!("{} is not running: {}", self.name, e)))
+    }
+
+    /// Send `content` as the text of `path`, opening it if needed, and save it.
+    /// Nothing is sent when the server already has this content.
+    pub async fn sync(&self, path: &Path, content: &str) -> Result<Synced> {
+        let uri = path_to_uri(path);
+        let before = self.diagnostics.generation_of(&uri);
+        let mut documents = self.documents.lock().await;
+        match documents.get_mut(path) {
+            Some(document) if document.content == content => {
+                return Ok(Synced {
+                    sent: false,
+                    marker: document.marker,
+                })
+            }
+            Some(document) => {
+                document.version += 1;
+                document.content = content.to_string();
+                document.marker = before;
+                self.notify(
+                    "textDocument/didChange",
+                    json!({
+                        "textDocument": {"uri": uri, "version": document.version},
+                        "contentChanges": [{"text": content}]
+                    }),
+                )
+                .await?;
+            }
+            None => {
+                self.notify(
+                    "textDocument/didOpen",
+                    json!({
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": protocol::language_id(path),
+                            "version": 1,
+                            "text": content
+                        }
+                    }),
+                )
+                .await?;
+                documents.insert(
+                    path.to_path_buf(),
+                    OpenDocument {
+                        version: 1,
+                        content: content.to_string(),
+                        marker: before,
+                    },
+                );
+            }
+        }
+        // Files are already on disk; saving triggers checks such as `cargo check`
+        self.notify("textDocument/didSave", json!({"textDocument": {"uri": uri}})).await?;
+        Ok(Synced {
+            sent: true,
+            marker: before,
+        })
+    }
+
+    /// Stop tracking `path`, e.g. after it was deleted
+    pub async fn close(&self, path: &Path) -> Result<()> {
+        if self.documents.lock().await.remove(path).is_some() {
+            self.notify("textDocument/didClose", json!({"textDocument": {"uri": path_to_uri(path)}}))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Documents sent to the server
+    pub async fn open_documents(&self) -> Vec<PathBuf> {
+        self.documents.lock().await.keys().cloned().collect()
+    }
+
+    /// Latest diagnostics of `path`
+    pub fn diagnostics(&self, path: &Path) -> Vec<Diagnostic> {
+        self.diagnostics.get(&path_to_uri(path))
+    }
+
+    /// Whether diagnostics of `path` were published after `marker`
+    pub fn published_since(&self, path: &Path, marker: u64) -> bool {
+        self.diagnostics.generation_of(&path_to_uri(path)) > marker
+    }
+
+    /// Diagnostics of every document the server reported on
+    pub fn all_diagnostics(&self) -> Vec<(PathBuf, Vec<Diagnostic>)> {
+        let entries = self.diagnostics.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter_map(|(uri, (_, diagnostics))| Some((protocol::uri_to_path(uri)?, diagnostics.clone())))
+            .collect()
+    }
+
+    /// Diagnostics of `path` published after `marker`, waiting at most `timeout`.
+    /// Returns the latest known ones if none arrive in time.
+    pub async fn wait_for_diagnostics(&self, path: &Path, marker: u64, timeout: Duration) -> Vec<Diagnostic> {
+        let uri = path_to_uri(path);
+        let deadline = Instant::now() + timeout;
+        let mut seen = marker;
+        let mut until = deadline;
+        loop {
+            let published = self.diagnostics.published.notified();
+            tokio::pin!(published);
+            published.as_mut().enable();
+
+            let generation = self.diagnostics.generation_of(&uri);
+            if generation > seen {
+                seen = generation;
+                until = (Instant::now() + DIAGNOSTICS_SETTLE).min(deadline);
+            }
+            if tokio::time::timeout_at(until, published).await.is_err() {
+                break;
+            }
+        }
+        self.diagnostics.get(&uri)
+    }
+
+    /// Ask the server to exit, killing it if it does not
+    pub async fn shutdown(&self) {
+        if self.request("shutdown", Value::Null).await.is_ok() {
+            let _ = self.notify("exit", Value::Null).await;
+        }
+        let mut child = self.child.lock().await;
+        if tokio::time::timeout(Duration::from_secs(2), child.wait()).await.is_err() {
+            let _ = child.kill().await;
+        }
+    }
+}
+
+async fn write_message(stdin: &tokio::sync::Mutex<ChildStdin>, message: &Value) -> std::io::Result<()> {
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(&protocol::frame(message)).await?;
+    stdin.flush().await
+}
+
+/// Route responses to their requests, store diagnostics and answer server requests
+async fn read_loop(
+    name: String,
+    mut stdout: BufReader<tokio::process::ChildStdout>,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    diagnostics: Arc<DiagnosticStore>,
+) {
+    loop {
+        let message = match protocol::read_message(&mut stdout).await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("{}: unreadable message: {}", name, e);
+                break;
+            }
+        };
+        let method = message.get("method").and_then(Value::as_str);
+        let id = message.get("id");
+
+        match (method, id) {
+            // Response to one of our requests
+            (None, Some(id)) => {
+                let Some(sender) = id.as_i64().and_then(|id| pending.lock().unwrap().remove(&id)) else {
+                    continue;
+                };
+                let outcome = match message.get("error") {
+                    Some(error) => Err(error
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .unwrap_or("request failed")
+                        .to_string()),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                let _ = sender.send(outcome);
+            }
+            // Request from the server; settings are left at their defaults
+            (Some(method), Some(id)) => {
+                let result = match method {
+                    "workspace/configuration" => {
+                        let items = message["params"]["items"].as_array().map_or(0, Vec::len);
+                        Value::Array(vec![Value::Null; items])
+                    }
+                    _ => Value::Null,
+                };
+                let reply = json!({"jsonrpc": "2.0", "id": id, "result": result});
+                if write_message(&stdin, &reply).await.is_err() {
+                    break;
+                }
+            }
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let params = &message["params"];
+                let Some(uri) = params["uri"].as_str() else {
+                    continue;
+                };
+                let published = serde_json::from_value(params["diagnostics"].clone()).unwrap_or_default();
+                diagnostics.publish(uri.to_string(), published);
+            }
+            (Some(method), None) => tracing::trace!("{}: {}", name, method),
+            (None, None) => {}
+        }
+    }
+
+    // Nothing will answer the requests still waiting
+    pending.lock().unwrap().clear();
+    tracing::debug!("{} exited", name);
+}
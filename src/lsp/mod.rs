@@ -0,0 +1,261 @@
+//! Language server integration
+//!
+//! [`LspManager`] starts the configured servers over stdio the first time a
+//! file of theirs is used, keeps them in sync with the files on disk and asks
+//! them for diagnostics, hover text, definitions and renames. Servers that
+//! are not installed are skipped.
+
+pub mod client;
+pub mod protocol;
+
+pub use client::{LspClient, Synced};
+pub use protocol::{Diagnostic, Location, Position, Severity};
+
+use crate::config::LspConfig;
+use crate::error::Result;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A file changed by a rename, not yet written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEdit {
+    pub path: PathBuf,
+    pub original: String,
+    pub modified: String,
+}
+
+/// Language servers of a project
+pub struct LspManager {
+    root: PathBuf,
+    config: LspConfig,
+    /// Servers started so far by name; `None` for ones that failed to start
+    clients: tokio::sync::Mutex<HashMap<String, Option<Arc<LspClient>>>>,
+}
+
+impl std::fmt::Debug for LspManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LspManager")
+            .field("root", &self.root)
+            .field("servers", &self.config.servers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl LspManager {
+    /// Manager for the project at `root`; servers start on first use
+    pub fn new(root: &Path, config: LspConfig) -> Self {
+        Self {
+            root: absolute(root),
+            config,
+            clients: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether `path` is inside the project root
+    pub fn contains(&self, path: &Path) -> bool {
+        absolute(path).starts_with(&self.root)
+    }
+
+    /// `path` relative to the project root when inside it
+    pub fn display_path(&self, path: &Path) -> String {
+        absolute(path)
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    /// Name of the server configured for `path`
+    pub fn server_for(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?;
+        self.config
+            .servers
+            .iter()
+            .find(|(_, server)| server.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Running server for `path`, started if needed
+    pub async fn client(&self, path: &Path) -> Option<Arc<LspClient>> {
+        let name = self.server_for(path)?;
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(name) {
+            return client.clone();
+        }
+        let client = match LspClient::start(name, &self.config.servers[name], &self.root).await {
+            Ok(client) => Some(Arc::new(client)),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                None
+            }
+        };
+        clients.insert(name.to_string(), client.clone());
+        client
+    }
+
+    /// Send the contents of `path` on disk to its server; returns the server and what was sent
+    async fn sync_file(&self, path: &Path) -> Result<Option<(Arc<LspClient>, Synced)>> {
+        let path = absolute(path);
+        let Some(client) = self.client(&path).await else {
+            return Ok(None);
+        };
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => {
+                let synced = client.sync(&path, &content).await?;
+                Ok(Some((client, synced)))
+            }
+            Err(_) => {
+                client.close(&path).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Diagnostics of `path` after its latest change on disk, or `None` without a server for it
+    pub async fn diagnostics(&self, path: &Path) -> Result<Option<Vec<Diagnostic>>> {
+        let Some((client, synced)) = self.sync_file(path).await? else {
+            return Ok(None);
+        };
+        let path = absolute(path);
+        // Unchanged since diagnostics were last published, so they are current
+        if !synced.sent && client.published_since(&path, synced.marker) {
+            return Ok(Some(client.diagnostics(&path)));
+        }
+        let timeout = Duration::from_millis(self.config.diagnostics_timeout_ms);
+        Ok(Some(client.wait_for_diagnostics(&path, synced.marker, timeout).await))
+    }
+
+    /// Re-send open documents changed on disk by other means, such as shell commands
+    pub async fn refresh(&self) -> Result<()> {
+        for client in self.running().await {
+            for path in client.open_documents().await {
+                match tokio::fs::read_to_string(&path).await {
+                    Ok(content) => {
+                        client.sync(&path, &content).await?;
+                    }
+                    Err(_) => client.close(&path).await?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Diagnostics reported so far for any file, worst first
+    pub async fn all_diagnostics(&self) -> Vec<(PathBuf, Vec<Diagnostic>)> {
+        let mut all: Vec<(PathBuf, Vec<Diagnostic>)> = Vec::new();
+        for client in self.running().await {
+            all.extend(client.all_diagnostics().into_iter().filter(|(_, d)| !d.is_empty()));
+        }
+        all.sort_by_key(|(path, diagnostics)| {
+            (diagnostics.iter().map(Diagnostic::severity).min(), path.clone())
+        });
+        all
+    }
+
+    /// Hover text at `position` in `path`
+    pub async fn hover(&self, path: &Path, position: Position) -> Result<Option<String>> {
+        let Some((client, _)) = self.sync_file(path).await? else {
+            return Ok(None);
+        };
+        let result = client
+            .request("textDocument/hover", text_document_position(path, position))
+            .await?;
+        Ok(protocol::hover_text(&result))
+    }
+
+    /// Where the symbol at `position` in `path` is defined
+    pub async fn definition(&self, path: &Path, position: Position) -> Result<Vec<Location>> {
+        let Some((client, _)) = self.sync_file(path).await? else {
+            return Ok(Vec::new());
+        };
+        let result = client
+            .request("textDocument/definition", text_document_position(path, position))
+            .await?;
+        Ok(protocol::locations(&result))
+    }
+
+    /// Edits renaming the symbol at `position` in `path` to `new_name`; nothing is written
+    pub async fn rename(&self, path: &Path, position: Position, new_name: &str) -> Result<Vec<FileEdit>> {
+        let Some((client, _)) = self.sync_file(path).await? else {
+            return Ok(Vec::new());
+        };
+        let mut params = text_document_position(path, position);
+        params["newName"] = json!(new_name);
+        let result = client.request("textDocument/rename", params).await?;
+
+        let mut edits = Vec::new();
+        for (path, text_edits) in protocol::workspace_edits(&result)? {
+            let original = tokio::fs::read_to_string(&path).await?;
+            let modified = protocol::apply_edits(&original, &text_edits);
+            if modified != original {
+                edits.push(FileEdit {
+                    path,
+                    original,
+                    modified,
+                });
+            }
+        }
+        Ok(edits)
+    }
+
+    /// Stop every running server
+    pub async fn shutdown(&self) {
+        for client in self.running().await {
+            client.shutdown().await;
+        }
+        self.clients.lock().await.clear();
+    }
+
+    async fn running(&self) -> Vec<Arc<LspClient>> {
+        self.clients.lock().await.values().flatten().cloned().collect()
+    }
+}
+
+fn text_document_position(path: &Path, position: Position) -> serde_json::Value {
+    json!({
+        "textDocument": {"uri": protocol::path_to_uri(&absolute(path))},
+        "position": position
+    })
+}
+
+/// Canonical form of `path`, so documents have one URI however they were named
+fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LspServerConfig;
+
+    #[tokio::test]
+    async fn test_missing_server_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}\n").unwrap();
+
+        let config = LspConfig {
+            servers: [(
+                "rust".to_string(),
+                LspServerConfig::new("promptline-no-such-server", &[], &["rs"]),
+            )]
+            .into(),
+            ..LspConfig::default()
+        };
+        let manager = LspManager::new(dir.path(), config);
+
+        assert_eq!(manager.server_for(&file), Some("rust"));
+        assert_eq!(manager.server_for(Path::new("notes.txt")), None);
+        assert!(manager.diagnostics(&file).await.unwrap().is_none());
+        assert_eq!(manager.display_path(&file), "main.rs");
+        assert!(manager.contains(&file));
+        assert!(!manager.contains(dir.path().parent().unwrap()));
+    }
+}
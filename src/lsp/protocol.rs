@@ -0,0 +1,368 @@
+//! The parts of the Language Server Protocol the client uses
+//!
+//! Positions follow the protocol: 0-based lines and UTF-16 code unit offsets.
+//! Tools take 1-based lines and character columns and convert with
+//! [`Position::from_line_column`].
+
+use crate::error::{PromptLineError, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+impl Position {
+    /// Position of 1-based `line` and character `column` in `content`
+    pub fn from_line_column(content: &str, line: usize, column: usize) -> Self {
+        let text = content.split('\n').nth(line.saturating_sub(1)).unwrap_or_default();
+        let character = text
+            .chars()
+            .take(column.saturating_sub(1))
+            .map(char::len_utf16)
+            .sum::<usize>();
+        Self {
+            line: line.saturating_sub(1) as u32,
+            character: character as u32,
+        }
+    }
+
+    /// 1-based line and character column of this position in `content`
+    pub fn to_line_column(&self, content: &str) -> (usize, usize) {
+        let text = content.split('\n').nth(self.line as usize).unwrap_or_default();
+        let mut units = 0;
+        let column = text
+            .chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= self.character as usize
+            })
+            .count();
+        (self.line as usize + 1, column + 1)
+    }
+
+    /// Byte offset of this position in `content`, clamped to its end
+    fn offset(&self, content: &str) -> usize {
+        let mut line_start = 0;
+        for _ in 0..self.line {
+            match content[line_start..].find('\n') {
+                Some(i) => line_start += i + 1,
+                None => return content.len(),
+            }
+        }
+        let line_end = content[line_start..].find('\n').map_or(content.len(), |i| line_start + i);
+        let mut units = 0;
+        for (i, c) in content[line_start..line_end].char_indices() {
+            if units >= self.character as usize {
+                return line_start + i;
+            }
+            units += c.len_utf16();
+        }
+        line_end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub uri: String,
+    pub range: Range,
+}
+
+impl Location {
+    /// Accepts both `Location` and `LocationLink` objects
+    fn from_value(value: &Value) -> Option<Self> {
+        if let Ok(location) = serde_json::from_value::<Location>(value.clone()) {
+            return Some(location);
+        }
+        Some(Self {
+            uri: value.get("targetUri")?.as_str()?.to_string(),
+            range: serde_json::from_value(
+                value
+                    .get("targetSelectionRange")
+                    .or_else(|| value.get("targetRange"))?
+                    .clone(),
+            )
+            .ok()?,
+        })
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        uri_to_path(&self.uri)
+    }
+}
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Information => "info",
+            Self::Hint => "hint",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub range: Range,
+    /// 1 error, 2 warning, 3 information, 4 hint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Servers leave the severity out to mean an error
+    pub fn severity(&self) -> Severity {
+        match self.severity {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            _ => Severity::Error,
+        }
+    }
+
+    /// `path:line:col: severity[code]: message (source)`, with 1-based line and column
+    pub fn render(&self, path: &str, content: &str) -> String {
+        let (line, column) = self.range.start.to_line_column(content);
+        let code = match &self.code {
+            Some(Value::String(code)) => format!("[{}]", code),
+            Some(Value::Number(code)) => format!("[{}]", code),
+            _ => String::new(),
+        };
+        let source = self.source.as_deref().map(|s| format!(" ({})", s)).unwrap_or_default();
+        let message = self.message.lines().next().unwrap_or_default();
+        format!("{}:{}:{}: {}{}: {}{}", path, line, column, self.severity(), code, message, source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+/// Apply `edits` to `content`; edits must not overlap
+pub fn apply_edits(content: &str, edits: &[TextEdit]) -> String {
+    let mut edits: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| (edit.range.start.offset(content), edit.range.end.offset(content), edit.new_text.as_str()))
+        .collect();
+    edits.sort_by_key(|(start, end, _)| (*start, *end));
+
+    let mut result = String::with_capacity(content.len());
+    let mut cursor = 0;
+    for (start, end, text) in edits {
+        let start = start.max(cursor);
+        result.push_str(&content[cursor..start]);
+        result.push_str(text);
+        cursor = end.max(start);
+    }
+    result.push_str(&content[cursor..]);
+    result
+}
+
+/// Text edits per file of a `WorkspaceEdit`, from either `changes` or `documentChanges`
+pub fn workspace_edits(edit: &Value) -> Result<Vec<(PathBuf, Vec<TextEdit>)>> {
+    let mut files = Vec::new();
+    if let Some(changes) = edit.get("documentChanges").and_then(Value::as_array) {
+        for change in changes {
+            // Creating, renaming or deleting files is left to the user
+            let (Some(document), Some(edits)) = (change.get("textDocument"), change.get("edits")) else {
+                return Err(PromptLineError::Other(format!(
+                    "Unsupported workspace change: {}",
+                    change.get("kind").and_then(Value::as_str).unwrap_or("unknown")
+                )));
+            };
+            let uri = document.get("uri").and_then(Value::as_str).unwrap_or_default();
+            files.push((uri_path(uri)?, serde_json::from_value(edits.clone())?));
+        }
+    } else if let Some(changes) = edit.get("changes").and_then(Value::as_object) {
+        for (uri, edits) in changes {
+            files.push((uri_path(uri)?, serde_json::from_value(edits.clone())?));
+        }
+    }
+    Ok(files)
+}
+
+/// Locations of a definition response, which may be one location, a list or links
+pub fn locations(result: &Value) -> Vec<Location> {
+    match result {
+        Value::Array(items) => items.iter().filter_map(Location::from_value).collect(),
+        Value::Null => Vec::new(),
+        single => Location::from_value(single).into_iter().collect(),
+    }
+}
+
+/// Text of a hover response, whose contents may be markup, a marked string or a list of them
+pub fn hover_text(result: &Value) -> Option<String> {
+    fn text(contents: &Value) -> String {
+        match contents {
+            Value::String(s) => s.clone(),
+            Value::Array(items) => items.iter().map(text).filter(|t| !t.is_empty()).collect::<Vec<_>>().join("\n\n"),
+            Value::Object(object) => {
+                let value = object.get("value").and_then(Value::as_str).unwrap_or_default();
+                match object.get("language").and_then(Value::as_str) {
+                    Some(language) => format!("```{}\n{}\n```", language, value),
+                    None => value.to_string(),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+    let text = text(result.get("contents")?);
+    (!text.trim().is_empty()).then(|| text.trim().to_string())
+}
+
+/// `file://` URI of `path`, which should be absolute
+pub fn path_to_uri(path: &Path) -> String {
+    Url::from_file_path(path)
+        .map(String::from)
+        .unwrap_or_else(|_| format!("file://{}", path.display()))
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+fn uri_path(uri: &str) -> Result<PathBuf> {
+    uri_to_path(uri).ok_or_else(|| PromptLineError::Other(format!("Not a file URI: {}", uri)))
+}
+
+/// Language identifier for `textDocument/didOpen`
+pub fn language_id(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or_default() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "typescriptreact",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "javascriptreact",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        _ => "plaintext",
+    }
+}
+
+/// `message` with the `Content-Length` header the protocol frames messages with
+pub fn frame(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    let mut framed = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    framed.extend_from_slice(body.as_bytes());
+    framed
+}
+
+/// Next framed message, or `None` at the end of the stream
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range {
+            start: Position { line: start.0, character: start.1 },
+            end: Position { line: end.0, character: end.1 },
+        }
+    }
+
+    #[test]
+    fn test_positions_count_utf16_units() {
+        let content = "let ä = 1;\nlet 😀x = 2;\n";
+        let position = Position::from_line_column(content, 2, 6);
+        assert_eq!(position, Position { line: 1, character: 6 });
+        assert_eq!(position.to_line_column(content), (2, 6));
+        assert_eq!(position.offset(content), "let ä = 1;\nlet 😀".len());
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let content = "fn old() {}\nfn main() { old(); }\n";
+        let edits = vec![
+            TextEdit { range: range((1, 12), (1, 15)), new_text: "new".to_string() },
+            TextEdit { range: range((0, 3), (0, 6)), new_text: "new".to_string() },
+        ];
+        assert_eq!(apply_edits(content, &edits), "fn new() {}\nfn main() { new(); }\n");
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let uri = path_to_uri(Path::new("/tmp/a b.rs"));
+        assert_eq!(uri, "file:///tmp/a%20b.rs");
+        assert_eq!(uri_to_path(&uri), Some(PathBuf::from("/tmp/a b.rs")));
+
+        let link = json!([{"targetUri": uri, "targetRange": range((0, 0), (3, 1)), "targetSelectionRange": range((0, 3), (0, 6))}]);
+        assert_eq!(locations(&link)[0].range.start.character, 3);
+        assert!(locations(&Value::Null).is_empty());
+
+        let hover = json!({"contents": [{"language": "rust", "value": "fn old()"}, "Docs"]});
+        assert_eq!(hover_text(&hover).unwrap(), "```rust\nfn old()\n```\n\nDocs");
+
+        let edit = json!({"documentChanges": [{"textDocument": {"uri": uri, "version": 1}, "edits": [{"range": range((0, 0), (0, 1)), "newText": "x"}]}]});
+        let files = workspace_edits(&edit).unwrap();
+        assert_eq!(files[0].0, PathBuf::from("/tmp/a b.rs"));
+        assert!(workspace_edits(&json!({"documentChanges": [{"kind": "create", "uri": uri}]})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_framing_round_trip() {
+        let message = json!({"jsonrpc": "2.0", "id": 1, "result": null});
+        let mut framed = frame(&message);
+        framed.extend(frame(&json!({"jsonrpc": "2.0", "method": "exit"})));
+        let mut reader = tokio::io::BufReader::new(framed.as_slice());
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(message));
+        assert!(read_message(&mut reader).await.unwrap().is_some());
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+    }
+}
@@ -1,8 +1,12 @@
 mod cli;
 
-use cli::{Cli, Commands};
+use cli::{CacheAction, Cli, Commands, IndexAction, OutputFormat, SessionAction};
 use promptline::prelude::*;
-use promptline::{model::openai::OpenAIProvider, tools::*};
+use promptline::{
+    model::cache::ResponseCache,
+    model::factory::{ProviderFactory, ProviderOverrides},
+    tools::*,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -12,6 +16,7 @@ async fn main() -> anyhow::Result<()> {
     // Initialize logging - only show warnings and errors by default
     // Set RUST_LOG=info to see debug logs
     tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
                 .add_directive(tracing::Level::WARN.into()),
@@ -33,39 +38,94 @@ async fn main() -> anyhow::Result<()> {
         Config::load()?
     };
 
+    if let Some(dir) = &config.context.tokenizer_dir {
+        promptline::tokenizer::set_vocab_dir(dir);
+    }
+
     // Apply CLI overrides
+    let auto_approve = cli.auto_approve;
     if cli.auto_approve {
         config.safety.require_approval = false;
         tracing::warn!("Auto-approve enabled - all actions will execute without confirmation!");
     }
 
+    if cli.cache {
+        config.cache.enabled = true;
+    }
+    if cli.no_cache {
+        config.cache.enabled = false;
+    }
+
+    let overrides = ProviderOverrides {
+        provider: cli.provider.clone(),
+        model: cli.model.clone(),
+        thinking_budget: cli.thinking,
+    };
+
+    // Start a fresh recording; reloads within the session append to it
+    if let Some(path) = &cli.record {
+        std::fs::File::create(path)?;
+    }
+    let options = RunOptions {
+        record: cli.record.clone(),
+        replay: cli.replay.clone(),
+    };
+    let resume = match (cli.resume, cli.continue_session) {
+        (Some(id), _) => Some(ResumeFrom::Id(id)),
+        (None, true) => Some(ResumeFrom::Latest),
+        (None, false) => None,
+    };
+
     // Handle subcommands
     match cli.command {
         Some(Commands::Init) => {
             handle_init()?;
         }
         Some(Commands::Doctor) => {
-            handle_doctor(&config)?;
+            handle_doctor(&config, &overrides)?;
+        }
+        Some(Commands::Cache { action }) => {
+            handle_cache(action, &config)?;
+        }
+        Some(Commands::Sessions { action }) => {
+            handle_sessions(action)?;
+        }
+        Some(Commands::Index { action }) => {
+            handle_index(action, &config)?;
         }
         Some(Commands::Plan { task }) => {
             handle_plan(&task, config).await?;
         }
         Some(Commands::Agent { task }) => {
-            handle_agent(&task, config).await?;
+            handle_agent(&task, config, &overrides, &options).await?;
+        }
+        Some(Commands::Exec {
+            task,
+            output_format,
+            allow_tools,
+            deny_tools,
+        }) => {
+            let policy = promptline::exec::ToolPolicy {
+                allow: allow_tools,
+                deny: deny_tools,
+                allow_all: auto_approve,
+            };
+            let code = handle_exec(task, output_format, policy, config, &overrides, &options).await?;
+            std::process::exit(code);
         }
         Some(Commands::Chat) => {
-            handle_chat(config).await?;
+            handle_chat(config, overrides.clone(), &options, resume).await?;
         }
         Some(Commands::Edit { file, instruction }) => {
             handle_edit(&file, &instruction, config).await?;
         }
         None => {
             // Direct task execution or start chat mode
-            if let Some(task) = cli.task {
-                handle_agent(&task, config).await?;
+            if let (Some(task), None) = (cli.task, &resume) {
+                handle_agent(&task, config, &overrides, &options).await?;
             } else {
                 // No command or task, start interactive chat by default
-                handle_chat(config).await?;
+                handle_chat(config, overrides.clone(), &options, resume).await?;
             }
         }
     }
@@ -113,19 +173,19 @@ fn handle_init() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn handle_doctor(config: &Config) -> anyhow::Result<()> {
+fn handle_doctor(config: &Config, overrides: &ProviderOverrides) -> anyhow::Result<()> {
     println!("🔍 PromptLine Health Check\n");
 
     println!("✓ Binary version: {}", promptline::VERSION);
 
-    // Check API key
-    match std::env::var("OPENAI_API_KEY") {
-        Ok(key) if !key.is_empty() => {
-            println!("✓ OpenAI API key configured");
+    // Check the selected provider can be built (API key present, provider known)
+    let factory = ProviderFactory::new(&config.models).with_overrides(overrides.clone());
+    match factory.build() {
+        Ok(_) => {
+            println!("✓ Provider {} configured (model {})", factory.provider_name(), factory.model_name());
         }
-        _ => {
-            println!("✗ OpenAI API key not found");
-            println!("  Set OPENAI_API_KEY environment variable");
+        Err(e) => {
+            println!("✗ {}", e);
         }
     }
 
@@ -140,6 +200,154 @@ fn handle_doctor(config: &Config) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn handle_cache(action: CacheAction, config: &Config) -> anyhow::Result<()> {
+    let cache = ResponseCache::from_config(&config.cache);
+    match action {
+        CacheAction::Clear => {
+            let removed = cache.clear()?;
+            println!("✓ Removed {} cached responses from {}", removed, cache.dir().display());
+        }
+        CacheAction::Stats => {
+            println!("Response cache: {}", cache.dir().display());
+            println!("  {}", cache.stats()?);
+            println!("  Enabled: {}", config.cache.enabled);
+            println!(
+                "  Caching: {}",
+                if config.cache.all_temperatures { "all requests" } else { "temperature 0 requests only" }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Response cache for model requests, if enabled
+fn response_cache(config: &Config) -> Option<std::sync::Arc<ResponseCache>> {
+    config
+        .cache
+        .enabled
+        .then(|| std::sync::Arc::new(ResponseCache::from_config(&config.cache)))
+}
+
+fn handle_sessions(action: SessionAction) -> anyhow::Result<()> {
+    use promptline::session::{format_timestamp, SessionStore};
+
+    let store = SessionStore::for_project(&std::env::current_dir()?)?;
+    match action {
+        SessionAction::List => print_sessions(&store)?,
+        SessionAction::Show { id } => {
+            let session = store.load(&id)?;
+            let meta = &session.meta;
+            println!("Session {}: {}", meta.id, meta.title);
+            println!("  Model:   {} ({})", meta.model, meta.provider);
+            println!("  Created: {} UTC", format_timestamp(meta.created_at));
+            println!("  Updated: {} UTC", format_timestamp(meta.updated_at));
+            if let Some(parent) = &meta.forked_from {
+                println!("  Forked from: {}", parent);
+            }
+            for message in session.messages.iter().filter(|m| m.role != promptline::model::Role::System) {
+                println!("\n\x1b[1m{}:\x1b[0m\n{}", message.role, message.transcript());
+            }
+        }
+        SessionAction::Delete { id } => {
+            let id = store.delete(&id)?;
+            println!("✓ Deleted session {}", id);
+        }
+        SessionAction::Export { id, format, output } => {
+            let session = store.load(&id)?;
+            let path = export_session(&session, format.parse()?, output)?;
+            println!("✓ Exported session {} to {}", session.meta.id, path.display());
+        }
+    }
+    Ok(())
+}
+
+fn handle_index(action: IndexAction, config: &Config) -> anyhow::Result<()> {
+    use promptline::index::{index_dir, CodeIndex, SymbolIndex};
+
+    let root = promptline::context::project_root(&std::env::current_dir()?);
+    let embedder = config.index.embedder.build();
+    match action {
+        IndexAction::Build { full } => {
+            if full {
+                let dir = index_dir(&root);
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+            }
+            let started = std::time::Instant::now();
+            let mut index = CodeIndex::open(&root).with_embedder(embedder);
+            let indexed = index.update()?;
+            index.save()?;
+            let mut symbols = SymbolIndex::open(&root);
+            symbols.update();
+            symbols.save()?;
+
+            let status = index.status();
+            println!(
+                "✓ Indexed {} files ({} updated, {} chunks, {} with symbols) in {:.1}s",
+                status.files,
+                indexed,
+                status.chunks,
+                symbols.len(),
+                started.elapsed().as_secs_f32()
+            );
+        }
+        IndexAction::Status => {
+            let index = CodeIndex::open(&root);
+            let status = index.status();
+            println!("Code index: {}", index_dir(&root).display());
+            println!("  Files:    {}", status.files);
+            println!("  Chunks:   {}", status.chunks);
+            println!("  Symbols:  {} files", SymbolIndex::open(&root).len());
+            println!("  Embedder: {}", status.embedder.as_deref().unwrap_or("none"));
+            if status.files == 0 {
+                println!("  Not built yet; run `promptline index build` or let code_retrieve build it");
+            } else if status.embedder != embedder.map(|e| e.id()) {
+                println!("  The configured embedder differs; the next build re-indexes every file");
+            } else if status.stale == 0 {
+                println!("  Up to date");
+            } else {
+                println!("  {} files changed since the last build", status.stale);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `session` as a transcript, by default to `<id>.<ext>` in the current directory
+fn export_session(
+    session: &promptline::session::Session,
+    format: promptline::export::ExportFormat,
+    path: Option<std::path::PathBuf>,
+) -> anyhow::Result<std::path::PathBuf> {
+    let path = path.unwrap_or_else(|| std::path::PathBuf::from(format!("{}.{}", session.meta.id, format.extension())));
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, promptline::export::export(session, format)?)?;
+    Ok(path)
+}
+
+/// Print the sessions of the current project, most recent first
+fn print_sessions(store: &promptline::session::SessionStore) -> anyhow::Result<()> {
+    let sessions = store.list()?;
+    if sessions.is_empty() {
+        println!("No saved sessions for this project.");
+        return Ok(());
+    }
+    for meta in sessions {
+        println!(
+            "{}  {}  {:>3} msgs  {:<24}  {}",
+            meta.id,
+            promptline::session::format_timestamp(meta.updated_at),
+            meta.message_count,
+            meta.model,
+            meta.title
+        );
+    }
+    Ok(())
+}
+
 async fn handle_plan(task: &str, _config: Config) -> anyhow::Result<()> {
     println!("🤔 Planning mode (read-only)\n");
 
@@ -151,49 +359,67 @@ async fn handle_plan(task: &str, _config: Config) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn handle_agent(task: &str, config: Config) -> anyhow::Result<()> {
-    println!("⚙️  Agent mode\n");
+/// Print a notice when a model request is retried
+fn retry_notice() -> promptline::model::retry::RetryObserver {
+    std::sync::Arc::new(|event| {
+        eprintln!("\x1b[33m↻ {}\x1b[0m", event);
+    })
+}
 
-    // Determine provider from environment or config
-    let provider = std::env::var("PROMPTLINE_PROVIDER")
-        .unwrap_or_else(|_| "openai".to_string());
-
-    // Create model provider based on type
-    let model: Box<dyn promptline::model::LanguageModel> = match provider.as_str() {
-        "ollama" => {
-            let api_key = std::env::var("OLLAMA_API_KEY").ok().or_else(|| {
-                config.models.providers.get("ollama")
-                    .and_then(|p| p.api_key.clone())
-            });
-            
-            let base_url = config.models.providers.get("ollama")
-                .and_then(|p| p.base_url.clone());
-
-            Box::new(promptline::model::ollama::OllamaProvider::new(
-                base_url,
-                api_key,
-                Some(config.models.default.clone())
-            ))
-        }
-        "openai" | _ => {
-            // Try environment variable first
-            let api_key = std::env::var("OPENAI_API_KEY").ok().or_else(|| {
-                // Fallback to config
-                config.models.providers.get("openai")
-                    .and_then(|p| p.api_key.clone())
-            });
-
-            let api_key = api_key.ok_or_else(|| {
-                anyhow::anyhow!("OPENAI_API_KEY not set. You can set it via:\n1. Environment variable: OPENAI_API_KEY\n2. Config file: ~/.promptline/config.yaml (under models.providers.openai.api_key)")
-            })?;
-
-            Box::new(OpenAIProvider::new(api_key, Some(config.models.default.clone())))
-        }
-    };
+/// Print a notice when a request falls back to another model
+fn failover_notice() -> promptline::model::fallback::FailoverObserver {
+    std::sync::Arc::new(|event| {
+        eprintln!("\x1b[33m⇄ {}\x1b[0m", event);
+    })
+}
+
+/// Chat session to pick up from `--resume` or `--continue`
+#[derive(Debug, Clone)]
+enum ResumeFrom {
+    Id(String),
+    Latest,
+}
+
+/// Per-process model options from the command line
+#[derive(Debug, Clone, Default)]
+struct RunOptions {
+    record: Option<std::path::PathBuf>,
+    replay: Option<std::path::PathBuf>,
+}
+
+/// Provider factory with the notices, cache and recording shared by every command
+fn model_factory<'a>(config: &'a Config, overrides: &ProviderOverrides, options: &RunOptions) -> ProviderFactory<'a> {
+    ProviderFactory::new(&config.models)
+        .with_overrides(overrides.clone())
+        .with_retry_observer(retry_notice())
+        .with_failover_observer(failover_notice())
+        .with_cache(response_cache(config))
+        .with_recording(options.record.clone())
+        .with_replay(options.replay.clone())
+}
+
+/// Language servers for the current project, when enabled
+fn language_servers(config: &Config) -> anyhow::Result<Option<std::sync::Arc<promptline::lsp::LspManager>>> {
+    if !config.lsp.enabled {
+        return Ok(None);
+    }
+    let root = promptline::context::project_root(&std::env::current_dir()?);
+    Ok(Some(std::sync::Arc::new(promptline::lsp::LspManager::new(&root, config.lsp.clone()))))
+}
 
-    // Create tool registry
+/// Tools backed by the language servers
+fn register_lsp_tools(tools: &mut ToolRegistry) {
+    tools.register(lsp_ops::DiagnosticsTool);
+    tools.register(lsp_ops::HoverTool);
+    tools.register(lsp_ops::GotoDefinitionTool);
+    tools.register(lsp_ops::RenameSymbolTool);
+}
+
+/// Tools available to the agent from the command line
+fn default_tools(config: &Config) -> ToolRegistry {
     let mut tools = ToolRegistry::new();
     tools.register(file_ops::FileReadTool::new());
+    tools.register(file_ops::ImageReadTool::new());
     tools.register(file_ops::FileWriteTool::new());
     tools.register(file_ops::FileListTool::new());
     tools.register(shell::ShellTool::new());
@@ -202,6 +428,94 @@ async fn handle_agent(task: &str, config: Config) -> anyhow::Result<()> {
     tools.register(git_ops::GitCommitTool::new());
     tools.register(web_ops::WebGetTool::new());
     tools.register(search_ops::CodebaseSearchTool::new());
+    tools.register(code_nav::CodeOutlineTool);
+    tools.register(code_nav::FindDefinitionTool);
+    tools.register(code_nav::FindReferencesTool);
+    tools.register(code_nav::CodeRetrieveTool::new());
+    if config.lsp.enabled {
+        register_lsp_tools(&mut tools);
+    }
+    tools
+}
+
+/// Run a task without prompts and return the process exit code
+async fn handle_exec(
+    task: Option<String>,
+    format: OutputFormat,
+    policy: promptline::exec::ToolPolicy,
+    config: Config,
+    overrides: &ProviderOverrides,
+    options: &RunOptions,
+) -> anyhow::Result<i32> {
+    use promptline::exec::{ExecReport, JsonLinesObserver};
+    use std::sync::Arc;
+
+    let task = match task.filter(|t| t != "-") {
+        Some(task) => task,
+        None => {
+            let mut input = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)?;
+            input.trim().to_string()
+        }
+    };
+    if task.is_empty() {
+        anyhow::bail!("No task given. Pass it as an argument or on stdin.");
+    }
+
+    let model = model_factory(&config, overrides, options).build()?;
+    let tools = default_tools(&config);
+    let permission_manager = policy.permission_manager(&tools);
+    let lsp = language_servers(&config)?;
+
+    // Only allowed tools get this far, so their diffs are applied without asking
+    let mut agent = Agent::new(model, tools, config, Vec::new(), permission_manager)
+        .await?
+        .with_approval_handler(Arc::new(promptline::approval::AutoApprove));
+    if let Some(lsp) = &lsp {
+        agent = agent.with_lsp(lsp.clone());
+    }
+
+    let stream = Arc::new(JsonLinesObserver::new(std::io::stdout()));
+    let mut agent = match format {
+        OutputFormat::Text => agent.with_observer(Arc::new(promptline::render::TerminalRenderer::new())),
+        OutputFormat::Json => agent,
+        OutputFormat::StreamJson => agent.with_observer(stream.clone()),
+    };
+
+    let report = ExecReport::new(agent.run(&task).await);
+    if let Some(lsp) = &lsp {
+        lsp.shutdown().await;
+    }
+    match format {
+        OutputFormat::Text => {
+            match (&report.result, &report.error) {
+                (Some(result), _) => {
+                    println!("\n{}", result.output);
+                    println!("\x1b[90m[{} iterations, usage: {}]\x1b[0m", result.iterations, result.usage);
+                }
+                (None, Some(error)) => eprintln!("\x1b[1;31mError:\x1b[0m {}", error),
+                (None, None) => {}
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::StreamJson => stream.write_line(&report),
+    }
+
+    Ok(report.exit_code)
+}
+
+async fn handle_agent(
+    task: &str,
+    config: Config,
+    overrides: &ProviderOverrides,
+    options: &RunOptions,
+) -> anyhow::Result<()> {
+    println!("⚙️  Agent mode\n");
+
+    let model = model_factory(&config, overrides, options).build()?;
+
+    let tools = default_tools(&config);
+    let lsp = language_servers(&config)?;
 
     // Create permission manager
     let permission_manager = std::sync::Arc::new(std::sync::Mutex::new(
@@ -209,11 +523,20 @@ async fn handle_agent(task: &str, config: Config) -> anyhow::Result<()> {
     ));
 
     // Create agent
-    let mut agent = Agent::new(model, tools, config, Vec::new(), permission_manager).await?;
+    let mut agent = Agent::new(model, tools, config, Vec::new(), permission_manager)
+        .await?
+        .with_observer(std::sync::Arc::new(promptline::render::TerminalRenderer::new()));
+    if let Some(lsp) = &lsp {
+        agent = agent.with_lsp(lsp.clone());
+    }
 
     // Run agent
     println!("Task: {}\n", task);
-    let result = agent.run(task).await?;
+    let result = agent.run(task).await;
+    if let Some(lsp) = &lsp {
+        lsp.shutdown().await;
+    }
+    let result = result?;
 
     // Display result
     println!("\n{}", "=".repeat(60));
@@ -224,13 +547,20 @@ async fn handle_agent(task: &str, config: Config) -> anyhow::Result<()> {
     }
     println!("Iterations: {}", result.iterations);
     println!("Tools used: {}", result.tool_calls.join(", "));
+    println!("Usage: {}", result.usage);
     println!("{}", "=".repeat(60));
     println!("\nResult:\n{}", result.output);
 
     Ok(())
 }
 
-async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
+async fn handle_chat(
+    mut config: Config,
+    overrides: ProviderOverrides,
+    options: &RunOptions,
+    resume: Option<ResumeFrom>,
+) -> anyhow::Result<()> {
+    let mut overrides = overrides;
     use std::io::{self, Write};
     
     // Clear screen and show banner
@@ -274,64 +604,51 @@ async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
         }
     }
 
-    // Outer loop for reloading agent
-    loop {
-        // Reload config if this is a reload
-        // (For the first run, we use the passed config, but subsequent runs should reload)
-        // Actually, let's just reload it every time we loop back here
-        if let Ok(new_config) = Config::load() {
-            config = new_config;
-        }
+    // Pick up a saved session or start a new one; it is saved after every turn
+    let sessions = promptline::session::SessionStore::for_project(&std::env::current_dir()?)?;
+    let mut session = match resume {
+        Some(ResumeFrom::Id(id)) => sessions.load(&id)?,
+        Some(ResumeFrom::Latest) => sessions
+            .latest()?
+            .ok_or_else(|| anyhow::anyhow!("No saved sessions for this project"))?,
+        None => sessions.create(),
+    };
+    if !session.messages.is_empty() {
+        println!(
+            "\x1b[90m↻ Resumed session {} ({} messages): {}\x1b[0m\n",
+            session.meta.id,
+            session.messages.len(),
+            session.meta.title
+        );
+    }
 
-        // Get provider from environment or use default
-        let provider = std::env::var("PROMPTLINE_PROVIDER").unwrap_or_else(|_| "ollama".to_string());
-
-        // Create model based on provider
-        let model: Box<dyn promptline::model::LanguageModel> = match provider.as_str() {
-            "ollama" => {
-                let api_key = std::env::var("OLLAMA_API_KEY").ok().or_else(|| {
-                    config.models.providers.get("ollama")
-                        .and_then(|p| p.api_key.clone())
-                });
-                
-                let base_url = config.models.providers.get("ollama")
-                    .and_then(|p| p.base_url.clone());
-
-                Box::new(promptline::model::ollama::OllamaProvider::new(
-                    base_url,
-                    api_key,
-                    Some(config.models.default.clone())
-                ))
-            }
-            "openai" => {
-                let api_key = std::env::var("OPENAI_API_KEY").ok().or_else(|| {
-                    config.models.providers.get("openai")
-                        .and_then(|p| p.api_key.clone())
-                });
-
-                // If no key, warn but don't crash yet (allow setting it via command)
-                if api_key.is_none() {
-                    println!("\x1b[1;33mWarning:\x1b[0m OpenAI API key not set. Use '/model config openai key <key>' to set it.");
-                }
+    // Language servers outlive agent reloads so they stay warm
+    let lsp = language_servers(&config)?;
 
-                let api_key = api_key.unwrap_or_default();
-                Box::new(OpenAIProvider::new(api_key, Some(config.models.default.clone())))
-            }
-            unknown => {
-                println!("\x1b[1;31mError:\x1b[0m Unknown provider '{}'. Defaulting to OpenAI.", unknown);
-                // Fallback to OpenAI to keep the loop running, but user sees the error
-                 let api_key = std::env::var("OPENAI_API_KEY").ok().or_else(|| {
-                    config.models.providers.get("openai")
-                        .and_then(|p| p.api_key.clone())
-                });
-                let api_key = api_key.unwrap_or_default();
-                Box::new(OpenAIProvider::new(api_key, Some(config.models.default.clone())))
+    // Outer loop for reloading agent
+    loop {
+        // Build the model; a missing key is reported but can still be set with /model config
+        let factory = model_factory(&config, &overrides, options).require_api_key(false);
+        let model = match factory.build() {
+            Ok(model) => model,
+            Err(e) => {
+                println!("\x1b[1;31mError:\x1b[0m {}", e);
+                println!("Falling back to OpenAI. Use '/model set <provider> [model]' to switch.");
+                let openai = ProviderOverrides {
+                    provider: Some("openai".to_string()),
+                    model: overrides.model.clone(),
+                    thinking_budget: None,
+                };
+                model_factory(&config, &openai, options).require_api_key(false).build()?
             }
         };
+        let model_info = model.model_info();
+        session.set_model(&model_info);
 
         // Register tools
         let mut tools = ToolRegistry::new();
         tools.register(file_ops::FileReadTool::new());
+        tools.register(file_ops::ImageReadTool::new());
         tools.register(file_ops::FileWriteTool::new());
         tools.register(file_ops::FileListTool::new());
         tools.register(shell::ShellTool::new());
@@ -339,6 +656,13 @@ async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
         tools.register(git_ops::GitDiffTool::new());
         tools.register(web_ops::WebGetTool::new());
         tools.register(search_ops::CodebaseSearchTool::new());
+        tools.register(code_nav::CodeOutlineTool);
+        tools.register(code_nav::FindDefinitionTool);
+        tools.register(code_nav::FindReferencesTool);
+        tools.register(code_nav::CodeRetrieveTool::new());
+        if lsp.is_some() {
+            register_lsp_tools(&mut tools);
+        }
 
         // Create shared permission manager
         let permission_manager = std::sync::Arc::new(std::sync::Mutex::new(
@@ -350,12 +674,24 @@ async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
             model, 
             tools, 
             config.clone(), 
-            Vec::new(),
+            session.messages.clone(),
             permission_manager.clone()
         ).await?;
+        agent.restore_usage(session.usage.clone());
+        // Tool outcomes are kept with the session for /export
+        let tool_runs = std::sync::Arc::new(promptline::session::ToolRunLog::new());
+        let observers: Vec<std::sync::Arc<dyn promptline::agent::AgentObserver>> = vec![
+            std::sync::Arc::new(promptline::render::TerminalRenderer::new()),
+            tool_runs.clone(),
+        ];
+        agent = agent.with_observer(std::sync::Arc::new(observers));
+        if let Some(lsp) = &lsp {
+            agent = agent.with_lsp(lsp.clone());
+        }
         
         // Create command handler
         let mut command_handler = promptline::commands::CommandHandler::new(config.clone(), permission_manager);
+        command_handler.set_model_info(model_info);
 
         // Inner loop for REPL
         #[allow(unused_assignments)]
@@ -381,17 +717,76 @@ async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
 
                     // Check for slash commands
                     if let Some(command) = promptline::commands::CommandHandler::parse(input) {
+                        command_handler.set_context_usage(agent.context_usage());
+                        command_handler.set_message_tokens(&agent.conversation_history, &agent.message_token_counts());
+                        command_handler.set_instructions(agent.instructions().clone());
+                        command_handler.set_usage(agent.usage());
                         match command_handler.execute(command) {
                             Ok(output) => {
                                 println!("{}", output.message);
                                 match output.action {
                                     promptline::commands::CommandAction::Quit => return Ok(()),
                                     promptline::commands::CommandAction::ClearHistory => {
-                                        agent.conversation_history.clear();
+                                        agent.set_history(Vec::new());
+                                        session = sessions.create();
+                                        agent.restore_usage(session.usage.clone());
+                                        session.set_model(&agent.model_info());
                                         println!("✓ Session cleared");
                                     }
+                                    promptline::commands::CommandAction::Resume(None) => {
+                                        print_sessions(&sessions)?;
+                                        println!("\nUse /resume <id> to switch to a session.");
+                                    }
+                                    promptline::commands::CommandAction::Resume(Some(id)) => match sessions.load(&id) {
+                                        Ok(loaded) => {
+                                            session = loaded;
+                                            agent.set_history(session.messages.clone());
+                                            agent.restore_usage(session.usage.clone());
+                                            println!(
+                                                "✓ Resumed session {} ({} messages): {}",
+                                                session.meta.id,
+                                                session.messages.len(),
+                                                session.meta.title
+                                            );
+                                        }
+                                        Err(e) => eprintln!("\x1b[1;31mError:\x1b[0m {}", e),
+                                    },
+                                    promptline::commands::CommandAction::Fork => {
+                                        session.messages = agent.conversation_history.clone();
+                                        let mut fork = sessions.fork(&session);
+                                        match sessions.save(&mut fork) {
+                                            Ok(()) => {
+                                                println!("✓ Forked session {} into {}", session.meta.id, fork.meta.id);
+                                                session = fork;
+                                            }
+                                            Err(e) => eprintln!("\x1b[1;31mError:\x1b[0m {}", e),
+                                        }
+                                    }
+                                    promptline::commands::CommandAction::Export { format, path } => {
+                                        session.messages = agent.conversation_history.clone();
+                                        match export_session(&session, format, path) {
+                                            Ok(path) => println!("✓ Exported to {}", path.display()),
+                                            Err(e) => eprintln!("\x1b[1;31mError:\x1b[0m {}", e),
+                                        }
+                                    }
+                                    promptline::commands::CommandAction::Compact => {
+                                        match agent.compact().await {
+                                            Ok(report) => println!("✓ {}", report),
+                                            Err(e) => eprintln!("\x1b[1;31mError:\x1b[0m {}", e),
+                                        }
+                                    }
                                     promptline::commands::CommandAction::ReloadAgent => {
                                         println!("↻ Reloading agent...");
+                                        // An explicit /model set replaces the command-line selection
+                                        let updated = command_handler.config().clone();
+                                        if updated.models.active_provider != config.models.active_provider
+                                            || updated.models.default != config.models.default
+                                        {
+                                            overrides = ProviderOverrides::default();
+                                        }
+                                        config = updated;
+                                        // Carry the conversation over to the reloaded agent
+                                        session.messages = agent.conversation_history.clone();
                                         reload_requested = true;
                                         break; // Break inner loop to reload
                                     }
@@ -414,7 +809,13 @@ async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
                     print!("\n\x1b[1;34mPromptLine:\x1b[0m ");
                     io::stdout().flush()?;
 
-                    match agent.run(input).await {
+                    let (message, attached) =
+                        promptline::repl::user_message(input, &std::env::current_dir().unwrap_or_default());
+                    for path in &attached {
+                        println!("\x1b[90m📎 Attached {}\x1b[0m", path.display());
+                    }
+
+                    match agent.run_message(message).await {
                         Ok(result) => {
                             // Use the result output directly
                             let response_content = &result.output;
@@ -427,11 +828,19 @@ async fn handle_chat(mut config: Config) -> anyhow::Result<()> {
 
                             // Add the response to history so the model remembers it
                             agent.conversation_history.push(promptline::model::Message::assistant(response_content.clone()));
+                            println!("\x1b[90m[context: {}]\x1b[0m", agent.context_usage());
                         }
                         Err(e) => {
                             eprintln!("\n\x1b[1;31mError:\x1b[0m {}\n", e);
                         }
                     }
+
+                    session.messages = agent.conversation_history.clone();
+                    session.tool_runs.extend(tool_runs.take());
+                    session.usage.merge(agent.usage().run());
+                    if let Err(e) = sessions.save(&mut session) {
+                        tracing::warn!("Failed to save session {}: {}", session.meta.id, e);
+                    }
                 }
                 Err(rustyline::error::ReadlineError::Interrupted) => {
                     println!("^C");
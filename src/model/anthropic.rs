@@ -0,0 +1,773 @@
+//! Anthropic Messages API provider implementation
+
+use super::{flatten_tool_messages, ContentPart, LanguageModel, Message, Role, ModelInfo, ModelResponse, TokenUsage, ToolCall, ToolDefinition};
+use super::retry::error_from_response;
+use crate::error::{ModelError, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
+
+/// Content block in an Anthropic message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageBlockSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+}
+
+/// A role and the content blocks sent in that turn
+type Turn = (String, Vec<ContentBlock>);
+
+/// Inline image data for an image block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageBlockSource {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+pub struct AnthropicProvider {
+    api_key: String,
+    model: String,
+    base_url: String,
+    temperature: f32,
+    max_tokens: usize,
+    thinking_budget: Option<usize>,
+    prompt_caching: bool,
+    client: reqwest::Client,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String, model: Option<String>) -> Self {
+        Self {
+            api_key,
+            model: model.unwrap_or_else(|| "claude-3-5-sonnet-latest".to_string()),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            temperature: 0.2,
+            max_tokens: 4096,
+            thinking_budget: None,
+            prompt_caching: true,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_params(mut self, temperature: f32, max_tokens: usize) -> Self {
+        self.temperature = temperature;
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Use a different API endpoint (e.g. a proxy or a local test server)
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Enable extended thinking with the given token budget
+    ///
+    /// The budget is added on top of `max_tokens`.
+    pub fn with_thinking(mut self, budget_tokens: usize) -> Self {
+        self.thinking_budget = Some(budget_tokens);
+        self
+    }
+
+    /// Enable or disable prompt-caching breakpoints (enabled by default)
+    pub fn with_prompt_caching(mut self, enabled: bool) -> Self {
+        self.prompt_caching = enabled;
+        self
+    }
+
+    /// Convert messages to Anthropic's format
+    ///
+    /// System messages are joined into the top-level `system` field and
+    /// consecutive messages with the same role are merged into one turn.
+    fn convert_messages(&self, messages: &[Message]) -> Result<(Vec<String>, Vec<Turn>)> {
+        let mut system = Vec::new();
+        let mut turns: Vec<Turn> = Vec::new();
+
+        for msg in messages {
+            let role = match msg.role {
+                Role::System => {
+                    system.push(msg.text());
+                    continue;
+                }
+                Role::Assistant => "assistant",
+                Role::User | Role::Tool => "user",
+            };
+
+            for part in &msg.content {
+                let (role, block) = match part {
+                    ContentPart::Text { text } => (role, ContentBlock::Text { text: text.clone() }),
+                    ContentPart::Image { image } => {
+                        let image = image.load()?;
+                        let source = ImageBlockSource {
+                            kind: "base64".to_string(),
+                            media_type: image.media_type,
+                            data: image.data,
+                        };
+                        (role, ContentBlock::Image { source })
+                    }
+                    ContentPart::ToolCall(call) => (
+                        "assistant",
+                        ContentBlock::ToolUse {
+                            id: call.id.clone(),
+                            name: call.name.clone(),
+                            input: call.arguments.clone(),
+                        },
+                    ),
+                    // Signed thinking goes back unchanged so tool use can continue after it
+                    ContentPart::Thinking {
+                        thinking,
+                        signature,
+                        redacted,
+                    } => (
+                        "assistant",
+                        if *redacted {
+                            ContentBlock::RedactedThinking { data: signature.clone() }
+                        } else {
+                            ContentBlock::Thinking {
+                                thinking: thinking.clone(),
+                                signature: signature.clone(),
+                            }
+                        },
+                    ),
+                    // Tool results are always sent back in a user turn
+                    ContentPart::ToolResult {
+                        tool_call_id,
+                        content,
+                        is_error,
+                    } => (
+                        "user",
+                        ContentBlock::ToolResult {
+                            tool_use_id: tool_call_id.clone(),
+                            content: content.clone(),
+                            is_error: *is_error,
+                        },
+                    ),
+                };
+                match turns.last_mut() {
+                    Some((last_role, blocks)) if last_role == role => blocks.push(block),
+                    _ => turns.push((role.to_string(), vec![block])),
+                }
+            }
+        }
+
+        Ok((system, turns))
+    }
+
+    fn build_request(&self, messages: &[Message], tools: &[ToolDefinition], stream: bool) -> Result<Value> {
+        // tool_use and tool_result blocks are only accepted alongside tool definitions
+        let (system, turns) = if tools.is_empty() {
+            self.convert_messages(&flatten_tool_messages(messages))?
+        } else {
+            self.convert_messages(messages)?
+        };
+        let cache_control = json!({"type": "ephemeral"});
+
+        let mut api_messages = turns
+            .into_iter()
+            .map(|(role, content)| Ok(json!({"role": role, "content": serde_json::to_value(content)?})))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Cache breakpoint at the end of the conversation so the next turn reuses the prefix
+        if self.prompt_caching {
+            if let Some(block) = api_messages
+                .last_mut()
+                .and_then(|m| m["content"].as_array_mut())
+                .and_then(|blocks| blocks.last_mut())
+            {
+                block["cache_control"] = cache_control.clone();
+            }
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens + self.thinking_budget.unwrap_or(0),
+            "messages": api_messages,
+        });
+
+        if !system.is_empty() {
+            let mut block = json!({"type": "text", "text": system.join("\n\n")});
+            if self.prompt_caching {
+                block["cache_control"] = cache_control.clone();
+            }
+            body["system"] = json!([block]);
+        }
+
+        if !tools.is_empty() {
+            let mut api_tools: Vec<Value> = tools
+                .iter()
+                .map(|t| {
+                    json!({
+                        "name": t.name,
+                        "description": t.description,
+                        "input_schema": t.parameters,
+                    })
+                })
+                .collect();
+            if self.prompt_caching {
+                if let Some(last) = api_tools.last_mut() {
+                    last["cache_control"] = cache_control;
+                }
+            }
+            body["tools"] = json!(api_tools);
+        }
+
+        match self.thinking_budget {
+            // Extended thinking requires the default temperature
+            Some(budget) => body["thinking"] = json!({"type": "enabled", "budget_tokens": budget}),
+            None => body["temperature"] = json!(self.temperature),
+        }
+
+        if stream {
+            body["stream"] = json!(true);
+        }
+
+        Ok(body)
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let response = self
+            .client
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(body)
+            .send()
+            .await
+            .map_err(ModelError::Request)?;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        Err(error_from_response("Anthropic", response).await.into())
+    }
+
+    fn parse_response(&self, response: &Value) -> Result<ModelResponse> {
+        let blocks: Vec<ContentBlock> = serde_json::from_value(response["content"].clone())
+            .map_err(|e| ModelError::InvalidResponse(format!("Failed to parse content blocks: {}", e)))?;
+
+        Ok(assemble_response(
+            blocks,
+            response["model"].as_str().unwrap_or(&self.model).to_string(),
+            parse_usage(&response["usage"]),
+            response["stop_reason"].as_str().map(|s| s.to_string()),
+        ))
+    }
+}
+
+fn parse_usage(usage: &Value) -> TokenUsage {
+    let count = |key: &str| usage[key].as_u64().unwrap_or(0) as usize;
+    // Cached prompt tokens are billed separately but still count towards the prompt
+    let prompt_tokens = count("input_tokens")
+        + count("cache_creation_input_tokens")
+        + count("cache_read_input_tokens");
+    let completion_tokens = count("output_tokens");
+
+    TokenUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+fn assemble_response(
+    blocks: Vec<ContentBlock>,
+    model: String,
+    usage: TokenUsage,
+    finish_reason: Option<String>,
+) -> ModelResponse {
+    let mut content = String::new();
+    let mut thinking = String::new();
+    let mut tool_calls = Vec::new();
+    let mut thinking_parts = Vec::new();
+
+    for block in blocks {
+        match block {
+            ContentBlock::Text { text } => content.push_str(&text),
+            ContentBlock::Thinking { thinking: t, signature } => {
+                thinking.push_str(&t);
+                thinking_parts.push(ContentPart::Thinking {
+                    thinking: t,
+                    signature,
+                    redacted: false,
+                });
+            }
+            ContentBlock::RedactedThinking { data } => thinking_parts.push(ContentPart::Thinking {
+                thinking: String::new(),
+                signature: data,
+                redacted: true,
+            }),
+            ContentBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                id,
+                name,
+                arguments: input,
+            }),
+            ContentBlock::Image { .. } | ContentBlock::ToolResult { .. } => {}
+        }
+    }
+
+    ModelResponse {
+        content,
+        model,
+        usage,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        finish_reason,
+        thinking: (!thinking.is_empty()).then_some(thinking),
+        thinking_parts,
+    }
+}
+
+/// Accumulates server-sent events of a streamed response
+#[derive(Default)]
+struct StreamState {
+    /// Bytes after the last complete line, which may end inside a UTF-8 character
+    pending: Vec<u8>,
+    buffer: String,
+    model: String,
+    blocks: BTreeMap<u64, ContentBlock>,
+    partial_json: BTreeMap<u64, String>,
+    usage: Value,
+    stop_reason: Option<String>,
+}
+
+impl StreamState {
+    /// Feed raw bytes and return the data payloads of completed events
+    fn push(&mut self, chunk: &[u8]) -> Vec<Value> {
+        self.pending.extend_from_slice(chunk);
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Vec::new();
+        };
+        let lines: Vec<u8> = self.pending.drain(..=end).collect();
+        self.buffer.push_str(&String::from_utf8_lossy(&lines).replace("\r\n", "\n"));
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let raw: String = self.buffer.drain(..end + 2).collect();
+            let data: String = raw
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect::<Vec<_>>()
+                .join("\n");
+            if let Ok(value) = serde_json::from_str(&data) {
+                events.push(value);
+            }
+        }
+        events
+    }
+
+    /// Apply one event, returning any text delta
+    fn apply(&mut self, event: &Value) -> Result<Option<String>> {
+        let index = event["index"].as_u64().unwrap_or(0);
+
+        match event["type"].as_str().unwrap_or("") {
+            "message_start" => {
+                let message = &event["message"];
+                self.model = message["model"].as_str().unwrap_or_default().to_string();
+                self.usage = message["usage"].clone();
+            }
+            "content_block_start" => {
+                let block = serde_json::from_value(event["content_block"].clone()).map_err(|e| {
+                    ModelError::InvalidResponse(format!("Invalid content block in stream: {}", e))
+                })?;
+                self.blocks.insert(index, block);
+            }
+            "content_block_delta" => {
+                let delta = &event["delta"];
+                match (delta["type"].as_str().unwrap_or(""), self.blocks.get_mut(&index)) {
+                    ("text_delta", Some(ContentBlock::Text { text })) => {
+                        let fragment = delta["text"].as_str().unwrap_or_default();
+                        text.push_str(fragment);
+                        return Ok(Some(fragment.to_string()));
+                    }
+                    ("thinking_delta", Some(ContentBlock::Thinking { thinking, .. })) => {
+                        thinking.push_str(delta["thinking"].as_str().unwrap_or_default());
+                    }
+                    ("signature_delta", Some(ContentBlock::Thinking { signature, .. })) => {
+                        signature.push_str(delta["signature"].as_str().unwrap_or_default());
+                    }
+                    ("input_json_delta", Some(ContentBlock::ToolUse { .. })) => {
+                        self.partial_json
+                            .entry(index)
+                            .or_default()
+                            .push_str(delta["partial_json"].as_str().unwrap_or_default());
+                    }
+                    _ => {}
+                }
+            }
+            "content_block_stop" => {
+                if let (Some(json), Some(ContentBlock::ToolUse { input, .. })) =
+                    (self.partial_json.remove(&index), self.blocks.get_mut(&index))
+                {
+                    if !json.is_empty() {
+                        *input = serde_json::from_str(&json).map_err(|e| {
+                            ModelError::InvalidResponse(format!("Invalid tool input in stream: {}", e))
+                        })?;
+                    }
+                }
+            }
+            "message_delta" => {
+                self.stop_reason = event["delta"]["stop_reason"].as_str().map(|s| s.to_string());
+                if let Some(output) = event["usage"]["output_tokens"].as_u64() {
+                    self.usage["output_tokens"] = json!(output);
+                }
+            }
+            "error" => {
+                let message = event["error"]["message"].as_str().unwrap_or("unknown error");
+                return Err(match event["error"]["type"].as_str() {
+                    Some("rate_limit_error") => ModelError::RateLimit { retry_after: None },
+                    Some("overloaded_error") | Some("api_error") => ModelError::Server {
+                        status: 529,
+                        message: message.to_string(),
+                        retry_after: None,
+                    },
+                    Some("authentication_error") => ModelError::Auth(message.to_string()),
+                    _ => ModelError::Api(format!("Anthropic stream error: {}", message)),
+                }
+                .into());
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn finish(self, fallback_model: &str) -> ModelResponse {
+        let model = if self.model.is_empty() {
+            fallback_model.to_string()
+        } else {
+            self.model
+        };
+        assemble_response(
+            self.blocks.into_values().collect(),
+            model,
+            parse_usage(&self.usage),
+            self.stop_reason,
+        )
+    }
+}
+
+#[async_trait]
+impl LanguageModel for AnthropicProvider {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        let mut messages = Vec::new();
+
+        if let Some(sys) = system_prompt {
+            messages.push(Message::system(sys));
+        }
+
+        messages.push(Message::user(prompt));
+
+        self.chat(&messages).await
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        self.chat_with_tools(messages, &[]).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        let body = self.build_request(messages, tools, false)?;
+        let response: Value = self
+            .send(&body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| ModelError::InvalidResponse(format!("Failed to parse response: {}", e)))?;
+
+        self.parse_response(&response)
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let body = self.build_request(messages, tools, true)?;
+        let mut response = self.send(&body).await?;
+        let mut state = StreamState::default();
+
+        while let Some(chunk) = response.chunk().await.map_err(ModelError::Request)? {
+            for event in state.push(&chunk) {
+                if let Some(delta) = state.apply(&event)? {
+                    on_delta(&delta);
+                }
+            }
+        }
+
+        Ok(state.finish(&self.model))
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            provider: "anthropic".to_string(),
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            context_window: super::context_window_for(&self.model),
+            supports_tools: true,
+            supports_streaming: true,
+        }
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::retry::map_http_error;
+    use crate::model::ImageSource;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn provider(server: &MockServer) -> AnthropicProvider {
+        AnthropicProvider::new("test-key".to_string(), Some("claude-3-5-sonnet-latest".to_string()))
+            .with_base_url(server.uri())
+    }
+
+    fn read_file_tool() -> ToolDefinition {
+        ToolDefinition {
+            name: "file_read".to_string(),
+            description: "Read a file".to_string(),
+            parameters: json!({"type": "object", "properties": {"path": {"type": "string"}}}),
+        }
+    }
+
+    #[test]
+    fn test_request_format() {
+        let provider = AnthropicProvider::new("key".to_string(), None);
+        let messages = vec![
+            Message::system("You are helpful"),
+            Message::user("Hello"),
+            Message::user("Are you there?"),
+            Message::assistant("Hi"),
+        ];
+
+        let body = provider.build_request(&messages, &[read_file_tool()], false).unwrap();
+
+        assert_eq!(body["system"][0]["text"], "You are helpful");
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(body["messages"][0]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(body["messages"][1]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+        assert!(body.get("stream").is_none());
+    }
+
+    #[test]
+    fn test_multimodal_and_tool_messages() {
+        let provider = AnthropicProvider::new("key".to_string(), None).with_prompt_caching(false);
+        let messages = vec![
+            Message::user("What is this?").with_image(ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }),
+            Message::new(
+                Role::Assistant,
+                vec![ContentPart::ToolCall(ToolCall {
+                    id: "call_1".to_string(),
+                    name: "file_read".to_string(),
+                    arguments: json!({"path": "a.rs"}),
+                })],
+            ),
+            Message::tool_result("call_1", "fn main() {}", false),
+        ];
+
+        let body = provider.build_request(&messages, &[read_file_tool()], false).unwrap();
+
+        assert_eq!(body["messages"][0]["content"][1]["type"], "image");
+        assert_eq!(body["messages"][0]["content"][1]["source"]["media_type"], "image/png");
+        assert_eq!(body["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(body["messages"][2]["role"], "user");
+        assert_eq!(body["messages"][2]["content"][0]["tool_use_id"], "call_1");
+
+        // Without tool definitions the exchange is sent as text
+        let body = provider.build_request(&messages, &[], false).unwrap();
+        assert_eq!(body["messages"][1]["content"][0]["text"], "{\"args\":{\"path\":\"a.rs\"},\"tool\":\"file_read\"}");
+        assert_eq!(body["messages"][2]["content"][0]["text"], "Tool 'file_read' result: fn main() {}");
+    }
+
+    #[test]
+    fn test_thinking_request() {
+        let provider = AnthropicProvider::new("key".to_string(), None)
+            .with_params(0.2, 1000)
+            .with_thinking(2000)
+            .with_prompt_caching(false);
+
+        let body = provider.build_request(&[Message::user("Think")], &[], false).unwrap();
+
+        assert_eq!(body["thinking"]["budget_tokens"], 2000);
+        assert_eq!(body["max_tokens"], 3000);
+        assert!(body.get("temperature").is_none());
+        assert!(body["messages"][0]["content"][0].get("cache_control").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_tool_use() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(header("x-api-key", "test-key"))
+            .and(header("anthropic-version", API_VERSION))
+            .and(body_partial_json(json!({"system": [{"text": "sys"}]})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "claude-3-5-sonnet-20241022",
+                "stop_reason": "tool_use",
+                "content": [
+                    {"type": "thinking", "thinking": "Need the file", "signature": "sig"},
+                    {"type": "text", "text": "Let me read it."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "file_read", "input": {"path": "main.rs"}}
+                ],
+                "usage": {"input_tokens": 10, "cache_read_input_tokens": 90, "output_tokens": 5}
+            })))
+            .mount(&server)
+            .await;
+
+        let response = provider(&server)
+            .chat_with_tools(&[Message::system("sys"), Message::user("Read main.rs")], &[read_file_tool()])
+            .await
+            .unwrap();
+
+        assert_eq!(response.content, "Let me read it.");
+        assert_eq!(response.thinking.as_deref(), Some("Need the file"));
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.usage.prompt_tokens, 100);
+        assert_eq!(response.usage.total_tokens, 105);
+        let calls = response.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].arguments["path"], "main.rs");
+
+        // The signed thinking block is sent back ahead of the tool use
+        let mut content = response.thinking_parts;
+        content.push(ContentPart::ToolCall(calls[0].clone()));
+        let history = [
+            Message::user("Read main.rs"),
+            Message::new(Role::Assistant, content),
+            Message::tool_result("toolu_1", "fn main() {}", false),
+        ];
+        let body = provider(&server).build_request(&history, &[read_file_tool()], false).unwrap();
+        assert_eq!(
+            body["messages"][1]["content"][0],
+            json!({"type": "thinking", "thinking": "Need the file", "signature": "sig"})
+        );
+        assert_eq!(body["messages"][1]["content"][1]["type"], "tool_use");
+    }
+
+    #[tokio::test]
+    async fn test_error_mapping() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "type": "error",
+                "error": {"type": "authentication_error", "message": "invalid x-api-key"}
+            })))
+            .mount(&server)
+            .await;
+
+        let err = provider(&server).chat(&[Message::user("Hi")]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::PromptLineError::Model(ModelError::Auth(ref msg)) if msg == "invalid x-api-key"
+        ));
+
+        assert!(matches!(map_http_error("Anthropic", 429, None, ""), ModelError::RateLimit { .. }));
+        assert!(matches!(map_http_error("Anthropic", 400, None, "oops"), ModelError::Api(_)));
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let events = [
+            json!({"type": "message_start", "message": {"model": "claude-3-5-sonnet-20241022", "usage": {"input_tokens": 12, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hel"}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "lo"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_2", "name": "file_read", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"path\": "}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"a.rs\"}"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 7}}),
+            json!({"type": "message_stop"}),
+        ];
+        let body: String = events
+            .iter()
+            .map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e))
+            .collect();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"stream": true})))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let mut deltas = Vec::new();
+        let response = provider(&server)
+            .chat_stream(&[Message::user("Hi")], &[read_file_tool()], &mut |d| deltas.push(d.to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        assert_eq!(response.content, "Hello");
+        assert_eq!(response.usage.completion_tokens, 7);
+        assert_eq!(response.tool_calls.unwrap()[0].arguments["path"], "a.rs");
+    }
+
+    #[test]
+    fn test_stream_characters_split_across_chunks() {
+        let mut state = StreamState::default();
+        let start = json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}});
+        state.apply(&start).unwrap();
+        let event = json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "café 🚀"}});
+        let bytes = format!("event: content_block_delta\r\ndata: {}\r\n\r\n", event).into_bytes();
+
+        // Split inside "é" and again inside the emoji
+        let e_acute = bytes.windows(2).position(|w| w == "é".as_bytes()).unwrap() + 1;
+        let rocket = bytes.windows(4).position(|w| w == "🚀".as_bytes()).unwrap() + 2;
+        let mut events = state.push(&bytes[..e_acute]);
+        events.extend(state.push(&bytes[e_acute..rocket]));
+        events.extend(state.push(&bytes[rocket..]));
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(state.apply(&events[0]).unwrap().as_deref(), Some("café 🚀"));
+    }
+}
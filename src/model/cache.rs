@@ -0,0 +1,513 @@
+//! On-disk response cache
+//!
+//! [`CachingModel`] stores responses under a hash of the model, sampling
+//! parameters, messages and tools. Only deterministic (temperature 0)
+//! requests are cached unless `cache.all_temperatures` is set.
+
+use super::{ContentPart, ImageSource, LanguageModel, Message, ModelInfo, ModelResponse, TokenUsage, ToolDefinition};
+use crate::config::{CacheConfig, ModelParams};
+use crate::error::Result;
+use crate::tokenizer::Tokenizer;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Bumped when the key or entry format changes, so old entries are never read
+const KEY_VERSION: &str = "v2";
+
+const ENTRY_EXTENSION: &str = "json";
+
+/// Directory of response files with a time-to-live and a total size limit
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+    all_temperatures: bool,
+}
+
+/// A cached response and when it was stored
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Seconds since the Unix epoch
+    created_at: u64,
+    response: ModelResponse,
+}
+
+/// Summary of the cache contents
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+    /// Entries past their TTL, removed on next access or eviction
+    pub expired: usize,
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} entries, {:.1} KB ({} expired)",
+            self.entries,
+            self.total_bytes as f64 / 1024.0,
+            self.expired
+        )
+    }
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let defaults = CacheConfig::default();
+        Self {
+            dir: dir.into(),
+            ttl: Duration::from_secs(defaults.ttl_secs),
+            max_bytes: defaults.max_size_mb * 1024 * 1024,
+            all_temperatures: defaults.all_temperatures,
+        }
+    }
+
+    /// Cache configured by the `cache` section, in its default directory unless one is set
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self::new(config.dir.clone().unwrap_or_else(Self::default_dir))
+            .with_ttl(Duration::from_secs(config.ttl_secs))
+            .with_max_bytes(config.max_size_mb * 1024 * 1024)
+            .with_all_temperatures(config.all_temperatures)
+    }
+
+    /// `<config dir>/promptline/cache`
+    pub fn default_dir() -> PathBuf {
+        let mut dir = dirs::config_dir().unwrap_or_else(|| PathBuf::from(".promptline"));
+        dir.push("promptline");
+        dir.push("cache");
+        dir
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Also cache requests sampled at a non-zero temperature
+    pub fn with_all_temperatures(mut self, all: bool) -> Self {
+        self.all_temperatures = all;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether requests at `temperature` are cached
+    pub fn caches(&self, temperature: f32) -> bool {
+        self.all_temperatures || temperature == 0.0
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    /// Stored response for `key`, if present and not expired
+    pub fn get(&self, key: &str) -> Option<ModelResponse> {
+        let path = self.entry_path(key);
+        let content = std::fs::read_to_string(&path).ok()?;
+        let entry: CacheEntry = match serde_json::from_str(&content) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("Discarding unreadable cache entry {}: {}", path.display(), e);
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+        };
+
+        if self.is_expired(entry.created_at) {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.response)
+    }
+
+    /// Store a response, then evict the oldest entries if the cache is over its size limit
+    pub fn put(&self, key: &str, response: &ModelResponse) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let entry = CacheEntry {
+            created_at: now_secs(),
+            response: response.clone(),
+        };
+        std::fs::write(self.entry_path(key), serde_json::to_string(&entry)?)?;
+        self.evict()
+    }
+
+    /// Remove all entries, returning how many were removed
+    pub fn clear(&self) -> Result<usize> {
+        let entries = self.entries()?;
+        for (path, _) in &entries {
+            std::fs::remove_file(path)?;
+        }
+        Ok(entries.len())
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        for (_, metadata) in self.entries()? {
+            stats.entries += 1;
+            stats.total_bytes += metadata.len();
+            if self.is_expired(modified_secs(&metadata)) {
+                stats.expired += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Drop expired entries, then the oldest ones until the cache fits its size limit
+    fn evict(&self) -> Result<()> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(_, metadata)| metadata.modified().ok());
+
+        let mut total: u64 = entries.iter().map(|(_, metadata)| metadata.len()).sum();
+        for (path, metadata) in entries {
+            if total <= self.max_bytes && !self.is_expired(modified_secs(&metadata)) {
+                continue;
+            }
+            std::fs::remove_file(&path)?;
+            total -= metadata.len();
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, std::fs::Metadata)>> {
+        let read_dir = match std::fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for entry in read_dir {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == ENTRY_EXTENSION) {
+                let metadata = std::fs::metadata(&path)?;
+                entries.push((path, metadata));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn is_expired(&self, created_at: u64) -> bool {
+        now_secs().saturating_sub(created_at) > self.ttl.as_secs()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Hash identifying a request
+///
+/// Images attached by path are hashed by their bytes, so editing an image
+/// file changes the key.
+pub fn cache_key(model: &str, params: &ModelParams, messages: &[Message], tools: &[ToolDefinition]) -> String {
+    let messages: Vec<Message> = messages.iter().map(with_inline_images).collect();
+    let request = serde_json::json!({
+        "version": KEY_VERSION,
+        "model": model,
+        "temperature": params.temperature,
+        "max_tokens": params.max_tokens,
+        "messages": messages,
+        "tools": tools,
+    });
+
+    Sha256::digest(request.to_string().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `message` with images read from disk; unreadable ones keep their path and fail when sent
+fn with_inline_images(message: &Message) -> Message {
+    let mut message = message.clone();
+    for part in &mut message.content {
+        if let ContentPart::Image { image } = part {
+            if let (ImageSource::Path { .. }, Ok(data)) = (&*image, image.load()) {
+                *image = ImageSource::Base64 {
+                    media_type: data.media_type,
+                    data: data.data,
+                };
+            }
+        }
+    }
+    message
+}
+
+/// Serves repeated requests from a [`ResponseCache`]
+///
+/// Cache hits report no token usage since nothing was billed.
+pub struct CachingModel {
+    inner: Box<dyn LanguageModel>,
+    cache: Arc<ResponseCache>,
+    params: ModelParams,
+}
+
+impl CachingModel {
+    /// `params` must match the sampling parameters `inner` was built with
+    pub fn new(inner: Box<dyn LanguageModel>, cache: Arc<ResponseCache>, params: ModelParams) -> Self {
+        Self { inner, cache, params }
+    }
+
+    /// Key for a request, or `None` if it should not be cached
+    fn key(&self, messages: &[Message], tools: &[ToolDefinition]) -> Option<String> {
+        if !self.cache.caches(self.params.temperature) {
+            return None;
+        }
+        let info = self.inner.model_info();
+        let model = format!("{}/{}", info.provider, info.model);
+        Some(cache_key(&model, &self.params, messages, tools))
+    }
+
+    fn lookup(&self, key: Option<&str>) -> Option<ModelResponse> {
+        let mut response = self.cache.get(key?)?;
+        tracing::debug!("Serving response from cache");
+        response.usage = TokenUsage::default();
+        Some(response)
+    }
+
+    fn store(&self, key: Option<&str>, response: &ModelResponse) {
+        if let Some(key) = key {
+            if let Err(e) = self.cache.put(key, response) {
+                tracing::warn!("Failed to cache response: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl LanguageModel for CachingModel {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        let mut messages = Vec::new();
+        if let Some(system) = system_prompt {
+            messages.push(Message::system(system));
+        }
+        messages.push(Message::user(prompt));
+
+        let key = self.key(&messages, &[]);
+        if let Some(response) = self.lookup(key.as_deref()) {
+            return Ok(response);
+        }
+        let response = self.inner.complete(prompt, system_prompt).await?;
+        self.store(key.as_deref(), &response);
+        Ok(response)
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        let key = self.key(messages, &[]);
+        if let Some(response) = self.lookup(key.as_deref()) {
+            return Ok(response);
+        }
+        let response = self.inner.chat(messages).await?;
+        self.store(key.as_deref(), &response);
+        Ok(response)
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        let key = self.key(messages, tools);
+        if let Some(response) = self.lookup(key.as_deref()) {
+            return Ok(response);
+        }
+        let response = self.inner.chat_with_tools(messages, tools).await?;
+        self.store(key.as_deref(), &response);
+        Ok(response)
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let key = self.key(messages, tools);
+        if let Some(response) = self.lookup(key.as_deref()) {
+            on_delta(&response.content);
+            return Ok(response);
+        }
+        let response = self.inner.chat_stream(messages, tools, on_delta).await?;
+        self.store(key.as_deref(), &response);
+        Ok(response)
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        self.inner.model_info()
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.inner.tokenizer()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        self.inner.supports_streaming()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScriptedModel;
+
+    /// Caching model over a scripted one answering "response 1", "response 2", ...
+    fn caching_model(cache: ResponseCache, temperature: f32) -> (CachingModel, ScriptedModel) {
+        let mut inner = ScriptedModel::new().with_usage(TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+        });
+        for n in 1..=3 {
+            inner = inner.respond(format!("response {}", n));
+        }
+        let params = ModelParams {
+            temperature,
+            max_tokens: 100,
+        };
+        (CachingModel::new(Box::new(inner.clone()), Arc::new(cache), params), inner)
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_requests_are_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let (model, calls) = caching_model(ResponseCache::new(dir.path()), 0.0);
+
+        let first = model.chat(&[Message::user("hi")]).await.unwrap();
+        let second = model.chat(&[Message::user("hi")]).await.unwrap();
+        let other = model.chat(&[Message::user("bye")]).await.unwrap();
+
+        assert_eq!(calls.request_count(), 2);
+        assert_eq!(second.content, first.content);
+        assert_eq!(second.usage, TokenUsage::default());
+        assert_eq!(other.content, "response 2");
+        assert_eq!(ResponseCache::new(dir.path()).stats().unwrap().entries, 2);
+    }
+
+    #[tokio::test]
+    async fn test_sampled_requests_bypass_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (model, calls) = caching_model(ResponseCache::new(dir.path()), 0.7);
+        model.chat(&[Message::user("hi")]).await.unwrap();
+        model.chat(&[Message::user("hi")]).await.unwrap();
+        assert_eq!(calls.request_count(), 2);
+
+        let (model, calls) = caching_model(ResponseCache::new(dir.path()).with_all_temperatures(true), 0.7);
+        model.chat(&[Message::user("hi")]).await.unwrap();
+        model.chat(&[Message::user("hi")]).await.unwrap();
+        assert_eq!(calls.request_count(), 1);
+    }
+
+    #[test]
+    fn test_key_depends_on_params_and_tools() {
+        let params = ModelParams {
+            temperature: 0.0,
+            max_tokens: 100,
+        };
+        let messages = [Message::user("hi")];
+        let tool = ToolDefinition {
+            name: "file_read".to_string(),
+            description: "Read a file".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+
+        let key = cache_key("gpt-4o", &params, &messages, &[]);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache_key("gpt-4o", &params, &messages, &[]));
+        assert_ne!(key, cache_key("gpt-4o-mini", &params, &messages, &[]));
+        assert_ne!(key, cache_key("gpt-4o", &params, &messages, &[tool]));
+        let longer = ModelParams {
+            max_tokens: 200,
+            ..params
+        };
+        assert_ne!(key, cache_key("gpt-4o", &longer, &messages, &[]));
+    }
+
+    #[test]
+    fn test_key_depends_on_image_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("screenshot.png");
+        let params = ModelParams {
+            temperature: 0.0,
+            max_tokens: 100,
+        };
+        let messages = [Message::new(
+            crate::model::Role::User,
+            vec![ContentPart::Image {
+                image: ImageSource::Path { path: path.clone() },
+            }],
+        )];
+
+        std::fs::write(&path, b"first image").unwrap();
+        let key = cache_key("gpt-4o", &params, &messages, &[]);
+        assert_eq!(key, cache_key("gpt-4o", &params, &messages, &[]));
+
+        std::fs::write(&path, b"second image").unwrap();
+        assert_ne!(key, cache_key("gpt-4o", &params, &messages, &[]));
+    }
+
+    #[test]
+    fn test_ttl_size_limit_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let response = ModelResponse {
+            content: "x".repeat(400),
+            model: "m".to_string(),
+            usage: TokenUsage::default(),
+            tool_calls: None,
+            finish_reason: None,
+            thinking: None,
+            thinking_parts: Vec::new(),
+        };
+
+        let cache = ResponseCache::new(dir.path()).with_max_bytes(1500);
+        cache.put("a", &response).unwrap();
+        cache.put("b", &response).unwrap();
+        cache.put("c", &response).unwrap();
+        assert_eq!(cache.stats().unwrap().entries, 2);
+        assert!(cache.get("c").is_some());
+
+        let expired = ResponseCache::new(dir.path()).with_ttl(Duration::ZERO);
+        std::fs::write(
+            expired.entry_path("old"),
+            serde_json::to_string(&CacheEntry {
+                created_at: now_secs() - 10,
+                response: response.clone(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(expired.get("old").is_none());
+        assert!(!expired.entry_path("old").exists());
+
+        assert_eq!(cache.clear().unwrap(), 2);
+        assert_eq!(cache.stats().unwrap(), CacheStats::default());
+    }
+}
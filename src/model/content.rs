@@ -0,0 +1,199 @@
+//! Message content parts (text, images, tool calls and tool results)
+
+use super::ToolCall;
+use crate::error::{Result, ToolError};
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Images larger than this are rejected instead of being sent to the model
+pub const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Rough token cost of an image, used for context accounting
+pub const IMAGE_TOKEN_ESTIMATE: usize = 1_000;
+
+/// One part of a message's content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    Image {
+        image: ImageSource,
+    },
+    /// A tool invocation requested by the assistant
+    ToolCall(ToolCall),
+    /// The output of a tool invocation
+    ToolResult {
+        tool_call_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    /// Extended-thinking reasoning, kept so it can be sent back with its signature
+    Thinking {
+        thinking: String,
+        /// Provider signature, or the encrypted reasoning when `redacted`
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        signature: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        redacted: bool,
+    },
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::Text { text: text.into() }
+    }
+
+    pub fn image(image: ImageSource) -> Self {
+        Self::Image { image }
+    }
+
+    pub fn tool_result(tool_call_id: impl Into<String>, content: impl Into<String>, is_error: bool) -> Self {
+        Self::ToolResult {
+            tool_call_id: tool_call_id.into(),
+            content: content.into(),
+            is_error,
+        }
+    }
+}
+
+/// Where an image's bytes come from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum ImageSource {
+    /// Inline base64-encoded data
+    Base64 { media_type: String, data: String },
+    /// A file on disk, read when the message is sent
+    Path { path: PathBuf },
+}
+
+/// An image ready to be sent to a provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageData {
+    pub media_type: String,
+    /// Base64-encoded bytes
+    pub data: String,
+}
+
+impl ImageData {
+    /// `data:` URL form used by OpenAI-style APIs
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.data)
+    }
+}
+
+impl ImageSource {
+    /// Read an image file into an inline source
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let data = Self::Path {
+            path: path.as_ref().to_path_buf(),
+        }
+        .load()?;
+        Ok(Self::Base64 {
+            media_type: data.media_type,
+            data: data.data,
+        })
+    }
+
+    /// Resolve the media type and base64 data, reading the file for path sources
+    pub fn load(&self) -> Result<ImageData> {
+        match self {
+            Self::Base64 { media_type, data } => Ok(ImageData {
+                media_type: media_type.clone(),
+                data: data.clone(),
+            }),
+            Self::Path { path } => {
+                let media_type = media_type_for(path).ok_or_else(|| {
+                    ToolError::InvalidArgs(format!("Unsupported image type: {}", path.display()))
+                })?;
+                let size = std::fs::metadata(path)?.len();
+                if size > MAX_IMAGE_BYTES {
+                    return Err(ToolError::InvalidArgs(format!(
+                        "Image too large: {} bytes (max {} bytes)",
+                        size, MAX_IMAGE_BYTES
+                    ))
+                    .into());
+                }
+                let bytes = std::fs::read(path)?;
+                Ok(ImageData {
+                    media_type: media_type.to_string(),
+                    data: base64::engine::general_purpose::STANDARD.encode(bytes),
+                })
+            }
+        }
+    }
+}
+
+/// Media type for a supported image file extension
+pub fn media_type_for(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Deserialize message content from either a plain string (older history
+/// files) or a list of content parts
+pub(crate) fn deserialize_content<'de, D>(deserializer: D) -> std::result::Result<Vec<ContentPart>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Content {
+        Text(String),
+        Parts(Vec<ContentPart>),
+    }
+
+    Ok(match Content::deserialize(deserializer)? {
+        Content::Text(text) if text.is_empty() => Vec::new(),
+        Content::Text(text) => vec![ContentPart::Text { text }],
+        Content::Parts(parts) => parts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Message;
+
+    #[test]
+    fn test_legacy_string_content() {
+        let msg: Message = serde_json::from_str(r#"{"role": "user", "content": "Hello"}"#).unwrap();
+        assert_eq!(msg.content, vec![ContentPart::text("Hello")]);
+        assert_eq!(msg.text(), "Hello");
+    }
+
+    #[test]
+    fn test_parts_round_trip() {
+        let msg = Message::user("Look at this").with_image(ImageSource::Path {
+            path: PathBuf::from("shot.png"),
+        });
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["content"][1]["type"], "image");
+        assert_eq!(json["content"][1]["image"]["source"], "path");
+
+        let parsed: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.content, msg.content);
+    }
+
+    #[test]
+    fn test_load_image_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pixel.PNG");
+        std::fs::write(&path, [0x89, b'P', b'N', b'G']).unwrap();
+
+        let image = ImageSource::from_file(&path).unwrap().load().unwrap();
+        assert_eq!(image.media_type, "image/png");
+        assert_eq!(image.data_url(), "data:image/png;base64,iVBORw==");
+
+        assert!(ImageSource::from_file(dir.path().join("notes.txt")).is_err());
+    }
+}
@@ -0,0 +1,478 @@
+//! Provider construction from configuration
+//!
+//! The provider is chosen by, in order: the `--provider` flag, the
+//! `models.active_provider` config field, the legacy `PROMPTLINE_PROVIDER`
+//! environment variable, and finally `openai`.
+
+use super::anthropic::AnthropicProvider;
+use super::cache::{CachingModel, ResponseCache};
+use super::fallback::{FailoverObserver, FallbackModel};
+use super::gemini::GeminiProvider;
+use super::ollama::OllamaProvider;
+use super::openai::OpenAIProvider;
+use super::openai_compat::OpenAICompatibleProvider;
+use super::replay::{RecordingModel, ReplayProvider};
+use super::retry::{RetryObserver, RetryPolicy, RetryingModel};
+use super::LanguageModel;
+use crate::config::{ModelConfig, ProviderConfig};
+use crate::error::{ConfigError, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Provider implementations built into PromptLine
+pub const BUILTIN_PROVIDERS: &[&str] = &["openai", "anthropic", "gemini", "ollama"];
+
+/// `type` of configured providers that use the generic OpenAI-compatible client
+pub const OPENAI_COMPATIBLE: &str = "openai-compatible";
+
+const DEFAULT_PROVIDER: &str = "openai";
+
+/// Command-line overrides of the configured provider and model
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderOverrides {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Extended thinking budget, for providers that support it
+    pub thinking_budget: Option<usize>,
+}
+
+/// Builds language models from `models` configuration
+pub struct ProviderFactory<'a> {
+    config: &'a ModelConfig,
+    overrides: ProviderOverrides,
+    require_api_key: bool,
+    retry_observer: Option<RetryObserver>,
+    failover_observer: Option<FailoverObserver>,
+    cache: Option<Arc<ResponseCache>>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+}
+
+impl<'a> ProviderFactory<'a> {
+    pub fn new(config: &'a ModelConfig) -> Self {
+        Self {
+            config,
+            overrides: ProviderOverrides::default(),
+            require_api_key: true,
+            retry_observer: None,
+            failover_observer: None,
+            cache: None,
+            record: None,
+            replay: None,
+        }
+    }
+
+    /// Report switches to a fallback model to `observer`
+    pub fn with_failover_observer(mut self, observer: FailoverObserver) -> Self {
+        self.failover_observer = Some(observer);
+        self
+    }
+
+    /// Report retries of the built model to `observer`
+    pub fn with_retry_observer(mut self, observer: RetryObserver) -> Self {
+        self.retry_observer = Some(observer);
+        self
+    }
+
+    /// Serve repeated requests from `cache` (`None` disables caching)
+    pub fn with_cache(mut self, cache: Option<Arc<ResponseCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Append every exchange with the built model to a JSONL file
+    pub fn with_recording(mut self, path: Option<PathBuf>) -> Self {
+        self.record = path;
+        self
+    }
+
+    /// Serve responses from a recording instead of a live provider
+    pub fn with_replay(mut self, path: Option<PathBuf>) -> Self {
+        self.replay = path;
+        self
+    }
+
+    pub fn with_overrides(mut self, overrides: ProviderOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Whether a missing API key is an error (default) or left for the API to reject
+    ///
+    /// Interactive mode disables this so the key can still be set with `/model config`.
+    pub fn require_api_key(mut self, require: bool) -> Self {
+        self.require_api_key = require;
+        self
+    }
+
+    /// Name of the provider that will be built
+    pub fn provider_name(&self) -> String {
+        self.overrides
+            .provider
+            .clone()
+            .or_else(|| self.config.active_provider.clone())
+            .or_else(|| std::env::var("PROMPTLINE_PROVIDER").ok())
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| DEFAULT_PROVIDER.to_string())
+    }
+
+    /// Name of the model that will be requested
+    pub fn model_name(&self) -> String {
+        self.overrides
+            .model
+            .clone()
+            .unwrap_or_else(|| self.config.default.clone())
+    }
+
+    /// Build the selected provider, followed by `models.fallbacks` if any
+    pub fn build(&self) -> Result<Box<dyn LanguageModel>> {
+        if let Some(path) = &self.replay {
+            return Ok(Box::new(ReplayProvider::from_file(path)?));
+        }
+
+        let model = self.build_chain()?;
+        match &self.record {
+            Some(path) => Ok(Box::new(RecordingModel::new(model, path)?)),
+            None => Ok(model),
+        }
+    }
+
+    fn build_chain(&self) -> Result<Box<dyn LanguageModel>> {
+        let primary = self.build_provider(&self.provider_name(), self.model_name())?;
+        if self.config.fallbacks.is_empty() {
+            return Ok(primary);
+        }
+
+        let mut chain = vec![primary];
+        for fallback in &self.config.fallbacks {
+            let model = fallback.model.clone().unwrap_or_else(|| self.config.default.clone());
+            match self.build_provider(&fallback.provider, model) {
+                Ok(model) => chain.push(model),
+                // A broken fallback should not prevent using the primary model
+                Err(e) => tracing::warn!("Skipping fallback provider '{}': {}", fallback.provider, e),
+            }
+        }
+
+        let mut model = FallbackModel::new(chain, self.config.fallback_on.clone());
+        if let Some(observer) = &self.failover_observer {
+            model = model.with_observer(observer.clone());
+        }
+        Ok(Box::new(model))
+    }
+
+    /// Build one provider, wrapped in its retry policy and the response cache
+    fn build_provider(&self, name: &str, model: String) -> Result<Box<dyn LanguageModel>> {
+        let name = name.to_string();
+        let default_config = ProviderConfig::default();
+        let provider_config = self.config.providers.get(&name);
+        let kind = provider_config
+            .and_then(|p| p.kind.clone())
+            .unwrap_or_else(|| name.clone());
+
+        if provider_config.is_none() && !BUILTIN_PROVIDERS.contains(&name.as_str()) {
+            return Err(self.unknown_provider(&name).into());
+        }
+        let provider_config = provider_config.unwrap_or(&default_config);
+        let params = &provider_config.default_params;
+
+        let model: Box<dyn LanguageModel> = match kind.as_str() {
+            "openai" => {
+                let api_key = self.api_key(&name, provider_config, true)?;
+                Box::new(
+                    OpenAIProvider::new(api_key.unwrap_or_default(), Some(model))
+                        .with_config(provider_config),
+                )
+            }
+            "anthropic" => {
+                let api_key = self.api_key(&name, provider_config, true)?;
+                let mut anthropic = AnthropicProvider::new(api_key.unwrap_or_default(), Some(model))
+                    .with_params(params.temperature, params.max_tokens);
+                if let Some(base_url) = &provider_config.base_url {
+                    anthropic = anthropic.with_base_url(base_url.clone());
+                }
+                if let Some(budget) = self.overrides.thinking_budget.or(provider_config.thinking_budget) {
+                    anthropic = anthropic.with_thinking(budget);
+                }
+                if let Some(enabled) = provider_config.prompt_caching {
+                    anthropic = anthropic.with_prompt_caching(enabled);
+                }
+                Box::new(anthropic)
+            }
+            "gemini" => {
+                let api_key = self.api_key(&name, provider_config, true)?;
+                let mut gemini = GeminiProvider::new(api_key.unwrap_or_default(), Some(model))
+                    .with_params(params.temperature, params.max_tokens);
+                if let Some(base_url) = &provider_config.base_url {
+                    gemini = gemini.with_base_url(base_url.clone());
+                }
+                Box::new(gemini)
+            }
+            "ollama" => {
+                let api_key = self.api_key(&name, provider_config, false)?;
+                let ollama = OllamaProvider::new(provider_config.base_url.clone(), api_key, Some(model));
+                // Only override the server's sampling defaults when explicitly configured
+                if self.config.providers.contains_key(&name) {
+                    Box::new(ollama.with_params(params.temperature, params.max_tokens))
+                } else {
+                    Box::new(ollama)
+                }
+            }
+            OPENAI_COMPATIBLE => {
+                let api_key = self.api_key(&name, provider_config, false)?;
+                let config = ProviderConfig {
+                    api_key,
+                    ..provider_config.clone()
+                };
+                Box::new(OpenAICompatibleProvider::from_config(&name, &config, model)?)
+            }
+            other => {
+                return Err(ConfigError::Invalid(format!(
+                    "Provider '{}' has unknown type '{}'. Supported types: {}, {}",
+                    name,
+                    other,
+                    BUILTIN_PROVIDERS.join(", "),
+                    OPENAI_COMPATIBLE
+                ))
+                .into())
+            }
+        };
+
+        let mut model = RetryingModel::new(model, RetryPolicy::from(&provider_config.retry));
+        if let Some(observer) = &self.retry_observer {
+            model = model.with_observer(observer.clone());
+        }
+
+        // Cache outside the retry layer so hits never wait on backoff
+        Ok(match &self.cache {
+            Some(cache) => Box::new(CachingModel::new(Box::new(model), cache.clone(), params.clone())),
+            None => Box::new(model),
+        })
+    }
+
+    /// Resolve the API key from `<NAME>_API_KEY`, then the provider's config
+    fn api_key(&self, name: &str, config: &ProviderConfig, required: bool) -> Result<Option<String>> {
+        let env_var = api_key_env_var(name);
+        let key = std::env::var(&env_var)
+            .ok()
+            .or_else(|| config.api_key.clone())
+            .filter(|k| !k.is_empty());
+
+        if key.is_none() && required && self.require_api_key {
+            return Err(ConfigError::MissingApiKey {
+                provider: name.to_string(),
+                env_var,
+            }
+            .into());
+        }
+        Ok(key)
+    }
+
+    fn unknown_provider(&self, name: &str) -> ConfigError {
+        let mut known: Vec<&str> = BUILTIN_PROVIDERS.to_vec();
+        let mut configured: Vec<&str> = self
+            .config
+            .providers
+            .keys()
+            .map(String::as_str)
+            .filter(|p| !BUILTIN_PROVIDERS.contains(p))
+            .collect();
+        configured.sort_unstable();
+        known.extend(configured);
+
+        ConfigError::UnknownProvider {
+            name: name.to_string(),
+            known: known.join(", "),
+        }
+    }
+}
+
+/// Environment variable holding a provider's API key (e.g. `OPENAI_API_KEY`)
+pub fn api_key_env_var(provider: &str) -> String {
+    format!("{}_API_KEY", provider.to_uppercase().replace(['-', '.', ' '], "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FallbackModelConfig, ModelParams};
+    use crate::error::PromptLineError;
+
+    fn config_with(name: &str, provider: ProviderConfig) -> ModelConfig {
+        let mut config = ModelConfig {
+            active_provider: Some(name.to_string()),
+            default: "test-model".to_string(),
+            ..ModelConfig::default()
+        };
+        config.providers.insert(name.to_string(), provider);
+        config
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let config = config_with("ollama", ProviderConfig::default());
+        let factory = ProviderFactory::new(&config).with_overrides(ProviderOverrides {
+            provider: Some("anthropic".to_string()),
+            model: Some("claude-3-5-haiku-latest".to_string()),
+            thinking_budget: None,
+        });
+
+        assert_eq!(factory.provider_name(), "anthropic");
+        assert_eq!(factory.model_name(), "claude-3-5-haiku-latest");
+        assert_eq!(ProviderFactory::new(&config).provider_name(), "ollama");
+    }
+
+    #[test]
+    fn test_applies_default_params() {
+        let config = config_with(
+            "ollama",
+            ProviderConfig {
+                default_params: ModelParams {
+                    temperature: 0.0,
+                    max_tokens: 1234,
+                },
+                ..ProviderConfig::default()
+            },
+        );
+
+        let model = ProviderFactory::new(&config).build().unwrap();
+        let info = model.model_info();
+        assert_eq!(info.provider, "ollama");
+        assert_eq!(info.model, "test-model");
+        assert_eq!(info.max_tokens, 1234);
+    }
+
+    #[test]
+    fn test_named_openai_compatible_provider() {
+        let config = config_with(
+            "local-vllm",
+            ProviderConfig {
+                kind: Some(OPENAI_COMPATIBLE.to_string()),
+                base_url: Some("http://localhost:8000/v1".to_string()),
+                ..ProviderConfig::default()
+            },
+        );
+
+        let model = ProviderFactory::new(&config).build().unwrap();
+        assert_eq!(model.model_info().provider, "local-vllm");
+    }
+
+    #[tokio::test]
+    async fn test_builds_fallback_chain() {
+        let mut config = config_with(
+            "primary",
+            ProviderConfig {
+                kind: Some(OPENAI_COMPATIBLE.to_string()),
+                // Nothing listens here, so the request fails to connect
+                base_url: Some("http://127.0.0.1:9/v1".to_string()),
+                retry: crate::config::RetryConfig {
+                    max_retries: 0,
+                    ..Default::default()
+                },
+                ..ProviderConfig::default()
+            },
+        );
+        config.providers.insert(
+            "ollama".to_string(),
+            ProviderConfig {
+                base_url: Some("http://127.0.0.1:9".to_string()),
+                retry: crate::config::RetryConfig {
+                    max_retries: 0,
+                    ..Default::default()
+                },
+                ..ProviderConfig::default()
+            },
+        );
+        config.fallbacks = vec![
+            FallbackModelConfig {
+                provider: "missing".to_string(),
+                model: None,
+            },
+            FallbackModelConfig {
+                provider: "ollama".to_string(),
+                model: Some("llama3".to_string()),
+            },
+        ];
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let model = ProviderFactory::new(&config)
+            .with_failover_observer(std::sync::Arc::new(move |e| sink.lock().unwrap().push(e.to.clone())))
+            .build()
+            .unwrap();
+
+        assert_eq!(model.model_info().provider, "primary");
+        // The fallback is unreachable too, but the failover to it is still reported
+        let _ = model.chat(&[crate::model::Message::user("Hi")]).await;
+        assert_eq!(*events.lock().unwrap(), vec!["ollama/llama3".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_thinking_and_caching_from_config() {
+        use wiremock::matchers::body_partial_json;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(body_partial_json(serde_json::json!({"thinking": {"type": "enabled", "budget_tokens": 1024}})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "model": "claude-test",
+                "content": [{"type": "text", "text": "Hi"}],
+                "usage": {"input_tokens": 1, "output_tokens": 1}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let config = config_with(
+            "anthropic",
+            ProviderConfig {
+                api_key: Some("key".to_string()),
+                base_url: Some(server.uri()),
+                thinking_budget: Some(1024),
+                prompt_caching: Some(false),
+                ..ProviderConfig::default()
+            },
+        );
+
+        let model = ProviderFactory::new(&config).build().unwrap();
+        model.chat(&[crate::model::Message::system("sys"), crate::model::Message::user("Hi")]).await.unwrap();
+
+        let request: serde_json::Value = server.received_requests().await.unwrap()[0].body_json().unwrap();
+        assert!(request["system"][0].get("cache_control").is_none());
+    }
+
+    #[test]
+    fn test_unknown_provider() {
+        let config = ModelConfig::default();
+        let err = ProviderFactory::new(&config)
+            .with_overrides(ProviderOverrides {
+                provider: Some("nope".to_string()),
+                model: None,
+                thinking_budget: None,
+            })
+            .build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            err,
+            PromptLineError::Config(ConfigError::UnknownProvider { ref name, ref known })
+                if name == "nope" && known.contains("anthropic")
+        ));
+    }
+
+    #[test]
+    fn test_missing_api_key() {
+        let config = config_with("test-missing-key", ProviderConfig {
+            kind: Some("anthropic".to_string()),
+            ..ProviderConfig::default()
+        });
+
+        let err = ProviderFactory::new(&config).build().err().unwrap();
+        assert!(matches!(
+            err,
+            PromptLineError::Config(ConfigError::MissingApiKey { ref env_var, .. })
+                if env_var == "TEST_MISSING_KEY_API_KEY"
+        ));
+
+        assert!(ProviderFactory::new(&config).require_api_key(false).build().is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+//! Fallback chains across models
+//!
+//! [`FallbackModel`] sends each request to the first model in its chain and
+//! moves on to the next one when a request fails with one of the configured
+//! error classes.
+
+use super::{LanguageModel, Message, ModelInfo, ModelResponse, ToolDefinition};
+use crate::error::{ErrorClass, PromptLineError, Result};
+use crate::tokenizer::Tokenizer;
+use async_trait::async_trait;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A switch from a failing model to the next one in the chain
+#[derive(Debug, Clone)]
+pub struct FailoverEvent {
+    /// `provider/model` that failed
+    pub from: String,
+    /// `provider/model` tried next
+    pub to: String,
+    pub error: String,
+}
+
+impl fmt::Display for FailoverEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed ({}); falling back to {}", self.from, self.error, self.to)
+    }
+}
+
+/// Callback notified on each failover
+pub type FailoverObserver = Arc<dyn Fn(&FailoverEvent) + Send + Sync>;
+
+/// Tries an ordered list of models until one succeeds
+pub struct FallbackModel {
+    models: Vec<Box<dyn LanguageModel>>,
+    failover_on: Vec<ErrorClass>,
+    observer: Option<FailoverObserver>,
+    /// Index of the model that served the last request
+    active: AtomicUsize,
+}
+
+impl FallbackModel {
+    /// Create a chain from the primary model followed by its fallbacks
+    ///
+    /// # Panics
+    /// Panics if `models` is empty.
+    pub fn new(models: Vec<Box<dyn LanguageModel>>, failover_on: Vec<ErrorClass>) -> Self {
+        assert!(!models.is_empty(), "a fallback chain needs at least one model");
+        Self {
+            models,
+            failover_on,
+            observer: None,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Report each failover to `observer`
+    pub fn with_observer(mut self, observer: FailoverObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Model that served the most recent request
+    pub fn active(&self) -> &dyn LanguageModel {
+        self.models[self.active.load(Ordering::Relaxed)].as_ref()
+    }
+
+    fn label(model: &dyn LanguageModel) -> String {
+        let info = model.model_info();
+        format!("{}/{}", info.provider, info.model)
+    }
+
+    /// Decide whether to move on from model `index` after `error`
+    fn should_fail_over(&self, index: usize, error: &PromptLineError) -> bool {
+        let class = match error {
+            PromptLineError::Model(e) => e.class(),
+            _ => return false,
+        };
+        if index + 1 >= self.models.len() || !self.failover_on.contains(&class) {
+            return false;
+        }
+
+        let event = FailoverEvent {
+            from: Self::label(self.models[index].as_ref()),
+            to: Self::label(self.models[index + 1].as_ref()),
+            error: error.to_string(),
+        };
+        tracing::warn!("{}", event);
+        if let Some(observer) = &self.observer {
+            observer(&event);
+        }
+        true
+    }
+
+    fn finish(&self, index: usize, mut response: ModelResponse) -> ModelResponse {
+        self.active.store(index, Ordering::Relaxed);
+        if response.model.is_empty() {
+            response.model = self.models[index].model_info().model;
+        }
+        response
+    }
+
+    async fn run<'a, F, Fut>(&'a self, mut request: F) -> Result<ModelResponse>
+    where
+        F: FnMut(&'a dyn LanguageModel) -> Fut,
+        Fut: Future<Output = Result<ModelResponse>>,
+    {
+        let mut index = 0;
+        loop {
+            match request(self.models[index].as_ref()).await {
+                Ok(response) => return Ok(self.finish(index, response)),
+                Err(error) if self.should_fail_over(index, &error) => index += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl LanguageModel for FallbackModel {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        self.run(|model| model.complete(prompt, system_prompt)).await
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        self.run(|model| model.chat(messages)).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        self.run(|model| model.chat_with_tools(messages, tools)).await
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let mut index = 0;
+        loop {
+            let mut emitted = false;
+            let result = {
+                let mut forward = |delta: &str| {
+                    emitted = true;
+                    on_delta(delta);
+                };
+                self.models[index].chat_stream(messages, tools, &mut forward).await
+            };
+
+            match result {
+                Ok(response) => return Ok(self.finish(index, response)),
+                // Text already shown to the user cannot be taken back
+                Err(error) if !emitted && self.should_fail_over(index, &error) => index += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        self.active().model_info()
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.active().tokenizer()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.active().supports_tools()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        self.active().supports_streaming()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ModelError;
+    use crate::testing::ScriptedModel;
+    use std::sync::Mutex;
+
+    /// Fails with the given error, or answers with its name
+    fn stub(name: &'static str, error: Option<fn() -> ModelError>) -> Box<dyn LanguageModel> {
+        let model = ScriptedModel::new().with_model_info(ModelInfo {
+            provider: format!("{}-provider", name),
+            model: name.to_string(),
+            max_tokens: 100,
+            context_window: 1000,
+            supports_tools: false,
+            supports_streaming: false,
+        });
+        Box::new(match error {
+            Some(error) => model.fail_with(error),
+            None => model.respond(format!("answer from {}", name)),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_on_retryable_error() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let chain = FallbackModel::new(
+            vec![
+                stub("primary", Some(|| ModelError::RateLimit { retry_after: None })),
+                stub("secondary", None),
+            ],
+            vec![ErrorClass::Retryable],
+        )
+        .with_observer(Arc::new(move |e: &FailoverEvent| sink.lock().unwrap().push(e.to_string())));
+
+        let response = chain.chat(&[Message::user("Hi")]).await.unwrap();
+
+        assert_eq!(response.model, "secondary");
+        assert_eq!(chain.model_info().provider, "secondary-provider");
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].starts_with("primary-provider/primary failed"));
+    }
+
+    #[tokio::test]
+    async fn test_does_not_fail_over_on_other_classes() {
+        let chain = FallbackModel::new(
+            vec![stub("primary", Some(|| ModelError::Auth("bad key".to_string()))), stub("secondary", None)],
+            vec![ErrorClass::Retryable],
+        );
+
+        let err = chain.chat(&[Message::user("Hi")]).await.unwrap_err();
+        assert!(matches!(err, PromptLineError::Model(ModelError::Auth(_))));
+        assert_eq!(chain.model_info().model, "primary");
+    }
+
+    #[tokio::test]
+    async fn test_last_error_returned_when_chain_exhausted() {
+        let chain = FallbackModel::new(
+            vec![
+                stub("primary", Some(|| ModelError::Timeout(std::time::Duration::from_secs(1)))),
+                stub("secondary", Some(|| ModelError::RateLimit { retry_after: None })),
+            ],
+            vec![ErrorClass::Retryable],
+        );
+
+        let err = chain.chat(&[Message::user("Hi")]).await.unwrap_err();
+        assert!(matches!(err, PromptLineError::Model(ModelError::RateLimit { .. })));
+    }
+}
@@ -1,13 +1,21 @@
 //! Google Gemini API provider implementation
 
-use super::{LanguageModel, Message, ModelInfo, ModelResponse, ToolDefinition, TokenUsage};
+use super::{flatten_tool_messages, ContentPart, LanguageModel, Message, Role, ModelInfo, ModelResponse, ToolCall, ToolDefinition, TokenUsage};
 use crate::error::{ModelError, Result};
 use async_trait::async_trait;
 use serde_json::json;
 
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Finish reasons that mean the candidate was withheld by a content filter
+const BLOCKED_FINISH_REASONS: &[&str] = &[
+    "SAFETY", "RECITATION", "BLOCKLIST", "PROHIBITED_CONTENT", "SPII", "IMAGE_SAFETY",
+];
+
 pub struct GeminiProvider {
     api_key: String,
     model: String,
+    base_url: String,
     temperature: f32,
     max_tokens: usize,
     client: reqwest::Client,
@@ -18,6 +26,7 @@ impl GeminiProvider {
         Self {
             api_key,
             model: model.unwrap_or_else(|| "gemini-pro".to_string()),
+            base_url: DEFAULT_BASE_URL.to_string(),
             temperature: 0.2,
             max_tokens: 4096,
             client: reqwest::Client::new(),
@@ -30,28 +39,186 @@ impl GeminiProvider {
         self
     }
 
-    fn convert_messages(&self, messages: &[Message]) -> Vec<serde_json::Value> {
-        let mut parts = Vec::new();
-        
+    /// Use a different API endpoint (e.g. a local stub server)
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Convert non-system messages to `contents`
+    fn convert_messages(&self, messages: &[Message]) -> Result<Vec<serde_json::Value>> {
+        let mut contents = Vec::new();
+        // functionResponse parts are matched to calls by name, not id
+        let mut call_names = std::collections::HashMap::new();
+
         for msg in messages {
-            let role = match msg.role.as_str() {
-                "system" => "user", // Gemini doesn't have system role, merge into user
-                "assistant" => "model",
-                _ => "user",
+            let role = match msg.role {
+                Role::System => continue, // Sent separately as systemInstruction
+                Role::Assistant => "model",
+                Role::User | Role::Tool => "user",
             };
-            
-            parts.push(json!({
+
+            let mut parts = Vec::new();
+            for part in &msg.content {
+                parts.push(match part {
+                    ContentPart::Text { text } => json!({"text": text}),
+                    ContentPart::Image { image } => {
+                        let image = image.load()?;
+                        json!({"inlineData": {"mimeType": image.media_type, "data": image.data}})
+                    }
+                    ContentPart::ToolCall(call) => {
+                        call_names.insert(call.id.as_str(), call.name.as_str());
+                        json!({"functionCall": {"name": call.name, "args": call.arguments}})
+                    }
+                    ContentPart::ToolResult {
+                        tool_call_id,
+                        content,
+                        ..
+                    } => json!({
+                        "functionResponse": {
+                            "name": call_names.get(tool_call_id.as_str()).copied().unwrap_or(tool_call_id.as_str()),
+                            "response": {"content": content},
+                        }
+                    }),
+                    ContentPart::Thinking { .. } => continue,
+                });
+            }
+
+            contents.push(json!({
                 "role": role,
-                "parts": [{
-                    "text": msg.content
-                }]
+                "parts": parts,
             }));
         }
-        
-        parts
+
+        Ok(contents)
+    }
+
+    /// Join system messages into a `systemInstruction`
+    fn system_instruction(&self, messages: &[Message]) -> Option<serde_json::Value> {
+        let system: Vec<String> = messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .map(|m| m.text())
+            .collect();
+
+        (!system.is_empty()).then(|| json!({"parts": [{"text": system.join("\n\n")}]}))
+    }
+
+    fn parse_response(&self, response_json: &serde_json::Value) -> Result<ModelResponse> {
+        if let Some(reason) = response_json["promptFeedback"]["blockReason"].as_str() {
+            return Err(ModelError::ContentFiltered(format!("prompt blocked ({})", reason)).into());
+        }
+
+        let candidate = response_json["candidates"]
+            .get(0)
+            .ok_or_else(|| ModelError::InvalidResponse("Gemini returned no candidates".to_string()))?;
+        let finish_reason = candidate["finishReason"].as_str();
+
+        if let Some(reason) = finish_reason.filter(|r| BLOCKED_FINISH_REASONS.contains(r)) {
+            return Err(ModelError::ContentFiltered(format!("response blocked ({})", reason)).into());
+        }
+        if finish_reason == Some("MALFORMED_FUNCTION_CALL") {
+            return Err(ModelError::InvalidResponse("Gemini produced a malformed function call".to_string()).into());
+        }
+
+        let mut content = String::new();
+        let mut tool_calls = Vec::new();
+
+        for part in candidate["content"]["parts"].as_array().into_iter().flatten() {
+            if let Some(text) = part["text"].as_str() {
+                content.push_str(text);
+            }
+            if let Some(call) = part.get("functionCall") {
+                // Gemini does not assign call ids, so number the calls
+                tool_calls.push(ToolCall {
+                    id: format!("call_{}", tool_calls.len()),
+                    name: call["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: call.get("args").cloned().unwrap_or_else(|| json!({})),
+                });
+            }
+        }
+
+        // Extract token usage if available
+        let usage = if let Some(usage_metadata) = response_json.get("usageMetadata") {
+            TokenUsage {
+                prompt_tokens: usage_metadata["promptTokenCount"].as_u64().unwrap_or(0) as usize,
+                completion_tokens: usage_metadata["candidatesTokenCount"].as_u64().unwrap_or(0) as usize,
+                total_tokens: usage_metadata["totalTokenCount"].as_u64().unwrap_or(0) as usize,
+            }
+        } else {
+            TokenUsage::default()
+        };
+
+        if content.is_empty() && tool_calls.is_empty() {
+            return Err(ModelError::InvalidResponse(format!(
+                "Gemini returned an empty candidate (finish reason: {})",
+                finish_reason.unwrap_or("unknown")
+            ))
+            .into());
+        }
+
+        Ok(ModelResponse {
+            content,
+            model: self.model.clone(),
+            usage,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            thinking: None,
+            thinking_parts: Vec::new(),
+            finish_reason: finish_reason.map(|s| s.to_string()),
+        })
     }
 }
 
+/// Whether a Gemini model supports function calling
+fn model_supports_tools(model: &str) -> bool {
+    model.starts_with("gemini") && !model.contains("vision")
+}
+
+/// Keywords of the OpenAPI schema subset accepted in function declarations
+const SCHEMA_KEYS: &[&str] = &[
+    "type", "format", "description", "nullable", "enum", "properties", "required", "items",
+    "minItems", "maxItems", "minimum", "maximum",
+];
+
+/// Convert a JSON Schema into Gemini's OpenAPI-style schema
+///
+/// Unsupported keywords such as `additionalProperties` or `$schema` are
+/// dropped, and `"type": ["string", "null"]` becomes a nullable string.
+fn to_gemini_schema(schema: &serde_json::Value) -> serde_json::Value {
+    let Some(object) = schema.as_object() else {
+        return schema.clone();
+    };
+
+    let mut converted = serde_json::Map::new();
+    for (key, value) in object {
+        if !SCHEMA_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let value = match key.as_str() {
+            "type" => match value.as_array() {
+                Some(types) => {
+                    if types.iter().any(|t| t == "null") {
+                        converted.insert("nullable".to_string(), json!(true));
+                    }
+                    types.iter().find(|t| *t != "null").cloned().unwrap_or(json!("string"))
+                }
+                None => value.clone(),
+            },
+            "properties" => json!(value
+                .as_object()
+                .map(|props| props
+                    .iter()
+                    .map(|(name, prop)| (name.clone(), to_gemini_schema(prop)))
+                    .collect::<serde_json::Map<_, _>>())
+                .unwrap_or_default()),
+            "items" => to_gemini_schema(value),
+            _ => value.clone(),
+        };
+        converted.insert(key.clone(), value);
+    }
+    serde_json::Value::Object(converted)
+}
+
 #[async_trait]
 impl LanguageModel for GeminiProvider {
     async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
@@ -67,14 +234,23 @@ impl LanguageModel for GeminiProvider {
     }
 
     async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1/models/{}:generateContent?key={}",
-            self.model, self.api_key
-        );
+        self.chat_with_tools(messages, &[]).await
+    }
 
-        let contents = self.convert_messages(messages);
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        let url = format!("{}/models/{}:generateContent", self.base_url, self.model);
+
+        let contents = if tools.is_empty() || !self.supports_tools() {
+            self.convert_messages(&flatten_tool_messages(messages))?
+        } else {
+            self.convert_messages(messages)?
+        };
         
-        let request_body = json!({
+        let mut request_body = json!({
             "contents": contents,
             "generationConfig": {
                 "temperature": self.temperature,
@@ -82,17 +258,35 @@ impl LanguageModel for GeminiProvider {
             }
         });
 
+        if let Some(instruction) = self.system_instruction(messages) {
+            request_body["systemInstruction"] = instruction;
+        }
+
+        if !tools.is_empty() && self.supports_tools() {
+            let declarations: Vec<_> = tools
+                .iter()
+                .map(|t| {
+                    json!({
+                        "name": t.name,
+                        "description": t.description,
+                        "parameters": to_gemini_schema(&t.parameters),
+                    })
+                })
+                .collect();
+            request_body["tools"] = json!([{ "functionDeclarations": declarations }]);
+        }
+
         let response = self
             .client
             .post(&url)
+            .header("x-goog-api-key", &self.api_key)
             .json(&request_body)
             .send()
             .await
-            .map_err(|e| ModelError::Api(format!("Request failed: {}", e)))?;
+            .map_err(ModelError::Request)?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ModelError::Api(format!("API error: {}", error_text)).into());
+            return Err(super::retry::error_from_response("Gemini", response).await.into());
         }
 
         let response_json: serde_json::Value = response
@@ -100,42 +294,7 @@ impl LanguageModel for GeminiProvider {
             .await
             .map_err(|e| ModelError::InvalidResponse(format!("Failed to parse response: {}", e)))?;
 
-        // Extract content from Gemini response
-        let content = response_json["candidates"][0]["content"]["parts"][0]["text"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
-
-        // Extract token usage if available
-        let usage = if let Some(usage_metadata) = response_json.get("usageMetadata") {
-            TokenUsage {
-                prompt_tokens: usage_metadata["promptTokenCount"].as_u64().unwrap_or(0) as usize,
-                completion_tokens: usage_metadata["candidatesTokenCount"].as_u64().unwrap_or(0) as usize,
-                total_tokens: usage_metadata["totalTokenCount"].as_u64().unwrap_or(0) as usize,
-            }
-        } else {
-            TokenUsage::default()
-        };
-
-        Ok(ModelResponse {
-            content,
-            model: self.model.clone(),
-            usage,
-            tool_calls: None,
-            finish_reason: response_json["candidates"][0]["finishReason"]
-                .as_str()
-                .map(|s| s.to_string()),
-        })
-    }
-
-    async fn chat_with_tools(
-        &self,
-        messages: &[Message],
-        _tools: &[ToolDefinition],
-    ) -> Result<ModelResponse> {
-        // For MVP, use regular chat
-        // Gemini supports function calling but we'll implement it in Phase 2
-        self.chat(messages).await
+        self.parse_response(&response_json)
     }
 
     fn model_info(&self) -> ModelInfo {
@@ -143,13 +302,14 @@ impl LanguageModel for GeminiProvider {
             provider: "gemini".to_string(),
             model: self.model.clone(),
             max_tokens: self.max_tokens,
-            supports_tools: true,
+            context_window: super::context_window_for(&self.model),
+            supports_tools: self.supports_tools(),
             supports_streaming: false,
         }
     }
 
     fn supports_tools(&self) -> bool {
-        true
+        model_supports_tools(&self.model)
     }
 
     fn supports_streaming(&self) -> bool {
@@ -160,6 +320,7 @@ impl LanguageModel for GeminiProvider {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::ImageSource;
 
     #[test]
     fn test_gemini_provider_creation() {
@@ -180,7 +341,141 @@ mod tests {
             Message::assistant("Hi there"),
         ];
 
-        let converted = provider.convert_messages(&messages);
-        assert_eq!(converted.len(), 3);
+        let converted = provider.convert_messages(&messages).unwrap();
+        assert_eq!(converted.len(), 2);
+        assert_eq!(converted[1]["role"], "model");
+
+        let instruction = provider.system_instruction(&messages).unwrap();
+        assert_eq!(instruction["parts"][0]["text"], "You are helpful");
+    }
+
+    #[test]
+    fn test_schema_conversion() {
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "path": {"type": "string", "description": "File path"},
+                "limit": {"type": ["integer", "null"], "default": 10},
+                "tags": {"type": "array", "items": {"type": "string", "additionalProperties": false}}
+            },
+            "required": ["path"]
+        });
+
+        let converted = to_gemini_schema(&schema);
+
+        assert!(converted.get("$schema").is_none());
+        assert!(converted.get("additionalProperties").is_none());
+        assert_eq!(converted["properties"]["limit"], json!({"type": "integer", "nullable": true}));
+        assert_eq!(converted["properties"]["tags"]["items"], json!({"type": "string"}));
+        assert_eq!(converted["required"], json!(["path"]));
+    }
+
+    #[test]
+    fn test_parse_function_call() {
+        let provider = GeminiProvider::new("test-key".to_string(), Some("gemini-1.5-pro".to_string()));
+        let response = provider.parse_response(&json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "Reading it. "},
+                    {"functionCall": {"name": "file_read", "args": {"path": "main.rs"}}}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 12, "candidatesTokenCount": 4, "totalTokenCount": 16}
+        })).unwrap();
+
+        assert_eq!(response.content, "Reading it. ");
+        assert_eq!(response.usage.total_tokens, 16);
+        let calls = response.tool_calls.unwrap();
+        assert_eq!(calls[0].name, "file_read");
+        assert_eq!(calls[0].arguments["path"], "main.rs");
+    }
+
+    #[test]
+    fn test_multimodal_and_tool_messages() {
+        let provider = GeminiProvider::new("test-key".to_string(), None);
+        let messages = vec![
+            Message::user("What is this?").with_image(ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }),
+            Message::new(
+                Role::Assistant,
+                vec![ContentPart::ToolCall(ToolCall {
+                    id: "call_1".to_string(),
+                    name: "file_read".to_string(),
+                    arguments: json!({"path": "a.rs"}),
+                })],
+            ),
+            Message::tool_result("call_1", "fn main() {}", false),
+        ];
+
+        let contents = provider.convert_messages(&messages).unwrap();
+
+        assert_eq!(contents[0]["parts"][1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(contents[1]["parts"][0]["functionCall"]["name"], "file_read");
+        assert_eq!(contents[2]["parts"][0]["functionResponse"]["name"], "file_read");
+        assert_eq!(contents[2]["parts"][0]["functionResponse"]["response"]["content"], "fn main() {}");
+    }
+
+    #[test]
+    fn test_blocked_and_empty_responses() {
+        let provider = GeminiProvider::new("test-key".to_string(), None);
+        let is_filtered = |response: serde_json::Value| {
+            matches!(
+                provider.parse_response(&response),
+                Err(crate::error::PromptLineError::Model(ModelError::ContentFiltered(_)))
+            )
+        };
+
+        assert!(is_filtered(json!({"promptFeedback": {"blockReason": "SAFETY"}})));
+        assert!(is_filtered(json!({"candidates": [{"finishReason": "RECITATION"}]})));
+        assert!(matches!(
+            provider.parse_response(&json!({"candidates": []})),
+            Err(crate::error::PromptLineError::Model(ModelError::InvalidResponse(_)))
+        ));
+        assert!(matches!(
+            provider.parse_response(&json!({"candidates": [{"content": {"parts": []}, "finishReason": "MAX_TOKENS"}]})),
+            Err(crate::error::PromptLineError::Model(ModelError::InvalidResponse(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_uses_header_auth_and_system_instruction() {
+        use wiremock::matchers::{body_partial_json, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-1.5-flash:generateContent"))
+            .and(header("x-goog-api-key", "secret"))
+            .and(body_partial_json(json!({"systemInstruction": {"parts": [{"text": "Be brief"}]}})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": "Hello, "}, {"text": "world"}]},
+                    "finishReason": "STOP"
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = GeminiProvider::new("secret".to_string(), Some("gemini-1.5-flash".to_string()))
+            .with_base_url(server.uri());
+        let response = provider
+            .chat(&[Message::system("Be brief"), Message::user("Hi")])
+            .await
+            .unwrap();
+
+        assert_eq!(response.content, "Hello, world");
+        let request = &server.received_requests().await.unwrap()[0];
+        assert!(!request.url.as_str().contains("secret"));
+    }
+
+    #[test]
+    fn test_supports_tools_per_model() {
+        assert!(GeminiProvider::new("k".to_string(), Some("gemini-1.5-flash".to_string())).supports_tools());
+        assert!(!GeminiProvider::new("k".to_string(), Some("gemini-pro-vision".to_string())).supports_tools());
     }
 }
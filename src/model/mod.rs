@@ -1,43 +1,206 @@
 //! Language model provider interface
 
 use crate::error::Result;
+use crate::tokenizer::Tokenizer;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
+pub mod anthropic;
+pub mod cache;
+pub mod content;
+pub mod factory;
+pub mod fallback;
 pub mod gemini;
 pub mod openai;
+pub mod openai_compat;
+pub mod replay;
+pub mod retry;
 pub mod ollama;
 
+pub use content::{ContentPart, ImageData, ImageSource};
+
+/// Who a message is from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    /// Output of a tool call, linked to the call by id
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::User => "user",
+            Self::Assistant => "assistant",
+            Self::Tool => "tool",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Message in a conversation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
-    pub role: String,
-    pub content: String,
+    pub role: Role,
+    /// Content parts; plain-string content from older history files is read as one text part
+    #[serde(deserialize_with = "content::deserialize_content")]
+    pub content: Vec<ContentPart>,
+    /// Pinned messages are kept verbatim when the conversation is compacted
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
 }
 
 impl Message {
-    pub fn system(content: impl Into<String>) -> Self {
+    /// Create a message from content parts
+    pub fn new(role: Role, content: Vec<ContentPart>) -> Self {
         Self {
-            role: "system".to_string(),
-            content: content.into(),
+            role,
+            content,
+            pinned: false,
         }
     }
 
+    fn text_message(role: Role, text: String) -> Self {
+        Self::new(role, vec![ContentPart::Text { text }])
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::text_message(Role::System, content.into())
+    }
+
     pub fn user(content: impl Into<String>) -> Self {
-        Self {
-            role: "user".to_string(),
-            content: content.into(),
-        }
+        Self::text_message(Role::User, content.into())
     }
 
     pub fn assistant(content: impl Into<String>) -> Self {
-        Self {
-            role: "assistant".to_string(),
-            content: content.into(),
-        }
+        Self::text_message(Role::Assistant, content.into())
+    }
+
+    /// Output of the tool call with id `tool_call_id`
+    pub fn tool_result(tool_call_id: impl Into<String>, content: impl Into<String>, is_error: bool) -> Self {
+        Self::new(Role::Tool, vec![ContentPart::tool_result(tool_call_id, content, is_error)])
+    }
+
+    /// Mark this message as pinned
+    pub fn pinned(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+
+    /// Append an image part
+    pub fn with_image(mut self, image: ImageSource) -> Self {
+        self.content.push(ContentPart::Image { image });
+        self
+    }
+
+    /// Text parts joined with newlines
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// All parts rendered as text, for transcripts and summaries
+    pub fn transcript(&self) -> String {
+        self.content
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => text.clone(),
+                ContentPart::Image { .. } => "[image]".to_string(),
+                ContentPart::ToolCall(call) => format!("[tool call {}: {} {}]", call.id, call.name, call.arguments),
+                ContentPart::ToolResult { tool_call_id, content, .. } => {
+                    format!("[tool result {}]: {}", tool_call_id, content)
+                }
+                ContentPart::Thinking { .. } => "[thinking]".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Whether the message consists only of text
+    pub fn is_text_only(&self) -> bool {
+        self.content.iter().all(|part| matches!(part, ContentPart::Text { .. }))
+    }
+
+    /// Images attached to the message
+    pub fn images(&self) -> impl Iterator<Item = &ImageSource> {
+        self.content.iter().filter_map(|part| match part {
+            ContentPart::Image { image } => Some(image),
+            _ => None,
+        })
+    }
+
+    /// Tool calls requested in the message
+    pub fn tool_calls(&self) -> impl Iterator<Item = &ToolCall> {
+        self.content.iter().filter_map(|part| match part {
+            ContentPart::ToolCall(call) => Some(call),
+            _ => None,
+        })
     }
 }
 
+/// Rewrite tool calls and results as plain text, for requests made without native tools
+///
+/// Tool calls are dropped from assistant messages that already have text (the
+/// text protocol puts the call JSON there) and tool messages become user messages.
+pub fn flatten_tool_messages(messages: &[Message]) -> Vec<Message> {
+    let mut call_names = std::collections::HashMap::new();
+
+    messages
+        .iter()
+        .map(|msg| {
+            let has_text = msg.content.iter().any(|part| matches!(part, ContentPart::Text { .. }));
+            let mut content = Vec::new();
+            for part in &msg.content {
+                match part {
+                    ContentPart::ToolCall(call) => {
+                        call_names.insert(call.id.as_str(), call.name.as_str());
+                        if !has_text {
+                            let call_json = serde_json::json!({"tool": call.name, "args": call.arguments});
+                            content.push(ContentPart::text(call_json.to_string()));
+                        }
+                    }
+                    ContentPart::ToolResult {
+                        tool_call_id,
+                        content: output,
+                        is_error,
+                    } => {
+                        let name = call_names.get(tool_call_id.as_str()).copied().unwrap_or("unknown");
+                        let outcome = if *is_error { "failed" } else { "result" };
+                        content.push(ContentPart::text(format!("Tool '{}' {}: {}", name, outcome, output)));
+                    }
+                    part => content.push(part.clone()),
+                }
+            }
+
+            let role = match msg.role {
+                Role::Tool => Role::User,
+                role => role,
+            };
+            Message {
+                role,
+                content,
+                pinned: msg.pinned,
+            }
+        })
+        .collect()
+}
+
 /// Tool definition for function calling
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolDefinition {
@@ -47,17 +210,22 @@ pub struct ToolDefinition {
 }
 
 /// Model response
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelResponse {
     pub content: String,
     pub model: String,
     pub usage: TokenUsage,
     pub tool_calls: Option<Vec<ToolCall>>,
     pub finish_reason: Option<String>,
+    /// Reasoning produced by models with extended thinking
+    pub thinking: Option<String>,
+    /// Signed thinking parts to keep in the assistant message, for providers that require them back
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thinking_parts: Vec<ContentPart>,
 }
 
 /// Token usage information
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct TokenUsage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
@@ -65,7 +233,7 @@ pub struct TokenUsage {
 }
 
 /// Tool call from model
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ToolCall {
     pub id: String,
     pub name: String,
@@ -92,13 +260,33 @@ pub trait LanguageModel: Send + Sync {
         tools: &[ToolDefinition],
     ) -> Result<ModelResponse>;
 
+    /// Generate a chat completion, passing each text fragment to `on_delta` as it arrives
+    ///
+    /// Providers without streaming support deliver the whole response as one fragment.
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let response = self.chat_with_tools(messages, tools).await?;
+        on_delta(&response.content);
+        Ok(response)
+    }
+
     /// Get model information
     fn model_info(&self) -> ModelInfo;
 
+    /// Tokenizer used for counting tokens
+    ///
+    /// Picks a BPE vocabulary from the model name, falling back to a heuristic.
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        crate::tokenizer::for_model(&self.model_info().model)
+    }
+
     /// Estimate token count for text
     fn estimate_tokens(&self, text: &str) -> usize {
-        // Rough estimate: 1 token ≈ 4 characters
-        (text.len() + 3) / 4
+        self.tokenizer().count(text)
     }
 
     /// Check if model supports tool calling
@@ -117,11 +305,46 @@ pub trait LanguageModel: Send + Sync {
 pub struct ModelInfo {
     pub provider: String,
     pub model: String,
+    /// Maximum tokens generated per response
     pub max_tokens: usize,
+    /// Total context window (prompt + completion) in tokens
+    pub context_window: usize,
     pub supports_tools: bool,
     pub supports_streaming: bool,
 }
 
+/// Look up the context window size for a model name
+///
+/// Unknown models get a conservative 8k window.
+pub fn context_window_for(model: &str) -> usize {
+    let model = model.to_lowercase();
+    let known: &[(&str, usize)] = &[
+        ("gpt-4o", 128_000),
+        ("gpt-4.1", 1_047_576),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4-32k", 32_768),
+        ("gpt-4", 8_192),
+        ("gpt-3.5-turbo", 16_385),
+        ("o1", 200_000),
+        ("o3", 200_000),
+        ("o4", 200_000),
+        ("claude", 200_000),
+        ("gemini-1.5", 1_048_576),
+        ("gemini-2", 1_048_576),
+        ("gemini-pro", 32_760),
+        ("llama3", 8_192),
+        ("llama2", 4_096),
+        ("mistral", 32_768),
+        ("qwen", 32_768),
+    ];
+
+    known
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, size)| *size)
+        .unwrap_or(8_192)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,49 +352,71 @@ mod tests {
     #[test]
     fn test_message_creation() {
         let msg = Message::user("Hello");
-        assert_eq!(msg.role, "user");
-        assert_eq!(msg.content, "Hello");
+        assert_eq!(msg.role, Role::User);
+        assert_eq!(msg.text(), "Hello");
 
         let sys = Message::system("System prompt");
-        assert_eq!(sys.role, "system");
+        assert_eq!(sys.role, Role::System);
     }
 
     #[test]
     fn test_token_estimation() {
-        struct MockModel;
+        let model = crate::testing::ScriptedModel::new();
+        let tokens = model.estimate_tokens("Hello world!");
+        assert!(tokens > 0);
+        assert!(tokens < 10);
+    }
 
-        #[async_trait]
-        impl LanguageModel for MockModel {
-            async fn complete(&self, _: &str, _: Option<&str>) -> Result<ModelResponse> {
-                unimplemented!()
-            }
+    #[test]
+    fn test_context_window_lookup() {
+        assert_eq!(context_window_for("gpt-4o-mini"), 128_000);
+        assert_eq!(context_window_for("gpt-4"), 8_192);
+        assert_eq!(context_window_for("some-unknown-model"), 8_192);
+    }
 
-            async fn chat(&self, _: &[Message]) -> Result<ModelResponse> {
-                unimplemented!()
-            }
+    #[test]
+    fn test_pinned_message_roundtrip() {
+        let json = serde_json::to_string(&Message::user("Hi")).unwrap();
+        assert!(!json.contains("pinned"));
 
-            async fn chat_with_tools(
-                &self,
-                _: &[Message],
-                _: &[ToolDefinition],
-            ) -> Result<ModelResponse> {
-                unimplemented!()
-            }
+        let legacy: Message = serde_json::from_str(r#"{"role":"user","content":"Hi"}"#).unwrap();
+        assert!(!legacy.pinned);
 
-            fn model_info(&self) -> ModelInfo {
-                ModelInfo {
-                    provider: "mock".to_string(),
-                    model: "test".to_string(),
-                    max_tokens: 4096,
-                    supports_tools: false,
-                    supports_streaming: false,
-                }
-            }
-        }
+        let pinned = Message::user("Keep me").pinned();
+        let json = serde_json::to_string(&pinned).unwrap();
+        let parsed: Message = serde_json::from_str(&json).unwrap();
+        assert!(parsed.pinned);
+    }
 
-        let model = MockModel;
-        let tokens = model.estimate_tokens("Hello world!");
-        assert!(tokens > 0);
-        assert!(tokens < 10);
+    #[test]
+    fn test_flatten_tool_messages() {
+        let messages = vec![
+            Message::new(
+                Role::Assistant,
+                vec![
+                    ContentPart::text("Reading it. {\"tool\": \"file_read\", \"args\": {}}"),
+                    ContentPart::ToolCall(ToolCall {
+                        id: "call_1".to_string(),
+                        name: "file_read".to_string(),
+                        arguments: serde_json::json!({}),
+                    }),
+                ],
+            ),
+            Message::tool_result("call_1", "no such file", true),
+        ];
+
+        let flat = flatten_tool_messages(&messages);
+
+        assert!(flat[0].is_text_only());
+        assert_eq!(flat[0].text(), messages[0].text());
+        assert_eq!(flat[1].role, Role::User);
+        assert_eq!(flat[1].text(), "Tool 'file_read' failed: no such file");
+    }
+
+    #[test]
+    fn test_role_serialization() {
+        let json = serde_json::to_value(Message::tool_result("call_1", "ok", false)).unwrap();
+        assert_eq!(json["role"], "tool");
+        assert_eq!(json["content"][0]["tool_call_id"], "call_1");
     }
 }
@@ -1,5 +1,5 @@
 use crate::error::{Result, ModelError};
-use crate::model::{LanguageModel, ModelResponse};
+use crate::model::{flatten_tool_messages, ContentPart, LanguageModel, ModelResponse, Role};
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::Deserialize;
@@ -10,6 +10,8 @@ pub struct OllamaProvider {
     base_url: String,
     api_key: Option<String>,
     default_model: String,
+    /// Sampling parameters sent as `options`; the server's defaults apply when unset
+    params: Option<(f32, usize)>,
 }
 
 impl OllamaProvider {
@@ -19,8 +21,26 @@ impl OllamaProvider {
             base_url: base_url.unwrap_or_else(|| "http://localhost:11434".to_string()),
             api_key,
             default_model: default_model.unwrap_or_else(|| "llama2".to_string()),
+            params: None,
         }
     }
+
+    pub fn with_params(mut self, temperature: f32, max_tokens: usize) -> Self {
+        self.params = Some((temperature, max_tokens));
+        self
+    }
+}
+
+/// Model families whose Ollama chat templates support tool calling
+const TOOL_MODELS: &[&str] = &[
+    "llama3.1", "llama3.2", "llama3.3", "llama4", "qwen2", "qwen2.5", "qwen3", "mistral", "mixtral",
+    "command-r", "firefunction", "hermes3", "granite3", "nemotron", "smollm2", "deepseek-v3", "gpt-oss",
+];
+
+/// Whether an Ollama model accepts the `tools` parameter
+fn model_supports_tools(model: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    TOOL_MODELS.iter().any(|family| name.starts_with(family))
 }
 
 #[derive(Debug, Deserialize)]
@@ -28,16 +48,89 @@ struct OllamaResponse {
     message: OllamaMessage,
     #[allow(dead_code)]
     done: bool,
+    #[serde(default)]
+    prompt_eval_count: usize,
+    #[serde(default)]
+    eval_count: usize,
 }
 
 #[derive(Debug, Deserialize)]
 struct OllamaMessage {
+    #[serde(default)]
     content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+/// Convert a message to Ollama's format
+///
+/// Images go in a separate `images` list and each tool result becomes a `tool` message.
+fn convert_message(msg: &crate::model::Message) -> Result<Vec<serde_json::Value>> {
+    let mut images = Vec::new();
+    let mut tool_calls = Vec::new();
+    let mut tool_results = Vec::new();
+    for part in &msg.content {
+        match part {
+            ContentPart::Text { .. } | ContentPart::Thinking { .. } => {}
+            ContentPart::Image { image } => images.push(image.load()?.data),
+            ContentPart::ToolCall(call) => {
+                tool_calls.push(json!({"function": {"name": call.name, "arguments": call.arguments}}))
+            }
+            ContentPart::ToolResult { content, .. } => tool_results.push(json!({"role": "tool", "content": content})),
+        }
+    }
+
+    let mut converted = Vec::new();
+    if tool_results.len() < msg.content.len() || msg.content.is_empty() {
+        let role = match msg.role {
+            Role::Tool => Role::User,
+            role => role,
+        };
+        let mut message = json!({"role": role, "content": msg.text()});
+        if !images.is_empty() {
+            message["images"] = json!(images);
+        }
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = json!(tool_calls);
+        }
+        converted.push(message);
+    }
+    converted.extend(tool_results);
+    Ok(converted)
 }
 
 #[async_trait]
 impl LanguageModel for OllamaProvider {
     async fn chat(&self, messages: &[crate::model::Message]) -> Result<ModelResponse> {
+        self.chat_with_tools(messages, &[]).await
+    }
+
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        let mut messages = Vec::new();
+        if let Some(sys) = system_prompt {
+            messages.push(crate::model::Message::system(sys));
+        }
+        messages.push(crate::model::Message::user(prompt));
+        self.chat(&messages).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[crate::model::Message],
+        tools: &[crate::model::ToolDefinition],
+    ) -> Result<ModelResponse> {
         let url = format!("{}/api/chat", self.base_url);
         
         // Debug logging
@@ -52,20 +145,43 @@ impl LanguageModel for OllamaProvider {
             tracing::info!("Ollama Chat: URL={}, Key=None, Model={}", url, self.default_model);
         }
 
+        let native_tools = !tools.is_empty() && self.supports_tools();
+        let messages = if native_tools {
+            messages.to_vec()
+        } else {
+            flatten_tool_messages(messages)
+        };
         let mut ollama_messages = Vec::new();
-        for msg in messages {
-            ollama_messages.push(json!({
-                "role": msg.role,
-                "content": msg.content
-            }));
+        for msg in &messages {
+            ollama_messages.extend(convert_message(msg)?);
+        }
+
+        let mut body = json!({
+            "model": self.default_model,
+            "messages": ollama_messages,
+            "stream": false
+        });
+        if let Some((temperature, max_tokens)) = self.params {
+            body["options"] = json!({"temperature": temperature, "num_predict": max_tokens});
+        }
+        // Models without tool support reject the parameter, so leave it out for them
+        if native_tools {
+            body["tools"] = tools
+                .iter()
+                .map(|t| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": t.name,
+                            "description": t.description,
+                            "parameters": t.parameters,
+                        }
+                    })
+                })
+                .collect();
         }
 
-        let mut request = self.client.post(&url)
-            .json(&json!({
-                "model": self.default_model,
-                "messages": ollama_messages,
-                "stream": false
-            }));
+        let mut request = self.client.post(&url).json(&body);
 
         if let Some(key) = &self.api_key {
             request = request.header("Authorization", format!("Bearer {}", key));
@@ -74,46 +190,158 @@ impl LanguageModel for OllamaProvider {
         let response = request.send().await.map_err(ModelError::Request)?;
         
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ModelError::Api(format!("Ollama API error: {}", error_text)).into());
+            return Err(crate::model::retry::error_from_response("Ollama", response).await.into());
         }
 
         let ollama_resp: OllamaResponse = response.json().await.map_err(ModelError::Request)?;
 
+        // Ollama does not assign call ids, so number the calls
+        let tool_calls: Vec<_> = ollama_resp
+            .message
+            .tool_calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, call)| crate::model::ToolCall {
+                id: format!("call_{}", i),
+                name: call.function.name,
+                arguments: call.function.arguments,
+            })
+            .collect();
+
         Ok(ModelResponse {
             content: ollama_resp.message.content,
             model: self.default_model.clone(),
-            usage: crate::model::TokenUsage::default(), // Ollama usage not parsed yet
-            tool_calls: None,
-            finish_reason: Some("stop".to_string()),
+            usage: crate::model::TokenUsage {
+                prompt_tokens: ollama_resp.prompt_eval_count,
+                completion_tokens: ollama_resp.eval_count,
+                total_tokens: ollama_resp.prompt_eval_count + ollama_resp.eval_count,
+            },
+            finish_reason: Some(if tool_calls.is_empty() { "stop" } else { "tool_calls" }.to_string()),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            thinking: None,
+            thinking_parts: Vec::new(),
         })
     }
 
-    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
-        let mut messages = Vec::new();
-        if let Some(sys) = system_prompt {
-            messages.push(crate::model::Message::system(sys));
-        }
-        messages.push(crate::model::Message::user(prompt));
-        self.chat(&messages).await
-    }
-
-    async fn chat_with_tools(
-        &self,
-        messages: &[crate::model::Message],
-        _tools: &[crate::model::ToolDefinition],
-    ) -> Result<ModelResponse> {
-        // For now, just ignore tools and chat normally
-        self.chat(messages).await
-    }
-
     fn model_info(&self) -> crate::model::ModelInfo {
         crate::model::ModelInfo {
             provider: "ollama".to_string(),
             model: self.default_model.clone(),
-            max_tokens: 4096, // Default assumption
-            supports_tools: false,
+            max_tokens: self.params.map_or(4096, |(_, max_tokens)| max_tokens),
+            context_window: crate::model::context_window_for(&self.default_model),
+            supports_tools: self.supports_tools(),
             supports_streaming: false,
         }
     }
+
+    fn supports_tools(&self) -> bool {
+        model_supports_tools(&self.default_model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ImageSource, Message, ToolCall, ToolDefinition};
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn read_file_tool() -> ToolDefinition {
+        ToolDefinition {
+            name: "file_read".to_string(),
+            description: "Read a file".to_string(),
+            parameters: json!({"type": "object", "properties": {"path": {"type": "string"}}}),
+        }
+    }
+    #[test]
+    fn test_multimodal_and_tool_messages() {
+        let messages: Vec<Message> = vec![
+            Message::user("What is this?").with_image(ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }),
+            Message::new(
+                Role::Assistant,
+                vec![ContentPart::ToolCall(ToolCall {
+                    id: "call_1".to_string(),
+                    name: "file_read".to_string(),
+                    arguments: json!({"path": "a.rs"}),
+                })],
+            ),
+            Message::tool_result("call_1", "fn main() {}", false),
+        ];
+
+        let converted: Vec<_> = messages.iter().map(|m| convert_message(m).unwrap()).collect();
+
+        assert_eq!(converted[0][0]["images"], json!(["iVBORw=="]));
+        assert_eq!(converted[1][0]["tool_calls"][0]["function"]["arguments"]["path"], "a.rs");
+        assert_eq!(converted[2], vec![json!({"role": "tool", "content": "fn main() {}"})]);
+    }
+
+
+    #[test]
+    fn test_supports_tools_per_model() {
+        assert!(model_supports_tools("llama3.1:8b"));
+        assert!(model_supports_tools("qwen2.5-coder:7b"));
+        assert!(!model_supports_tools("llama2"));
+        assert!(!model_supports_tools("llama3"));
+        assert!(!model_supports_tools("codellama:13b"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_tools() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({"tools": [{"type": "function", "function": {"name": "file_read"}}]})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{"function": {"name": "file_read", "arguments": {"path": "Cargo.toml"}}}]
+                },
+                "done": true,
+                "prompt_eval_count": 30,
+                "eval_count": 8
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = OllamaProvider::new(Some(server.uri()), None, Some("llama3.1".to_string()));
+        let response = provider
+            .chat_with_tools(&[Message::user("Read Cargo.toml")], &[read_file_tool()])
+            .await
+            .unwrap();
+
+        let calls = response.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "call_0");
+        assert_eq!(calls[0].name, "file_read");
+        assert_eq!(calls[0].arguments["path"], "Cargo.toml");
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+    }
+
+    #[tokio::test]
+    async fn test_tools_omitted_for_unsupported_model() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(move |req: &wiremock::Request| {
+                let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+                assert!(body.get("tools").is_none());
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "message": {"role": "assistant", "content": "hi"},
+                    "done": true
+                }))
+            })
+            .mount(&server)
+            .await;
+
+        let provider = OllamaProvider::new(Some(server.uri()), None, Some("llama2".to_string()));
+        let response = provider
+            .chat_with_tools(&[Message::user("Hi")], &[read_file_tool()])
+            .await
+            .unwrap();
+
+        assert_eq!(response.content, "hi");
+        assert!(response.tool_calls.is_none());
+    }
 }
@@ -1,160 +1,111 @@
 //! OpenAI API provider implementation
 
-use super::{LanguageModel, Message, ModelInfo, ModelResponse, ToolDefinition, TokenUsage};
-use crate::error::{ModelError, Result};
+use super::openai_compat::{OpenAICompatibleProvider, OPENAI_BASE_URL};
+use super::{LanguageModel, Message, ModelInfo, ModelResponse, ToolDefinition};
+use crate::config::{MaxTokensField, ProviderConfig, ProviderQuirks};
+use crate::error::Result;
 use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
 
+/// OpenAI's hosted API, built on the generic OpenAI-compatible client
 pub struct OpenAIProvider {
-    client: async_openai::Client<async_openai::config::OpenAIConfig>,
-    model: String,
-    temperature: f32,
-    max_tokens: usize,
+    inner: OpenAICompatibleProvider,
 }
 
 impl OpenAIProvider {
     pub fn new(api_key: String, model: Option<String>) -> Self {
-        let config = async_openai::config::OpenAIConfig::new().with_api_key(api_key);
-        let client = async_openai::Client::with_config(config);
-
-        Self {
-            client,
-            model: model.unwrap_or_else(|| "gpt-4".to_string()),
-            temperature: 0.2,
-            max_tokens: 4096,
+        let model = model.unwrap_or_else(|| "gpt-4".to_string());
+        let inner = OpenAICompatibleProvider::new("openai", OPENAI_BASE_URL, Some(api_key), model)
+            .with_model_quirks(reasoning_model_quirks());
+
+        Self { inner }
+    }
+
+    /// Apply `base_url`, headers, organization and quirks from `models.providers.openai`
+    pub fn with_config(mut self, config: &ProviderConfig) -> Self {
+        let mut inner = self
+            .inner
+            .with_params(config.default_params.temperature, config.default_params.max_tokens)
+            .with_headers(config.headers.clone())
+            .with_organization(config.organization.clone())
+            .with_api_version(config.api_version.clone())
+            .with_model_quirks(config.model_quirks.clone());
+        if !config.quirks.is_default() {
+            inner = inner.with_quirks(config.quirks.clone());
+        }
+        if let Some(base_url) = &config.base_url {
+            inner = inner.with_base_url(base_url.clone());
         }
+        self.inner = inner;
+        self
     }
 
     pub fn with_params(mut self, temperature: f32, max_tokens: usize) -> Self {
-        self.temperature = temperature;
-        self.max_tokens = max_tokens;
+        self.inner = self.inner.with_params(temperature, max_tokens);
         self
     }
 
-    fn convert_message(&self, msg: &Message) -> async_openai::types::ChatCompletionRequestMessage {
-        use async_openai::types::*;
-
-        match msg.role.as_str() {
-            "system" => ChatCompletionRequestMessage::System(
-                ChatCompletionRequestSystemMessage {
-                    content: msg.content.clone(),
-                    role: Role::System,
-                    name: None,
-                },
-            ),
-            "user" => ChatCompletionRequestMessage::User(
-                ChatCompletionRequestUserMessage {
-                    content: ChatCompletionRequestUserMessageContent::Text(msg.content.clone()),
-                    role: Role::User,
-                    name: None,
-                },
-            ),
-            "assistant" => ChatCompletionRequestMessage::Assistant(
-                ChatCompletionRequestAssistantMessage {
-                    content: Some(msg.content.clone()),
-                    name: None,
-                    role: Role::Assistant,
-                    #[allow(deprecated)]
-                    function_call: None,
-                    tool_calls: None,
-                },
-            ),
-            _ => ChatCompletionRequestMessage::User(
-                ChatCompletionRequestUserMessage {
-                    content: ChatCompletionRequestUserMessageContent::Text(msg.content.clone()),
-                    role: Role::User,
-                    name: None,
-                },
-            ),
-        }
+    /// Request JSON for a single message (tool results expand to extra `tool` messages)
+    pub fn convert_message(&self, msg: &Message) -> Result<Vec<Value>> {
+        self.inner.convert_message(msg)
     }
 }
 
+/// Reasoning models take `max_completion_tokens`, and the early ones reject system messages
+fn reasoning_model_quirks() -> HashMap<String, ProviderQuirks> {
+    let completion_tokens = ProviderQuirks {
+        max_tokens_field: MaxTokensField::MaxCompletionTokens,
+        ..ProviderQuirks::default()
+    };
+    let no_system = ProviderQuirks {
+        no_system_role: true,
+        ..completion_tokens.clone()
+    };
+
+    HashMap::from([
+        ("o1".to_string(), completion_tokens.clone()),
+        ("o1-mini".to_string(), no_system.clone()),
+        ("o1-preview".to_string(), no_system),
+        ("o3".to_string(), completion_tokens.clone()),
+        ("o4".to_string(), completion_tokens.clone()),
+        ("gpt-5".to_string(), completion_tokens),
+    ])
+}
+
 #[async_trait]
 impl LanguageModel for OpenAIProvider {
     async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
-        let mut messages = Vec::new();
-
-        if let Some(sys) = system_prompt {
-            messages.push(Message::system(sys));
-        }
-
-        messages.push(Message::user(prompt));
-
-        self.chat(&messages).await
+        self.inner.complete(prompt, system_prompt).await
     }
 
     async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
-        use async_openai::types::*;
-
-        let openai_messages: Vec<_> = messages.iter().map(|m| self.convert_message(m)).collect();
-
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(&self.model)
-            .messages(openai_messages)
-            .temperature(self.temperature)
-            .max_tokens(self.max_tokens as u16)
-            .build()
-            .map_err(|e| ModelError::Api(format!("Failed to build request: {}", e)))?;
-
-        let response = self
-            .client
-            .chat()
-            .create(request)
-            .await
-            .map_err(|e| ModelError::Api(format!("API request failed: {}", e)))?;
-
-        let choice = response
-            .choices
-            .first()
-            .ok_or_else(|| ModelError::InvalidResponse("No choices in response".to_string()))?;
-
-        let content = choice
-            .message
-            .content
-            .clone()
-            .unwrap_or_default();
-
-        let usage = if let Some(usage) = response.usage {
-            TokenUsage {
-                prompt_tokens: usage.prompt_tokens as usize,
-                completion_tokens: usage.completion_tokens as usize,
-                total_tokens: usage.total_tokens as usize,
-            }
-        } else {
-            TokenUsage::default()
-        };
-
-        Ok(ModelResponse {
-            content,
-            model: response.model,
-            usage,
-            tool_calls: None,
-            finish_reason: choice.finish_reason.as_ref().map(|r| format!("{:?}", r)),
-        })
+        self.inner.chat(messages).await
     }
 
     async fn chat_with_tools(
         &self,
         messages: &[Message],
-        _tools: &[ToolDefinition],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        self.inner.chat_with_tools(messages, tools).await
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
     ) -> Result<ModelResponse> {
-        // For MVP, we'll use regular chat
-        // Phase 2 will add proper function calling support
-        self.chat(messages).await
+        self.inner.chat_stream(messages, tools, on_delta).await
     }
 
     fn model_info(&self) -> ModelInfo {
-        ModelInfo {
-            provider: "openai".to_string(),
-            model: self.model.clone(),
-            max_tokens: self.max_tokens,
-            supports_tools: true,
-            supports_streaming: true,
-        }
+        self.inner.model_info()
     }
 
     fn supports_tools(&self) -> bool {
-        self.model.starts_with("gpt-4") || self.model.starts_with("gpt-3.5")
+        self.inner.supports_tools()
     }
 
     fn supports_streaming(&self) -> bool {
@@ -174,6 +125,12 @@ mod tests {
         assert_eq!(info.provider, "openai");
         assert_eq!(info.model, "gpt-4");
         assert!(info.supports_tools);
+
+        for model in ["gpt-4o", "o3", "gpt-5"] {
+            let provider = OpenAIProvider::new("test-key".to_string(), Some(model.to_string()));
+            assert_eq!(provider.supports_tools(), provider.model_info().supports_tools, "{}", model);
+            assert!(provider.supports_tools(), "{}", model);
+        }
     }
 
     #[test]
@@ -185,4 +142,22 @@ mod tests {
 
         // Just testing that conversion doesn't panic
     }
+
+    #[test]
+    fn test_large_max_tokens_not_truncated() {
+        let provider = OpenAIProvider::new("test-key".to_string(), Some("gpt-4.1".to_string()))
+            .with_params(0.2, 100_000);
+
+        let body = provider.inner.build_request(&[Message::user("Hi")], &[], false).unwrap();
+        assert_eq!(body["max_tokens"], 100_000);
+    }
+
+    #[test]
+    fn test_reasoning_model_quirks() {
+        let provider = OpenAIProvider::new("test-key".to_string(), Some("o1-mini".to_string()));
+
+        let body = provider.inner.build_request(&[Message::system("sys")], &[], false).unwrap();
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert!(body.get("max_completion_tokens").is_some());
+    }
 }
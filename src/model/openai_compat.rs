@@ -0,0 +1,707 @@
+//! Generic provider for OpenAI-compatible chat completion APIs
+//!
+//! Works with any server that speaks the `/chat/completions` protocol, such as
+//! vLLM, LM Studio, llama.cpp server, LiteLLM or Azure-style gateways.
+
+use super::{flatten_tool_messages, ContentPart, LanguageModel, Message, Role, ModelInfo, ModelResponse, TokenUsage, ToolCall, ToolDefinition};
+use super::retry::error_from_response;
+use crate::config::{MaxTokensField, ProviderConfig, ProviderQuirks};
+use crate::error::{ModelError, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+pub struct OpenAICompatibleProvider {
+    name: String,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    temperature: f32,
+    max_tokens: usize,
+    headers: HashMap<String, String>,
+    organization: Option<String>,
+    api_version: Option<String>,
+    quirks: ProviderQuirks,
+    model_quirks: HashMap<String, ProviderQuirks>,
+    client: reqwest::Client,
+}
+
+impl OpenAICompatibleProvider {
+    /// Create a provider named `name` talking to `base_url`
+    pub fn new(
+        name: impl Into<String>,
+        base_url: impl Into<String>,
+        api_key: Option<String>,
+        model: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: api_key.filter(|k| !k.is_empty()),
+            model: model.into(),
+            temperature: 0.2,
+            max_tokens: 4096,
+            headers: HashMap::new(),
+            organization: None,
+            api_version: None,
+            quirks: ProviderQuirks::default(),
+            model_quirks: HashMap::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Create a provider from its `models.providers.<name>` entry
+    pub fn from_config(name: &str, config: &ProviderConfig, model: impl Into<String>) -> Result<Self> {
+        let base_url = config.base_url.clone().ok_or_else(|| {
+            crate::error::ConfigError::Invalid(format!(
+                "Provider '{}' needs a base_url (models.providers.{}.base_url)",
+                name, name
+            ))
+        })?;
+
+        Ok(Self::new(name, base_url, config.api_key.clone(), model)
+            .with_params(config.default_params.temperature, config.default_params.max_tokens)
+            .with_headers(config.headers.clone())
+            .with_organization(config.organization.clone())
+            .with_api_version(config.api_version.clone())
+            .with_quirks(config.quirks.clone())
+            .with_model_quirks(config.model_quirks.clone()))
+    }
+
+    pub fn with_params(mut self, temperature: f32, max_tokens: usize) -> Self {
+        self.temperature = temperature;
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Extra headers sent with every request
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn with_organization(mut self, organization: Option<String>) -> Self {
+        self.organization = organization;
+        self
+    }
+
+    /// Send `api-version` as a query parameter
+    pub fn with_api_version(mut self, api_version: Option<String>) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    pub fn with_quirks(mut self, quirks: ProviderQuirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Quirks for models matching a name prefix
+    pub fn with_model_quirks(mut self, model_quirks: HashMap<String, ProviderQuirks>) -> Self {
+        self.model_quirks.extend(model_quirks);
+        self
+    }
+
+    /// Quirks in effect for the current model (longest matching prefix wins)
+    pub fn quirks(&self) -> &ProviderQuirks {
+        self.model_quirks
+            .iter()
+            .filter(|(prefix, _)| self.model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, quirks)| quirks)
+            .unwrap_or(&self.quirks)
+    }
+
+    /// Convert a message to one or more chat messages
+    ///
+    /// Images become `image_url` parts with a data URL, and each tool result
+    /// becomes its own `tool` message.
+    pub(crate) fn convert_message(&self, msg: &Message) -> Result<Vec<Value>> {
+        let role = match msg.role {
+            Role::System if self.quirks().no_system_role => "user",
+            Role::System => "system",
+            Role::Assistant => "assistant",
+            // Tool output is sent as `tool` messages below; any other content goes as user text
+            Role::User | Role::Tool => "user",
+        };
+
+        let mut content = Vec::new();
+        let mut tool_calls = Vec::new();
+        let mut tool_results = Vec::new();
+        for part in &msg.content {
+            match part {
+                ContentPart::Text { text } => content.push(json!({"type": "text", "text": text})),
+                ContentPart::Image { image } => {
+                    let image = image.load()?;
+                    content.push(json!({"type": "image_url", "image_url": {"url": image.data_url()}}));
+                }
+                ContentPart::ToolCall(call) => tool_calls.push(json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": {"name": call.name, "arguments": call.arguments.to_string()},
+                })),
+                ContentPart::ToolResult {
+                    tool_call_id,
+                    content,
+                    ..
+                } => tool_results.push(json!({"role": "tool", "tool_call_id": tool_call_id, "content": content})),
+                ContentPart::Thinking { .. } => {}
+            }
+        }
+
+        let mut converted = Vec::new();
+        if !content.is_empty() || !tool_calls.is_empty() || tool_results.is_empty() {
+            let mut message = json!({"role": role});
+            message["content"] = if content.iter().any(|part| part["type"] != "text") {
+                json!(content)
+            } else if content.is_empty() && !tool_calls.is_empty() {
+                Value::Null
+            } else {
+                json!(msg.text())
+            };
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = json!(tool_calls);
+            }
+            converted.push(message);
+        }
+        converted.extend(tool_results);
+        Ok(converted)
+    }
+
+    pub(crate) fn build_request(&self, messages: &[Message], tools: &[ToolDefinition], stream: bool) -> Result<Value> {
+        let quirks = self.quirks();
+
+        // Without tool definitions the API rejects tool messages, so send them as text
+        let flattened;
+        let messages = if tools.is_empty() {
+            flattened = flatten_tool_messages(messages);
+            &flattened
+        } else {
+            messages
+        };
+
+        let mut api_messages = Vec::new();
+        for msg in messages {
+            api_messages.extend(self.convert_message(msg)?);
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": api_messages,
+            "temperature": self.temperature,
+        });
+
+        let max_tokens_field = match quirks.max_tokens_field {
+            MaxTokensField::MaxTokens => "max_tokens",
+            MaxTokensField::MaxCompletionTokens => "max_completion_tokens",
+        };
+        body[max_tokens_field] = json!(self.max_tokens);
+
+        if !tools.is_empty() {
+            body["tools"] = tools
+                .iter()
+                .map(|t| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": t.name,
+                            "description": t.description,
+                            "parameters": t.parameters,
+                        }
+                    })
+                })
+                .collect();
+        }
+
+        if stream {
+            body["stream"] = json!(true);
+            if !quirks.no_stream_usage {
+                body["stream_options"] = json!({"include_usage": true});
+            }
+        }
+
+        Ok(body)
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let mut request = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(body);
+
+        if let Some(version) = &self.api_version {
+            request = request.query(&[("api-version", version)]);
+        }
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        if let Some(org) = &self.organization {
+            request = request.header("OpenAI-Organization", org);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await.map_err(ModelError::Request)?;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        Err(error_from_response(&self.name, response).await.into())
+    }
+
+    fn parse_response(&self, response: &Value) -> Result<ModelResponse> {
+        let choice = response["choices"]
+            .get(0)
+            .ok_or_else(|| ModelError::InvalidResponse("No choices in response".to_string()))?;
+        let message = &choice["message"];
+
+        let tool_calls = message["tool_calls"]
+            .as_array()
+            .map(|calls| calls.iter().map(parse_tool_call).collect::<Result<Vec<_>>>())
+            .transpose()?
+            .filter(|calls| !calls.is_empty());
+
+        Ok(ModelResponse {
+            content: message["content"].as_str().unwrap_or_default().to_string(),
+            model: response["model"].as_str().unwrap_or(&self.model).to_string(),
+            usage: parse_usage(&response["usage"]),
+            tool_calls,
+            finish_reason: choice["finish_reason"].as_str().map(|s| s.to_string()),
+            thinking: message["reasoning_content"].as_str().map(|s| s.to_string()),
+            thinking_parts: Vec::new(),
+        })
+    }
+}
+
+fn parse_usage(usage: &Value) -> TokenUsage {
+    let count = |key: &str| usage[key].as_u64().unwrap_or(0) as usize;
+    TokenUsage {
+        prompt_tokens: count("prompt_tokens"),
+        completion_tokens: count("completion_tokens"),
+        total_tokens: count("total_tokens"),
+    }
+}
+
+fn parse_tool_call(call: &Value) -> Result<ToolCall> {
+    // Calls to tools without parameters may stream no argument text at all
+    let arguments = call["function"]["arguments"]
+        .as_str()
+        .filter(|arguments| !arguments.trim().is_empty())
+        .unwrap_or("{}");
+    Ok(ToolCall {
+        id: call["id"].as_str().unwrap_or_default().to_string(),
+        name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+        arguments: serde_json::from_str(arguments)
+            .map_err(|e| ModelError::InvalidResponse(format!("Invalid tool call arguments: {}", e)))?,
+    })
+}
+
+/// Accumulates the chunks of a streamed completion
+#[derive(Default)]
+struct StreamState {
+    /// Bytes of the line being received, which may end inside a UTF-8 character
+    buffer: Vec<u8>,
+    done: bool,
+    model: String,
+    content: String,
+    reasoning: String,
+    tool_calls: BTreeMap<u64, Value>,
+    usage: Option<TokenUsage>,
+    finish_reason: Option<String>,
+}
+
+impl StreamState {
+    /// Feed raw bytes and return the text deltas they complete
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut deltas = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                self.done = true;
+                continue;
+            }
+            if let Ok(event) = serde_json::from_str::<Value>(data) {
+                deltas.extend(self.apply(&event));
+            }
+        }
+        deltas
+    }
+
+    fn apply(&mut self, event: &Value) -> Option<String> {
+        if let Some(model) = event["model"].as_str() {
+            self.model = model.to_string();
+        }
+        if event["usage"].is_object() {
+            self.usage = Some(parse_usage(&event["usage"]));
+        }
+
+        let choice = event["choices"].get(0)?;
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+
+        let delta = &choice["delta"];
+        if let Some(reasoning) = delta["reasoning_content"].as_str() {
+            self.reasoning.push_str(reasoning);
+        }
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let entry = self
+                .tool_calls
+                .entry(call["index"].as_u64().unwrap_or(0))
+                .or_insert_with(|| json!({"id": "", "function": {"name": "", "arguments": ""}}));
+            for (target, source) in [
+                ("/id", &call["id"]),
+                ("/function/name", &call["function"]["name"]),
+                ("/function/arguments", &call["function"]["arguments"]),
+            ] {
+                if let (Some(fragment), Some(Value::String(value))) = (source.as_str(), entry.pointer_mut(target)) {
+                    value.push_str(fragment);
+                }
+            }
+        }
+
+        let text = delta["content"].as_str().filter(|s| !s.is_empty())?;
+        self.content.push_str(text);
+        Some(text.to_string())
+    }
+
+    fn finish(self, fallback_model: &str) -> Result<ModelResponse> {
+        let tool_calls = self
+            .tool_calls
+            .values()
+            .map(parse_tool_call)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ModelResponse {
+            content: self.content,
+            model: if self.model.is_empty() {
+                fallback_model.to_string()
+            } else {
+                self.model
+            },
+            usage: self.usage.unwrap_or_default(),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            finish_reason: self.finish_reason,
+            thinking: (!self.reasoning.is_empty()).then_some(self.reasoning),
+            thinking_parts: Vec::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl LanguageModel for OpenAICompatibleProvider {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        let mut messages = Vec::new();
+
+        if let Some(sys) = system_prompt {
+            messages.push(Message::system(sys));
+        }
+
+        messages.push(Message::user(prompt));
+
+        self.chat(&messages).await
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        self.chat_with_tools(messages, &[]).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        let body = self.build_request(messages, tools, false)?;
+        let response: Value = self
+            .send(&body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| ModelError::InvalidResponse(format!("Failed to parse response: {}", e)))?;
+
+        self.parse_response(&response)
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let body = self.build_request(messages, tools, true)?;
+        let mut response = self.send(&body).await?;
+        let mut state = StreamState::default();
+
+        while let Some(chunk) = response.chunk().await.map_err(ModelError::Request)? {
+            for delta in state.push(&chunk) {
+                on_delta(&delta);
+            }
+            if state.done {
+                break;
+            }
+        }
+
+        state.finish(&self.model)
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            provider: self.name.clone(),
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            context_window: super::context_window_for(&self.model),
+            supports_tools: true,
+            supports_streaming: true,
+        }
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::retry::map_http_error;
+    use crate::model::ImageSource;
+    use wiremock::matchers::{body_partial_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_multimodal_and_tool_messages() {
+        let provider = OpenAICompatibleProvider::new("local", "http://localhost:8000/v1", None, "gpt-4o");
+        let messages = vec![
+            Message::user("What is this?").with_image(ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }),
+            Message::new(
+                Role::Assistant,
+                vec![ContentPart::ToolCall(ToolCall {
+                    id: "call_1".to_string(),
+                    name: "file_read".to_string(),
+                    arguments: json!({"path": "a.rs"}),
+                })],
+            ),
+            Message::tool_result("call_1", "fn main() {}", false),
+        ];
+
+        let tools = [ToolDefinition {
+            name: "file_read".to_string(),
+            description: "Read a file".to_string(),
+            parameters: json!({"type": "object"}),
+        }];
+
+        let body = provider.build_request(&messages, &tools, false).unwrap();
+        let api_messages = body["messages"].as_array().unwrap();
+
+        assert_eq!(api_messages.len(), 3);
+        assert_eq!(api_messages[0]["content"][0], json!({"type": "text", "text": "What is this?"}));
+        assert_eq!(api_messages[0]["content"][1]["image_url"]["url"], "data:image/png;base64,iVBORw==");
+        assert!(api_messages[1]["content"].is_null());
+        assert_eq!(api_messages[1]["tool_calls"][0]["function"]["arguments"], "{\"path\":\"a.rs\"}");
+        assert_eq!(api_messages[2], json!({"role": "tool", "tool_call_id": "call_1", "content": "fn main() {}"}));
+
+        let body = provider.build_request(&messages, &[], false).unwrap();
+        assert_eq!(body["messages"][2], json!({"role": "user", "content": "Tool 'file_read' result: fn main() {}"}));
+    }
+
+    #[test]
+    fn test_quirks() {
+        let provider = OpenAICompatibleProvider::new("local", "http://localhost:8000/v1", None, "o1-mini")
+            .with_params(0.0, 100_000)
+            .with_model_quirks(HashMap::from([(
+                "o1".to_string(),
+                ProviderQuirks {
+                    no_system_role: true,
+                    max_tokens_field: MaxTokensField::MaxCompletionTokens,
+                    no_stream_usage: true,
+                },
+            )]));
+
+        let body = provider.build_request(&[Message::system("Be brief"), Message::user("Hi")], &[], true).unwrap();
+
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["max_completion_tokens"], 100_000);
+        assert!(body.get("max_tokens").is_none());
+        assert!(body.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn test_from_config_requires_base_url() {
+        let config = ProviderConfig::default();
+        assert!(OpenAICompatibleProvider::from_config("vllm", &config, "m").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_config() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(query_param("api-version", "2024-06-01"))
+            .and(header("authorization", "Bearer secret"))
+            .and(header("openai-organization", "org-1"))
+            .and(header("x-gateway", "promptline"))
+            .and(body_partial_json(json!({"model": "qwen2.5-coder", "max_tokens": 70000})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "qwen2.5-coder",
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "content": "",
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "file_read", "arguments": "{\"path\":\"a.rs\"}"}
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }],
+                "usage": {"prompt_tokens": 20, "completion_tokens": 5, "total_tokens": 25}
+            })))
+            .mount(&server)
+            .await;
+
+        let config = ProviderConfig {
+            kind: Some("openai-compatible".to_string()),
+            api_key: Some("secret".to_string()),
+            base_url: Some(format!("{}/v1/", server.uri())),
+            organization: Some("org-1".to_string()),
+            api_version: Some("2024-06-01".to_string()),
+            headers: HashMap::from([("X-Gateway".to_string(), "promptline".to_string())]),
+            default_params: crate::config::ModelParams {
+                temperature: 0.0,
+                max_tokens: 70_000,
+            },
+            ..ProviderConfig::default()
+        };
+        let provider = OpenAICompatibleProvider::from_config("gateway", &config, "qwen2.5-coder").unwrap();
+
+        let response = provider.chat(&[Message::user("Read a.rs")]).await.unwrap();
+
+        assert_eq!(provider.model_info().provider, "gateway");
+        assert_eq!(response.usage.total_tokens, 25);
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(response.tool_calls.unwrap()[0].arguments["path"], "a.rs");
+    }
+
+    #[tokio::test]
+    async fn test_error_mapping() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).set_body_string("slow down"))
+            .mount(&server)
+            .await;
+
+        let provider = OpenAICompatibleProvider::new("local", server.uri(), None, "m");
+        let err = provider.chat(&[Message::user("Hi")]).await.unwrap_err();
+        assert!(matches!(err, crate::error::PromptLineError::Model(ModelError::RateLimit { .. })));
+
+        assert!(matches!(map_http_error("local", 401, None, "{\"error\":{\"message\":\"bad key\"}}"), ModelError::Auth(m) if m == "bad key"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let chunks = [
+            json!({"model": "llama", "choices": [{"delta": {"role": "assistant", "content": "Hel"}}]}),
+            json!({"model": "llama", "choices": [{"delta": {"content": "lo"}}]}),
+            json!({"model": "llama", "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "c1", "function": {"name": "file_read", "arguments": "{\"path\""}}]}}]}),
+            json!({"model": "llama", "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": ": \"b.rs\"}"}}]}, "finish_reason": "tool_calls"}]}),
+            json!({"model": "llama", "choices": [], "usage": {"prompt_tokens": 3, "completion_tokens": 4, "total_tokens": 7}}),
+        ];
+        let body: String = chunks
+            .iter()
+            .map(|c| format!("data: {}\n\n", c))
+            .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+            .collect();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"stream": true, "stream_options": {"include_usage": true}})))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let provider = OpenAICompatibleProvider::new("local", server.uri(), None, "llama");
+        let mut deltas = Vec::new();
+        let response = provider
+            .chat_stream(&[Message::user("Hi")], &[], &mut |d| deltas.push(d.to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        assert_eq!(response.content, "Hello");
+        assert_eq!(response.usage.total_tokens, 7);
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(response.tool_calls.unwrap()[0].arguments["path"], "b.rs");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_tool_call_without_arguments() {
+        let chunks = [
+            json!({"model": "llama", "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "c1", "function": {"name": "git_status", "arguments": ""}}]}}]}),
+            json!({"model": "llama", "choices": [{"delta": {}, "finish_reason": "tool_calls"}]}),
+        ];
+        let body: String = chunks
+            .iter()
+            .map(|c| format!("data: {}\n\n", c))
+            .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+            .collect();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let provider = OpenAICompatibleProvider::new("local", server.uri(), None, "llama");
+        let response = provider.chat_stream(&[Message::user("Hi")], &[], &mut |_| {}).await.unwrap();
+
+        let calls = response.tool_calls.unwrap();
+        assert_eq!(calls[0].name, "git_status");
+        assert_eq!(calls[0].arguments, json!({}));
+    }
+
+    #[test]
+    fn test_stream_characters_split_across_chunks() {
+        let chunks = [
+            json!({"choices": [{"delta": {"content": "café 🚀"}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "c1", "function": {"name": "file_read", "arguments": "{\"path\": \"é🚀.rs\"}"}}]}}]}),
+        ];
+        let bytes: Vec<u8> = chunks.iter().flat_map(|c| format!("data: {}\r\n\r\n", c).into_bytes()).collect();
+
+        // Split inside every multi-byte character
+        let mut state = StreamState::default();
+        let mut deltas = Vec::new();
+        let mut start = 0;
+        for (i, _) in bytes.iter().enumerate().filter(|(_, b)| **b >= 0xC0) {
+            deltas.extend(state.push(&bytes[start..i + 1]));
+            start = i + 1;
+        }
+        deltas.extend(state.push(&bytes[start..]));
+
+        assert_eq!(deltas, vec!["café 🚀"]);
+        let response = state.finish("m").unwrap();
+        assert_eq!(response.tool_calls.unwrap()[0].arguments["path"], "é🚀.rs");
+    }
+}
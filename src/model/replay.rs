@@ -0,0 +1,380 @@
+//! Recording and replaying model exchanges
+//!
+//! [`RecordingModel`] appends every request and response to a JSONL file;
+//! [`ReplayProvider`] serves those responses back in order without network
+//! access and reports where the new requests diverge from the recorded ones.
+
+use super::{context_window_for, LanguageModel, Message, ModelInfo, ModelResponse, Role, ToolDefinition};
+use crate::error::{ModelError, Result};
+use crate::tokenizer::Tokenizer;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// One request and its outcome, stored as a line of JSONL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    /// Position in the recording, starting at 0
+    pub seq: usize,
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<ModelResponse>,
+    /// Error message when the request failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Messages for a `complete` call, so every exchange is recorded the same way
+fn completion_messages(prompt: &str, system_prompt: Option<&str>) -> Vec<Message> {
+    let mut messages = Vec::new();
+    if let Some(system) = system_prompt {
+        messages.push(Message::system(system));
+    }
+    messages.push(Message::user(prompt));
+    messages
+}
+
+/// Appends each exchange with the inner model to a JSONL file
+pub struct RecordingModel {
+    inner: Box<dyn LanguageModel>,
+    file: Mutex<std::fs::File>,
+    seq: Mutex<usize>,
+}
+
+impl RecordingModel {
+    /// Record to `path`, appending if the file already exists
+    pub fn new(inner: Box<dyn LanguageModel>, path: &Path) -> Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner,
+            file: Mutex::new(file),
+            seq: Mutex::new(0),
+        })
+    }
+
+    fn record(&self, messages: &[Message], tools: &[ToolDefinition], result: &Result<ModelResponse>) {
+        let mut seq = self.seq.lock().unwrap();
+        let exchange = Exchange {
+            seq: *seq,
+            messages: messages.to_vec(),
+            tools: tools.to_vec(),
+            response: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        *seq += 1;
+
+        let written = serde_json::to_string(&exchange)
+            .map_err(std::io::Error::from)
+            .and_then(|line| writeln!(self.file.lock().unwrap(), "{}", line));
+        if let Err(e) = written {
+            tracing::warn!("Failed to record model exchange: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl LanguageModel for RecordingModel {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        let result = self.inner.complete(prompt, system_prompt).await;
+        self.record(&completion_messages(prompt, system_prompt), &[], &result);
+        result
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        let result = self.inner.chat(messages).await;
+        self.record(messages, &[], &result);
+        result
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        let result = self.inner.chat_with_tools(messages, tools).await;
+        self.record(messages, tools, &result);
+        result
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let result = self.inner.chat_stream(messages, tools, on_delta).await;
+        self.record(messages, tools, &result);
+        result
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        self.inner.model_info()
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.inner.tokenizer()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        self.inner.supports_streaming()
+    }
+}
+
+/// Where a replayed request differs from the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the request in the replay
+    pub request: usize,
+    pub detail: String,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request {} diverged from the recording: {}", self.request, self.detail)
+    }
+}
+
+/// Serves recorded responses in order
+///
+/// System messages are not compared, since the system prompt embeds the
+/// working directory and git branch of the machine that recorded the run.
+pub struct ReplayProvider {
+    exchanges: Vec<Exchange>,
+    next: Mutex<usize>,
+    divergences: Mutex<Vec<Divergence>>,
+    strict: bool,
+}
+
+impl ReplayProvider {
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        Self {
+            exchanges,
+            next: Mutex::new(0),
+            divergences: Mutex::new(Vec::new()),
+            strict: false,
+        }
+    }
+
+    /// Load a recording made with `--record`
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut exchanges = Vec::new();
+        for line in std::io::BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                exchanges.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Self::new(exchanges))
+    }
+
+    /// Fail requests that diverge instead of only reporting them
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Divergences seen so far
+    pub fn divergences(&self) -> Vec<Divergence> {
+        self.divergences.lock().unwrap().clone()
+    }
+
+    /// Number of recorded responses not yet served
+    pub fn remaining(&self) -> usize {
+        self.exchanges.len() - *self.next.lock().unwrap()
+    }
+
+    fn serve(&self, messages: &[Message], tools: &[ToolDefinition]) -> Result<ModelResponse> {
+        let index = {
+            let mut next = self.next.lock().unwrap();
+            let index = *next;
+            *next += 1;
+            index
+        };
+        let exchange = self.exchanges.get(index).ok_or_else(|| {
+            ModelError::NotAvailable(format!(
+                "replay exhausted: request {} but only {} were recorded",
+                index,
+                self.exchanges.len()
+            ))
+        })?;
+
+        if let Some(detail) = compare(exchange, messages, tools) {
+            let divergence = Divergence { request: index, detail };
+            tracing::warn!("{}", divergence);
+            self.divergences.lock().unwrap().push(divergence.clone());
+            if self.strict {
+                return Err(ModelError::InvalidResponse(divergence.to_string()).into());
+            }
+        }
+
+        match (&exchange.response, &exchange.error) {
+            (Some(response), _) => Ok(response.clone()),
+            (None, error) => Err(ModelError::Api(error.clone().unwrap_or_else(|| "recorded failure".to_string())).into()),
+        }
+    }
+}
+
+/// First difference between a recorded request and a new one
+fn compare(recorded: &Exchange, messages: &[Message], tools: &[ToolDefinition]) -> Option<String> {
+    let conversation = |messages: &[Message]| -> Vec<Message> {
+        messages.iter().filter(|m| m.role != Role::System).cloned().collect()
+    };
+    let (expected, actual) = (conversation(&recorded.messages), conversation(messages));
+
+    for (i, (want, got)) in expected.iter().zip(&actual).enumerate() {
+        if want.role != got.role || want.content != got.content {
+            return Some(format!(
+                "message {} was {} {:?}, recorded {} {:?}",
+                i,
+                got.role,
+                excerpt(&got.transcript()),
+                want.role,
+                excerpt(&want.transcript())
+            ));
+        }
+    }
+    if expected.len() != actual.len() {
+        return Some(format!(
+            "{} non-system messages, recorded {}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+
+    let names = |tools: &[ToolDefinition]| tools.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+    if names(&recorded.tools) != names(tools) {
+        return Some(format!("tools {:?}, recorded {:?}", names(tools), names(&recorded.tools)));
+    }
+    None
+}
+
+fn excerpt(text: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[async_trait]
+impl LanguageModel for ReplayProvider {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        self.serve(&completion_messages(prompt, system_prompt), &[])
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        self.serve(messages, &[])
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        self.serve(messages, tools)
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        let model = self
+            .exchanges
+            .iter()
+            .find_map(|e| e.response.as_ref().map(|r| r.model.clone()))
+            .unwrap_or_else(|| "replay".to_string());
+        ModelInfo {
+            provider: "replay".to_string(),
+            context_window: context_window_for(&model),
+            model,
+            max_tokens: 4096,
+            supports_tools: true,
+            supports_streaming: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TokenUsage;
+
+    fn response(content: &str) -> ModelResponse {
+        ModelResponse {
+            content: content.to_string(),
+            model: "gpt-4o".to_string(),
+            usage: TokenUsage::default(),
+            tool_calls: None,
+            finish_reason: Some("stop".to_string()),
+            thinking: None,
+            thinking_parts: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.jsonl");
+
+        let live = ReplayProvider::new(vec![
+            Exchange {
+                seq: 0,
+                messages: Vec::new(),
+                tools: Vec::new(),
+                response: Some(response("first")),
+                error: None,
+            },
+            Exchange {
+                seq: 1,
+                messages: Vec::new(),
+                tools: Vec::new(),
+                response: None,
+                error: Some("rate limited".to_string()),
+            },
+        ]);
+        let recorder = RecordingModel::new(Box::new(live), &path).unwrap();
+        recorder.chat(&[Message::system("sys"), Message::user("hi")]).await.unwrap();
+        assert!(recorder.chat(&[Message::user("again")]).await.is_err());
+
+        let replay = ReplayProvider::from_file(&path).unwrap().strict(true);
+        assert_eq!(replay.model_info().model, "gpt-4o");
+        assert_eq!(
+            replay.chat(&[Message::system("other machine"), Message::user("hi")]).await.unwrap().content,
+            "first"
+        );
+        let err = replay.chat(&[Message::user("again")]).await.unwrap_err();
+        assert!(err.to_string().contains("rate limited"));
+        assert!(replay.divergences().is_empty());
+        assert!(replay.chat(&[Message::user("more")]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_divergence_reported() {
+        let exchange = Exchange {
+            seq: 0,
+            messages: vec![Message::user("list files")],
+            tools: Vec::new(),
+            response: Some(response("ok")),
+            error: None,
+        };
+
+        let replay = ReplayProvider::new(vec![exchange.clone()]);
+        let served = replay.chat(&[Message::user("delete files")]).await.unwrap();
+        assert_eq!(served.content, "ok");
+        let divergences = replay.divergences();
+        assert_eq!(divergences.len(), 1);
+        assert!(divergences[0].detail.contains("delete files"));
+
+        let strict = ReplayProvider::new(vec![exchange]).strict(true);
+        assert!(strict
+            .chat(&[Message::user("list files"), Message::assistant("extra")])
+            .await
+            .is_err());
+    }
+}
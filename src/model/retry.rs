@@ -0,0 +1,455 @@
+//! Retry layer for model requests
+//!
+//! [`RetryingModel`] wraps any [`LanguageModel`] and retries transient
+//! failures (rate limits, server errors, timeouts, dropped connections) with
+//! exponential backoff and jitter, honoring the server's `Retry-After` up to
+//! the maximum backoff.
+
+use super::{LanguageModel, Message, ModelInfo, ModelResponse, ToolDefinition};
+use crate::config::RetryConfig;
+use crate::error::{ErrorClass, ModelError, PromptLineError, Result};
+use crate::tokenizer::Tokenizer;
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Build a model error from an unsuccessful HTTP response
+pub(crate) async fn error_from_response(provider: &str, response: reqwest::Response) -> ModelError {
+    let status = response.status().as_u16();
+    let retry_after = parse_retry_after(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    map_http_error(provider, status, retry_after, &body)
+}
+
+/// Map an HTTP status and error body to a model error
+pub(crate) fn map_http_error(provider: &str, status: u16, retry_after: Option<Duration>, body: &str) -> ModelError {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| {
+            v["error"]["message"]
+                .as_str()
+                .or_else(|| v["error"].as_str())
+                .map(|s| s.to_string())
+        })
+        .unwrap_or_else(|| body.to_string());
+
+    match status {
+        429 => ModelError::RateLimit { retry_after },
+        401 | 403 => ModelError::Auth(message),
+        404 => ModelError::NotAvailable(message),
+        408 | 500..=599 => ModelError::Server {
+            status,
+            message,
+            retry_after,
+        },
+        _ => ModelError::Api(format!("{} API error ({}): {}", provider, status, message)),
+    }
+}
+
+/// Read `retry-after-ms` or `retry-after` (in seconds) from response headers
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+
+    value("retry-after-ms")
+        .map(|ms| ms / 1000.0)
+        .or_else(|| value("retry-after"))
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+/// A retry about to happen, reported to the UI
+#[derive(Debug, Clone)]
+pub struct RetryEvent {
+    pub provider: String,
+    /// Number of this retry (1 for the first retry)
+    pub retry: u32,
+    pub max_retries: u32,
+    pub delay: Duration,
+    /// The error that caused the retry
+    pub error: String,
+}
+
+impl fmt::Display for RetryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}; retrying in {:.1}s ({}/{})",
+            self.provider,
+            self.error,
+            self.delay.as_secs_f64(),
+            self.retry,
+            self.max_retries
+        )
+    }
+}
+
+/// Callback notified before each retry
+pub type RetryObserver = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// When and how long to wait between attempts
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Timeout for each attempt
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from(&RetryConfig::default())
+    }
+}
+
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(config: &RetryConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            timeout: config.timeout_secs.map(Duration::from_secs),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before the given retry (1-based): exponential, capped, with jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        // Equal jitter: wait between half and all of the exponential delay
+        exponential.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Delay before retrying after `error`, or `None` if it should not be retried
+    ///
+    /// A server's `Retry-After` is capped at `max_backoff` too, so a bad
+    /// header can't stall a run for hours.
+    pub fn delay_for(&self, error: &ModelError, retry: u32) -> Option<Duration> {
+        if retry > self.max_retries || error.class() != ErrorClass::Retryable {
+            return None;
+        }
+        match error.retry_after() {
+            Some(delay) => Some(delay.min(self.max_backoff)),
+            None => Some(self.backoff(retry)),
+        }
+    }
+}
+
+/// Retries transient failures of the wrapped model
+pub struct RetryingModel {
+    inner: Box<dyn LanguageModel>,
+    policy: RetryPolicy,
+    observer: Option<RetryObserver>,
+}
+
+impl RetryingModel {
+    pub fn new(inner: Box<dyn LanguageModel>, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            observer: None,
+        }
+    }
+
+    /// Report each retry to `observer`
+    pub fn with_observer(mut self, observer: RetryObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    async fn attempt<F>(&self, request: F) -> Result<ModelResponse>
+    where
+        F: Future<Output = Result<ModelResponse>>,
+    {
+        match self.policy.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .unwrap_or_else(|_| Err(ModelError::Timeout(timeout).into())),
+            None => request.await,
+        }
+    }
+
+    /// Wait before the next attempt if `error` is retryable; otherwise give the error back
+    async fn backoff(&self, error: PromptLineError, retry: u32) -> Result<()> {
+        let delay = match &error {
+            PromptLineError::Model(e) => self.policy.delay_for(e, retry),
+            _ => None,
+        };
+        let Some(delay) = delay else {
+            return Err(error);
+        };
+
+        let event = RetryEvent {
+            provider: self.inner.model_info().provider,
+            retry,
+            max_retries: self.policy.max_retries,
+            delay,
+            error: error.to_string(),
+        };
+        tracing::warn!("{}", event);
+        if let Some(observer) = &self.observer {
+            observer(&event);
+        }
+
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    async fn retry<F, Fut>(&self, mut request: F) -> Result<ModelResponse>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<ModelResponse>>,
+    {
+        let mut retry = 0;
+        loop {
+            match self.attempt(request()).await {
+                Ok(response) => return Ok(response),
+                Err(error) => {
+                    retry += 1;
+                    self.backoff(error, retry).await?;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl LanguageModel for RetryingModel {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        self.retry(|| self.inner.complete(prompt, system_prompt)).await
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        self.retry(|| self.inner.chat(messages)).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        self.retry(|| self.inner.chat_with_tools(messages, tools)).await
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_delta: &mut (dyn for<'s> FnMut(&'s str) + Send),
+    ) -> Result<ModelResponse> {
+        let mut retry = 0;
+        loop {
+            let mut emitted = false;
+            let result = {
+                let mut forward = |delta: &str| {
+                    emitted = true;
+                    on_delta(delta);
+                };
+                self.attempt(self.inner.chat_stream(messages, tools, &mut forward)).await
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                // Text already shown to the user cannot be taken back
+                Err(error) if emitted => return Err(error),
+                Err(error) => {
+                    retry += 1;
+                    self.backoff(error, retry).await?;
+                }
+            }
+        }
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        self.inner.model_info()
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.inner.tokenizer()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    fn supports_streaming(&self) -> bool {
+        self.inner.supports_streaming()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::openai_compat::OpenAICompatibleProvider;
+    use serde_json::json;
+    use std::sync::Mutex;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            timeout: None,
+        }
+    }
+
+    fn success() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "model": "m",
+            "choices": [{"message": {"role": "assistant", "content": "ok"}, "finish_reason": "stop"}]
+        }))
+    }
+
+    async fn model_for(server: &MockServer, policy: RetryPolicy) -> (RetryingModel, Arc<Mutex<Vec<RetryEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let provider = OpenAICompatibleProvider::new("local", server.uri(), None, "m");
+        let model = RetryingModel::new(Box::new(provider), policy)
+            .with_observer(Arc::new(move |e: &RetryEvent| sink.lock().unwrap().push(e.clone())));
+        (model, events)
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            timeout: None,
+        };
+
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        assert!(policy.backoff(10) <= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_classification_and_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "2".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", "250".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_millis(250)));
+
+        let rate_limit = map_http_error("p", 429, Some(Duration::from_secs(7)), "");
+        assert_eq!(rate_limit.class(), ErrorClass::Retryable);
+        assert_eq!(policy(3).delay_for(&rate_limit, 1), Some(Duration::from_millis(5)));
+        assert_eq!(policy(3).delay_for(&rate_limit, 4), None);
+        let patient = RetryPolicy {
+            max_backoff: Duration::from_secs(60),
+            ..policy(3)
+        };
+        assert_eq!(patient.delay_for(&rate_limit, 1), Some(Duration::from_secs(7)));
+
+        assert_eq!(map_http_error("p", 503, None, "").class(), ErrorClass::Retryable);
+        assert_eq!(map_http_error("p", 401, None, "").class(), ErrorClass::Auth);
+        assert_eq!(map_http_error("p", 400, None, "").class(), ErrorClass::Fatal);
+    }
+
+    #[tokio::test]
+    async fn test_recovers_after_transient_failures() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("overloaded"))
+            .up_to_n_times(2)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST")).respond_with(success()).mount(&server).await;
+
+        let (model, events) = model_for(&server, policy(3)).await;
+        let response = model.chat(&[Message::user("Hi")]).await.unwrap();
+
+        assert_eq!(response.content, "ok");
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].retry, 2);
+    }
+
+    #[tokio::test]
+    async fn test_honors_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after-ms", "20"))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST")).respond_with(success()).mount(&server).await;
+
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_millis(100),
+            ..policy(3)
+        };
+        let (model, events) = model_for(&server, policy).await;
+        model.chat(&[Message::user("Hi")]).await.unwrap();
+
+        assert_eq!(events.lock().unwrap()[0].delay, Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let (model, events) = model_for(&server, policy(2)).await;
+        let err = model.chat(&[Message::user("Hi")]).await.unwrap_err();
+
+        assert!(matches!(err, PromptLineError::Model(ModelError::Server { status: 500, .. })));
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_auth_errors_are_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (model, events) = model_for(&server, policy(3)).await;
+        let err = model.chat(&[Message::user("Hi")]).await.unwrap_err();
+
+        assert!(matches!(err, PromptLineError::Model(ModelError::Auth(_))));
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_timeout_is_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(success().set_delay(Duration::from_millis(500)))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST")).respond_with(success()).mount(&server).await;
+
+        let (model, events) = model_for(
+            &server,
+            RetryPolicy {
+                timeout: Some(Duration::from_millis(100)),
+                ..policy(1)
+            },
+        )
+        .await;
+        model.chat(&[Message::user("Hi")]).await.unwrap();
+
+        assert!(events.lock().unwrap()[0].error.contains("timed out"));
+    }
+}
@@ -2,13 +2,14 @@
 //!
 //! Provides persistent permission storage with Once/Always/Never options
 
+use crate::approval::ApprovalDecision;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
 /// Permission level for tool execution
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum PermissionLevel {
     /// Allow once (this session only)
@@ -18,6 +19,7 @@ pub enum PermissionLevel {
     /// Never allow (persist to config)
     Never,
     /// Ask every time
+    #[default]
     Ask,
 }
 
@@ -32,6 +34,9 @@ pub struct PermissionManager {
     /// Session-only permissions (not saved)
     #[serde(skip)]
     session_permissions: HashMap<String, PermissionLevel>,
+    /// Level for tools without a stored permission
+    #[serde(skip)]
+    default_level: PermissionLevel,
 }
 
 impl PermissionManager {
@@ -51,9 +56,26 @@ impl PermissionManager {
             permissions,
             storage_path,
             session_permissions: HashMap::new(),
+            default_level: PermissionLevel::Ask,
         })
     }
 
+    /// Create a permission manager that never reads or writes the permissions file
+    pub fn in_memory() -> Self {
+        Self {
+            permissions: HashMap::new(),
+            storage_path: PathBuf::new(),
+            session_permissions: HashMap::new(),
+            default_level: PermissionLevel::Ask,
+        }
+    }
+
+    /// Use `level` for tools without a stored permission instead of asking
+    pub fn with_default_level(mut self, level: PermissionLevel) -> Self {
+        self.default_level = level;
+        self
+    }
+
     /// Get the storage path for permissions
     fn get_storage_path() -> Result<PathBuf> {
         let home = dirs::home_dir()
@@ -77,8 +99,7 @@ impl PermissionManager {
             return level.clone();
         }
 
-        // Default to Ask
-        PermissionLevel::Ask
+        self.default_level.clone()
     }
 
     /// Set permission for a tool
@@ -105,6 +126,9 @@ impl PermissionManager {
 
     /// Save permissions to disk
     fn save(&self) -> Result<()> {
+        if self.storage_path.as_os_str().is_empty() {
+            return Ok(());
+        }
         let content = serde_yaml::to_string(&self.permissions)?;
         std::fs::write(&self.storage_path, content)?;
         Ok(())
@@ -115,51 +139,24 @@ impl PermissionManager {
         self.permissions.clone()
     }
 
-    /// Prompt user for permission
-    pub fn prompt_for_permission(&mut self, tool_name: &str) -> Result<bool> {
-        use dialoguer::{theme::ColorfulTheme, Select};
-
-        println!("\n⚠️  Permission Required: {}", tool_name);
-        println!();
-
-        let options = vec![
-            "Once     - Allow this time only",
-            "Always   - Always allow (recommended)",
-            "Never    - Block this tool",
-        ];
-
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Choice")
-            .items(&options)
-            .default(0)
-            .interact()?;
-
-        let level = match selection {
-            0 => PermissionLevel::Once,
-            1 => PermissionLevel::Always,
-            2 => PermissionLevel::Never,
-            _ => PermissionLevel::Ask,
+    /// Store an approval decision for a tool and return whether it allows the call
+    ///
+    /// One-off denials are not stored, so the tool is asked about again next time.
+    pub fn record_decision(&mut self, tool_name: &str, decision: ApprovalDecision) -> Result<bool> {
+        let level = match decision {
+            ApprovalDecision::AllowOnce => PermissionLevel::Once,
+            ApprovalDecision::AllowAlways => PermissionLevel::Always,
+            ApprovalDecision::DenyAlways => PermissionLevel::Never,
+            ApprovalDecision::Deny => return Ok(false),
         };
-
-        self.set_permission(tool_name.to_string(), level.clone())?;
-
-        if level != PermissionLevel::Never {
-            println!("\n✓ Saved: {} = {:?}\n", tool_name, level);
-        } else {
-            println!("\n✗ Blocked: {}\n", tool_name);
-        }
-
-        Ok(level != PermissionLevel::Never)
+        self.set_permission(tool_name.to_string(), level)?;
+        Ok(decision.is_allowed())
     }
 }
 
 impl Default for PermissionManager {
     fn default() -> Self {
-        Self::new().unwrap_or_else(|_| Self {
-            permissions: HashMap::new(),
-            storage_path: PathBuf::new(),
-            session_permissions: HashMap::new(),
-        })
+        Self::new().unwrap_or_else(|_| Self::in_memory())
     }
 }
 
@@ -181,4 +178,14 @@ mod tests {
         manager.set_permission("test_tool".to_string(), PermissionLevel::Always).unwrap();
         assert_eq!(manager.check_permission("test_tool"), PermissionLevel::Always);
     }
+
+    #[test]
+    fn test_in_memory_default_level() {
+        let mut manager = PermissionManager::in_memory().with_default_level(PermissionLevel::Never);
+        assert_eq!(manager.check_permission("shell"), PermissionLevel::Never);
+
+        manager.set_permission("file_read".to_string(), PermissionLevel::Always).unwrap();
+        assert_eq!(manager.check_permission("file_read"), PermissionLevel::Always);
+        assert!(!manager.storage_path.exists());
+    }
 }
@@ -50,7 +50,7 @@ impl TemplateManager {
 
         while let Some(entry) = entries.next_entry().await? {
             let path = entry.path();
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "yaml" || ext == "yml") {
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml") {
                 let content = fs::read_to_string(&path).await?;
                 let template: PromptTemplate = serde_yaml::from_str(&content)
                     .map_err(|e| PromptLineError::Config(crate::error::ConfigError::Invalid(format!("Failed to parse template {}: {}", path.display(), e))))?;
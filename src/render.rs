@@ -0,0 +1,77 @@
+//! Terminal rendering of agent events
+//!
+//! Shows the loading indicator while the model thinks, formatted tool
+//! results and the contents of written files.
+
+use crate::agent::{AgentEvent, AgentObserver};
+use crate::formatter::ResponseFormatter;
+use crate::loading::LoadingIndicator;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Renders agent events to stdout for the interactive CLI
+pub struct TerminalRenderer {
+    formatter: ResponseFormatter,
+    loading: Mutex<LoadingIndicator>,
+}
+
+impl TerminalRenderer {
+    pub fn new() -> Self {
+        Self {
+            formatter: ResponseFormatter::new(),
+            loading: Mutex::new(LoadingIndicator::new()),
+        }
+    }
+}
+
+impl Default for TerminalRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AgentObserver for TerminalRenderer {
+    fn on_event(&self, event: &AgentEvent) {
+        match event {
+            AgentEvent::ThinkingStarted { .. } => self.loading.lock().unwrap().start(),
+            AgentEvent::ThinkingFinished { .. } => self.loading.lock().unwrap().cancel(),
+            AgentEvent::InstructionsLoaded { paths } => {
+                for path in paths {
+                    println!("\n\x1b[90m📋 Loaded instructions from {}\x1b[0m", path);
+                }
+            }
+            AgentEvent::CompactionStarted { percent } => {
+                println!("\n♻️  Context {}% full, compacting conversation...", percent);
+            }
+            AgentEvent::ContextWarning {
+                used_tokens,
+                context_window,
+            } => {
+                println!(
+                    "\n⚠️  Conversation uses {}/{} tokens and may not fit the model's context window. Try /compact or /clear.",
+                    used_tokens, context_window
+                );
+            }
+            AgentEvent::ToolCallFinished { name, output, .. } => {
+                print!("{}", self.formatter.format_tool_result(name, output));
+                std::io::stdout().flush().ok();
+            }
+            AgentEvent::FileChanged {
+                path,
+                content: Some(content),
+            } => {
+                let ext = std::path::Path::new(path)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("txt");
+
+                println!("\n\x1b[1;32mWritten to {}:\x1b[0m", path);
+                println!("```{}", ext);
+                println!("{}", content);
+                println!("```\n");
+            }
+            // The final answer is printed by the caller; deltas would race the loading indicator
+            _ => {}
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::model::content::media_type_for;
+use crate::model::{ContentPart, ImageSource, Message, Role};
 use rustyline::completion::{Completer as CompleterTrait, Pair};
 use rustyline::error::ReadlineError;
 use rustyline::highlight::MatchingBracketHighlighter;
@@ -5,6 +7,7 @@ use rustyline::hint::{Hinter as HinterTrait, HistoryHinter};
 use rustyline::validate::MatchingBracketValidator;
 use rustyline::Context;
 use rustyline::{Helper, Highlighter, Validator};
+use std::path::{Path, PathBuf};
 
 /// REPL Helper for autocomplete and highlighting
 #[derive(Helper, Validator, Highlighter)]
@@ -30,6 +33,12 @@ impl ReplHelper {
     }
 }
 
+impl Default for ReplHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CompleterTrait for ReplHelper {
     type Candidate = Pair;
 
@@ -43,9 +52,16 @@ impl CompleterTrait for ReplHelper {
             "/help",
             "/settings",
             "/clear",
+            "/compact",
+            "/context",
             "/status",
+            "/usage",
+            "/cost",
             "/model",
             "/permissions",
+            "/resume",
+            "/fork",
+            "/export",
             "/quit",
             "/exit",
             "/version",
@@ -78,3 +94,63 @@ impl HinterTrait for ReplHelper {
         self.hinter.hint(line, pos, ctx)
     }
 }
+
+/// Build the user message for REPL input, attaching images mentioned as `@path`
+///
+/// Mentions are resolved against `base_dir`. The `@` is dropped from attached
+/// mentions so the model still sees the file name; anything that is not a
+/// readable image is left as plain text.
+pub fn user_message(input: &str, base_dir: &Path) -> (Message, Vec<PathBuf>) {
+    let mut text = input.to_string();
+    let mut images = Vec::new();
+    let mut attached = Vec::new();
+
+    for word in input.split_whitespace() {
+        let Some(mention) = word.strip_prefix('@') else {
+            continue;
+        };
+        let mention = mention.trim_end_matches([',', '.', ';', ':', '!', '?', ')']);
+        if mention.is_empty() || media_type_for(Path::new(mention)).is_none() {
+            continue;
+        }
+
+        let path = base_dir.join(mention);
+        match ImageSource::from_file(&path) {
+            Ok(image) if !attached.contains(&path) => {
+                images.push(ContentPart::image(image));
+                attached.push(path);
+                text = text.replacen(&format!("@{}", mention), mention, 1);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Not attaching {}: {}", path.display(), e),
+        }
+    }
+
+    let mut content = vec![ContentPart::text(text)];
+    content.extend(images);
+    (Message::new(Role::User, content), attached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_mentions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("shot.png"), [0x89, b'P', b'N', b'G']).unwrap();
+
+        let (message, attached) = user_message("What's wrong in @shot.png? Also see @missing.png and @user", dir.path());
+
+        assert_eq!(attached, vec![dir.path().join("shot.png")]);
+        assert_eq!(message.text(), "What's wrong in shot.png? Also see @missing.png and @user");
+        assert_eq!(message.images().count(), 1);
+    }
+
+    #[test]
+    fn test_plain_input() {
+        let (message, attached) = user_message("hello", Path::new("."));
+        assert!(attached.is_empty());
+        assert!(message.is_text_only());
+    }
+}
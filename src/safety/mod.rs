@@ -1,8 +1,8 @@
 //! Safety validation and approval system
 
+use crate::approval::{ApprovalHandler, ApprovalRequest};
 use crate::config::Config;
 use crate::error::{Result, ToolError};
-use dialoguer::Confirm;
 use regex::Regex;
 
 pub struct SafetyValidator {
@@ -56,26 +56,13 @@ impl SafetyValidator {
         ValidationResult::Allowed
     }
 
-    /// Request approval from user
-    pub fn request_approval(&self, action: &str, details: &str) -> Result<bool> {
+    /// Ask `handler` to approve an action, unless approval is turned off
+    pub async fn request_approval(&self, handler: &dyn ApprovalHandler, request: &ApprovalRequest) -> Result<bool> {
         if !self.config.safety.require_approval {
             return Ok(true);
         }
 
-        println!("\n{}", "=".repeat(60));
-        println!("⚠️  Approval Required");
-        println!("{}", "=".repeat(60));
-        println!("Action: {}", action);
-        println!("Details:\n{}", details);
-        println!("{}", "=".repeat(60));
-
-        let approved = Confirm::new()
-            .with_prompt("Approve this action?")
-            .default(false)
-            .interact()
-            .unwrap_or(false);
-
-        Ok(approved)
+        Ok(handler.decide(request).await?.is_allowed())
     }
 
     /// Check if file is protected
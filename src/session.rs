@@ -0,0 +1,419 @@
+//! Saved conversations
+//!
+//! Each REPL session is stored as a JSON file under
+//! `<config dir>/promptline/sessions/<project>/<id>.json`, so sessions from
+//! different projects never mix. Sessions can be listed, resumed and forked.
+
+use crate::agent::{AgentEvent, AgentObserver};
+use crate::error::{PromptLineError, Result};
+use crate::model::{Message, Role};
+use crate::usage::UsageSummary;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Longest title taken from the first user message
+const MAX_TITLE_CHARS: usize = 60;
+
+/// Everything about a session except its messages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionMeta {
+    pub id: String,
+    /// First line of the first user message
+    pub title: String,
+    /// Directory the session was started in
+    pub project: PathBuf,
+    #[serde(default)]
+    pub provider: String,
+    #[serde(default)]
+    pub model: String,
+    /// Unix timestamps in seconds
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub message_count: usize,
+    /// Session this one was forked from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<String>,
+}
+
+/// Outcome of one tool call, kept alongside the messages for transcripts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolRun {
+    /// Id of the tool call in the messages
+    pub call_id: String,
+    pub tool: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    /// Unified diff of the file the tool wrote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// Unix timestamp in seconds
+    pub finished_at: u64,
+}
+
+/// A saved conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(flatten)]
+    pub meta: SessionMeta,
+    pub messages: Vec<Message>,
+    /// Token usage and cost over the whole session
+    #[serde(default)]
+    pub usage: UsageSummary,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_runs: Vec<ToolRun>,
+}
+
+impl Session {
+    /// Find the outcome of the tool call with id `call_id`
+    pub fn tool_run(&self, call_id: &str) -> Option<&ToolRun> {
+        self.tool_runs.iter().rev().find(|run| run.call_id == call_id)
+    }
+
+    /// Record the model the session is using
+    pub fn set_model(&mut self, info: &crate::model::ModelInfo) {
+        self.meta.provider = info.provider.clone();
+        self.meta.model = info.model.clone();
+    }
+}
+
+/// Sessions of one project
+pub struct SessionStore {
+    dir: PathBuf,
+    project: PathBuf,
+}
+
+impl SessionStore {
+    /// Store for `project` in the user's config directory
+    pub fn for_project(project: &Path) -> Result<Self> {
+        let root = match dirs::config_dir() {
+            Some(dir) => dir.join("promptline").join("sessions"),
+            None => PathBuf::from(".promptline").join("sessions"),
+        };
+        Ok(Self::new(&root, project))
+    }
+
+    /// Store for `project` under `root`
+    pub fn new(root: &Path, project: &Path) -> Self {
+        let project = project.canonicalize().unwrap_or_else(|_| project.to_path_buf());
+        Self {
+            dir: root.join(project_key(&project)),
+            project,
+        }
+    }
+
+    /// Start an empty session; it is written on the first save
+    pub fn create(&self) -> Session {
+        let now = now();
+        Session {
+            meta: SessionMeta {
+                id: new_id(now),
+                title: String::new(),
+                project: self.project.clone(),
+                provider: String::new(),
+                model: String::new(),
+                created_at: now,
+                updated_at: now,
+                message_count: 0,
+                forked_from: None,
+            },
+            messages: Vec::new(),
+            usage: UsageSummary::default(),
+            tool_runs: Vec::new(),
+        }
+    }
+
+    /// Copy `session` under a new id
+    pub fn fork(&self, session: &Session) -> Session {
+        let mut fork = self.create();
+        fork.meta.title = session.meta.title.clone();
+        fork.meta.provider = session.meta.provider.clone();
+        fork.meta.model = session.meta.model.clone();
+        fork.meta.forked_from = Some(session.meta.id.clone());
+        fork.messages = session.messages.clone();
+        fork.tool_runs = session.tool_runs.clone();
+        fork
+    }
+
+    /// Write the session, refreshing its title, message count and update time
+    ///
+    /// Sessions without any messages are not written.
+    pub fn save(&self, session: &mut Session) -> Result<()> {
+        if session.messages.is_empty() {
+            return Ok(());
+        }
+        if session.meta.title.is_empty() {
+            session.meta.title = title_for(&session.messages);
+        }
+        session.meta.message_count = session.messages.len();
+        session.meta.updated_at = now();
+
+        std::fs::create_dir_all(&self.dir)?;
+        let content = serde_json::to_string_pretty(session)?;
+        std::fs::write(self.path(&session.meta.id), content)?;
+        Ok(())
+    }
+
+    /// Sessions of this project, most recently updated first
+    pub fn list(&self) -> Result<Vec<SessionMeta>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut sessions = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_session(&path) {
+                Ok(session) => sessions.push(session.meta),
+                Err(e) => tracing::warn!("Skipping unreadable session {}: {}", path.display(), e),
+            }
+        }
+        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    /// Load a session by id or unique id prefix
+    pub fn load(&self, id: &str) -> Result<Session> {
+        let id = self.resolve(id)?;
+        read_session(&self.path(&id))
+    }
+
+    /// The most recently updated session
+    pub fn latest(&self) -> Result<Option<Session>> {
+        match self.list()?.first() {
+            Some(meta) => Ok(Some(self.load(&meta.id)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete a session by id or unique id prefix, returning its full id
+    pub fn delete(&self, id: &str) -> Result<String> {
+        let id = self.resolve(id)?;
+        std::fs::remove_file(self.path(&id))?;
+        Ok(id)
+    }
+
+    fn resolve(&self, id: &str) -> Result<String> {
+        let matches: Vec<String> = self
+            .list()?
+            .into_iter()
+            .map(|meta| meta.id)
+            .filter(|candidate| candidate.starts_with(id))
+            .collect();
+
+        if let Some(exact) = matches.iter().find(|candidate| *candidate == id) {
+            return Ok(exact.clone());
+        }
+        match matches.as_slice() {
+            [only] => Ok(only.clone()),
+            [] => Err(PromptLineError::Other(format!("No session matching '{}' in this project", id))),
+            _ => Err(PromptLineError::Other(format!(
+                "Session id '{}' is ambiguous: {}",
+                id,
+                matches.join(", ")
+            ))),
+        }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+/// Observer collecting [`ToolRun`]s as the agent finishes tool calls
+#[derive(Debug, Default)]
+pub struct ToolRunLog {
+    runs: Mutex<Vec<ToolRun>>,
+}
+
+impl ToolRunLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs collected since the last call
+    pub fn take(&self) -> Vec<ToolRun> {
+        std::mem::take(&mut *self.runs.lock().unwrap())
+    }
+}
+
+impl AgentObserver for ToolRunLog {
+    fn on_event(&self, event: &AgentEvent) {
+        if let AgentEvent::ToolCallFinished {
+            id,
+            name,
+            success,
+            metadata,
+            ..
+        } = event
+        {
+            self.runs.lock().unwrap().push(ToolRun {
+                call_id: id.clone(),
+                tool: name.clone(),
+                success: *success,
+                exit_code: metadata.get("exit_code").and_then(|c| c.as_i64()),
+                diff: metadata.get("diff").and_then(|d| d.as_str()).map(str::to_string),
+                finished_at: now(),
+            });
+        }
+    }
+}
+
+fn read_session(path: &Path) -> Result<Session> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Directory name for a project: its base name plus a hash of the full path
+fn project_key(project: &Path) -> String {
+    let name: String = project
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_string())
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let hash = Sha256::digest(project.to_string_lossy().as_bytes());
+    let hex: String = hash.iter().take(6).map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", name, hex)
+}
+
+/// Sortable id from the creation time, with a random suffix
+fn new_id(timestamp: u64) -> String {
+    let suffix: u16 = rand::random();
+    let stamp: String = format_timestamp(timestamp)
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+    format!("{}-{}-{:04x}", &stamp[..8], &stamp[8..], suffix)
+}
+
+fn title_for(messages: &[Message]) -> String {
+    let Some(first) = messages.iter().find(|m| m.role == Role::User) else {
+        return "(untitled)".to_string();
+    };
+    let text = first.text();
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("(untitled)");
+    if line.chars().count() > MAX_TITLE_CHARS {
+        format!("{}…", line.chars().take(MAX_TITLE_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM:SS` in UTC
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_list_and_resume() {
+        let root = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(root.path(), project.path());
+
+        let mut empty = store.create();
+        store.save(&mut empty).unwrap();
+        assert!(store.list().unwrap().is_empty());
+
+        let mut session = store.create();
+        session.messages = vec![Message::user("Fix the failing test\nin src/lib.rs"), Message::assistant("Done")];
+        store.save(&mut session).unwrap();
+
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].title, "Fix the failing test");
+        assert_eq!(listed[0].message_count, 2);
+
+        let loaded = store.load(&session.meta.id[..8]).unwrap();
+        assert_eq!(loaded.messages.len(), 2);
+        assert_eq!(store.latest().unwrap().unwrap().meta.id, session.meta.id);
+
+        // Another project sees none of it
+        let other = tempfile::tempdir().unwrap();
+        assert!(SessionStore::new(root.path(), other.path()).list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fork_and_delete() {
+        let root = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(root.path(), Path::new("/work/app"));
+
+        let mut session = store.create();
+        session.messages = vec![Message::user("hello")];
+        store.save(&mut session).unwrap();
+
+        let mut fork = store.fork(&session);
+        store.save(&mut fork).unwrap();
+        assert_ne!(fork.meta.id, session.meta.id);
+        assert_eq!(fork.meta.forked_from.as_deref(), Some(session.meta.id.as_str()));
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        assert_eq!(store.delete(&session.meta.id).unwrap(), session.meta.id);
+        assert!(store.load(&session.meta.id).is_err());
+        assert!(store.load(&fork.meta.id).is_ok());
+    }
+
+    #[test]
+    fn test_tool_run_log() {
+        let log = ToolRunLog::new();
+        log.on_event(&AgentEvent::ToolCallFinished {
+            id: "call_1".to_string(),
+            name: "shell_execute".to_string(),
+            success: false,
+            output: "boom".to_string(),
+            metadata: [("exit_code".to_string(), serde_json::json!(2))].into_iter().collect(),
+        });
+        log.on_event(&AgentEvent::ThinkingStarted { iteration: 1 });
+
+        let runs = log.take();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].exit_code, Some(2));
+        assert!(runs[0].diff.is_none());
+        assert!(log.take().is_empty());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1_709_210_096), "2024-02-29 12:34:56");
+    }
+}
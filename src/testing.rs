@@ -0,0 +1,560 @@
+//! Test support for code built on PromptLine
+//!
+//! Enabled with the `test-utils` feature. Provides a scripted
+//! [`LanguageModel`], a harness for running tools without an agent, a
+//! temporary workspace fixture, permission policies that never prompt and an
+//! observer that records agent events.
+//!
+//! ```ignore
+//! let model = ScriptedModel::new()
+//!     .respond_tool_call("file_list", serde_json::json!({}))
+//!     .respond("Done. FINISH");
+//! let probe = model.clone();
+//!
+//! let mut agent = build_agent(model, tools, PermissionPolicy::AllowAll).await?;
+//! agent.run("List the files").await?;
+//!
+//! probe.assert_exhausted();
+//! assert!(probe.request(1).iter().any(|m| m.role == Role::Tool));
+//! ```
+
+use crate::agent::{Agent, AgentEvent, AgentObserver};
+use crate::approval::{ApprovalDecision, ApprovalHandler, AutoApprove, AutoDeny, ScriptedApproval};
+use crate::config::Config;
+use crate::error::{ModelError, Result};
+use crate::model::{LanguageModel, Message, ModelInfo, ModelResponse, TokenUsage, ToolCall, ToolDefinition};
+use crate::permissions::{PermissionLevel, PermissionManager};
+use crate::tools::{Tool, ToolContext, ToolRegistry, ToolResult};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// One scripted reply
+enum Step {
+    Respond(ModelResponse),
+    Fail(Box<dyn Fn() -> ModelError + Send + Sync>),
+}
+
+/// A request received by a [`ScriptedModel`]
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub messages: Vec<Message>,
+    pub tools: Vec<ToolDefinition>,
+}
+
+#[derive(Default)]
+struct Script {
+    steps: VecDeque<Step>,
+    received: Vec<ReceivedRequest>,
+}
+
+/// Language model that replies from a queue of scripted responses
+///
+/// Clones share the queue and the log of received requests, so keep a clone
+/// to make assertions after handing the model to an [`Agent`].
+#[derive(Clone)]
+pub struct ScriptedModel {
+    script: Arc<Mutex<Script>>,
+    info: ModelInfo,
+    usage: TokenUsage,
+}
+
+impl ScriptedModel {
+    pub fn new() -> Self {
+        Self {
+            script: Arc::new(Mutex::new(Script::default())),
+            info: ModelInfo {
+                provider: "scripted".to_string(),
+                model: "scripted".to_string(),
+                max_tokens: 4096,
+                context_window: 8192,
+                supports_tools: false,
+                supports_streaming: false,
+            },
+            usage: TokenUsage::default(),
+        }
+    }
+
+    /// Report `info` from `model_info`, e.g. to pick a priced model name
+    pub fn with_model_info(mut self, info: ModelInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Report `usage` on the responses queued after this, e.g. to exercise cost budgets
+    pub fn with_usage(mut self, usage: TokenUsage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Queue a full response
+    pub fn respond_with(self, response: ModelResponse) -> Self {
+        self.script.lock().unwrap().steps.push_back(Step::Respond(response));
+        self
+    }
+
+    /// Queue a plain text response
+    pub fn respond(self, content: impl Into<String>) -> Self {
+        let response = self.response(content.into(), None);
+        self.respond_with(response)
+    }
+
+    /// Queue a response calling tool `name`
+    ///
+    /// The call is written in the agent's text protocol and also set as a
+    /// native tool call, so it works with either style of caller.
+    pub fn respond_tool_call(self, name: impl Into<String>, args: serde_json::Value) -> Self {
+        let name = name.into();
+        let content = serde_json::json!({"tool": name, "args": args}).to_string();
+        let call = ToolCall {
+            id: format!("scripted_{}", self.script.lock().unwrap().steps.len()),
+            name,
+            arguments: args,
+        };
+        let response = self.response(content, Some(vec![call]));
+        self.respond_with(response)
+    }
+
+    /// Queue an API error
+    pub fn fail(self, message: impl Into<String>) -> Self {
+        let message = message.into();
+        self.fail_with(move || ModelError::Api(message.clone()))
+    }
+
+    /// Queue an error built by `error`, e.g. a rate limit
+    pub fn fail_with(self, error: impl Fn() -> ModelError + Send + Sync + 'static) -> Self {
+        self.script.lock().unwrap().steps.push_back(Step::Fail(Box::new(error)));
+        self
+    }
+
+    fn response(&self, content: String, tool_calls: Option<Vec<ToolCall>>) -> ModelResponse {
+        ModelResponse {
+            content,
+            model: self.info.model.clone(),
+            usage: self.usage.clone(),
+            tool_calls,
+            finish_reason: Some("stop".to_string()),
+            thinking: None,
+            thinking_parts: Vec::new(),
+        }
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.script.lock().unwrap().received.clone()
+    }
+
+    /// Messages of request `index`
+    ///
+    /// # Panics
+    /// If fewer than `index + 1` requests were received.
+    pub fn request(&self, index: usize) -> Vec<Message> {
+        let script = self.script.lock().unwrap();
+        match script.received.get(index) {
+            Some(request) => request.messages.clone(),
+            None => panic!(
+                "scripted model received {} request(s), no request {}",
+                script.received.len(),
+                index
+            ),
+        }
+    }
+
+    /// Messages of the most recent request
+    ///
+    /// # Panics
+    /// If no request was received.
+    pub fn last_request(&self) -> Vec<Message> {
+        let count = self.request_count();
+        assert!(count > 0, "scripted model received no requests");
+        self.request(count - 1)
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.script.lock().unwrap().received.len()
+    }
+
+    /// Number of scripted replies not yet used
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().steps.len()
+    }
+
+    /// Panic unless every scripted reply was used
+    pub fn assert_exhausted(&self) {
+        let remaining = self.remaining();
+        assert!(remaining == 0, "scripted model has {} unused response(s)", remaining);
+    }
+
+    fn next(&self, messages: &[Message], tools: &[ToolDefinition]) -> Result<ModelResponse> {
+        let mut script = self.script.lock().unwrap();
+        script.received.push(ReceivedRequest {
+            messages: messages.to_vec(),
+            tools: tools.to_vec(),
+        });
+        match script.steps.pop_front() {
+            Some(Step::Respond(response)) => Ok(response),
+            Some(Step::Fail(error)) => Err(error().into()),
+            None => Err(ModelError::InvalidResponse(format!(
+                "scripted model has no response for request {}",
+                script.received.len() - 1
+            ))
+            .into()),
+        }
+    }
+}
+
+impl Default for ScriptedModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl LanguageModel for ScriptedModel {
+    async fn complete(&self, prompt: &str, system_prompt: Option<&str>) -> Result<ModelResponse> {
+        let mut messages = Vec::new();
+        if let Some(system) = system_prompt {
+            messages.push(Message::system(system));
+        }
+        messages.push(Message::user(prompt));
+        self.next(&messages, &[])
+    }
+
+    async fn chat(&self, messages: &[Message]) -> Result<ModelResponse> {
+        self.next(messages, &[])
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ModelResponse> {
+        self.next(messages, tools)
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        self.info.clone()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.info.supports_tools
+    }
+
+    fn supports_streaming(&self) -> bool {
+        self.info.supports_streaming
+    }
+}
+
+/// Tool with a fixed name and canned result that records its arguments
+///
+/// Clones share the call log.
+#[derive(Clone)]
+pub struct StubTool {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+    result: ToolResult,
+    read_only: bool,
+    calls: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl StubTool {
+    /// A tool that accepts any arguments and succeeds with empty output
+    pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Self {
+            description: format!("Stub tool {}", name),
+            name,
+            parameters: serde_json::json!({"type": "object", "properties": {}}),
+            result: ToolResult::success(""),
+            read_only: false,
+            calls: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Return `result` from every call
+    pub fn returns(mut self, result: ToolResult) -> Self {
+        self.result = result;
+        self
+    }
+
+    /// Parameter schema; its `required` fields are validated before execution
+    pub fn with_parameters(mut self, parameters: serde_json::Value) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Arguments of each call so far
+    pub fn calls(&self) -> Vec<serde_json::Value> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Tool for StubTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        self.parameters.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    async fn execute(&self, args: serde_json::Value, _ctx: &ToolContext, _config: &Config) -> Result<ToolResult> {
+        self.calls.lock().unwrap().push(args);
+        Ok(self.result.clone())
+    }
+}
+
+/// Runs tools from an in-memory registry without an agent or model
+///
+/// Approval requests from tools are granted unless another handler is set.
+pub struct ToolHarness {
+    registry: ToolRegistry,
+    config: Config,
+    ctx: ToolContext,
+}
+
+impl ToolHarness {
+    pub fn new() -> Self {
+        Self {
+            registry: ToolRegistry::new(),
+            config: Config::default(),
+            ctx: ToolContext {
+                approval: Arc::new(AutoApprove),
+                ..ToolContext::default()
+            },
+        }
+    }
+
+    /// Register a tool
+    pub fn with_tool<T: Tool + 'static>(mut self, tool: T) -> Self {
+        self.registry.register(tool);
+        self
+    }
+
+    /// Config passed to tools
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Decide on approval requests from tools with `handler`
+    pub fn with_approval_handler(mut self, handler: Arc<dyn ApprovalHandler>) -> Self {
+        self.ctx.approval = handler;
+        self
+    }
+
+    /// Give tools access to language servers
+    pub fn with_lsp(mut self, lsp: Arc<crate::lsp::LspManager>) -> Self {
+        self.ctx.lsp = Some(lsp);
+        self
+    }
+
+    /// Run tools with `workspace` as the working directory
+    pub fn in_workspace(mut self, workspace: &TempWorkspace) -> Self {
+        self.ctx.working_dir = workspace.path().to_path_buf();
+        self.ctx.current_working_dir = workspace.path().to_path_buf();
+        self
+    }
+
+    /// Validate `args` and execute tool `name`
+    pub async fn execute(&self, name: &str, args: serde_json::Value) -> Result<ToolResult> {
+        self.registry.execute(name, args, &self.ctx, &self.config).await
+    }
+
+    pub fn registry(&self) -> &ToolRegistry {
+        &self.registry
+    }
+
+    /// Hand the registry to an agent
+    pub fn into_registry(self) -> ToolRegistry {
+        self.registry
+    }
+}
+
+impl Default for ToolHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Temporary directory removed when dropped
+pub struct TempWorkspace {
+    dir: tempfile::TempDir,
+}
+
+impl TempWorkspace {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            dir: tempfile::tempdir()?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Absolute path of `relative` inside the workspace
+    pub fn join(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.dir.path().join(relative)
+    }
+
+    /// Write a file, creating parent directories, and return its path
+    pub fn write(&self, relative: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<PathBuf> {
+        let path = self.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    pub fn read(&self, relative: impl AsRef<Path>) -> Result<String> {
+        Ok(std::fs::read_to_string(self.join(relative))?)
+    }
+
+    pub fn exists(&self, relative: impl AsRef<Path>) -> bool {
+        self.join(relative).exists()
+    }
+}
+
+/// Permission decisions made without prompting or touching the permissions file
+#[derive(Debug, Clone)]
+pub enum PermissionPolicy {
+    /// Every tool may run
+    AllowAll,
+    /// No tool may run
+    DenyAll,
+    /// Only the named tools may run
+    AllowOnly(Vec<String>),
+}
+
+impl PermissionPolicy {
+    /// Build an in-memory permission manager applying this policy
+    pub fn into_manager(self) -> Arc<Mutex<PermissionManager>> {
+        let manager = match self {
+            Self::AllowAll => PermissionManager::in_memory().with_default_level(PermissionLevel::Always),
+            Self::DenyAll => PermissionManager::in_memory().with_default_level(PermissionLevel::Never),
+            Self::AllowOnly(tools) => {
+                let mut manager = PermissionManager::in_memory().with_default_level(PermissionLevel::Never);
+                for tool in tools {
+                    // Session-level, so nothing is written to disk
+                    let _ = manager.set_permission(tool, PermissionLevel::Once);
+                }
+                manager
+            }
+        };
+        Arc::new(Mutex::new(manager))
+    }
+
+    /// Approval handler giving the same answers, for diffs and other tool prompts
+    pub fn approval_handler(&self) -> Arc<dyn ApprovalHandler> {
+        match self {
+            Self::AllowAll => Arc::new(AutoApprove),
+            Self::DenyAll => Arc::new(AutoDeny),
+            Self::AllowOnly(tools) => Arc::new(tools.iter().fold(
+                ScriptedApproval::new(ApprovalDecision::Deny),
+                |handler, tool| handler.with_rule(tool.as_str(), ApprovalDecision::AllowOnce),
+            )),
+        }
+    }
+}
+
+/// Observer that keeps every event it receives
+///
+/// Clones share the event log.
+#[derive(Debug, Clone, Default)]
+pub struct EventRecorder {
+    events: Arc<Mutex<Vec<AgentEvent>>>,
+}
+
+impl EventRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events received so far, oldest first
+    pub fn events(&self) -> Vec<AgentEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl AgentObserver for EventRecorder {
+    fn on_event(&self, event: &AgentEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// Create an agent with the default config and no prior history
+pub async fn build_agent(
+    model: impl LanguageModel + 'static,
+    tools: ToolRegistry,
+    policy: PermissionPolicy,
+) -> Result<Agent> {
+    let approval = policy.approval_handler();
+    Ok(Agent::new(Box::new(model), tools, Config::default(), Vec::new(), policy.into_manager())
+        .await?
+        .with_approval_handler(approval))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Role;
+
+    #[tokio::test]
+    async fn test_scripted_model_replies_in_order() {
+        let model = ScriptedModel::new().respond("first").fail("boom");
+        let probe = model.clone();
+
+        assert_eq!(model.chat(&[Message::user("one")]).await.unwrap().content, "first");
+        assert!(model.chat(&[Message::user("two")]).await.is_err());
+        assert!(model.chat(&[Message::user("three")]).await.is_err());
+
+        assert_eq!(probe.request_count(), 3);
+        assert_eq!(probe.request(1)[0].text(), "two");
+        assert_eq!(probe.last_request()[0].role, Role::User);
+        probe.assert_exhausted();
+    }
+
+    #[tokio::test]
+    async fn test_tool_harness_in_workspace() {
+        let workspace = TempWorkspace::new().unwrap();
+        let path = workspace.write("notes/todo.txt", "ship it").unwrap();
+
+        let harness = ToolHarness::new()
+            .with_tool(crate::tools::file_ops::FileReadTool::new())
+            .in_workspace(&workspace);
+        let result = harness
+            .execute("file_read", serde_json::json!({"path": path.to_string_lossy()}))
+            .await
+            .unwrap();
+
+        assert!(result.success);
+        assert!(result.output.contains("ship it"));
+    }
+
+    #[test]
+    fn test_allow_only_policy() {
+        let manager = PermissionPolicy::AllowOnly(vec!["file_read".to_string()]).into_manager();
+        let manager = manager.lock().unwrap();
+
+        assert_eq!(manager.check_permission("file_read"), PermissionLevel::Once);
+        assert_eq!(manager.check_permission("shell"), PermissionLevel::Never);
+    }
+}
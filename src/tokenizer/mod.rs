@@ -0,0 +1,294 @@
+//! Token counting
+//!
+//! Uses the bundled BPE vocabularies for OpenAI model families (cl100k/o200k)
+//! and falls back to a character heuristic for other models. A vocabulary can
+//! also be loaded from a local `.tiktoken` file via [`set_vocab_dir`].
+
+use crate::error::{ConfigError, Result};
+use crate::model::content::IMAGE_TOKEN_ESTIMATE;
+use crate::model::{ContentPart, Message};
+use base64::{engine::general_purpose, Engine as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tiktoken_rs::CoreBPE;
+
+/// Tokens added per message for role and separators (OpenAI chat format)
+const TOKENS_PER_MESSAGE: usize = 3;
+
+const CL100K_PATTERN: &str = "(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+
+const O200K_PATTERN: &str = concat!(
+    "[^\\r\\n\\p{L}\\p{N}]?[\\p{Lu}\\p{Lt}\\p{Lm}\\p{Lo}\\p{M}]*[\\p{Ll}\\p{Lm}\\p{Lo}\\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    "|[^\\r\\n\\p{L}\\p{N}]?[\\p{Lu}\\p{Lt}\\p{Lm}\\p{Lo}\\p{M}]+[\\p{Ll}\\p{Lm}\\p{Lo}\\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    "|\\p{N}{1,3}",
+    "| ?[^\\s\\p{L}\\p{N}]+[\\r\\n/]*",
+    "|\\s*[\\r\\n]+",
+    "|\\s+(?!\\S)",
+    "|\\s+",
+);
+
+static VOCAB_DIR: OnceLock<PathBuf> = OnceLock::new();
+static CL100K: OnceLock<Option<Arc<BpeTokenizer>>> = OnceLock::new();
+static O200K: OnceLock<Option<Arc<BpeTokenizer>>> = OnceLock::new();
+
+/// Counts tokens in text
+pub trait Tokenizer: Send + Sync {
+    /// Tokenizer name shown to the user
+    fn name(&self) -> &'static str;
+
+    /// Number of tokens in `text`
+    fn count(&self, text: &str) -> usize;
+}
+
+/// BPE vocabularies used by OpenAI model families
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Cl100kBase,
+    O200kBase,
+}
+
+impl Encoding {
+    /// Pick the encoding used by a model, if it is a known OpenAI family
+    pub fn for_model(model: &str) -> Option<Self> {
+        let model = model.to_lowercase();
+        let model = model.rsplit('/').next().unwrap_or(&model);
+
+        if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || model.starts_with("gpt-5")
+            || model.starts_with("o1")
+            || model.starts_with("o3")
+            || model.starts_with("o4")
+        {
+            Some(Self::O200kBase)
+        } else if model.starts_with("gpt-4")
+            || model.starts_with("gpt-3.5")
+            || model.starts_with("text-embedding")
+        {
+            Some(Self::Cl100kBase)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cl100kBase => "cl100k_base",
+            Self::O200kBase => "o200k_base",
+        }
+    }
+
+    fn pattern(&self) -> &'static str {
+        match self {
+            Self::Cl100kBase => CL100K_PATTERN,
+            Self::O200kBase => O200K_PATTERN,
+        }
+    }
+
+    fn special_tokens(&self) -> &'static [(&'static str, u32)] {
+        match self {
+            Self::Cl100kBase => &[
+                ("<|endoftext|>", 100257),
+                ("<|fim_prefix|>", 100258),
+                ("<|fim_middle|>", 100259),
+                ("<|fim_suffix|>", 100260),
+                ("<|endofprompt|>", 100276),
+            ],
+            Self::O200kBase => &[("<|endoftext|>", 199999), ("<|endofprompt|>", 200018)],
+        }
+    }
+}
+
+/// Byte-pair encoding tokenizer
+pub struct BpeTokenizer {
+    encoding: Encoding,
+    bpe: CoreBPE,
+}
+
+impl BpeTokenizer {
+    /// Load the vocabulary bundled with the binary
+    pub fn bundled(encoding: Encoding) -> Result<Self> {
+        let bpe = match encoding {
+            Encoding::Cl100kBase => tiktoken_rs::cl100k_base(),
+            Encoding::O200kBase => tiktoken_rs::o200k_base(),
+        }
+        .map_err(|e| ConfigError::Invalid(format!("Failed to load {} vocabulary: {}", encoding.name(), e)))?;
+
+        Ok(Self { encoding, bpe })
+    }
+
+    /// Load a vocabulary from a `.tiktoken` file (base64 token and rank per line)
+    pub fn from_file(encoding: Encoding, path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+
+        let encoder = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (token, rank) = line
+                    .split_once(' ')
+                    .ok_or_else(|| ConfigError::Invalid(format!("Malformed vocabulary line: {}", line)))?;
+                let token = general_purpose::STANDARD
+                    .decode(token)
+                    .map_err(|e| ConfigError::Invalid(format!("Invalid token encoding: {}", e)))?;
+                let rank = rank
+                    .trim()
+                    .parse()
+                    .map_err(|e| ConfigError::Invalid(format!("Invalid token rank: {}", e)))?;
+                Ok((token, rank))
+            })
+            .collect::<Result<_>>()?;
+
+        let special_tokens = encoding
+            .special_tokens()
+            .iter()
+            .map(|(token, rank)| (token.to_string(), *rank))
+            .collect();
+
+        let bpe = CoreBPE::new(encoder, special_tokens, encoding.pattern())
+            .map_err(|e| ConfigError::Invalid(format!("Failed to build tokenizer from {}: {}", path.display(), e)))?;
+
+        Ok(Self { encoding, bpe })
+    }
+
+    /// Encode text into token ids
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.bpe.encode_ordinary(text)
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
+
+/// Character-based estimate used for models without a known vocabulary
+pub struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn name(&self) -> &'static str {
+        "heuristic"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        // Rough estimate: 1 token ≈ 4 characters
+        text.len().div_ceil(4)
+    }
+}
+
+/// Directory searched for `<encoding>.tiktoken` files before the bundled vocabularies
+///
+/// Must be called before the first tokenizer is loaded; later calls are ignored.
+pub fn set_vocab_dir(dir: impl Into<PathBuf>) {
+    let _ = VOCAB_DIR.set(dir.into());
+}
+
+fn load(encoding: Encoding) -> Option<Arc<BpeTokenizer>> {
+    let local = VOCAB_DIR
+        .get()
+        .map(|dir| dir.join(format!("{}.tiktoken", encoding.name())))
+        .filter(|path| path.exists());
+
+    let tokenizer = match local {
+        Some(path) => BpeTokenizer::from_file(encoding, &path),
+        None => BpeTokenizer::bundled(encoding),
+    };
+
+    match tokenizer {
+        Ok(tokenizer) => Some(Arc::new(tokenizer)),
+        Err(e) => {
+            tracing::warn!("Falling back to heuristic token counting: {}", e);
+            None
+        }
+    }
+}
+
+/// Get the tokenizer for an encoding (loaded once and cached)
+pub fn for_encoding(encoding: Encoding) -> Option<Arc<BpeTokenizer>> {
+    let cell = match encoding {
+        Encoding::Cl100kBase => &CL100K,
+        Encoding::O200kBase => &O200K,
+    };
+    cell.get_or_init(|| load(encoding)).clone()
+}
+
+/// Get the best available tokenizer for a model
+pub fn for_model(model: &str) -> Arc<dyn Tokenizer> {
+    match Encoding::for_model(model).and_then(for_encoding) {
+        Some(tokenizer) => tokenizer,
+        None => Arc::new(HeuristicTokenizer),
+    }
+}
+
+/// Token count for a single chat message, including role overhead
+pub fn count_message(tokenizer: &dyn Tokenizer, message: &Message) -> usize {
+    let content: usize = message
+        .content
+        .iter()
+        .map(|part| match part {
+            ContentPart::Text { text } => tokenizer.count(text),
+            ContentPart::Image { .. } => IMAGE_TOKEN_ESTIMATE,
+            ContentPart::ToolCall(call) => tokenizer.count(&call.name) + tokenizer.count(&call.arguments.to_string()),
+            ContentPart::ToolResult { content, .. } => tokenizer.count(content),
+            ContentPart::Thinking { thinking, .. } => tokenizer.count(thinking),
+        })
+        .sum();
+    TOKENS_PER_MESSAGE + tokenizer.count(message.role.as_str()) + content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_for_model() {
+        assert_eq!(Encoding::for_model("gpt-4o-mini"), Some(Encoding::O200kBase));
+        assert_eq!(Encoding::for_model("gpt-4-turbo"), Some(Encoding::Cl100kBase));
+        assert_eq!(Encoding::for_model("openai/gpt-3.5-turbo"), Some(Encoding::Cl100kBase));
+        assert_eq!(Encoding::for_model("llama3"), None);
+    }
+
+    #[test]
+    fn test_bpe_counts() {
+        let cl100k = for_encoding(Encoding::Cl100kBase).unwrap();
+        assert_eq!(cl100k.count("hello world"), 2);
+        assert_eq!(cl100k.count(""), 0);
+
+        let o200k = for_encoding(Encoding::O200kBase).unwrap();
+        assert_eq!(o200k.count("hello world"), 2);
+    }
+
+    #[test]
+    fn test_fallback_to_heuristic() {
+        let tokenizer = for_model("llama3");
+        assert_eq!(tokenizer.name(), "heuristic");
+        assert_eq!(tokenizer.count("Hello world!"), 3);
+    }
+
+    #[test]
+    fn test_load_vocab_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tiny.tiktoken");
+        let vocab: String = (0u8..=255)
+            .map(|b| format!("{} {}\n", general_purpose::STANDARD.encode([b]), b))
+            .chain(std::iter::once(format!("{} 256\n", general_purpose::STANDARD.encode("ab"))))
+            .collect();
+        std::fs::write(&path, vocab).unwrap();
+
+        let tokenizer = BpeTokenizer::from_file(Encoding::Cl100kBase, &path).unwrap();
+        assert_eq!(tokenizer.encode("ab"), vec![256]);
+        assert_eq!(tokenizer.count("abc"), 2);
+    }
+
+    #[test]
+    fn test_count_message() {
+        let tokenizer = HeuristicTokenizer;
+        let message = Message::user("x".repeat(40));
+        assert_eq!(count_message(&tokenizer, &message), 3 + 1 + 10);
+    }
+}
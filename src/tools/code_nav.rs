@@ -0,0 +1,466 @@
+//! Code navigation and retrieval tools backed by the project indexes
+
+use super::{Tool, ToolContext, ToolResult};
+use crate::context::project_root;
+use crate::error::{PromptLineError, Result, ToolError};
+use crate::index::outline::{Lang, ParsedFile};
+use crate::index::{CodeIndex, SymbolIndex};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most definitions or references listed
+const MAX_RESULTS: usize = 200;
+
+/// Chunks returned by `code_retrieve` unless the model asks for another number
+const DEFAULT_RETRIEVE_LIMIT: usize = 8;
+
+/// Most chunks `code_retrieve` returns
+const MAX_RETRIEVE_LIMIT: usize = 20;
+
+/// Lines of each retrieved chunk shown
+const SNIPPET_LINES: usize = 30;
+
+/// How long the in-memory retrieval index is used before looking for changed files again
+const INDEX_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Bring the index of the project containing `working_dir` up to date and query it
+async fn with_index<T, F>(working_dir: &Path, query: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&SymbolIndex) -> T + Send + 'static,
+{
+    let root = project_root(working_dir);
+    tokio::task::spawn_blocking(move || SymbolIndex::load(&root).map(|index| query(&index)))
+        .await
+        .map_err(|e| PromptLineError::Other(e.to_string()))?
+}
+
+/// `path` relative to the project root, if it is inside the project
+fn relative_to_root(ctx: &ToolContext, path: &Path) -> Option<PathBuf> {
+    let root = project_root(&ctx.working_dir);
+    path.strip_prefix(&root).ok().map(Path::to_path_buf)
+}
+
+/// Join `lines`, keeping at most [`MAX_RESULTS`]
+fn limited(lines: Vec<String>, noun: &str) -> String {
+    let total = lines.len();
+    let mut output: Vec<String> = lines.into_iter().take(MAX_RESULTS).collect();
+    if total > MAX_RESULTS {
+        output.push(format!("… {} more {} not shown", total - MAX_RESULTS, noun));
+    }
+    output.join("\n")
+}
+
+/// Lists the definitions in a file with their line ranges
+pub struct CodeOutlineTool;
+
+#[async_trait]
+impl Tool for CodeOutlineTool {
+    fn name(&self) -> &str {
+        "code_outline"
+    }
+
+    fn description(&self) -> &str {
+        "List the functions, structs, classes, methods and other definitions in a source file, nested as in the file, with their line ranges. Supports Rust, Python, JavaScript, TypeScript, Go and Java. Use it to find the lines to read before opening a large file."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path to the source file"
+                }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let path_str = args["path"]
+            .as_str()
+            .ok_or_else(|| ToolError::InvalidArgs("Missing path".to_string()))?;
+        let path = ctx.resolve(path_str);
+
+        if !path.is_file() {
+            return Ok(ToolResult::error(format!("File not found: {}", path.display())));
+        }
+        let Some(lang) = Lang::for_path(&path) else {
+            return Ok(ToolResult::error(format!(
+                "No outline for {}: supported languages are Rust, Python, JavaScript, TypeScript, Go and Java",
+                path_str
+            )));
+        };
+
+        let indexed = match relative_to_root(ctx, &path) {
+            Some(relative) => {
+                with_index(&ctx.working_dir, move |index| index.outline(&relative).map(<[_]>::to_vec)).await?
+            }
+            None => None,
+        };
+        // Files outside the project, or too large for the index, are parsed directly
+        let items = match indexed {
+            Some(items) => items,
+            None => ParsedFile::parse(lang, tokio::fs::read_to_string(&path).await?)?.outline(),
+        };
+
+        if items.is_empty() {
+            return Ok(ToolResult::success(format!("No definitions found in {}", path_str)).with_metadata("definitions", serde_json::json!(0)));
+        }
+        let count = items.len();
+        let lines = items.iter().map(|item| item.to_string()).collect();
+        Ok(ToolResult::success(limited(lines, "definitions")).with_metadata("definitions", serde_json::json!(count)))
+    }
+}
+
+/// Finds where a symbol is defined
+pub struct FindDefinitionTool;
+
+#[async_trait]
+impl Tool for FindDefinitionTool {
+    fn name(&self) -> &str {
+        "find_definition"
+    }
+
+    fn description(&self) -> &str {
+        "Find where a function, type, class, method or other symbol is defined in the project. Accepts a plain name or one qualified by its type, such as `Agent::new` or `Shop.buy`. Returns file paths with line ranges."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": {
+                    "type": "string",
+                    "description": "Symbol name, optionally qualified as Type::name or Type.name"
+                }
+            },
+            "required": ["name"]
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let name = args["name"]
+            .as_str()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| ToolError::InvalidArgs("Missing symbol name".to_string()))?
+            .to_string();
+
+        let query = name.clone();
+        let definitions = with_index(&ctx.working_dir, move |index| index.find_definition(&query)).await?;
+        if definitions.is_empty() {
+            return Ok(ToolResult::success(format!("No definition found for '{}'", name)).with_metadata("matches", serde_json::json!(0)));
+        }
+        let count = definitions.len();
+        let lines = definitions.iter().map(|d| d.to_string()).collect();
+        Ok(ToolResult::success(limited(lines, "definitions")).with_metadata("matches", serde_json::json!(count)))
+    }
+}
+
+/// Finds the uses of an identifier
+pub struct FindReferencesTool;
+
+#[async_trait]
+impl Tool for FindReferencesTool {
+    fn name(&self) -> &str {
+        "find_references"
+    }
+
+    fn description(&self) -> &str {
+        "Find every use of an identifier in the project's source code, matching whole identifiers only (not comments or strings). Returns file:line:column with the line's text. Optionally limit the search to a file or directory."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": {
+                    "type": "string",
+                    "description": "Identifier to look for"
+                },
+                "path": {
+                    "type": "string",
+                    "description": "Optional: only search this file or directory"
+                }
+            },
+            "required": ["name"]
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let name = args["name"]
+            .as_str()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| ToolError::InvalidArgs("Missing identifier name".to_string()))?
+            .to_string();
+        let within = match args["path"].as_str() {
+            Some(path_str) => match relative_to_root(ctx, &ctx.resolve(path_str)) {
+                Some(relative) => Some(relative),
+                None => return Ok(ToolResult::error(format!("{} is outside the project", path_str))),
+            },
+            None => None,
+        };
+
+        let query = name.clone();
+        let references =
+            with_index(&ctx.working_dir, move |index| index.find_references(&query, within.as_deref())).await?;
+        if references.is_empty() {
+            return Ok(ToolResult::success(format!("No references found for '{}'", name)).with_metadata("matches", serde_json::json!(0)));
+        }
+
+        let count = references.len();
+        let files = references.iter().map(|r| &r.path).collect::<std::collections::BTreeSet<_>>().len();
+        let mut output = limited(references.iter().map(|r| r.to_string()).collect(), "references");
+        output.push_str(&format!("\n\n{} references in {} files", count, files));
+        Ok(ToolResult::success(output).with_metadata("matches", serde_json::json!(count)))
+    }
+}
+
+/// A retrieval index held between calls, with the settings it was loaded with
+struct LoadedIndex {
+    root: PathBuf,
+    embedder: Option<String>,
+    weight: f32,
+    index: CodeIndex,
+    refreshed: Instant,
+}
+
+/// Finds the code most relevant to a natural-language question
+///
+/// The index is loaded once and kept in memory; it is brought up to date
+/// when it was last refreshed more than [`INDEX_REFRESH_INTERVAL`] ago.
+#[derive(Default)]
+pub struct CodeRetrieveTool {
+    loaded: Arc<Mutex<Option<LoadedIndex>>>,
+}
+
+impl CodeRetrieveTool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Tool for CodeRetrieveTool {
+    fn name(&self) -> &str {
+        "code_retrieve"
+    }
+
+    fn description(&self) -> &str {
+        "Search the project's code by meaning rather than exact text, e.g. \"where is auth handled?\" or \"retry backoff for HTTP requests\". Returns the most relevant functions, classes and file sections, ranked, with file paths, line ranges and their code. Use codebase_search instead when you know the exact text or regex."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "query": {
+                    "type": "string",
+                    "description": "What to look for, in words or identifiers"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Optional: number of results (default 8, max 20)"
+                },
+                "path": {
+                    "type": "string",
+                    "description": "Optional: only search this file or directory"
+                }
+            },
+            "required": ["query"]
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, config: &crate::config::Config) -> Result<ToolResult> {
+        let query = args["query"]
+            .as_str()
+            .map(str::trim)
+            .filter(|query| !query.is_empty())
+            .ok_or_else(|| ToolError::InvalidArgs("Missing query".to_string()))?
+            .to_string();
+        let limit = args["limit"]
+            .as_u64()
+            .map(|n| (n as usize).clamp(1, MAX_RETRIEVE_LIMIT))
+            .unwrap_or(DEFAULT_RETRIEVE_LIMIT);
+        let within = match args["path"].as_str() {
+            Some(path_str) => match relative_to_root(ctx, &ctx.resolve(path_str)) {
+                Some(relative) => Some(relative),
+                None => return Ok(ToolResult::error(format!("{} is outside the project", path_str))),
+            },
+            None => None,
+        };
+
+        let root = project_root(&ctx.working_dir);
+        let embedder = config.index.embedder.build();
+        let embedder_id = embedder.as_ref().map(|e| e.id());
+        let weight = config.index.embedding_weight;
+        let search_query = query.clone();
+        let loaded = self.loaded.clone();
+        let (root, hits) = tokio::task::spawn_blocking(move || {
+            let mut loaded = loaded.lock().unwrap_or_else(|e| e.into_inner());
+            let reusable = loaded
+                .as_ref()
+                .is_some_and(|l| l.root == root && l.embedder == embedder_id && l.weight == weight);
+            if !reusable {
+                *loaded = Some(LoadedIndex {
+                    root: root.clone(),
+                    embedder: embedder_id,
+                    weight,
+                    index: CodeIndex::load(&root, embedder)?.with_embedding_weight(weight),
+                    refreshed: Instant::now(),
+                });
+            }
+            let entry = loaded.as_mut().expect("index loaded above");
+            if entry.refreshed.elapsed() >= INDEX_REFRESH_INTERVAL {
+                entry.index.update()?;
+                entry.index.save()?;
+                entry.refreshed = Instant::now();
+            }
+            let hits = entry.index.search(&search_query, limit, within.as_deref())?;
+            Ok::<_, PromptLineError>((root, hits))
+        })
+        .await
+        .map_err(|e| PromptLineError::Other(e.to_string()))??;
+
+        if hits.is_empty() {
+            return Ok(ToolResult::success(format!("No code found for '{}'", query)).with_metadata("matches", serde_json::json!(0)));
+        }
+
+        let mut sections = Vec::new();
+        for (i, hit) in hits.iter().enumerate() {
+            let mut section = format!("{}. {}  (score {:.2})", i + 1, hit, hit.score);
+            let source = tokio::fs::read_to_string(root.join(&hit.path)).await.unwrap_or_default();
+            let lines: Vec<&str> = source.lines().skip(hit.start_line - 1).take(hit.end_line + 1 - hit.start_line).collect();
+            for (offset, line) in lines.iter().take(SNIPPET_LINES).enumerate() {
+                section.push_str(&format!("\n{:>5}  {}", hit.start_line + offset, line));
+            }
+            if lines.len() > SNIPPET_LINES {
+                section.push_str(&format!("\n       … {} more lines", lines.len() - SNIPPET_LINES));
+            }
+            sections.push(section);
+        }
+        Ok(ToolResult::success(sections.join("\n\n")).with_metadata("matches", serde_json::json!(hits.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempWorkspace, ToolHarness};
+    use serde_json::json;
+
+    fn workspace() -> TempWorkspace {
+        let workspace = TempWorkspace::new().unwrap();
+        workspace
+            .write(
+                "src/shop.rs",
+                "pub struct Shop;\n\nimpl Shop {\n    pub fn open() -> Self {\n        Shop\n    }\n}\n",
+            )
+            .unwrap();
+        workspace.write("src/main.rs", "fn main() {\n    let shop = Shop::open();\n}\n").unwrap();
+        workspace
+    }
+
+    fn harness(workspace: &TempWorkspace) -> ToolHarness {
+        ToolHarness::new()
+            .with_tool(CodeOutlineTool)
+            .with_tool(FindDefinitionTool)
+            .with_tool(FindReferencesTool)
+            .with_tool(CodeRetrieveTool::new())
+            .in_workspace(workspace)
+    }
+
+    #[tokio::test]
+    async fn test_code_outline() {
+        let workspace = workspace();
+        let harness = harness(&workspace);
+
+        let result = harness.execute("code_outline", json!({"path": "src/shop.rs"})).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.output, "struct Shop  L1-1\nimpl Shop  L3-7\n  method open  L4-6");
+        assert!(workspace.exists(".promptline/index/symbols.json"));
+
+        workspace.write("notes.txt", "plain text").unwrap();
+        let result = harness.execute("code_outline", json!({"path": "notes.txt"})).await.unwrap();
+        assert!(!result.success);
+    }
+
+    #[tokio::test]
+    async fn test_find_definition_and_references() {
+        let workspace = workspace();
+        let harness = harness(&workspace);
+
+        let result = harness.execute("find_definition", json!({"name": "Shop::open"})).await.unwrap();
+        assert_eq!(result.output, "src/shop.rs:4-6  method open (in Shop)");
+
+        let result = harness.execute("find_references", json!({"name": "Shop"})).await.unwrap();
+        assert!(result.output.contains("src/main.rs:2:16: let shop = Shop::open();"));
+        assert!(result.output.ends_with("4 references in 2 files"));
+
+        let result = harness
+            .execute("find_references", json!({"name": "open", "path": "src/main.rs"}))
+            .await
+            .unwrap();
+        assert_eq!(result.metadata["matches"], 1);
+
+        // Edits are picked up on the next lookup
+        workspace.write("src/main.rs", "fn main() {}\n").unwrap();
+        let result = harness.execute("find_references", json!({"name": "open"})).await.unwrap();
+        assert_eq!(result.metadata["matches"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_code_retrieve() {
+        let workspace = workspace();
+        workspace
+            .write("src/auth.rs", "pub fn check_credentials(user: &str) -> bool {\n    !user.is_empty()\n}\n")
+            .unwrap();
+        let harness = harness(&workspace);
+
+        let result = harness
+            .execute("code_retrieve", json!({"query": "where are user credentials checked?", "limit": 1}))
+            .await
+            .unwrap();
+        assert!(result.success);
+        assert!(result.output.starts_with("1. src/auth.rs:1-3  fn check_credentials  (score "));
+        assert!(result.output.contains("    1  pub fn check_credentials(user: &str) -> bool {"));
+        assert_eq!(result.metadata["matches"], 1);
+        assert!(workspace.exists(".promptline/index/chunks.json"));
+
+        let result = harness
+            .execute("code_retrieve", json!({"query": "credentials", "path": "src/main.rs"}))
+            .await
+            .unwrap();
+        assert_eq!(result.metadata["matches"], 0);
+
+        // Later calls search the index in memory instead of loading it again
+        std::fs::remove_file(workspace.path().join(".promptline/index/chunks.json")).unwrap();
+        let result = harness
+            .execute("code_retrieve", json!({"query": "credentials", "limit": 1}))
+            .await
+            .unwrap();
+        assert_eq!(result.metadata["matches"], 1);
+        assert!(!workspace.exists(".promptline/index/chunks.json"));
+    }
+}
@@ -1,12 +1,12 @@
 //! File operation tools
 
 use super::{Tool, ToolContext, ToolResult};
+use crate::approval::{ApprovalRequest, DiffPreview};
 use crate::error::{Result, ToolError};
+use crate::model::ImageSource;
+use crate::util::diff::unified_diff;
 use async_trait::async_trait;
 
-use crate::util::diff::display_diff;
-use dialoguer::Confirm;
-
 /// File read tool
 pub struct FileReadTool;
 
@@ -141,12 +141,7 @@ impl Tool for FileWriteTool {
             .as_str()
             .ok_or_else(|| ToolError::InvalidArgs("Missing content".to_string()))?;
 
-        // Resolve path relative to working directory
-        let path = if std::path::Path::new(path_str).is_absolute() {
-            std::path::PathBuf::from(path_str)
-        } else {
-            ctx.working_dir.join(path_str)
-        };
+        let path = ctx.resolve(path_str);
 
         tracing::info!("Writing to file: {} (resolved from {})", path.display(), path_str);
 
@@ -159,20 +154,28 @@ impl Tool for FileWriteTool {
             }
         }
 
-        // If file exists, generate and display diff
-        if path.exists() {
-            let original_content = tokio::fs::read_to_string(&path).await.unwrap_or_default();
-            display_diff(path_str, &original_content, content);
-
-            if config.safety.require_diff_preview {
-                let confirmation = Confirm::new()
-                    .with_prompt("Apply these changes?")
-                    .default(false)
-                    .interact()?;
+        let original_content = if path.exists() {
+            tokio::fs::read_to_string(&path).await.unwrap_or_default()
+        } else {
+            String::new()
+        };
 
-                if !confirmation {
-                    return Ok(ToolResult::error("User denied file write.".to_string()));
-                }
+        // If file exists, have the diff approved before overwriting
+        if path.exists() && config.safety.require_diff_preview {
+            let request = ApprovalRequest::new(
+                self.name(),
+                args.clone(),
+                format!("Overwrite {}", path.display()),
+                self.risk(),
+            )
+            .with_diff(DiffPreview {
+                path: path_str.to_string(),
+                original: original_content.clone(),
+                modified: content.to_string(),
+            });
+
+            if !ctx.approval.decide(&request).await?.is_allowed() {
+                return Ok(ToolResult::error("User denied file write.".to_string()));
             }
         }
 
@@ -181,9 +184,11 @@ impl Tool for FileWriteTool {
             ToolError::ExecutionFailed(format!("Failed to write file: {}", e))
         })?;
 
-        Ok(ToolResult::success(format!("Successfully wrote {} bytes to {}", content.len(), path.display()))
+        let result = ToolResult::success(format!("Successfully wrote {} bytes to {}", content.len(), path.display()))
             .with_metadata("path", serde_json::json!(path))
-            .with_metadata("bytes_written", serde_json::json!(content.len())))
+            .with_metadata("bytes_written", serde_json::json!(content.len()))
+            .with_metadata("diff", serde_json::json!(unified_diff(path_str, &original_content, content)));
+        Ok(super::lsp_ops::with_diagnostics(result, ctx, std::slice::from_ref(&path)).await)
     }
 }
 
@@ -284,6 +289,76 @@ impl Tool for FileListTool {
     }
 }
 
+/// Image read tool
+pub struct ImageReadTool;
+
+impl ImageReadTool {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for ImageReadTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Tool for ImageReadTool {
+    fn name(&self) -> &str {
+        "image_read"
+    }
+
+    fn description(&self) -> &str {
+        "Look at an image file (PNG, JPEG, GIF or WebP). Use this to inspect screenshots, diagrams or mockups."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path to the image file"
+                }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let path_str = args["path"]
+            .as_str()
+            .ok_or_else(|| ToolError::InvalidArgs("Missing path".to_string()))?;
+
+        let path = if std::path::Path::new(path_str).is_absolute() {
+            std::path::PathBuf::from(path_str)
+        } else {
+            ctx.working_dir.join(path_str)
+        };
+
+        if !path.exists() {
+            return Ok(ToolResult::error(format!("File not found: {}", path.display())));
+        }
+
+        let image = match ImageSource::from_file(&path) {
+            Ok(image) => image,
+            Err(e) => return Ok(ToolResult::error(e.to_string())),
+        };
+        let size = tokio::fs::metadata(&path).await?.len();
+
+        Ok(ToolResult::success(format!("Attached image {} ({} bytes)", path.display(), size))
+            .with_image(image)
+            .with_metadata("path", serde_json::json!(path))
+            .with_metadata("size", serde_json::json!(size)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -340,6 +415,35 @@ mod tests {
         assert_eq!(content, "Test content");
     }
 
+    #[tokio::test]
+    async fn test_file_write_denied_diff() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("output.txt");
+        std::fs::write(&file_path, "original").unwrap();
+
+        let approval = crate::approval::ScriptedApproval::new(crate::approval::ApprovalDecision::Deny);
+        let ctx = ToolContext {
+            approval: std::sync::Arc::new(approval.clone()),
+            ..ToolContext::default()
+        };
+        let config = crate::config::Config::default();
+
+        let result = FileWriteTool::new()
+            .execute(
+                serde_json::json!({"path": file_path.to_str().unwrap(), "content": "changed"}),
+                &ctx,
+                &config,
+            )
+            .await
+            .unwrap();
+
+        assert!(!result.success);
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "original");
+        let diff = approval.requests()[0].diff.clone().unwrap();
+        assert_eq!(diff.original, "original");
+        assert_eq!(diff.modified, "changed");
+    }
+
     #[tokio::test]
     async fn test_file_list() {
         let temp_dir = TempDir::new().unwrap();
@@ -363,4 +467,37 @@ mod tests {
         assert!(result.output.contains("file1.txt"));
         assert!(result.output.contains("file2.txt"));
     }
+
+    #[tokio::test]
+    async fn test_image_read() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("shot.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), "text").unwrap();
+
+        let tool = ImageReadTool::new();
+        let ctx = ToolContext {
+            working_dir: temp_dir.path().to_path_buf(),
+            ..ToolContext::default()
+        };
+        let config = crate::config::Config::default();
+
+        let result = tool
+            .execute(serde_json::json!({"path": "shot.png"}), &ctx, &config)
+            .await
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(
+            result.images,
+            vec![ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }]
+        );
+
+        let result = tool
+            .execute(serde_json::json!({"path": "notes.txt"}), &ctx, &config)
+            .await
+            .unwrap();
+        assert!(!result.success);
+    }
 }
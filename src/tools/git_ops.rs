@@ -168,6 +168,10 @@ impl Tool for GitCommitTool {
         false
     }
 
+    fn risk(&self) -> crate::approval::RiskLevel {
+        crate::approval::RiskLevel::High
+    }
+
     async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
         let message = args["message"]
             .as_str()
@@ -0,0 +1,382 @@
+//! Language server tools: diagnostics, hover, definitions and renames
+
+use super::{Tool, ToolContext, ToolResult};
+use crate::approval::{ApprovalRequest, DiffPreview};
+use crate::error::{Result, ToolError};
+use crate::lsp::{Diagnostic, LspManager, Position, Severity};
+use crate::util::diff::unified_diff;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+/// Most diagnostics listed
+const MAX_DIAGNOSTICS: usize = 100;
+
+fn not_enabled() -> ToolResult {
+    ToolResult::error("Language servers are not enabled (see `lsp` in the config)")
+}
+
+fn no_server(path_str: &str) -> ToolResult {
+    ToolResult::error(format!(
+        "No language server available for {} (configure one under `lsp.servers`)",
+        path_str
+    ))
+}
+
+fn string_arg<'a>(args: &'a serde_json::Value, name: &str) -> Result<&'a str> {
+    args[name]
+        .as_str()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| ToolError::InvalidArgs(format!("Missing {}", name)).into())
+}
+
+/// 1-based `line` and `column` arguments as a protocol position in `content`
+fn position_arg(args: &serde_json::Value, content: &str) -> Result<Position> {
+    let number = |name: &str| {
+        args[name]
+            .as_u64()
+            .filter(|n| *n >= 1)
+            .ok_or_else(|| ToolError::InvalidArgs(format!("{} must be a number starting at 1", name)))
+    };
+    Ok(Position::from_line_column(content, number("line")? as usize, number("column")? as usize))
+}
+
+/// Schema of the `path`, `line` and `column` arguments plus `extra` properties, of which `extra_required` are required
+fn position_parameters(extra: serde_json::Value, extra_required: &[&str]) -> serde_json::Value {
+    let mut properties = serde_json::json!({
+        "path": {
+            "type": "string",
+            "description": "Path to the source file"
+        },
+        "line": {
+            "type": "integer",
+            "description": "Line of the symbol, starting at 1"
+        },
+        "column": {
+            "type": "integer",
+            "description": "Column of any character of the symbol, starting at 1"
+        }
+    });
+    let mut required = vec!["path", "line", "column"];
+    if let serde_json::Value::Object(extra) = extra {
+        for (name, schema) in extra {
+            properties[&name] = schema;
+        }
+    }
+    required.extend_from_slice(extra_required);
+    serde_json::json!({
+        "type": "object",
+        "properties": properties,
+        "required": required
+    })
+}
+
+/// Errors and warnings, rendered one per line
+fn render_diagnostics(lsp: &LspManager, path: &Path, content: &str, diagnostics: &[Diagnostic]) -> Vec<String> {
+    let mut diagnostics: Vec<&Diagnostic> = diagnostics.iter().filter(|d| d.severity() <= Severity::Warning).collect();
+    diagnostics.sort_by_key(|d| (d.severity(), d.range.start));
+    let display = lsp.display_path(path);
+    diagnostics.iter().map(|d| d.render(&display, content)).collect()
+}
+
+/// Add the language servers' errors and warnings for `paths` to the result of a tool that changed them
+pub async fn with_diagnostics(result: ToolResult, ctx: &ToolContext, paths: &[PathBuf]) -> ToolResult {
+    let Some(lsp) = &ctx.lsp else {
+        return result;
+    };
+    let mut lines = Vec::new();
+    let mut servers: Vec<&str> = Vec::new();
+    for path in paths {
+        let diagnostics = match lsp.diagnostics(path).await {
+            Ok(Some(diagnostics)) => diagnostics,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!("No diagnostics for {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let content = tokio::fs::read_to_string(path).await.unwrap_or_default();
+        lines.extend(render_diagnostics(lsp, path, &content, &diagnostics));
+        let server = lsp.server_for(path).unwrap_or("language server");
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+    if servers.is_empty() {
+        return result;
+    }
+    let server = servers.join(", ");
+
+    let mut result = result;
+    if lines.is_empty() {
+        result.output.push_str(&format!("\n\nNo errors or warnings reported by {}.", server));
+    } else {
+        result.output.push_str(&format!("\n\nDiagnostics from {}:\n{}", server, lines.join("\n")));
+    }
+    result.with_metadata("diagnostics", serde_json::json!(lines))
+}
+
+/// Reports compiler errors and warnings from the language servers
+pub struct DiagnosticsTool;
+
+#[async_trait]
+impl Tool for DiagnosticsTool {
+    fn name(&self) -> &str {
+        "diagnostics"
+    }
+
+    fn description(&self) -> &str {
+        "Get compiler errors and warnings for a file from its language server (rust-analyzer, pyright, TypeScript) without running a full build. Without a path, lists the problems reported so far in any file."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Optional: file to check"
+                }
+            }
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let Some(lsp) = &ctx.lsp else {
+            return Ok(not_enabled());
+        };
+        lsp.refresh().await?;
+
+        let Some(path_str) = args["path"].as_str() else {
+            let mut lines = Vec::new();
+            for (path, diagnostics) in lsp.all_diagnostics().await {
+                let content = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+                lines.extend(render_diagnostics(lsp, &path, &content, &diagnostics));
+            }
+            if lines.is_empty() {
+                return Ok(ToolResult::success("No errors or warnings reported.").with_metadata("problems", serde_json::json!(0)));
+            }
+            let count = lines.len();
+            if count > MAX_DIAGNOSTICS {
+                lines.truncate(MAX_DIAGNOSTICS);
+                lines.push(format!("… {} more not shown", count - MAX_DIAGNOSTICS));
+            }
+            return Ok(ToolResult::success(lines.join("\n")).with_metadata("problems", serde_json::json!(count)));
+        };
+
+        let path = ctx.resolve(path_str);
+        if !path.is_file() {
+            return Ok(ToolResult::error(format!("File not found: {}", path.display())));
+        }
+        let Some(diagnostics) = lsp.diagnostics(&path).await? else {
+            return Ok(no_server(path_str));
+        };
+        let content = tokio::fs::read_to_string(&path).await?;
+        let lines = render_diagnostics(lsp, &path, &content, &diagnostics);
+        if lines.is_empty() {
+            return Ok(ToolResult::success(format!("No errors or warnings in {}.", path_str))
+                .with_metadata("problems", serde_json::json!(0)));
+        }
+        let count = lines.len();
+        Ok(ToolResult::success(lines.join("\n")).with_metadata("problems", serde_json::json!(count)))
+    }
+}
+
+/// Shows type and documentation of a symbol
+pub struct HoverTool;
+
+#[async_trait]
+impl Tool for HoverTool {
+    fn name(&self) -> &str {
+        "hover"
+    }
+
+    fn description(&self) -> &str {
+        "Show the type, signature and documentation of the symbol at a line and column of a file, as the language server reports it."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        position_parameters(serde_json::Value::Null, &[])
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let Some(lsp) = &ctx.lsp else {
+            return Ok(not_enabled());
+        };
+        let path_str = string_arg(&args, "path")?;
+        let path = ctx.resolve(path_str);
+        let Ok(content) = tokio::fs::read_to_string(&path).await else {
+            return Ok(ToolResult::error(format!("File not found: {}", path.display())));
+        };
+        let position = position_arg(&args, &content)?;
+        if lsp.server_for(&path).is_none() {
+            return Ok(no_server(path_str));
+        }
+
+        match lsp.hover(&path, position).await? {
+            Some(text) => Ok(ToolResult::success(text)),
+            None => Ok(ToolResult::success("No information for this position.")),
+        }
+    }
+}
+
+/// Finds the definition of the symbol at a position
+pub struct GotoDefinitionTool;
+
+#[async_trait]
+impl Tool for GotoDefinitionTool {
+    fn name(&self) -> &str {
+        "goto_definition"
+    }
+
+    fn description(&self) -> &str {
+        "Find where the symbol at a line and column of a file is defined, using the language server. More precise than find_definition for overloaded or imported names."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        position_parameters(serde_json::Value::Null, &[])
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
+        let Some(lsp) = &ctx.lsp else {
+            return Ok(not_enabled());
+        };
+        let path_str = string_arg(&args, "path")?;
+        let path = ctx.resolve(path_str);
+        let Ok(content) = tokio::fs::read_to_string(&path).await else {
+            return Ok(ToolResult::error(format!("File not found: {}", path.display())));
+        };
+        let position = position_arg(&args, &content)?;
+        if lsp.server_for(&path).is_none() {
+            return Ok(no_server(path_str));
+        }
+
+        let locations = lsp.definition(&path, position).await?;
+        if locations.is_empty() {
+            return Ok(ToolResult::success("No definition found.").with_metadata("matches", serde_json::json!(0)));
+        }
+        let mut lines = Vec::new();
+        for location in &locations {
+            let Some(target) = location.path() else {
+                lines.push(location.uri.clone());
+                continue;
+            };
+            let source = tokio::fs::read_to_string(&target).await.unwrap_or_default();
+            let (line, column) = location.range.start.to_line_column(&source);
+            let text = source.lines().nth(line - 1).unwrap_or_default().trim();
+            lines.push(format!("{}:{}:{}: {}", lsp.display_path(&target), line, column, text));
+        }
+        Ok(ToolResult::success(lines.join("\n")).with_metadata("matches", serde_json::json!(locations.len())))
+    }
+}
+
+/// Renames a symbol everywhere it is used
+pub struct RenameSymbolTool;
+
+#[async_trait]
+impl Tool for RenameSymbolTool {
+    fn name(&self) -> &str {
+        "rename_symbol"
+    }
+
+    fn description(&self) -> &str {
+        "Rename the symbol at a line and column of a file, and every reference to it across the project, using the language server. Safer than search and replace."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        position_parameters(
+            serde_json::json!({
+                "new_name": {
+                    "type": "string",
+                    "description": "New name for the symbol"
+                }
+            }),
+            &["new_name"],
+        )
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, config: &crate::config::Config) -> Result<ToolResult> {
+        let Some(lsp) = &ctx.lsp else {
+            return Ok(not_enabled());
+        };
+        let path_str = string_arg(&args, "path")?;
+        let new_name = string_arg(&args, "new_name")?.trim();
+        let path = ctx.resolve(path_str);
+        let Ok(content) = tokio::fs::read_to_string(&path).await else {
+            return Ok(ToolResult::error(format!("File not found: {}", path.display())));
+        };
+        let position = position_arg(&args, &content)?;
+        if lsp.server_for(&path).is_none() {
+            return Ok(no_server(path_str));
+        }
+
+        let edits = lsp.rename(&path, position, new_name).await?;
+        if edits.is_empty() {
+            return Ok(ToolResult::error("Nothing to rename at this position."));
+        }
+        let outside: Vec<String> = edits
+            .iter()
+            .filter(|edit| !lsp.contains(&edit.path))
+            .map(|edit| edit.path.display().to_string())
+            .collect();
+        if !outside.is_empty() {
+            return Ok(ToolResult::error(format!(
+                "The rename would change files outside the project, so nothing was changed:\n{}",
+                outside.join("\n")
+            )));
+        }
+
+        if config.safety.require_diff_preview {
+            for edit in &edits {
+                let display = lsp.display_path(&edit.path);
+                let request = ApprovalRequest::new(
+                    self.name(),
+                    args.clone(),
+                    format!("Rename to {} in {}", new_name, display),
+                    self.risk(),
+                )
+                .with_diff(DiffPreview {
+                    path: display,
+                    original: edit.original.clone(),
+                    modified: edit.modified.clone(),
+                });
+                if !ctx.approval.decide(&request).await?.is_allowed() {
+                    return Ok(ToolResult::error("User denied the rename."));
+                }
+            }
+        }
+
+        let mut diff = String::new();
+        let mut changed = Vec::new();
+        for edit in &edits {
+            tokio::fs::write(&edit.path, &edit.modified)
+                .await
+                .map_err(|e| ToolError::ExecutionFailed(format!("Failed to write {}: {}", edit.path.display(), e)))?;
+            let display = lsp.display_path(&edit.path);
+            diff.push_str(&unified_diff(&display, &edit.original, &edit.modified));
+            changed.push(display);
+        }
+
+        let result = ToolResult::success(format!(
+            "Renamed to {} in {} files:\n{}",
+            new_name,
+            changed.len(),
+            changed.join("\n")
+        ))
+        .with_metadata("files", serde_json::json!(changed))
+        .with_metadata("diff", serde_json::json!(diff));
+        let written: Vec<PathBuf> = edits.into_iter().map(|edit| edit.path).collect();
+        Ok(with_diagnostics(result, ctx, &written).await)
+    }
+}
@@ -1,12 +1,16 @@
 //! Tool execution interface
 
+use crate::approval::{ApprovalHandler, RiskLevel};
 use crate::error::{Result, ToolError};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 
+pub mod code_nav;
 pub mod file_ops;
 pub mod git_ops;
+pub mod lsp_ops;
 pub mod search_ops;
 pub mod shell;
 pub mod web_ops;
@@ -18,6 +22,9 @@ pub struct ToolResult {
     pub output: String,
     pub error: Option<String>,
     pub metadata: HashMap<String, serde_json::Value>,
+    /// Images for the model to look at alongside the output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<crate::model::ImageSource>,
 }
 
 impl ToolResult {
@@ -27,6 +34,7 @@ impl ToolResult {
             output: output.into(),
             error: None,
             metadata: HashMap::new(),
+            images: Vec::new(),
         }
     }
 
@@ -36,6 +44,7 @@ impl ToolResult {
             output: String::new(),
             error: Some(error.into()),
             metadata: HashMap::new(),
+            images: Vec::new(),
         }
     }
 
@@ -43,6 +52,11 @@ impl ToolResult {
         self.metadata.insert(key.into(), value);
         self
     }
+
+    pub fn with_image(mut self, image: crate::model::ImageSource) -> Self {
+        self.images.push(image);
+        self
+    }
 }
 
 /// Tool context passed to tools during execution
@@ -52,6 +66,10 @@ pub struct ToolContext {
     pub env_vars: HashMap<String, String>,
     pub current_working_dir: std::path::PathBuf,
     pub git_branch: Option<String>,
+    /// Decides on actions that need the user's approval, such as applying a diff
+    pub approval: Arc<dyn ApprovalHandler>,
+    /// Language servers for diagnostics and navigation, when enabled
+    pub lsp: Option<Arc<crate::lsp::LspManager>>,
 }
 
 impl Default for ToolContext {
@@ -61,6 +79,20 @@ impl Default for ToolContext {
             env_vars: HashMap::new(),
             current_working_dir: std::env::current_dir().unwrap_or_default(),
             git_branch: None,
+            approval: crate::approval::default_handler(),
+            lsp: None,
+        }
+    }
+}
+
+impl ToolContext {
+    /// `path` from tool arguments, relative to the working directory unless absolute
+    pub fn resolve(&self, path: &str) -> std::path::PathBuf {
+        let path = std::path::Path::new(path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.working_dir.join(path)
         }
     }
 }
@@ -82,6 +114,15 @@ pub trait Tool: Send + Sync {
         false
     }
 
+    /// Risk reported when asking for approval to run the tool
+    fn risk(&self) -> RiskLevel {
+        if self.is_read_only() {
+            RiskLevel::Low
+        } else {
+            RiskLevel::Medium
+        }
+    }
+
     /// Execute the tool with given arguments
     async fn execute(
         &self,
@@ -4,7 +4,7 @@ use super::{Tool, ToolContext, ToolResult};
 use crate::error::{Result, ToolError};
 use async_trait::async_trait;
 use tokio::process::Command;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Codebase Search tool
 pub struct CodebaseSearchTool;
@@ -14,7 +14,7 @@ impl CodebaseSearchTool {
         Self
     }
 
-    async fn search_with_powershell(&self, pattern: &str, path: &PathBuf, ctx: &ToolContext) -> Result<ToolResult> {
+    async fn search_with_powershell(&self, pattern: &str, path: &Path, ctx: &ToolContext) -> Result<ToolResult> {
         // Use PowerShell Select-String for Windows
         let ps_command = format!(
             "Get-ChildItem -Path '{}' -Recurse -File | Select-String -Pattern '{}' | ForEach-Object {{ \"$($_.Path):$($_.LineNumber):$($_.Line)\" }}",
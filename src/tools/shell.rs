@@ -55,6 +55,10 @@ impl Tool for ShellTool {
         false
     }
 
+    fn risk(&self) -> crate::approval::RiskLevel {
+        crate::approval::RiskLevel::High
+    }
+
     async fn execute(&self, args: serde_json::Value, ctx: &ToolContext, _config: &crate::config::Config) -> Result<ToolResult> {
         let command = args["command"]
             .as_str()
@@ -112,11 +116,7 @@ mod tests {
         let ctx = ToolContext::default();
         let config = crate::config::Config::default();
 
-        let command = if cfg!(target_os = "windows") {
-            "echo hello"
-        } else {
-            "echo hello"
-        };
+        let command = "echo hello";
 
         let result = tool
             .execute(serde_json::json!({"command": command}), &ctx, &config)
@@ -0,0 +1,261 @@
+//! Token usage and cost accounting
+//!
+//! Usage reported by the model is added up per iteration, per run and per
+//! session, and priced with the table in `ModelConfig::pricing`.
+
+use crate::config::ModelPrice;
+use crate::model::TokenUsage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+impl ModelPrice {
+    /// Cost in USD of a request with the given usage
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input_per_million
+            + usage.completion_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Price table keyed by provider, then model name prefix
+#[derive(Debug, Clone, Default)]
+pub struct PriceTable {
+    prices: HashMap<String, HashMap<String, ModelPrice>>,
+}
+
+impl PriceTable {
+    /// Build a price table from the built-in defaults overlaid with configured prices
+    pub fn new(configured: &HashMap<String, HashMap<String, ModelPrice>>) -> Self {
+        let mut prices = default_prices();
+        for (provider, models) in configured {
+            prices
+                .entry(provider.clone())
+                .or_default()
+                .extend(models.iter().map(|(m, p)| (m.clone(), p.clone())));
+        }
+        Self { prices }
+    }
+
+    /// Find the price for a model, matching the longest configured name prefix
+    pub fn lookup(&self, provider: &str, model: &str) -> Option<&ModelPrice> {
+        self.prices
+            .get(provider)?
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| price)
+    }
+
+    /// Cost in USD of a request, if the model is priced
+    pub fn cost(&self, provider: &str, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.lookup(provider, model).map(|price| price.cost(usage))
+    }
+}
+
+/// Built-in prices (USD per million tokens) for common hosted models
+fn default_prices() -> HashMap<String, HashMap<String, ModelPrice>> {
+    let table: &[(&str, &str, f64, f64)] = &[
+        ("openai", "gpt-4o-mini", 0.15, 0.60),
+        ("openai", "gpt-4o", 2.50, 10.00),
+        ("openai", "gpt-4-turbo", 10.00, 30.00),
+        ("openai", "gpt-4", 30.00, 60.00),
+        ("openai", "gpt-3.5-turbo", 0.50, 1.50),
+        ("anthropic", "claude-3-5-haiku", 0.80, 4.00),
+        ("anthropic", "claude-3-5-sonnet", 3.00, 15.00),
+        ("anthropic", "claude-3-opus", 15.00, 75.00),
+        ("gemini", "gemini-1.5-flash", 0.075, 0.30),
+        ("gemini", "gemini-1.5-pro", 1.25, 5.00),
+        ("gemini", "gemini-pro", 0.50, 1.50),
+        ("ollama", "", 0.0, 0.0),
+    ];
+
+    let mut prices: HashMap<String, HashMap<String, ModelPrice>> = HashMap::new();
+    for (provider, model, input, output) in table {
+        prices.entry(provider.to_string()).or_default().insert(
+            model.to_string(),
+            ModelPrice {
+                input_per_million: *input,
+                output_per_million: *output,
+            },
+        );
+    }
+    prices
+}
+
+/// Accumulated usage over a number of model requests
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub requests: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+    /// Cost in USD of the priced requests
+    pub cost_usd: f64,
+    /// Requests whose model had no price
+    #[serde(default)]
+    pub unpriced_requests: usize,
+}
+
+impl UsageSummary {
+    /// Add a single request
+    pub fn record(&mut self, usage: &TokenUsage, cost: Option<f64>) {
+        self.requests += 1;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.total_tokens += usage.total_tokens;
+        match cost {
+            Some(cost) => self.cost_usd += cost,
+            None => self.unpriced_requests += 1,
+        }
+    }
+
+    /// Add another summary's totals to this one
+    pub fn merge(&mut self, other: &UsageSummary) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost_usd += other.cost_usd;
+        self.unpriced_requests += other.unpriced_requests;
+    }
+
+    /// Human-readable cost, noting requests that could not be priced
+    pub fn cost_display(&self) -> String {
+        if self.unpriced_requests > 0 {
+            format!("${:.4} (+{} unpriced requests)", self.cost_usd, self.unpriced_requests)
+        } else {
+            format!("${:.4}", self.cost_usd)
+        }
+    }
+}
+
+impl fmt::Display for UsageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} tokens ({} prompt, {} completion) over {} requests · {}",
+            self.total_tokens,
+            self.prompt_tokens,
+            self.completion_tokens,
+            self.requests,
+            self.cost_display()
+        )
+    }
+}
+
+/// Tracks usage per iteration, per run and per session
+#[derive(Debug, Clone, Default)]
+pub struct UsageTracker {
+    prices: PriceTable,
+    iterations: Vec<TokenUsage>,
+    run: UsageSummary,
+    session: UsageSummary,
+}
+
+impl UsageTracker {
+    pub fn new(prices: PriceTable) -> Self {
+        Self {
+            prices,
+            ..Self::default()
+        }
+    }
+
+    /// Reset per-run counters at the start of a run
+    pub fn start_run(&mut self) {
+        self.iterations.clear();
+        self.run = UsageSummary::default();
+    }
+
+    /// Record usage of one model request and return its cost, if priced
+    pub fn record(&mut self, provider: &str, model: &str, usage: &TokenUsage) -> Option<f64> {
+        let cost = self.prices.cost(provider, model, usage);
+        self.iterations.push(usage.clone());
+        self.run.record(usage, cost);
+        self.session.record(usage, cost);
+        cost
+    }
+
+    /// Usage of each request in the current run
+    pub fn iterations(&self) -> &[TokenUsage] {
+        &self.iterations
+    }
+
+    /// Usage of the current run
+    pub fn run(&self) -> &UsageSummary {
+        &self.run
+    }
+
+    /// Usage of the whole session
+    pub fn session(&self) -> &UsageSummary {
+        &self.session
+    }
+
+    /// Continue from the usage of a saved session, e.g. after resuming it
+    pub fn restore_session(&mut self, session: UsageSummary) {
+        self.session = session;
+    }
+
+    /// Price table used for cost calculation
+    pub fn prices(&self) -> &PriceTable {
+        &self.prices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(prompt: usize, completion: usize) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            total_tokens: prompt + completion,
+        }
+    }
+
+    #[test]
+    fn test_price_lookup_prefers_longest_prefix() {
+        let table = PriceTable::new(&HashMap::new());
+        let mini = table.lookup("openai", "gpt-4o-mini-2024-07-18").unwrap();
+        assert_eq!(mini.input_per_million, 0.15);
+        assert!(table.lookup("openai", "unknown-model").is_none());
+    }
+
+    #[test]
+    fn test_configured_prices_override_defaults() {
+        let mut configured = HashMap::new();
+        configured.insert(
+            "openai".to_string(),
+            HashMap::from([(
+                "gpt-4o".to_string(),
+                ModelPrice {
+                    input_per_million: 1.0,
+                    output_per_million: 2.0,
+                },
+            )]),
+        );
+        let table = PriceTable::new(&configured);
+
+        let cost = table.cost("openai", "gpt-4o", &usage(1_000_000, 500_000)).unwrap();
+        assert!((cost - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tracker_accumulates_run_and_session() {
+        let mut tracker = UsageTracker::new(PriceTable::new(&HashMap::new()));
+
+        tracker.start_run();
+        tracker.record("openai", "gpt-4o", &usage(1000, 100));
+        tracker.record("custom", "my-model", &usage(10, 10));
+        assert_eq!(tracker.iterations().len(), 2);
+        assert_eq!(tracker.run().total_tokens, 1120);
+        assert_eq!(tracker.run().unpriced_requests, 1);
+
+        tracker.start_run();
+        tracker.record("openai", "gpt-4o", &usage(1000, 100));
+        assert_eq!(tracker.run().requests, 1);
+        assert_eq!(tracker.session().requests, 3);
+        assert!((tracker.session().cost_usd - 0.0070).abs() < 1e-9);
+    }
+}
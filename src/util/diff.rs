@@ -22,6 +22,15 @@ pub fn generate_diff(original: &str, modified: &str) -> String {
     output.join("")
 }
 
+/// Generate a plain unified diff with `path` in the headers
+pub fn unified_diff(path: &str, original: &str, modified: &str) -> String {
+    TextDiff::from_lines(original, modified)
+        .unified_diff()
+        .context_radius(3)
+        .header(path, path)
+        .to_string()
+}
+
 /// Generate a colored diff for terminal display
 pub fn display_diff(path: &str, original: &str, modified: &str) {
     println!("\n{}", "=".repeat(60));
@@ -50,6 +59,15 @@ mod tests {
         assert!(diff.contains("modified"));
     }
 
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("a.txt", "one\ntwo\n", "one\n2\n");
+
+        assert!(diff.starts_with("--- a.txt\n+++ a.txt\n"));
+        assert!(diff.contains("-two\n+2\n"));
+        assert!(!diff.contains('\x1b'));
+    }
+
     #[test]
     fn test_empty_diff() {
         let text = "same text\n";
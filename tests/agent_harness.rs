@@ -0,0 +1,42 @@
+// End-to-end agent tests written against the `test-utils` harness, the way a downstream crate would
+
+use promptline::model::Role;
+use promptline::testing::{build_agent, PermissionPolicy, ScriptedModel, StubTool, ToolHarness};
+use promptline::ToolResult;
+
+#[tokio::test]
+async fn test_agent_runs_custom_tool() {
+    let deploy = StubTool::new("deploy").returns(ToolResult::success("deployed to staging"));
+    let model = ScriptedModel::new()
+        .respond_tool_call("deploy", serde_json::json!({"env": "staging"}))
+        .respond("Deployment finished. FINISH");
+    let probe = model.clone();
+
+    let tools = ToolHarness::new().with_tool(deploy.clone()).into_registry();
+    let mut agent = build_agent(model, tools, PermissionPolicy::AllowOnly(vec!["deploy".to_string()]))
+        .await
+        .unwrap();
+
+    let result = agent.run("Deploy to staging").await.unwrap();
+
+    assert!(result.success);
+    assert_eq!(result.tool_calls, vec!["deploy"]);
+    assert_eq!(deploy.calls(), vec![serde_json::json!({"env": "staging"})]);
+
+    probe.assert_exhausted();
+    let observation = probe.last_request().pop().unwrap();
+    assert_eq!(observation.role, Role::Tool);
+    assert!(observation.transcript().contains("deployed to staging"));
+}
+
+#[tokio::test]
+async fn test_denied_tool_is_not_executed() {
+    let deploy = StubTool::new("deploy");
+    let model = ScriptedModel::new().respond_tool_call("deploy", serde_json::json!({}));
+
+    let tools = ToolHarness::new().with_tool(deploy.clone()).into_registry();
+    let mut agent = build_agent(model, tools, PermissionPolicy::DenyAll).await.unwrap();
+
+    assert!(agent.run("Deploy").await.is_err());
+    assert!(deploy.calls().is_empty());
+}
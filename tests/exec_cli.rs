@@ -0,0 +1,35 @@
+// Headless `exec` runs driven by a recorded session, checking output and exit codes
+
+use assert_cmd::Command;
+
+fn exec(args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("promptline").unwrap();
+    cmd.args(["--replay", "tests/fixtures/read_file_run.jsonl", "exec"]).args(args);
+    cmd
+}
+
+#[test]
+fn test_exec_json_reads_prompt_from_stdin() {
+    let output = exec(&["--output-format", "json"])
+        .write_stdin("What does tests/fixtures/hello.txt say?")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["status"], "success");
+    assert_eq!(report["result"]["tool_calls"], serde_json::json!(["file_read"]));
+}
+
+#[test]
+fn test_exec_denied_tool_exit_code() {
+    let output = exec(&["--output-format", "stream-json", "--deny-tools", "file_read", "What does it say?"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let events: Vec<serde_json::Value> = stdout.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert!(events.iter().any(|e| e["type"] == "tool_call_denied"));
+    assert_eq!(events.last().unwrap()["status"], "permission_denied");
+}
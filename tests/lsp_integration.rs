@@ -0,0 +1,207 @@
+// Language server tools driven against the stub server in tests/support/stub_lsp.rs
+
+use promptline::config::{LspConfig, LspServerConfig};
+use promptline::lsp::LspManager;
+use promptline::testing::{TempWorkspace, ToolHarness};
+use promptline::tools::file_ops::FileWriteTool;
+use promptline::tools::lsp_ops::{DiagnosticsTool, GotoDefinitionTool, HoverTool, RenameSymbolTool};
+use serde_json::json;
+use std::sync::Arc;
+
+fn stub_manager(workspace: &TempWorkspace) -> Arc<LspManager> {
+    let config = LspConfig {
+        servers: [(
+            "stub".to_string(),
+            LspServerConfig::new(env!("CARGO_BIN_EXE_stub-lsp"), &[], &["rs"]),
+        )]
+        .into(),
+        ..LspConfig::default()
+    };
+    Arc::new(LspManager::new(workspace.path(), config))
+}
+
+fn harness(workspace: &TempWorkspace, lsp: &Arc<LspManager>) -> ToolHarness {
+    ToolHarness::new()
+        .with_tool(FileWriteTool::new())
+        .with_tool(DiagnosticsTool)
+        .with_tool(HoverTool)
+        .with_tool(GotoDefinitionTool)
+        .with_tool(RenameSymbolTool)
+        .in_workspace(workspace)
+        .with_lsp(lsp.clone())
+}
+
+#[tokio::test]
+async fn test_file_write_reports_diagnostics() {
+    let workspace = TempWorkspace::new().unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+
+    let result = harness
+        .execute("file_write", json!({"path": "src/lib.rs", "content": "fn main() {\n    BUG();\n}\n"}))
+        .await
+        .unwrap();
+    assert!(result.success);
+    assert!(result.output.contains("Diagnostics from stub"), "{}", result.output);
+    assert!(result.output.contains("src/lib.rs:2:5"), "{}", result.output);
+    assert!(result.output.contains("unexpected BUG"), "{}", result.output);
+
+    let result = harness
+        .execute("file_write", json!({"path": "src/lib.rs", "content": "fn main() {}\n"}))
+        .await
+        .unwrap();
+    assert!(result.output.contains("No errors or warnings reported by stub."), "{}", result.output);
+
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_diagnostics_follow_changes_on_disk() {
+    let workspace = TempWorkspace::new().unwrap();
+    workspace.write("main.rs", "// TODO: tidy up\nfn main() {}\n").unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+
+    let result = harness.execute("diagnostics", json!({"path": "main.rs"})).await.unwrap();
+    assert!(result.output.contains("unfinished TODO"), "{}", result.output);
+    assert_eq!(result.metadata["problems"], json!(1));
+
+    // Changed behind the server's back, as a shell command would
+    workspace.write("main.rs", "fn main() { BUG }\n").unwrap();
+    let result = harness.execute("diagnostics", json!({"path": "main.rs"})).await.unwrap();
+    assert!(result.output.contains("unexpected BUG"), "{}", result.output);
+    assert!(!result.output.contains("TODO"), "{}", result.output);
+
+    let result = harness.execute("diagnostics", json!({})).await.unwrap();
+    assert!(result.output.contains("main.rs:1:13"), "{}", result.output);
+
+    let result = harness.execute("diagnostics", json!({"path": "notes.txt"})).await.unwrap();
+    assert!(!result.success);
+
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_unchanged_file_returns_cached_diagnostics() {
+    let workspace = TempWorkspace::new().unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+    let args = json!({"path": "main.rs", "content": "fn main() { BUG }\n"});
+    harness.execute("file_write", args.clone()).await.unwrap();
+
+    // Nothing new is published for unchanged content, so waiting would last the whole timeout
+    let started = std::time::Instant::now();
+    let result = harness.execute("diagnostics", json!({"path": "main.rs"})).await.unwrap();
+    assert!(result.output.contains("unexpected BUG"), "{}", result.output);
+    let result = harness.execute("file_write", args).await.unwrap();
+    assert!(result.output.contains("unexpected BUG"), "{}", result.output);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "{:?}", started.elapsed());
+
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_hover_and_goto_definition() {
+    let workspace = TempWorkspace::new().unwrap();
+    workspace
+        .write("main.rs", "fn greet() {}\n\nfn main() {\n    greet();\n}\n")
+        .unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+
+    let result = harness
+        .execute("hover", json!({"path": "main.rs", "line": 4, "column": 7}))
+        .await
+        .unwrap();
+    assert!(result.output.contains("greet"), "{}", result.output);
+
+    let result = harness
+        .execute("goto_definition", json!({"path": "main.rs", "line": 4, "column": 7}))
+        .await
+        .unwrap();
+    assert_eq!(result.output, "main.rs:1:4: fn greet() {}");
+
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_rename_symbol_edits_every_file() {
+    let workspace = TempWorkspace::new().unwrap();
+    workspace.write("lib.rs", "pub fn total() -> u32 { 1 }\n").unwrap();
+    workspace
+        .write("main.rs", "fn main() {\n    let total = lib::total();\n}\n")
+        .unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+
+    // Open both so the server knows about them
+    harness.execute("diagnostics", json!({"path": "lib.rs"})).await.unwrap();
+    let result = harness
+        .execute(
+            "rename_symbol",
+            json!({"path": "main.rs", "line": 2, "column": 23, "new_name": "sum"}),
+        )
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert!(result.output.starts_with("Renamed to sum in 2 files"), "{}", result.output);
+    assert_eq!(workspace.read("lib.rs").unwrap(), "pub fn sum() -> u32 { 1 }\n");
+    assert_eq!(workspace.read("main.rs").unwrap(), "fn main() {\n    let sum = lib::sum();\n}\n");
+    assert!(result.metadata["diff"].as_str().unwrap().contains("+pub fn sum()"));
+
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_rename_reports_diagnostics_for_every_file() {
+    let workspace = TempWorkspace::new().unwrap();
+    workspace.write("lib.rs", "pub fn total() -> u32 { 1 }\n").unwrap();
+    workspace.write("main.rs", "fn main() {\n    lib::total();\n}\n").unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+
+    harness.execute("diagnostics", json!({"path": "lib.rs"})).await.unwrap();
+    let result = harness
+        .execute(
+            "rename_symbol",
+            json!({"path": "main.rs", "line": 2, "column": 10, "new_name": "BUG"}),
+        )
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert!(result.output.contains("lib.rs:1:8"), "{}", result.output);
+    assert!(result.output.contains("main.rs:2:10"), "{}", result.output);
+    assert_eq!(result.metadata["diagnostics"].as_array().unwrap().len(), 2);
+
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_rename_outside_project_is_rejected() {
+    let workspace = TempWorkspace::new().unwrap();
+    workspace.write("main.rs", "fn main() {\n    shared();\n}\n").unwrap();
+    let elsewhere = TempWorkspace::new().unwrap();
+    elsewhere.write("shared.rs", "pub fn shared() {}\n").unwrap();
+    let lsp = stub_manager(&workspace);
+    let harness = harness(&workspace, &lsp);
+
+    // Opened by absolute path, so the server includes it in the rename
+    let outside = elsewhere.path().join("shared.rs");
+    harness
+        .execute("diagnostics", json!({"path": outside.to_str().unwrap()}))
+        .await
+        .unwrap();
+    let result = harness
+        .execute(
+            "rename_symbol",
+            json!({"path": "main.rs", "line": 2, "column": 5, "new_name": "common"}),
+        )
+        .await
+        .unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("outside the project"));
+    assert_eq!(workspace.read("main.rs").unwrap(), "fn main() {\n    shared();\n}\n");
+    assert_eq!(elsewhere.read("shared.rs").unwrap(), "pub fn shared() {}\n");
+
+    lsp.shutdown().await;
+}
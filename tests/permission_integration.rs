@@ -78,11 +78,11 @@ fn test_permission_storage_location() {
     
     let _manager = PermissionManager::new().unwrap();
     
-    // Just verify we can create it without errors
-    // The actual path is in ~/.promptline/permissions.yaml
+    // Permissions are kept in ~/.promptline/permissions.yaml; creating the
+    // manager creates the directory
     let home = dirs::home_dir().expect("Should have home directory");
     let expected_dir = home.join(".promptline");
     
-    assert!(expected_dir.exists() || true, 
-        "Config directory should exist or be creatable");
+    assert!(expected_dir.is_dir(), 
+        "Config directory should be created by PermissionManager::new");
 }
@@ -0,0 +1,38 @@
+// Offline regression tests for Agent::run, driven by recordings made with --record
+
+use promptline::model::replay::ReplayProvider;
+use promptline::permissions::{PermissionLevel, PermissionManager};
+use promptline::tools::{file_ops, ToolRegistry};
+use promptline::{Agent, Config};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn test_replayed_file_read_run() {
+    // Strict replay fails the run if the agent sends anything the recording did not
+    let model = ReplayProvider::from_file(Path::new("tests/fixtures/read_file_run.jsonl"))
+        .unwrap()
+        .strict(true);
+
+    let mut tools = ToolRegistry::new();
+    tools.register(file_ops::FileReadTool::new());
+
+    let permission_manager = Arc::new(Mutex::new(PermissionManager::default()));
+    permission_manager
+        .lock()
+        .unwrap()
+        .set_permission("file_read".to_string(), PermissionLevel::Once)
+        .unwrap();
+
+    let mut agent = Agent::new(Box::new(model), tools, Config::default(), Vec::new(), permission_manager)
+        .await
+        .unwrap();
+
+    let result = agent.run("What does tests/fixtures/hello.txt say?").await.unwrap();
+
+    assert!(result.success);
+    assert_eq!(result.iterations, 2);
+    assert_eq!(result.tool_calls, vec!["file_read"]);
+    assert!(result.output.contains("hello from a fixture"));
+    assert_eq!(result.usage.requests, 2);
+}
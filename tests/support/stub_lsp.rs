@@ -0,0 +1,200 @@
+//! Minimal language server speaking LSP over stdio, for the integration tests
+//!
+//! Reports an error for every `BUG` and a warning for every `TODO` in open
+//! documents, answers hover with the word under the cursor, resolves
+//! definitions to the first occurrence of that word and renames whole-word
+//! occurrences across open documents. Only ASCII text is handled.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+
+fn main() {
+    let stdin = std::io::stdin();
+    let mut input = BufReader::new(stdin.lock());
+    let mut documents: BTreeMap<String, String> = BTreeMap::new();
+
+    while let Some(message) = read_message(&mut input) {
+        let method = message["method"].as_str().unwrap_or_default();
+        let id = message.get("id").cloned();
+        let params = &message["params"];
+
+        // Replies to our own requests
+        if method.is_empty() {
+            continue;
+        }
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "renameProvider": true
+                },
+                "serverInfo": {"name": "stub-lsp"}
+            }),
+            "initialized" => {
+                send(&json!({
+                    "jsonrpc": "2.0",
+                    "id": "config-1",
+                    "method": "workspace/configuration",
+                    "params": {"items": [{"section": "stub"}]}
+                }));
+                continue;
+            }
+            "textDocument/didOpen" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default().to_string();
+                publish(&uri, &text);
+                documents.insert(uri, text);
+                continue;
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+                let text = params["contentChanges"][0]["text"].as_str().unwrap_or_default().to_string();
+                publish(&uri, &text);
+                documents.insert(uri, text);
+                continue;
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                documents.remove(uri);
+                continue;
+            }
+            "textDocument/hover" => match word_at(&documents, params) {
+                Some(word) => json!({"contents": {"kind": "markdown", "value": format!("```\n{}\n```", word)}}),
+                None => Value::Null,
+            },
+            "textDocument/definition" => match word_at(&documents, params) {
+                Some(word) => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                    occurrences(&documents[uri], &word)
+                        .first()
+                        .map(|&(line, start)| json!([{"uri": uri, "range": range(line, start, word.len())}]))
+                        .unwrap_or(Value::Null)
+                }
+                None => Value::Null,
+            },
+            "textDocument/rename" => match word_at(&documents, params) {
+                Some(word) => {
+                    let new_name = params["newName"].as_str().unwrap_or_default();
+                    let mut changes = serde_json::Map::new();
+                    for (uri, text) in &documents {
+                        let edits: Vec<Value> = occurrences(text, &word)
+                            .into_iter()
+                            .map(|(line, start)| json!({"range": range(line, start, word.len()), "newText": new_name}))
+                            .collect();
+                        if !edits.is_empty() {
+                            changes.insert(uri.clone(), json!(edits));
+                        }
+                    }
+                    json!({"changes": changes})
+                }
+                None => Value::Null,
+            },
+            "shutdown" => Value::Null,
+            "exit" => break,
+            _ => {
+                if let Some(id) = id {
+                    send(&json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": -32601, "message": format!("unsupported method {}", method)}
+                    }));
+                }
+                continue;
+            }
+        };
+        if let Some(id) = id {
+            send(&json!({"jsonrpc": "2.0", "id": id, "result": result}));
+        }
+    }
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn send(message: &Value) {
+    let body = message.to_string();
+    let mut stdout = std::io::stdout().lock();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
+
+fn publish(uri: &str, text: &str) {
+    let mut diagnostics = Vec::new();
+    for (marker, severity, message) in [("BUG", 1, "unexpected BUG"), ("TODO", 2, "unfinished TODO")] {
+        for (line, start) in occurrences(text, marker) {
+            diagnostics.push(json!({
+                "range": range(line, start, marker.len()),
+                "severity": severity,
+                "source": "stub",
+                "message": message
+            }));
+        }
+    }
+    send(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics}
+    }));
+}
+
+fn range(line: usize, start: usize, len: usize) -> Value {
+    json!({
+        "start": {"line": line, "character": start},
+        "end": {"line": line, "character": start + len}
+    })
+}
+
+fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Word under the position of a text document position request
+fn word_at(documents: &BTreeMap<String, String>, params: &Value) -> Option<String> {
+    let text = documents.get(params["textDocument"]["uri"].as_str()?)?;
+    let line = text.lines().nth(params["position"]["line"].as_u64()? as usize)?.as_bytes();
+    let column = params["position"]["character"].as_u64()? as usize;
+    if column >= line.len() || !is_word(line[column]) {
+        return None;
+    }
+    let start = (0..column).rev().take_while(|&i| is_word(line[i])).last().unwrap_or(column);
+    let end = (column..line.len()).find(|&i| !is_word(line[i])).unwrap_or(line.len());
+    Some(String::from_utf8_lossy(&line[start..end]).into_owned())
+}
+
+/// Whole-word occurrences of `word` as (line, column) pairs
+fn occurrences(text: &str, word: &str) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let bytes = line.as_bytes();
+        for (start, _) in line.match_indices(word) {
+            let end = start + word.len();
+            let before = start == 0 || !is_word(bytes[start - 1]);
+            let after = end == bytes.len() || !is_word(bytes[end]);
+            if before && after {
+                found.push((number, start));
+            }
+        }
+    }
+    found
+}